@@ -6,11 +6,12 @@
 use std::collections::HashMap;
 use std::fs;
 use std::io::{Read, Write};
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::os::unix::fs::PermissionsExt;
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::Path;
 use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
 use serde::{Deserialize, Serialize};
@@ -18,6 +19,58 @@ use serde::{Deserialize, Serialize};
 const SOCKET_PATH: &str = "/var/run/ple7-helper.sock";
 const HELPER_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Routes the helper has installed, persisted so a restarted helper can still flush
+/// them. Lives under /var/run because routes don't survive a reboot either.
+const ROUTES_STATE_PATH: &str = "/var/run/ple7-helper-routes.json";
+
+/// pf anchor for the DNS leak block. Sub-anchors of com.apple are evaluated by
+/// the stock /etc/pf.conf, so no changes to the main ruleset are needed.
+const DNS_BLOCK_ANCHOR: &str = "com.apple/250.Ple7DnsBlock";
+
+/// pf anchor blocking internet IPv6 while an IPv4-only tunnel carries all traffic
+const IPV6_BLOCK_ANCHOR: &str = "com.apple/251.Ple7Ipv6Block";
+
+/// Dynamic store key keeping the primary service's DNS settings while set_dns has
+/// replaced them. Outside State:/Network/Service, so configd doesn't use it as a resolver.
+const DNS_BACKUP_KEY: &str = "State:/Network/Ple7/OriginalDNS";
+
+/// Upper bound on simultaneously open utun devices, so a misbehaving client
+/// can't exhaust the system's utun units
+const MAX_TUN_DEVICES: usize = 4;
+
+/// Set to "json" to log one JSON object per line, matching the app's format
+const LOG_FORMAT_ENV: &str = "PLE7_LOG_FORMAT";
+
+/// launchd points stdout and stderr here (see the plist); the helper rotates it itself
+const LOG_PATH: &str = "/var/log/ple7-helper.log";
+
+/// Log files kept: the current one plus `.1` and `.2`
+const KEPT_LOG_FILES: usize = 3;
+
+/// The log is rotated once it grows past this, checked as clients connect
+const MAX_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Source of per-client connection ids, so log lines from one app session can be grouped
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// Id of the client connection this thread serves (None on the accept thread)
+    static CONN_ID: std::cell::Cell<Option<u64>> = const { std::cell::Cell::new(None) };
+    /// The app's VPN connection id for the request being handled, if it sent one
+    static APP_CONN_ID: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Requests larger than this are treated as a broken stream. A WritePacket of
+/// the largest IP packet is about 88KB of base64.
+const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// Largest IP packet plus the 4-byte utun header
+const UTUN_READ_BUFFER: usize = 4 + 65535;
+
+/// Bounds for SetMtu: the IPv6 minimum link MTU up to Ethernet
+const MIN_TUN_MTU: u16 = 1280;
+const MAX_TUN_MTU: u16 = 1500;
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "command")]
 enum HelperCommand {
@@ -48,6 +101,9 @@ enum HelperCommand {
         /// IP address to exclude from VPN routing (e.g., relay endpoint)
         #[serde(default)]
         exclude_ip: Option<String>,
+        /// Also route IPv6 through the tunnel (::/1 and 8000::/1)
+        #[serde(default)]
+        include_ipv6: bool,
     },
     #[serde(rename = "restore_default_gateway")]
     RestoreDefaultGateway,
@@ -69,6 +125,61 @@ enum HelperCommand {
     Ping,
     #[serde(rename = "get_version")]
     GetVersion,
+    #[serde(rename = "get_metrics")]
+    GetMetrics,
+    #[serde(rename = "list_ple7_routes")]
+    ListPle7Routes,
+    #[serde(rename = "flush_ple7_routes")]
+    FlushPle7Routes,
+    #[serde(rename = "block_dns")]
+    BlockDns {
+        tun_name: String,
+        /// Only resolvers reachable on port 53 (comma-separated), and only through the tunnel
+        allowed_dns: String,
+    },
+    #[serde(rename = "unblock_dns")]
+    UnblockDns,
+    #[serde(rename = "set_dns")]
+    SetDns {
+        tun_name: String,
+        /// Resolvers to use system-wide (comma-separated), first is primary
+        servers: String,
+    },
+    #[serde(rename = "restore_dns")]
+    RestoreDns,
+    #[serde(rename = "block_ipv6")]
+    BlockIpv6 {
+        /// IPv6 through this device stays allowed
+        tun_name: String,
+        /// WireGuard endpoints, reachable outside the tunnel
+        #[serde(default)]
+        allow_ips: Vec<String>,
+        /// Local UDP port WireGuard sends from
+        #[serde(default)]
+        udp_port: Option<u16>,
+    },
+    #[serde(rename = "unblock_ipv6")]
+    UnblockIpv6,
+    #[serde(rename = "set_mtu")]
+    SetMtu {
+        tun_name: String,
+        mtu: u16,
+    },
+    #[serde(rename = "set_ipv6_address")]
+    SetIpv6Address {
+        tun_name: String,
+        address: String,
+        prefix_len: u8,
+    },
+}
+
+/// A command as sent over the socket, with the app's connection id when it has one
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(flatten)]
+    command: HelperCommand,
+    #[serde(default)]
+    conn_id: Option<String>,
 }
 
 // Helper module for base64 serialization
@@ -102,17 +213,93 @@ struct HelperResponse {
 
 struct HelperState {
     tun_devices: HashMap<String, TunInfo>,
-    original_gateway: Option<String>,
+    original_gateway: Option<DefaultRoute>,
+    original_gateway_v6: Option<DefaultRoute>,
     /// IP that was excluded from VPN routing (needs to be cleaned up on restore)
     excluded_ip: Option<String>,
+    /// Every route we added and haven't removed yet, mirrored to ROUTES_STATE_PATH
+    routes: Vec<InstalledRoute>,
+    /// pf enable reference from `pfctl -E`, released when the DNS block is removed
+    pf_token: Option<String>,
+    /// Same for the IPv6 block, so either block can go without disabling pf under the other
+    pf_ipv6_token: Option<String>,
+    /// The system DNS settings set_dns replaced, to put back on restore
+    dns_override: Option<DnsOverride>,
+}
+
+/// A primary service DNS key set_dns wrote to
+#[derive(Debug)]
+struct DnsOverride {
+    /// State:/Network/Service/<id>/DNS
+    key: String,
+    /// Whether the key existed before, i.e. was copied to DNS_BACKUP_KEY
+    had_original: bool,
+}
+
+/// A route added by the helper, recorded so it can be removed after an unclean exit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct InstalledRoute {
+    /// "dest/prefix" for network routes, a bare address for host routes
+    target: String,
+    host: bool,
+}
+
+impl InstalledRoute {
+    fn net(destination: &str, prefix_len: u8) -> Self {
+        Self { target: format!("{}/{}", destination, prefix_len), host: false }
+    }
+
+    fn host(ip: &str) -> Self {
+        Self { target: ip.to_string(), host: true }
+    }
+
+    /// Delete the route from the routing table; a route that's already gone counts as success
+    fn delete(&self) -> Result<(), String> {
+        let kind = if self.host { "-host" } else { "-net" };
+        let output = Command::new("route")
+            .args(["-n", "delete", route_family(&self.target), kind, &self.target])
+            .output()
+            .map_err(|e| format!("Failed to execute route command: {}", e))?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if output.status.success() || stderr.contains("not in table") {
+            Ok(())
+        } else {
+            Err(stderr.trim().to_string())
+        }
+    }
 }
 
 struct TunInfo {
+    /// Name the client asked for (the kernel assigns the actual utunN name)
+    requested_name: String,
     address: Ipv4Addr,
     #[allow(dead_code)]
     netmask: Ipv4Addr,
     // File descriptor for the utun device
     fd: i32,
+    /// Packet counters, shared so reads can update them without holding the state lock
+    metrics: Arc<TunMetrics>,
+}
+
+/// Per-TUN packet counters for diagnosing app <-> helper packet loss
+#[derive(Default)]
+struct TunMetrics {
+    packets_read: AtomicU64,
+    packets_written: AtomicU64,
+    read_timeouts: AtomicU64,
+    short_packets: AtomicU64,
+}
+
+impl TunMetrics {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "packets_read": self.packets_read.load(Ordering::Relaxed),
+            "packets_written": self.packets_written.load(Ordering::Relaxed),
+            "read_timeouts": self.read_timeouts.load(Ordering::Relaxed),
+            "short_packets": self.short_packets.load(Ordering::Relaxed),
+        })
+    }
 }
 
 impl HelperState {
@@ -120,16 +307,136 @@ impl HelperState {
         Self {
             tun_devices: HashMap::new(),
             original_gateway: None,
+            original_gateway_v6: None,
             excluded_ip: None,
+            routes: load_routes(),
+            pf_token: None,
+            pf_ipv6_token: None,
+            dns_override: None,
+        }
+    }
+
+    fn record_route(&mut self, route: InstalledRoute) {
+        if !self.routes.contains(&route) {
+            self.routes.push(route);
+            save_routes(&self.routes);
+        }
+    }
+
+    fn forget_route(&mut self, route: &InstalledRoute) {
+        let before = self.routes.len();
+        self.routes.retain(|r| r != route);
+        if self.routes.len() != before {
+            save_routes(&self.routes);
+        }
+    }
+}
+
+fn load_routes() -> Vec<InstalledRoute> {
+    let routes: Vec<InstalledRoute> = fs::read_to_string(ROUTES_STATE_PATH)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+
+    if !routes.is_empty() {
+        log::warn!("Found {} routes left over from a previous run", routes.len());
+    }
+    routes
+}
+
+fn save_routes(routes: &[InstalledRoute]) {
+    let json = match serde_json::to_string(routes) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Failed to serialize route list: {}", e);
+            return;
+        }
+    };
+
+    // Write to a root-only temp file and rename, so a crash never leaves a torn list
+    let tmp_path = format!("{}.tmp", ROUTES_STATE_PATH);
+    let result = fs::write(&tmp_path, json)
+        .and_then(|_| fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o600)))
+        .and_then(|_| fs::rename(&tmp_path, ROUTES_STATE_PATH));
+
+    if let Err(e) = result {
+        log::error!("Failed to persist route list: {}", e);
+    }
+}
+
+fn init_logging() {
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    let json = std::env::var(LOG_FORMAT_ENV).is_ok_and(|format| format.eq_ignore_ascii_case("json"));
+    if json {
+        builder.format(|buf, record| {
+            let ts_ms = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0);
+            let line = serde_json::json!({
+                "ts_ms": ts_ms,
+                "level": record.level().as_str(),
+                "module": record.target(),
+                "message": record.args().to_string(),
+                "conn_id": CONN_ID.with(|id| id.get()),
+                "app_conn_id": APP_CONN_ID.with(|id| id.borrow().clone()),
+            });
+            writeln!(buf, "{}", line)
+        });
+    } else {
+        builder.format(|buf, record| {
+            let ts = buf.timestamp_seconds();
+            APP_CONN_ID.with(|id| match id.borrow().as_deref() {
+                Some(id) => writeln!(buf, "[{} {} {}] [conn {}] {}", ts, record.level(), record.target(), id, record.args()),
+                None => writeln!(buf, "[{} {} {}] {}", ts, record.level(), record.target(), record.args()),
+            })
+        });
+    }
+    builder.init();
+}
+
+/// `path.N` for the Nth previous log
+fn numbered_log(n: usize) -> String {
+    format!("{}.{}", LOG_PATH, n)
+}
+
+/// Shift the log files along, start a new one and point stdout/stderr at it
+fn rotate_log() {
+    for n in (1..KEPT_LOG_FILES).rev() {
+        let from = if n == 1 { LOG_PATH.to_string() } else { numbered_log(n - 1) };
+        if Path::new(&from).exists() {
+            let _ = fs::rename(&from, numbered_log(n));
+        }
+    }
+
+    let mut file = match fs::OpenOptions::new().create(true).append(true).open(LOG_PATH) {
+        Ok(file) => file,
+        Err(e) => {
+            // Keep writing to whatever launchd opened
+            eprintln!("Failed to open {}: {}", LOG_PATH, e);
+            return;
         }
+    };
+    let ts_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let _ = writeln!(file, "===== PLE7 Helper {} session start, pid {}, ts_ms {} =====",
+        HELPER_VERSION, std::process::id(), ts_ms);
+
+    use std::os::unix::io::AsRawFd;
+    unsafe {
+        libc::dup2(file.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::dup2(file.as_raw_fd(), libc::STDERR_FILENO);
     }
 }
 
 fn main() {
-    // Initialize logging
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-        .format_timestamp_secs()
-        .init();
+    // Under launchd stderr is the log file; a manual run keeps logging to its terminal
+    if unsafe { libc::isatty(libc::STDERR_FILENO) } == 0 {
+        rotate_log();
+    }
+    init_logging();
 
     log::info!("PLE7 Helper Daemon starting...");
 
@@ -166,6 +473,9 @@ fn main() {
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
+                if fs::metadata(LOG_PATH).is_ok_and(|meta| meta.len() > MAX_LOG_BYTES) {
+                    rotate_log();
+                }
                 let state = Arc::clone(&state);
                 std::thread::spawn(move || {
                     handle_connection(stream, state);
@@ -179,33 +489,29 @@ fn main() {
 }
 
 fn handle_connection(mut stream: UnixStream, state: Arc<Mutex<HelperState>>) {
+    CONN_ID.with(|id| id.set(Some(NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed))));
     log::debug!("New connection");
 
-    let mut buffer = vec![0u8; 4096];
+    let mut pending = Vec::new();
 
     loop {
         // Read command
-        let n = match stream.read(&mut buffer) {
-            Ok(0) => {
-                log::debug!("Connection closed");
-                return;
-            }
-            Ok(n) => n,
-            Err(e) => {
-                log::error!("Read error: {}", e);
-                return;
-            }
+        let Some(request) = read_command(&mut stream, &mut pending) else {
+            log::debug!("Connection closed");
+            return;
         };
 
-        let request = String::from_utf8_lossy(&buffer[..n]);
-        log::debug!("Received: {}", request);
-
         // Parse and handle command
-        let response = match serde_json::from_str::<HelperCommand>(&request) {
-            Ok(cmd) => handle_command(cmd, &state),
-            Err(e) => HelperResponse {
+        let response = match request {
+            Ok(request) => {
+                APP_CONN_ID.with(|id| *id.borrow_mut() = request.conn_id);
+                let response = handle_command(request.command, &state);
+                APP_CONN_ID.with(|id| *id.borrow_mut() = None);
+                response
+            }
+            Err(message) => HelperResponse {
                 success: false,
-                message: format!("Invalid command: {}", e),
+                message,
                 data: None,
             },
         };
@@ -223,6 +529,53 @@ fn handle_connection(mut stream: UnixStream, state: Arc<Mutex<HelperState>>) {
     }
 }
 
+/// Read the next command, however many reads it takes. Commands end with a
+/// newline; apps from before that send bare JSON, taken once it parses whole.
+/// None when the connection is closed.
+fn read_command(stream: &mut impl Read, pending: &mut Vec<u8>) -> Option<Result<Request, String>> {
+    let mut chunk = [0u8; 8192];
+
+    loop {
+        if let Some(end) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            // Left over when bare JSON was taken before its newline arrived
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            log::debug!("Received: {}", String::from_utf8_lossy(&line).trim_end());
+            return Some(serde_json::from_slice(&line).map_err(|e| format!("Invalid command: {}", e)));
+        }
+        if !pending.is_empty() {
+            match serde_json::from_slice::<Request>(pending) {
+                Ok(cmd) => {
+                    log::debug!("Received: {}", String::from_utf8_lossy(pending));
+                    pending.clear();
+                    return Some(Ok(cmd));
+                }
+                Err(e) if e.is_eof() => {}
+                Err(e) => {
+                    pending.clear();
+                    return Some(Err(format!("Invalid command: {}", e)));
+                }
+            }
+        }
+        if pending.len() > MAX_REQUEST_BYTES {
+            pending.clear();
+            return Some(Err(format!("Command exceeded {} bytes", MAX_REQUEST_BYTES)));
+        }
+
+        match stream.read(&mut chunk) {
+            Ok(0) => return None,
+            Ok(n) => pending.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                log::error!("Read error: {}", e);
+                return None;
+            }
+        }
+    }
+}
+
 fn handle_command(cmd: HelperCommand, state: &Arc<Mutex<HelperState>>) -> HelperResponse {
     match cmd {
         HelperCommand::Ping => {
@@ -256,6 +609,20 @@ fn handle_command(cmd: HelperCommand, state: &Arc<Mutex<HelperState>>) -> Helper
             }
         }
 
+        HelperCommand::GetMetrics => {
+            let state = state.lock().unwrap();
+            let tuns: serde_json::Map<String, serde_json::Value> = state.tun_devices.iter()
+                .map(|(name, info)| (name.clone(), info.metrics.to_json()))
+                .collect();
+            HelperResponse {
+                success: true,
+                message: "ok".to_string(),
+                data: Some(serde_json::json!({
+                    "tuns": tuns,
+                })),
+            }
+        }
+
         HelperCommand::CreateTun { name, address, netmask } => {
             create_tun(state, &name, &address, &netmask)
         }
@@ -265,21 +632,87 @@ fn handle_command(cmd: HelperCommand, state: &Arc<Mutex<HelperState>>) -> Helper
         }
 
         HelperCommand::AddRoute { destination, prefix_len, gateway } => {
-            add_route_with_state(state, &destination, prefix_len, &gateway)
+            let response = add_route_with_state(state, &destination, prefix_len, &gateway);
+            if response.success {
+                state.lock().unwrap().record_route(InstalledRoute::net(&destination, prefix_len));
+            }
+            response
         }
 
         HelperCommand::RemoveRoute { destination, prefix_len } => {
-            remove_route(&destination, prefix_len)
+            let response = remove_route(&destination, prefix_len);
+            if response.success {
+                state.lock().unwrap().forget_route(&InstalledRoute::net(&destination, prefix_len));
+            }
+            response
         }
 
-        HelperCommand::SetDefaultGateway { gateway, exclude_ip } => {
-            set_default_gateway(state, &gateway, exclude_ip.as_deref())
+        HelperCommand::SetDefaultGateway { gateway, exclude_ip, include_ipv6 } => {
+            set_default_gateway(state, &gateway, exclude_ip.as_deref(), include_ipv6)
         }
 
         HelperCommand::RestoreDefaultGateway => {
             restore_default_gateway(state)
         }
 
+        HelperCommand::ListPle7Routes => {
+            let state = state.lock().unwrap();
+            HelperResponse {
+                success: true,
+                message: format!("{} routes installed", state.routes.len()),
+                data: Some(serde_json::json!({ "routes": state.routes })),
+            }
+        }
+
+        HelperCommand::FlushPle7Routes => {
+            flush_routes(state)
+        }
+
+        HelperCommand::BlockDns { tun_name, allowed_dns } => {
+            block_dns(state, &tun_name, &allowed_dns)
+        }
+
+        HelperCommand::UnblockDns => {
+            remove_dns_block(&mut state.lock().unwrap());
+            HelperResponse {
+                success: true,
+                message: "DNS block removed".to_string(),
+                data: None,
+            }
+        }
+
+        HelperCommand::SetDns { tun_name, servers } => {
+            set_dns(state, &tun_name, &servers)
+        }
+
+        HelperCommand::RestoreDns => {
+            match restore_dns(&mut state.lock().unwrap()) {
+                Ok(()) => HelperResponse {
+                    success: true,
+                    message: "DNS restored".to_string(),
+                    data: None,
+                },
+                Err(e) => HelperResponse {
+                    success: false,
+                    message: format!("Failed to restore DNS: {}", e),
+                    data: None,
+                },
+            }
+        }
+
+        HelperCommand::BlockIpv6 { tun_name, allow_ips, udp_port } => {
+            block_ipv6(state, &tun_name, &allow_ips, udp_port)
+        }
+
+        HelperCommand::UnblockIpv6 => {
+            remove_ipv6_block(&mut state.lock().unwrap());
+            HelperResponse {
+                success: true,
+                message: "IPv6 block removed".to_string(),
+                data: None,
+            }
+        }
+
         HelperCommand::ReadPacket { tun_name, timeout_ms } => {
             read_packet(state, &tun_name, timeout_ms)
         }
@@ -287,6 +720,14 @@ fn handle_command(cmd: HelperCommand, state: &Arc<Mutex<HelperState>>) -> Helper
         HelperCommand::WritePacket { tun_name, data } => {
             write_packet(state, &tun_name, &data)
         }
+
+        HelperCommand::SetMtu { tun_name, mtu } => {
+            set_mtu(state, &tun_name, mtu)
+        }
+
+        HelperCommand::SetIpv6Address { tun_name, address, prefix_len } => {
+            set_ipv6_address(state, &tun_name, &address, prefix_len)
+        }
     }
 }
 
@@ -403,9 +844,18 @@ fn configure_utun(name: &str, address: &str, netmask: &str) -> Result<(), String
     Ok(())
 }
 
-fn create_tun(state: &Arc<Mutex<HelperState>>, _name: &str, address: &str, netmask: &str) -> HelperResponse {
+fn create_tun(state: &Arc<Mutex<HelperState>>, name: &str, address: &str, netmask: &str) -> HelperResponse {
     log::info!("Creating TUN device with address {}/{}", address, netmask);
 
+    // Names are only used for bookkeeping, but keep them within IFNAMSIZ
+    if name.is_empty() || name.len() >= libc::IFNAMSIZ || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return HelperResponse {
+            success: false,
+            message: format!("Invalid TUN name: {:?}", name),
+            data: None,
+        };
+    }
+
     let addr: Ipv4Addr = match address.parse() {
         Ok(a) => a,
         Err(e) => return HelperResponse {
@@ -424,6 +874,31 @@ fn create_tun(state: &Arc<Mutex<HelperState>>, _name: &str, address: &str, netma
         },
     };
 
+    {
+        let mut state = state.lock().unwrap();
+
+        // A device with the same name or address is left over from a client that
+        // went away without DestroyTun - replace it rather than stacking another
+        let stale: Vec<String> = state.tun_devices.iter()
+            .filter(|(_, info)| info.requested_name == name || info.address == addr)
+            .map(|(utun, _)| utun.clone())
+            .collect();
+        for utun in stale {
+            if let Some(info) = state.tun_devices.remove(&utun) {
+                log::warn!("Replacing stale TUN device {} ({} / {})", utun, info.requested_name, info.address);
+                unsafe { libc::close(info.fd); }
+            }
+        }
+
+        if state.tun_devices.len() >= MAX_TUN_DEVICES {
+            return HelperResponse {
+                success: false,
+                message: format!("Too many TUN devices open (limit {})", MAX_TUN_DEVICES),
+                data: None,
+            };
+        }
+    }
+
     // Create utun device
     let (fd, actual_name) = match create_utun() {
         Ok((fd, name)) => (fd, name),
@@ -451,9 +926,11 @@ fn create_tun(state: &Arc<Mutex<HelperState>>, _name: &str, address: &str, netma
     // Store device info
     let mut state = state.lock().unwrap();
     state.tun_devices.insert(actual_name.clone(), TunInfo {
+        requested_name: name.to_string(),
         address: addr,
         netmask: mask,
         fd,
+        metrics: Arc::new(TunMetrics::default()),
     });
 
     HelperResponse {
@@ -489,9 +966,204 @@ fn destroy_tun(state: &Arc<Mutex<HelperState>>, name: &str) -> HelperResponse {
     }
 }
 
+/// `route` address-family flag for a destination
+fn route_family(destination: &str) -> &'static str {
+    if destination.contains(':') {
+        "-inet6"
+    } else {
+        "-inet"
+    }
+}
+
+/// Reject prefixes longer than the destination's address family allows
+fn validate_prefix(destination: &str, prefix_len: u8) -> Result<(), HelperResponse> {
+    let max = if destination.contains(':') { 128 } else { 32 };
+    if prefix_len > max {
+        return Err(HelperResponse {
+            success: false,
+            message: format!("Invalid prefix length /{} for {}", prefix_len, destination),
+            data: None,
+        });
+    }
+    Ok(())
+}
+
+/// Default route for one address family, read from the kernel routing table
+#[derive(Debug, Clone, PartialEq)]
+struct DefaultRoute {
+    /// Next hop, or None when the default route points straight at an interface
+    gateway: Option<IpAddr>,
+    /// Outgoing interface, e.g. "en0"
+    interface: String,
+}
+
+impl DefaultRoute {
+    /// Trailing `route add` arguments that send traffic the same way as this default route
+    fn route_args(&self) -> Vec<String> {
+        match self.gateway {
+            // Link-local next hops are only meaningful with their scope
+            Some(IpAddr::V6(ip)) if ip.segments()[0] & 0xffc0 == 0xfe80 => {
+                vec![format!("{}%{}", ip, self.interface)]
+            }
+            Some(ip) => vec![ip.to_string()],
+            None => vec!["-interface".to_string(), self.interface.clone()],
+        }
+    }
+}
+
+impl std::fmt::Display for DefaultRoute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.gateway {
+            Some(ip) => write!(f, "{} on {}", ip, self.interface),
+            None => write!(f, "interface {}", self.interface),
+        }
+    }
+}
+
+/// Current default route for an address family (libc::AF_INET or libc::AF_INET6)
+fn get_default_gateway(family: libc::c_int) -> Option<DefaultRoute> {
+    match dump_routes(family) {
+        Ok(dump) => find_default_route(&dump),
+        Err(e) => {
+            log::warn!("Failed to read routing table: {}", e);
+            None
+        }
+    }
+}
+
+/// Raw `rt_msghdr` records for one family via sysctl(NET_RT_DUMP)
+fn dump_routes(family: libc::c_int) -> Result<Vec<u8>, String> {
+    let mut mib = [libc::CTL_NET, libc::PF_ROUTE, 0, family, libc::NET_RT_DUMP, 0];
+
+    // The table can grow between the size query and the read, so retry on ENOMEM
+    for _ in 0..3 {
+        let mut len: libc::size_t = 0;
+        let ret = unsafe {
+            libc::sysctl(mib.as_mut_ptr(), mib.len() as u32, std::ptr::null_mut(), &mut len, std::ptr::null_mut(), 0)
+        };
+        if ret != 0 {
+            return Err(format!("sysctl size query failed: {}", std::io::Error::last_os_error()));
+        }
+
+        let mut buf = vec![0u8; len + len / 8];
+        len = buf.len();
+        let ret = unsafe {
+            libc::sysctl(mib.as_mut_ptr(), mib.len() as u32, buf.as_mut_ptr() as *mut libc::c_void, &mut len, std::ptr::null_mut(), 0)
+        };
+        if ret == 0 {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ENOMEM) {
+            return Err(format!("sysctl NET_RT_DUMP failed: {}", err));
+        }
+    }
+
+    Err("Routing table kept changing size".to_string())
+}
+
+/// Pick the default route out of a NET_RT_DUMP buffer, preferring the unscoped one
+/// (macOS also keeps per-interface RTF_IFSCOPE defaults)
+fn find_default_route(dump: &[u8]) -> Option<DefaultRoute> {
+    let header_len = std::mem::size_of::<libc::rt_msghdr>();
+    let mut scoped = None;
+    let mut offset = 0;
+
+    while offset + header_len <= dump.len() {
+        let hdr: libc::rt_msghdr = unsafe {
+            std::ptr::read_unaligned(dump[offset..].as_ptr() as *const libc::rt_msghdr)
+        };
+        let msg_len = hdr.rtm_msglen as usize;
+        if msg_len < header_len || offset + msg_len > dump.len() {
+            break;
+        }
+        let msg = &dump[offset + header_len..offset + msg_len];
+        offset += msg_len;
+
+        if hdr.rtm_flags & libc::RTF_UP == 0 {
+            continue;
+        }
+
+        let addrs = route_sockaddrs(msg, hdr.rtm_addrs);
+        let is_default = addrs[libc::RTAX_DST as usize]
+            .and_then(sockaddr_ip)
+            .is_some_and(|dst| dst.is_unspecified())
+            && addrs[libc::RTAX_NETMASK as usize]
+                .is_none_or(|mask| mask.iter().skip(2).all(|&b| b == 0));
+        if !is_default {
+            continue;
+        }
+
+        let Some(interface) = interface_name(hdr.rtm_index) else {
+            continue;
+        };
+        let gateway = if hdr.rtm_flags & libc::RTF_GATEWAY != 0 {
+            addrs[libc::RTAX_GATEWAY as usize].and_then(sockaddr_ip)
+        } else {
+            // Gateway is an AF_LINK sockaddr - the route points at the interface itself
+            None
+        };
+
+        let route = DefaultRoute { gateway, interface };
+        if hdr.rtm_flags & libc::RTF_IFSCOPE == 0 {
+            return Some(route);
+        }
+        scoped.get_or_insert(route);
+    }
+
+    scoped
+}
+
+/// Split the sockaddrs following an rt_msghdr into their RTAX_* slots
+fn route_sockaddrs(mut data: &[u8], present: libc::c_int) -> [Option<&[u8]>; libc::RTAX_MAX as usize] {
+    let mut addrs = [None; libc::RTAX_MAX as usize];
+    for (i, slot) in addrs.iter_mut().enumerate() {
+        if present & (1 << i) == 0 {
+            continue;
+        }
+        let sa_len = data.first().copied().unwrap_or(0) as usize;
+        // Each sockaddr is padded to 4 bytes, and an empty one still takes 4
+        let padded = if sa_len == 0 { 4 } else { (sa_len + 3) & !3 };
+        if padded > data.len() {
+            break;
+        }
+        *slot = Some(&data[..sa_len]);
+        data = &data[padded..];
+    }
+    addrs
+}
+
+fn sockaddr_ip(sa: &[u8]) -> Option<IpAddr> {
+    match *sa.get(1)? as libc::c_int {
+        libc::AF_INET if sa.len() >= 8 => Some(IpAddr::from([sa[4], sa[5], sa[6], sa[7]])),
+        libc::AF_INET6 if sa.len() >= 24 => {
+            let mut octets: [u8; 16] = sa[8..24].try_into().ok()?;
+            // The kernel embeds the scope id in bytes 2-3 of link-local addresses
+            if octets[0] == 0xfe && octets[1] & 0xc0 == 0x80 {
+                octets[2] = 0;
+                octets[3] = 0;
+            }
+            Some(IpAddr::from(octets))
+        }
+        _ => None,
+    }
+}
+
+fn interface_name(index: u16) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    let name = unsafe { libc::if_indextoname(index as libc::c_uint, buf.as_mut_ptr()) };
+    if name.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
 fn add_route_via_gateway(destination: &str, prefix_len: u8, gateway: &str) -> HelperResponse {
     let output = Command::new("route")
-        .args(["-n", "add", "-net", &format!("{}/{}", destination, prefix_len), gateway])
+        .args(["-n", "add", route_family(destination), "-net", &format!("{}/{}", destination, prefix_len), gateway])
         .output();
 
     match output {
@@ -530,6 +1202,10 @@ fn add_route_via_gateway(destination: &str, prefix_len: u8, gateway: &str) -> He
 fn add_route_with_state(state: &Arc<Mutex<HelperState>>, destination: &str, prefix_len: u8, gateway: &str) -> HelperResponse {
     log::info!("Adding route: {}/{} via {}", destination, prefix_len, gateway);
 
+    if let Err(response) = validate_prefix(destination, prefix_len) {
+        return response;
+    }
+
     // Find the interface name by looking up the gateway IP in our TUN devices
     let interface_name = {
         let state = state.lock().unwrap();
@@ -550,12 +1226,12 @@ fn add_route_with_state(state: &Arc<Mutex<HelperState>>, destination: &str, pref
     let output = if let Some(ref iface) = interface_name {
         log::info!("Using interface-based route: {}/{} via interface {}", destination, prefix_len, iface);
         Command::new("route")
-            .args(["-n", "add", "-net", &format!("{}/{}", destination, prefix_len), "-interface", iface])
+            .args(["-n", "add", route_family(destination), "-net", &format!("{}/{}", destination, prefix_len), "-interface", iface])
             .output()
     } else {
         log::info!("Using gateway-based route: {}/{} via gateway {}", destination, prefix_len, gateway);
         Command::new("route")
-            .args(["-n", "add", "-net", &format!("{}/{}", destination, prefix_len), gateway])
+            .args(["-n", "add", route_family(destination), "-net", &format!("{}/{}", destination, prefix_len), gateway])
             .output()
     };
 
@@ -595,8 +1271,12 @@ fn add_route_with_state(state: &Arc<Mutex<HelperState>>, destination: &str, pref
 fn remove_route(destination: &str, prefix_len: u8) -> HelperResponse {
     log::info!("Removing route: {}/{}", destination, prefix_len);
 
+    if let Err(response) = validate_prefix(destination, prefix_len) {
+        return response;
+    }
+
     let output = Command::new("route")
-        .args(["-n", "delete", "-net", &format!("{}/{}", destination, prefix_len)])
+        .args(["-n", "delete", route_family(destination), "-net", &format!("{}/{}", destination, prefix_len)])
         .output();
 
     match output {
@@ -624,39 +1304,37 @@ fn remove_route(destination: &str, prefix_len: u8) -> HelperResponse {
     }
 }
 
-fn set_default_gateway(state: &Arc<Mutex<HelperState>>, gateway: &str, exclude_ip: Option<&str>) -> HelperResponse {
-    log::info!("Setting default gateway to: {}", gateway);
+fn set_default_gateway(state: &Arc<Mutex<HelperState>>, gateway: &str, exclude_ip: Option<&str>, include_ipv6: bool) -> HelperResponse {
+    log::info!("Setting default gateway to: {} (ipv6: {})", gateway, include_ipv6);
     if let Some(ip) = exclude_ip {
         log::info!("Excluding IP from VPN routing: {}", ip);
     }
 
-    // Save current default gateway
-    let mut original_gw: Option<String> = None;
-    let output = Command::new("route")
-        .args(["-n", "get", "default"])
-        .output();
-
-    if let Ok(output) = output {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        for line in stdout.lines() {
-            if line.contains("gateway:") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    original_gw = Some(parts[1].to_string());
-                    let mut state = state.lock().unwrap();
-                    state.original_gateway = Some(parts[1].to_string());
-                    log::info!("Saved original gateway: {}", parts[1]);
-                }
-            }
+    // Save current default gateways
+    let original_gw = get_default_gateway(libc::AF_INET);
+    let original_gw_v6 = get_default_gateway(libc::AF_INET6);
+    {
+        let mut state = state.lock().unwrap();
+        if let Some(ref gw) = original_gw {
+            log::info!("Saved original gateway: {}", gw);
+            state.original_gateway = Some(gw.clone());
+        }
+        if let Some(ref gw) = original_gw_v6 {
+            log::info!("Saved original IPv6 gateway: {}", gw);
+            state.original_gateway_v6 = Some(gw.clone());
         }
     }
 
     // Add bypass route for excluded IP (e.g., relay endpoint) via original gateway
     // This MUST be done BEFORE setting VPN routes to prevent routing loop
-    if let (Some(ip), Some(ref orig_gw)) = (exclude_ip, &original_gw) {
+    let bypass_gw = exclude_ip.and_then(|ip| {
+        if ip.contains(':') { original_gw_v6.clone() } else { original_gw.clone() }
+    });
+    if let (Some(ip), Some(ref orig_gw)) = (exclude_ip, &bypass_gw) {
         log::info!("Adding bypass route for {} via {}", ip, orig_gw);
         let result = Command::new("route")
-            .args(["-n", "add", "-host", ip, orig_gw])
+            .args(["-n", "add", route_family(ip), "-host", ip])
+            .args(orig_gw.route_args())
             .output();
 
         match result {
@@ -665,6 +1343,7 @@ fn set_default_gateway(state: &Arc<Mutex<HelperState>>, gateway: &str, exclude_i
                 // Store excluded IP so we can remove it on restore
                 let mut state = state.lock().unwrap();
                 state.excluded_ip = Some(ip.to_string());
+                state.record_route(InstalledRoute::host(ip));
             }
             Ok(o) => {
                 let stderr = String::from_utf8_lossy(&o.stderr);
@@ -672,6 +1351,7 @@ fn set_default_gateway(state: &Arc<Mutex<HelperState>>, gateway: &str, exclude_i
                 // Still store it so we can try to clean it up
                 let mut state = state.lock().unwrap();
                 state.excluded_ip = Some(ip.to_string());
+                state.record_route(InstalledRoute::host(ip));
             }
             Err(e) => {
                 log::error!("Failed to add bypass route: {}", e);
@@ -693,20 +1373,73 @@ fn set_default_gateway(state: &Arc<Mutex<HelperState>>, gateway: &str, exclude_i
         .args(["-n", "add", "-net", "128.0.0.0/1", gateway])
         .output();
 
+    // Record both halves before checking results - a half-applied split still needs flushing
+    {
+        let mut state = state.lock().unwrap();
+        state.record_route(InstalledRoute::net("0.0.0.0", 1));
+        state.record_route(InstalledRoute::net("128.0.0.0", 1));
+    }
+
     match (result1, result2) {
-        (Ok(o1), Ok(o2)) if o1.status.success() && o2.status.success() => {
-            HelperResponse {
-                success: true,
-                message: "Default gateway set".to_string(),
-                data: None,
-            }
-        }
-        _ => HelperResponse {
+        (Ok(o1), Ok(o2)) if o1.status.success() && o2.status.success() => {}
+        _ => return HelperResponse {
             success: false,
             message: "Failed to set default gateway".to_string(),
             data: None,
         },
     }
+
+    if include_ipv6 {
+        if let Err(e) = set_default_gateway_v6(state, gateway) {
+            return HelperResponse {
+                success: false,
+                message: format!("Failed to set IPv6 default gateway: {}", e),
+                data: None,
+            };
+        }
+    }
+
+    HelperResponse {
+        success: true,
+        message: "Default gateway set".to_string(),
+        data: None,
+    }
+}
+
+/// Add ::/1 and 8000::/1 split routes through the TUN whose address is `gateway`
+fn set_default_gateway_v6(state: &Arc<Mutex<HelperState>>, gateway: &str) -> Result<(), String> {
+    let gateway_ip: Ipv4Addr = gateway.parse()
+        .map_err(|_| format!("Invalid gateway IP: {}", gateway))?;
+
+    // utun interfaces have no IPv6 next hop, so the routes must be interface-based
+    let iface = {
+        let state = state.lock().unwrap();
+        state.tun_devices.iter()
+            .find(|(_, info)| info.address == gateway_ip)
+            .map(|(name, _)| name.clone())
+            .ok_or_else(|| format!("No TUN device with address {}", gateway))?
+    };
+
+    for net in ["::/1", "8000::/1"] {
+        let output = Command::new("route")
+            .args(["-n", "add", "-inet6", "-net", net, "-interface", &iface])
+            .output()
+            .map_err(|e| format!("Failed to execute route command: {}", e))?;
+
+        state.lock().unwrap().record_route(InstalledRoute {
+            target: net.to_string(),
+            host: false,
+        });
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stderr.contains("File exists") {
+                return Err(format!("{}: {}", net, stderr.trim()));
+            }
+        }
+    }
+
+    Ok(())
 }
 
 fn restore_default_gateway(state: &Arc<Mutex<HelperState>>) -> HelperResponse {
@@ -723,17 +1456,32 @@ fn restore_default_gateway(state: &Arc<Mutex<HelperState>>) -> HelperResponse {
         .output()
         .ok();
 
+    // IPv6 split routes (harmless if they were never added)
+    for net in ["::/1", "8000::/1"] {
+        Command::new("route")
+            .args(["-n", "delete", "-inet6", "-net", net])
+            .output()
+            .ok();
+    }
+
     let mut state = state.lock().unwrap();
+    for net in ["0.0.0.0/1", "128.0.0.0/1", "::/1", "8000::/1"] {
+        state.forget_route(&InstalledRoute { target: net.to_string(), host: false });
+    }
 
     // Remove bypass route for excluded IP
-    if let Some(ref excluded) = state.excluded_ip {
+    if let Some(excluded) = state.excluded_ip.take() {
         log::info!("Removing bypass route for {}", excluded);
         Command::new("route")
-            .args(["-n", "delete", "-host", excluded])
+            .args(["-n", "delete", route_family(&excluded), "-host", &excluded])
             .output()
             .ok();
+        state.forget_route(&InstalledRoute::host(&excluded));
     }
-    state.excluded_ip = None;
+
+    // The DNS and IPv6 blocks only make sense while traffic goes through the tunnel
+    remove_dns_block(&mut state);
+    remove_ipv6_block(&mut state);
 
     if let Some(ref original) = state.original_gateway {
         log::info!("Restored original gateway: {}", original);
@@ -746,12 +1494,441 @@ fn restore_default_gateway(state: &Arc<Mutex<HelperState>>) -> HelperResponse {
     }
 }
 
+/// Delete every route the helper believes it installed, e.g. after the app crashed mid-session
+fn flush_routes(state: &Arc<Mutex<HelperState>>) -> HelperResponse {
+    let mut state = state.lock().unwrap();
+    log::info!("Flushing {} PLE7 routes", state.routes.len());
+
+    let mut failed = Vec::new();
+    let mut removed = 0;
+    for route in std::mem::take(&mut state.routes) {
+        match route.delete() {
+            Ok(()) => removed += 1,
+            Err(e) => {
+                log::warn!("Failed to delete route {}: {}", route.target, e);
+                failed.push(route);
+            }
+        }
+    }
+
+    state.excluded_ip = None;
+    remove_dns_block(&mut state);
+    remove_ipv6_block(&mut state);
+    if let Err(e) = restore_dns(&mut state) {
+        log::warn!("Failed to restore DNS: {}", e);
+    }
+    let success = failed.is_empty();
+    let message = if success {
+        format!("Flushed {} routes", removed)
+    } else {
+        format!("Flushed {} routes, {} could not be removed", removed, failed.len())
+    };
+    // Keep failures on the list so a later flush can retry them
+    state.routes = failed;
+    save_routes(&state.routes);
+
+    HelperResponse {
+        success,
+        message,
+        data: Some(serde_json::json!({ "removed": removed, "remaining": state.routes })),
+    }
+}
+
+/// Drop port 53 traffic except to `allowed_dns` over the tunnel, so apps with
+/// hardcoded resolvers can't leak queries over the physical interface
+fn block_dns(state: &Arc<Mutex<HelperState>>, tun_name: &str, allowed_dns: &str) -> HelperResponse {
+    log::info!("Blocking DNS except {} via {}", allowed_dns, tun_name);
+
+    // Both values end up in a pf ruleset, so only accept exactly what we expect
+    let dns: Vec<Ipv4Addr> = match allowed_dns.split(',').map(|ip| ip.trim().parse()).collect() {
+        Ok(ips) => ips,
+        Err(_) => return HelperResponse {
+            success: false,
+            message: format!("Invalid DNS server: {}", allowed_dns),
+            data: None,
+        },
+    };
+    let dns = dns.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(" ");
+
+    let mut state = state.lock().unwrap();
+    if !state.tun_devices.contains_key(tun_name) {
+        return HelperResponse {
+            success: false,
+            message: format!("TUN device not found: {}", tun_name),
+            data: None,
+        };
+    }
+
+    let rules = format!(
+        "pass out quick on lo0 proto {{ udp tcp }} to any port 53\n\
+         pass out quick on {} proto {{ udp tcp }} to {{ {} }} port 53\n\
+         block drop out quick proto {{ udp tcp }} to any port 53\n",
+        tun_name, dns
+    );
+
+    if let Err(e) = load_pf_anchor(DNS_BLOCK_ANCHOR, &rules) {
+        return HelperResponse {
+            success: false,
+            message: format!("Failed to load DNS block rules: {}", e),
+            data: None,
+        };
+    }
+
+    if state.pf_token.is_none() {
+        state.pf_token = enable_pf();
+    }
+
+    HelperResponse {
+        success: true,
+        message: "DNS leak block enabled".to_string(),
+        data: None,
+    }
+}
+
+/// Point the system resolver at `servers` by replacing the primary service's DNS
+/// servers in the dynamic store, keeping the original for restore_dns. Without
+/// this the resolver keeps asking the router, which the DNS leak block drops.
+fn set_dns(state: &Arc<Mutex<HelperState>>, tun_name: &str, servers: &str) -> HelperResponse {
+    log::info!("Setting DNS to {} for {}", servers, tun_name);
+
+    // They go into a scutil script, so only accept addresses
+    let servers: Vec<Ipv4Addr> = match servers.split(',').map(|ip| ip.trim().parse()).collect() {
+        Ok(ips) => ips,
+        Err(_) => return HelperResponse {
+            success: false,
+            message: format!("Invalid DNS server: {}", servers),
+            data: None,
+        },
+    };
+    let servers = servers.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(" ");
+
+    let mut state = state.lock().unwrap();
+    if !state.tun_devices.contains_key(tun_name) {
+        return HelperResponse {
+            success: false,
+            message: format!("TUN device not found: {}", tun_name),
+            data: None,
+        };
+    }
+
+    match override_dns(&mut state, &servers) {
+        Ok(()) => HelperResponse {
+            success: true,
+            message: "DNS set".to_string(),
+            data: None,
+        },
+        Err(e) => {
+            if let Err(restore_error) = restore_dns(&mut state) {
+                log::warn!("Failed to undo partial DNS change: {}", restore_error);
+            }
+            HelperResponse {
+                success: false,
+                message: format!("Failed to set DNS: {}", e),
+                data: None,
+            }
+        }
+    }
+}
+
+/// Write `servers` (space-separated) into the primary service's DNS key, saving
+/// what was there first
+fn override_dns(state: &mut HelperState, servers: &str) -> Result<(), String> {
+    // A second call (e.g. resume) keeps the original saved by the first
+    let (key, had_original) = match &state.dns_override {
+        Some(current) => (current.key.clone(), current.had_original),
+        None => {
+            let key = format!("State:/Network/Service/{}/DNS", primary_service()?);
+            let had_original = !run_scutil(&format!("show {}\n", key))?.contains("No such key");
+            if had_original {
+                run_scutil(&format!("get {}\nset {}\n", key, DNS_BACKUP_KEY))?;
+            }
+            state.dns_override = Some(DnsOverride { key: key.clone(), had_original });
+            (key, had_original)
+        }
+    };
+
+    // Start from the saved settings so search domains survive
+    let base = if had_original { format!("get {}\n", DNS_BACKUP_KEY) } else { "d.init\n".to_string() };
+    run_scutil(&format!("{}d.add ServerAddresses * {}\nset {}\n", base, servers, key)).map(|_| ())
+}
+
+/// Put back the DNS settings set_dns replaced (safe to call when it didn't)
+fn restore_dns(state: &mut HelperState) -> Result<(), String> {
+    let Some(DnsOverride { key, had_original }) = state.dns_override.take() else {
+        return Ok(());
+    };
+
+    log::info!("Restoring DNS settings of {}", key);
+    let script = if had_original {
+        format!("get {}\nset {}\nremove {}\n", DNS_BACKUP_KEY, key, DNS_BACKUP_KEY)
+    } else {
+        format!("remove {}\n", key)
+    };
+    run_scutil(&script).map(|_| ())
+}
+
+/// Service ID of the primary network service (the one the default route goes through)
+fn primary_service() -> Result<String, String> {
+    let output = run_scutil("show State:/Network/Global/IPv4\n")?;
+    output.lines()
+        .find_map(|line| line.trim().strip_prefix("PrimaryService : "))
+        .map(|id| id.trim().to_string())
+        .ok_or_else(|| "No primary network service".to_string())
+}
+
+/// Feed a script to `scutil` and return what it printed
+fn run_scutil(script: &str) -> Result<String, String> {
+    use std::process::Stdio;
+
+    let mut child = Command::new("scutil")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run scutil: {}", e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(script.as_bytes())
+            .map_err(|e| format!("Failed to write scutil commands: {}", e))?;
+    }
+
+    let output = child.wait_with_output()
+        .map_err(|e| format!("scutil failed: {}", e))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Reject internet IPv6 (2000::/3) everywhere but `tun_name`, so apps preferring
+/// native IPv6 can't bypass a tunnel that only carries IPv4. WireGuard's own
+/// traffic - to `allow_ips` and from `udp_port` - still goes out.
+fn block_ipv6(state: &Arc<Mutex<HelperState>>, tun_name: &str, allow_ips: &[String], udp_port: Option<u16>) -> HelperResponse {
+    log::info!("Blocking IPv6 egress outside {} (allowing {:?}, UDP port {:?})", tun_name, allow_ips, udp_port);
+
+    // Parsed rather than pasted into the ruleset, so nothing else gets into pf
+    let allow_ips: Vec<Ipv6Addr> = match allow_ips.iter().map(|ip| ip.parse()).collect() {
+        Ok(ips) => ips,
+        Err(e) => {
+            return HelperResponse {
+                success: false,
+                message: format!("Invalid IPv6 address: {}", e),
+                data: None,
+            };
+        }
+    };
+
+    let mut state = state.lock().unwrap();
+    // Also keeps anything but our own utun name out of the pf ruleset
+    if !state.tun_devices.contains_key(tun_name) {
+        return HelperResponse {
+            success: false,
+            message: format!("TUN device not found: {}", tun_name),
+            data: None,
+        };
+    }
+
+    let mut rules = format!("pass out quick on {} inet6 all\n", tun_name);
+    if let Some(port) = udp_port {
+        rules.push_str(&format!("pass out quick inet6 proto udp from any port {}\n", port));
+    }
+    if !allow_ips.is_empty() {
+        let allow_ips: Vec<String> = allow_ips.iter().map(|ip| ip.to_string()).collect();
+        rules.push_str(&format!("pass out quick inet6 to {{ {} }}\n", allow_ips.join(", ")));
+    }
+    rules.push_str("block return out quick inet6 to 2000::/3\n");
+
+    if let Err(e) = load_pf_anchor(IPV6_BLOCK_ANCHOR, &rules) {
+        return HelperResponse {
+            success: false,
+            message: format!("Failed to load IPv6 block rules: {}", e),
+            data: None,
+        };
+    }
+
+    if state.pf_ipv6_token.is_none() {
+        state.pf_ipv6_token = enable_pf();
+    }
+
+    HelperResponse {
+        success: true,
+        message: "IPv6 leak block enabled".to_string(),
+        data: None,
+    }
+}
+
+/// pf may be disabled system-wide - take a reference so we only turn it off if we
+/// turned it on. Returns the token to release with release_pf().
+fn enable_pf() -> Option<String> {
+    match Command::new("pfctl").arg("-E").output() {
+        Ok(output) => {
+            // "Token : 1234..." is printed on stderr
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            stderr.lines()
+                .find_map(|line| line.strip_prefix("Token : "))
+                .map(|token| token.trim().to_string())
+        }
+        Err(e) => {
+            log::warn!("Failed to enable pf: {}", e);
+            None
+        }
+    }
+}
+
+fn release_pf(token: &str) {
+    log::info!("Releasing pf reference {}", token);
+    Command::new("pfctl")
+        .args(["-X", token])
+        .output()
+        .ok();
+}
+
+fn set_mtu(state: &Arc<Mutex<HelperState>>, tun_name: &str, mtu: u16) -> HelperResponse {
+    // Runs ifconfig as root, so only on our own devices and within sane bounds
+    if !state.lock().unwrap().tun_devices.contains_key(tun_name) {
+        return HelperResponse {
+            success: false,
+            message: format!("TUN device not found: {}", tun_name),
+            data: None,
+        };
+    }
+    if !(MIN_TUN_MTU..=MAX_TUN_MTU).contains(&mtu) {
+        return HelperResponse {
+            success: false,
+            message: format!("MTU {} outside {}-{}", mtu, MIN_TUN_MTU, MAX_TUN_MTU),
+            data: None,
+        };
+    }
+
+    log::info!("Setting {} MTU to {}", tun_name, mtu);
+    match Command::new("ifconfig").args([tun_name, "mtu", &mtu.to_string()]).output() {
+        Ok(output) if output.status.success() => HelperResponse {
+            success: true,
+            message: format!("MTU set to {}", mtu),
+            data: None,
+        },
+        Ok(output) => HelperResponse {
+            success: false,
+            message: format!("Failed to set MTU: {}", String::from_utf8_lossy(&output.stderr).trim()),
+            data: None,
+        },
+        Err(e) => HelperResponse {
+            success: false,
+            message: format!("Failed to execute ifconfig: {}", e),
+            data: None,
+        },
+    }
+}
+
+/// Give a TUN device an IPv6 address alongside its IPv4 one
+fn set_ipv6_address(state: &Arc<Mutex<HelperState>>, tun_name: &str, address: &str, prefix_len: u8) -> HelperResponse {
+    // Runs ifconfig as root, so only on our own devices and with a well-formed address
+    if !state.lock().unwrap().tun_devices.contains_key(tun_name) {
+        return HelperResponse {
+            success: false,
+            message: format!("TUN device not found: {}", tun_name),
+            data: None,
+        };
+    }
+    let addr: Ipv6Addr = match address.parse() {
+        Ok(a) => a,
+        Err(e) => return HelperResponse {
+            success: false,
+            message: format!("Invalid IPv6 address: {}", e),
+            data: None,
+        },
+    };
+    if let Err(response) = validate_prefix(address, prefix_len) {
+        return response;
+    }
+
+    log::info!("Setting {} IPv6 address to {}/{}", tun_name, addr, prefix_len);
+    let output = Command::new("ifconfig")
+        .args([tun_name, "inet6", &addr.to_string(), "prefixlen", &prefix_len.to_string(), "alias"])
+        .output();
+    match output {
+        Ok(output) if output.status.success() => HelperResponse {
+            success: true,
+            message: format!("IPv6 address set to {}/{}", addr, prefix_len),
+            data: None,
+        },
+        // Already there on a reused device
+        Ok(output) if String::from_utf8_lossy(&output.stderr).contains("File exists") => HelperResponse {
+            success: true,
+            message: "IPv6 address already set".to_string(),
+            data: None,
+        },
+        Ok(output) => HelperResponse {
+            success: false,
+            message: format!("Failed to set IPv6 address: {}", String::from_utf8_lossy(&output.stderr).trim()),
+            data: None,
+        },
+        Err(e) => HelperResponse {
+            success: false,
+            message: format!("Failed to execute ifconfig: {}", e),
+            data: None,
+        },
+    }
+}
+
+fn load_pf_anchor(anchor: &str, rules: &str) -> Result<(), String> {
+    use std::process::Stdio;
+
+    let mut child = Command::new("pfctl")
+        .args(["-a", anchor, "-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run pfctl: {}", e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(rules.as_bytes())
+            .map_err(|e| format!("Failed to write pf rules: {}", e))?;
+    }
+
+    let output = child.wait_with_output()
+        .map_err(|e| format!("pfctl failed: {}", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// Flush the DNS block anchor and release our pf reference (safe to call when not blocking)
+fn remove_dns_block(state: &mut HelperState) {
+    Command::new("pfctl")
+        .args(["-a", DNS_BLOCK_ANCHOR, "-F", "all"])
+        .output()
+        .ok();
+
+    if let Some(token) = state.pf_token.take() {
+        release_pf(&token);
+    }
+}
+
+/// Flush the IPv6 block anchor and release its pf reference (safe to call when not blocking)
+fn remove_ipv6_block(state: &mut HelperState) {
+    Command::new("pfctl")
+        .args(["-a", IPV6_BLOCK_ANCHOR, "-F", "all"])
+        .output()
+        .ok();
+
+    if let Some(token) = state.pf_ipv6_token.take() {
+        release_pf(&token);
+    }
+}
+
 fn read_packet(state: &Arc<Mutex<HelperState>>, tun_name: &str, timeout_ms: Option<u64>) -> HelperResponse {
     // Get fd without holding lock during blocking read
-    let fd = {
+    let (fd, metrics) = {
         let state = state.lock().unwrap();
         match state.tun_devices.get(tun_name) {
-            Some(info) => info.fd,
+            Some(info) => (info.fd, info.metrics.clone()),
             None => {
                 return HelperResponse {
                     success: false,
@@ -780,7 +1957,7 @@ fn read_packet(state: &Arc<Mutex<HelperState>>, tun_name: &str, timeout_ms: Opti
     }
 
     // Read from utun - utun packets have a 4-byte header (AF family)
-    let mut buf = vec![0u8; 65535];
+    let mut buf = vec![0u8; UTUN_READ_BUFFER];
     let n = unsafe {
         libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
     };
@@ -789,6 +1966,7 @@ fn read_packet(state: &Arc<Mutex<HelperState>>, tun_name: &str, timeout_ms: Opti
         let err = std::io::Error::last_os_error();
         if err.kind() == std::io::ErrorKind::WouldBlock || err.kind() == std::io::ErrorKind::TimedOut {
             // Don't log timeouts - they're expected and frequent
+            metrics.read_timeouts.fetch_add(1, Ordering::Relaxed);
             return HelperResponse {
                 success: true,
                 message: "timeout".to_string(),
@@ -805,6 +1983,7 @@ fn read_packet(state: &Arc<Mutex<HelperState>>, tun_name: &str, timeout_ms: Opti
 
     if n < 4 {
         log::warn!("[HELPER] Packet too short: {} bytes", n);
+        metrics.short_packets.fetch_add(1, Ordering::Relaxed);
         return HelperResponse {
             success: false,
             message: "Packet too short".to_string(),
@@ -812,6 +1991,8 @@ fn read_packet(state: &Arc<Mutex<HelperState>>, tun_name: &str, timeout_ms: Opti
         };
     }
 
+    metrics.packets_read.fetch_add(1, Ordering::Relaxed);
+
     // Log successful read with packet details
     let packet = &buf[4..n as usize];
     if packet.len() >= 20 {
@@ -885,6 +2066,8 @@ fn write_packet(state: &Arc<Mutex<HelperState>>, tun_name: &str, data: &[u8]) ->
         };
     }
 
+    tun_info.metrics.packets_written.fetch_add(1, Ordering::Relaxed);
+
     HelperResponse {
         success: true,
         message: "ok".to_string(),
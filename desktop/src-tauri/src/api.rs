@@ -1,11 +1,102 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 use tauri::State;
 
+use crate::error::PleError;
+use crate::tls::TlsSettings;
 use crate::tunnel::AppState;
 
+/// Longest Retry-After a GET will transparently wait out before giving up
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(10);
+
+/// Assumed wait when a 429 has no usable Retry-After header
+const DEFAULT_RETRY_AFTER_SECS: u64 = 5;
+
+/// Upper bound on the whole relay latency measurement (probes run concurrently)
+const RELAY_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Returns the Retry-After seconds if the response is a 429
+fn rate_limited(response: &reqwest::Response) -> Option<u64> {
+    if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let header = response.headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok());
+    Some(parse_retry_after(header))
+}
+
+/// 401/403 mean the token or credentials were rejected, anything else is a server-side failure
+fn status_error(status: reqwest::StatusCode, message: String) -> PleError {
+    match status {
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => PleError::Auth(message),
+        _ => PleError::Api(message),
+    }
+}
+
+/// For per-device calls a 403 means the device belongs to someone else rather than a
+/// bad token, so it mustn't send the user back to the login screen
+fn device_error(status: reqwest::StatusCode, message: String) -> PleError {
+    match status {
+        reqwest::StatusCode::NOT_FOUND => PleError::NotFound(format!("{}: device not found", message)),
+        reqwest::StatusCode::FORBIDDEN => PleError::Forbidden(format!("{}: not allowed for this device", message)),
+        _ => status_error(status, message),
+    }
+}
+
+/// Network create/delete answers 409 for a taken name and 403 when the plan's
+/// network limit is reached (or the user can't manage the network)
+fn network_error(status: reqwest::StatusCode, message: String) -> PleError {
+    match status {
+        reqwest::StatusCode::CONFLICT => PleError::Api(format!("{}: a network with this name already exists", message)),
+        reqwest::StatusCode::FORBIDDEN => PleError::Forbidden(format!("{}: not allowed by your plan", message)),
+        reqwest::StatusCode::NOT_FOUND => PleError::NotFound(format!("{}: network not found", message)),
+        _ => status_error(status, message),
+    }
+}
+
+/// Network IP ranges must be an IPv4 CIDR with no host bits set, e.g. 10.100.0.0/24
+fn validate_ip_range(ip_range: &str) -> Result<(), PleError> {
+    let net = ip_range
+        .trim()
+        .parse::<ipnet::Ipv4Net>()
+        .map_err(|_| PleError::Parse(format!("Invalid IP range '{}': expected CIDR like 10.100.0.0/24", ip_range)))?;
+    if net != net.trunc() {
+        return Err(PleError::Parse(format!("Invalid IP range '{}': did you mean {}?", ip_range, net.trunc())));
+    }
+    Ok(())
+}
+
+/// Request never got a response; a pinning rejection is reported separately so it isn't retried
+fn request_error(e: reqwest::Error) -> PleError {
+    if crate::tls::is_pin_mismatch(&e) {
+        return PleError::CertificatePinMismatch(format!(
+            "Server certificate doesn't match a pinned key - refusing to connect: {}", e));
+    }
+    if e.is_connect() {
+        if let Ok(Some(proxy)) = crate::proxy::current() {
+            return PleError::Network(format!("Couldn't reach the server through proxy {}: {}", proxy, e));
+        }
+    }
+    PleError::Network(e.to_string())
+}
+
+fn parse_error(e: reqwest::Error) -> PleError {
+    PleError::Parse(format!("Failed to parse response: {}", e))
+}
+
+/// Retry-After in delta-seconds form (HTTP-date values fall back to the default)
+fn parse_retry_after(value: Option<&str>) -> u64 {
+    value
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_RETRY_AFTER_SECS)
+}
+
 pub struct ApiClient {
     pub base_url: String,
-    client: reqwest::Client,
+    /// Swapped out when the proxy changes, see rebuild
+    client: parking_lot::RwLock<reqwest::Client>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -60,13 +151,29 @@ pub struct Device {
     pub platform: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Holds the device's private key in `config` - wiped on drop and never printed by Debug
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DeviceConfig {
     pub config: String,
     #[serde(rename = "hasPrivateKey")]
     pub has_private_key: bool,
 }
 
+impl Drop for DeviceConfig {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(&mut self.config);
+    }
+}
+
+impl std::fmt::Debug for DeviceConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceConfig")
+            .field("config", &format_args!("[{} bytes]", self.config.len()))
+            .field("has_private_key", &self.has_private_key)
+            .finish()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Relay {
     pub id: String,
@@ -77,26 +184,138 @@ pub struct Relay {
     pub status: String,
 }
 
+/// Measured round trip to a relay, `latency_ms` is None if it didn't answer in time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayLatency {
+    pub id: String,
+    pub name: String,
+    pub country_code: String,
+    pub public_endpoint: String,
+    pub latency_ms: Option<u64>,
+}
+
+impl Relay {
+    /// Whether the server lists the relay as usable
+    pub fn is_healthy(&self) -> bool {
+        !matches!(self.status.to_ascii_lowercase().as_str(), "offline" | "down" | "maintenance")
+    }
+}
+
+/// Fastest first, unreachable relays last
+fn sort_by_latency(latencies: &mut [RelayLatency]) {
+    latencies.sort_by_key(|r| (r.latency_ms.is_none(), r.latency_ms));
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExitNodeOption {
+    // Absent when the type is "none"
+    #[serde(default)]
     pub id: String,
+    #[serde(default)]
     pub name: String,
     #[serde(rename = "type")]
     pub node_type: String, // "none", "relay", "device"
     pub country_code: Option<String>,
 }
 
+impl ExitNodeOption {
+    /// No exit node - only mesh traffic goes through the VPN
+    pub fn none() -> Self {
+        Self {
+            id: String::new(),
+            name: String::new(),
+            node_type: "none".to_string(),
+            country_code: None,
+        }
+    }
+}
+
+/// Exit node from a GET /exit-node body - an empty body or `null` means none is set
+fn parse_exit_node(body: &str) -> Result<ExitNodeOption, PleError> {
+    let body = body.trim();
+    if body.is_empty() || body == "null" {
+        return Ok(ExitNodeOption::none());
+    }
+    serde_json::from_str(body)
+        .map_err(|e| PleError::Parse(format!("Failed to parse exit node: {}", e)))
+}
+
+/// Connection quality report for server-side relay selection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionMetrics {
+    #[serde(rename = "handshakeRttMs")]
+    pub handshake_rtt_ms: Option<u32>,
+    #[serde(rename = "keepaliveRttMs")]
+    pub keepalive_rtt_ms: Option<u32>,
+    /// Share of the handshake initiations sent since the last report that went unanswered
+    #[serde(rename = "packetLoss")]
+    pub packet_loss: f32,
+    #[serde(rename = "connectionType")]
+    pub connection_type: String,
+}
+
 impl ApiClient {
-    pub fn new(base_url: String) -> Self {
+    /// Create a client that also trusts a private CA (self-hosted control planes)
+    /// and checks the pinned keys
+    pub fn with_tls(base_url: String, tls: &TlsSettings) -> Result<Self, String> {
+        Ok(Self {
+            base_url,
+            client: parking_lot::RwLock::new(tls.http_client()?),
+        })
+    }
+
+    /// Send the requests that follow with `client`, e.g. after a proxy change
+    pub fn replace_client(&self, client: reqwest::Client) {
+        *self.client.write() = client;
+    }
+
+    fn client(&self) -> reqwest::Client {
+        self.client.read().clone()
+    }
+
+    /// Client that fails every request because the TLS settings (`reason`) couldn't
+    /// be applied, rather than talking to the server without them
+    pub fn refusing(base_url: String, reason: &str) -> Self {
         Self {
             base_url,
-            client: reqwest::Client::new(),
+            client: parking_lot::RwLock::new(crate::tls::refusing_http_client(reason)),
+        }
+    }
+
+    /// Authenticated GET that waits out a short Retry-After and retries once on 429
+    async fn get(&self, url: &str, token: &str) -> Result<reqwest::Response, PleError> {
+        let send = || async {
+            self.client()
+                .get(url)
+                .header("Authorization", format!("Bearer {}", token))
+                .send()
+                .await
+                .map_err(request_error)
+        };
+
+        let response = send().await?;
+        let Some(retry_after_secs) = rate_limited(&response) else {
+            return Ok(response);
+        };
+
+        let wait = Duration::from_secs(retry_after_secs);
+        if wait > MAX_RETRY_WAIT {
+            return Err(PleError::RateLimited { retry_after_secs });
+        }
+
+        log::warn!("[API] Rate limited on {}, retrying in {:?}", url, wait);
+        tokio::time::sleep(wait).await;
+
+        let response = send().await?;
+        match rate_limited(&response) {
+            Some(retry_after_secs) => Err(PleError::RateLimited { retry_after_secs }),
+            None => Ok(response),
         }
     }
 
-    pub async fn login(&self, email: &str, password: &str) -> Result<LoginResponse, String> {
+    pub async fn login(&self, email: &str, password: &str) -> Result<LoginResponse, PleError> {
         let response = self
-            .client
+            .client()
             .post(format!("{}/api/auth/login", self.base_url))
             .json(&serde_json::json!({
                 "email": email,
@@ -104,129 +323,178 @@ impl ApiClient {
             }))
             .send()
             .await
-            .map_err(|e| format!("Network error: {}", e))?;
+            .map_err(request_error)?;
 
-        if !response.status().is_success() {
+        if let Some(retry_after_secs) = rate_limited(&response) {
+            return Err(PleError::RateLimited { retry_after_secs });
+        }
+
+        let status = response.status();
+        if !status.is_success() {
             let error_text = response.text().await.unwrap_or_default();
-            return Err(format!("Login failed: {}", error_text));
+            return Err(status_error(status, format!("Login failed: {}", error_text)));
         }
 
         let result = response
             .json::<LoginResult>()
             .await
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
+            .map_err(parse_error)?;
 
         match result {
             LoginResult::Success { token, user } => Ok(LoginResponse { user, token }),
             LoginResult::MfaRequired { .. } => {
-                Err("MFA is enabled. Please use the web app to login with MFA.".to_string())
+                Err(PleError::Auth("MFA is enabled. Please use the web app to login with MFA.".to_string()))
             }
         }
     }
 
-    pub async fn verify_token(&self, token: &str) -> Result<User, String> {
+    pub async fn verify_token(&self, token: &str) -> Result<User, PleError> {
         let response = self
-            .client
-            .get(format!("{}/api/auth/me", self.base_url))
-            .header("Authorization", format!("Bearer {}", token))
-            .send()
+            .get(&format!("{}/api/auth/me", self.base_url), token)
+            .await?;
+
+        // Only a rejected token means logging in again - an outage must not
+        let status = response.status();
+        if !status.is_success() {
+            let message = match status {
+                reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => "Invalid or expired token".to_string(),
+                _ => format!("Failed to verify token: HTTP {}", status),
+            };
+            return Err(status_error(status, message));
+        }
+
+        response
+            .json::<User>()
             .await
-            .map_err(|e| format!("Network error: {}", e))?;
+            .map_err(parse_error)
+    }
+
+    pub async fn get_networks(&self, token: &str) -> Result<Vec<Network>, PleError> {
+        let response = self
+            .get(&format!("{}/api/mesh/networks", self.base_url), token)
+            .await?;
 
         if !response.status().is_success() {
-            return Err("Invalid or expired token".to_string());
+            return Err(status_error(response.status(), "Failed to fetch networks".to_string()));
         }
 
         response
-            .json::<User>()
+            .json::<Vec<Network>>()
             .await
-            .map_err(|e| format!("Failed to parse response: {}", e))
+            .map_err(parse_error)
     }
 
-    pub async fn get_networks(&self, token: &str) -> Result<Vec<Network>, String> {
+    pub async fn create_network(
+        &self,
+        token: &str,
+        name: &str,
+        description: Option<&str>,
+        ip_range: &str,
+    ) -> Result<Network, PleError> {
+        validate_ip_range(ip_range)?;
+
         let response = self
-            .client
-            .get(format!("{}/api/mesh/networks", self.base_url))
+            .client()
+            .post(format!("{}/api/mesh/networks", self.base_url))
             .header("Authorization", format!("Bearer {}", token))
+            .json(&serde_json::json!({
+                "name": name,
+                "description": description,
+                "ipRange": ip_range.trim()
+            }))
             .send()
             .await
-            .map_err(|e| format!("Network error: {}", e))?;
+            .map_err(request_error)?;
 
-        if !response.status().is_success() {
-            return Err("Failed to fetch networks".to_string());
+        if let Some(retry_after_secs) = rate_limited(&response) {
+            return Err(PleError::RateLimited { retry_after_secs });
+        }
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(network_error(status, "Failed to create network".to_string()));
         }
 
         response
-            .json::<Vec<Network>>()
+            .json::<Network>()
             .await
-            .map_err(|e| format!("Failed to parse response: {}", e))
+            .map_err(parse_error)
     }
 
-    pub async fn get_devices(&self, token: &str, network_id: &str) -> Result<Vec<Device>, String> {
+    pub async fn delete_network(&self, token: &str, network_id: &str) -> Result<(), PleError> {
         let response = self
-            .client
-            .get(format!(
-                "{}/api/mesh/networks/{}/devices",
-                self.base_url, network_id
-            ))
+            .client()
+            .delete(format!("{}/api/mesh/networks/{}", self.base_url, network_id))
             .header("Authorization", format!("Bearer {}", token))
             .send()
             .await
-            .map_err(|e| format!("Network error: {}", e))?;
+            .map_err(request_error)?;
+
+        if let Some(retry_after_secs) = rate_limited(&response) {
+            return Err(PleError::RateLimited { retry_after_secs });
+        }
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(network_error(status, "Failed to delete network".to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_devices(&self, token: &str, network_id: &str) -> Result<Vec<Device>, PleError> {
+        let response = self
+            .get(&format!(
+                "{}/api/mesh/networks/{}/devices",
+                self.base_url, network_id
+            ), token)
+            .await?;
 
         if !response.status().is_success() {
-            return Err("Failed to fetch devices".to_string());
+            return Err(status_error(response.status(), "Failed to fetch devices".to_string()));
         }
 
         response
             .json::<Vec<Device>>()
             .await
-            .map_err(|e| format!("Failed to parse response: {}", e))
+            .map_err(parse_error)
     }
 
     pub async fn get_device_config(
         &self,
         token: &str,
         device_id: &str,
-    ) -> Result<DeviceConfig, String> {
+    ) -> Result<DeviceConfig, PleError> {
         let response = self
-            .client
-            .get(format!(
+            .get(&format!(
                 "{}/api/mesh/devices/{}/config",
                 self.base_url, device_id
-            ))
-            .header("Authorization", format!("Bearer {}", token))
-            .send()
-            .await
-            .map_err(|e| format!("Network error: {}", e))?;
+            ), token)
+            .await?;
 
         if !response.status().is_success() {
-            return Err("Failed to fetch device config".to_string());
+            return Err(status_error(response.status(), "Failed to fetch device config".to_string()));
         }
 
         response
             .json::<DeviceConfig>()
             .await
-            .map_err(|e| format!("Failed to parse response: {}", e))
+            .map_err(parse_error)
     }
 
-    pub async fn get_relays(&self, token: &str) -> Result<Vec<Relay>, String> {
+    pub async fn get_relays(&self, token: &str) -> Result<Vec<Relay>, PleError> {
         let response = self
-            .client
-            .get(format!("{}/api/mesh/relays", self.base_url))
-            .header("Authorization", format!("Bearer {}", token))
-            .send()
-            .await
-            .map_err(|e| format!("Network error: {}", e))?;
+            .get(&format!("{}/api/mesh/relays", self.base_url), token)
+            .await?;
 
         if !response.status().is_success() {
-            return Err("Failed to fetch relays".to_string());
+            return Err(status_error(response.status(), "Failed to fetch relays".to_string()));
         }
 
         response
             .json::<Vec<Relay>>()
             .await
-            .map_err(|e| format!("Failed to parse response: {}", e))
+            .map_err(parse_error)
     }
 
     pub async fn auto_register_device(
@@ -235,9 +503,9 @@ impl ApiClient {
         network_id: &str,
         device_name: &str,
         platform: &str,
-    ) -> Result<Device, String> {
+    ) -> Result<Device, PleError> {
         let response = self
-            .client
+            .client()
             .post(format!(
                 "{}/api/mesh/networks/{}/auto-register",
                 self.base_url, network_id
@@ -249,17 +517,22 @@ impl ApiClient {
             }))
             .send()
             .await
-            .map_err(|e| format!("Network error: {}", e))?;
+            .map_err(request_error)?;
 
-        if !response.status().is_success() {
+        if let Some(retry_after_secs) = rate_limited(&response) {
+            return Err(PleError::RateLimited { retry_after_secs });
+        }
+
+        let status = response.status();
+        if !status.is_success() {
             let error_text = response.text().await.unwrap_or_default();
-            return Err(format!("Failed to register device: {}", error_text));
+            return Err(status_error(status, format!("Failed to register device: {}", error_text)));
         }
 
         response
             .json::<Device>()
             .await
-            .map_err(|e| format!("Failed to parse response: {}", e))
+            .map_err(parse_error)
     }
 
     pub async fn set_exit_node(
@@ -268,9 +541,9 @@ impl ApiClient {
         network_id: &str,
         exit_type: &str,
         exit_id: Option<&str>,
-    ) -> Result<(), String> {
+    ) -> Result<(), PleError> {
         let response = self
-            .client
+            .client()
             .patch(format!(
                 "{}/api/mesh/networks/{}/exit-node",
                 self.base_url, network_id
@@ -282,11 +555,155 @@ impl ApiClient {
             }))
             .send()
             .await
-            .map_err(|e| format!("Network error: {}", e))?;
+            .map_err(request_error)?;
 
-        if !response.status().is_success() {
+        if let Some(retry_after_secs) = rate_limited(&response) {
+            return Err(PleError::RateLimited { retry_after_secs });
+        }
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(status_error(status, format!("Failed to set exit node: {}", error_text)));
+        }
+
+        Ok(())
+    }
+
+    /// Exit node currently assigned to this device on a network, type "none" if there isn't one
+    pub async fn get_exit_node(&self, token: &str, network_id: &str) -> Result<ExitNodeOption, PleError> {
+        let response = self
+            .get(&format!(
+                "{}/api/mesh/networks/{}/exit-node",
+                self.base_url, network_id
+            ), token)
+            .await?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Err(PleError::NotFound("Failed to fetch exit node: network not found".to_string()));
+        }
+        if !status.is_success() {
+            return Err(status_error(status, "Failed to fetch exit node".to_string()));
+        }
+
+        let body = response.text().await.map_err(parse_error)?;
+        parse_exit_node(&body)
+    }
+
+    pub async fn delete_device(&self, token: &str, device_id: &str) -> Result<(), PleError> {
+        let response = self
+            .client()
+            .delete(format!("{}/api/mesh/devices/{}", self.base_url, device_id))
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await
+            .map_err(request_error)?;
+
+        if let Some(retry_after_secs) = rate_limited(&response) {
+            return Err(PleError::RateLimited { retry_after_secs });
+        }
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(device_error(status, "Failed to delete device".to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub async fn rename_device(
+        &self,
+        token: &str,
+        device_id: &str,
+        name: &str,
+    ) -> Result<Device, PleError> {
+        let response = self
+            .client()
+            .patch(format!("{}/api/mesh/devices/{}", self.base_url, device_id))
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&serde_json::json!({
+                "name": name
+            }))
+            .send()
+            .await
+            .map_err(request_error)?;
+
+        if let Some(retry_after_secs) = rate_limited(&response) {
+            return Err(PleError::RateLimited { retry_after_secs });
+        }
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(device_error(status, "Failed to rename device".to_string()));
+        }
+
+        response
+            .json::<Device>()
+            .await
+            .map_err(parse_error)
+    }
+
+    /// Register a new WireGuard public key for a device; the server stops
+    /// accepting the old key once this succeeds
+    pub async fn rotate_device_key(
+        &self,
+        token: &str,
+        device_id: &str,
+        public_key: &str,
+    ) -> Result<Device, PleError> {
+        let response = self
+            .client()
+            .post(format!("{}/api/mesh/devices/{}/rotate-key", self.base_url, device_id))
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&serde_json::json!({
+                "publicKey": public_key
+            }))
+            .send()
+            .await
+            .map_err(request_error)?;
+
+        if let Some(retry_after_secs) = rate_limited(&response) {
+            return Err(PleError::RateLimited { retry_after_secs });
+        }
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(device_error(status, "Failed to rotate device key".to_string()));
+        }
+
+        response
+            .json::<Device>()
+            .await
+            .map_err(parse_error)
+    }
+
+    pub async fn report_metrics(
+        &self,
+        token: &str,
+        device_id: &str,
+        metrics: &ConnectionMetrics,
+    ) -> Result<(), PleError> {
+        let response = self
+            .client()
+            .post(format!(
+                "{}/api/mesh/devices/{}/metrics",
+                self.base_url, device_id
+            ))
+            .header("Authorization", format!("Bearer {}", token))
+            .json(metrics)
+            .send()
+            .await
+            .map_err(request_error)?;
+
+        if let Some(retry_after_secs) = rate_limited(&response) {
+            return Err(PleError::RateLimited { retry_after_secs });
+        }
+
+        let status = response.status();
+        if !status.is_success() {
             let error_text = response.text().await.unwrap_or_default();
-            return Err(format!("Failed to set exit node: {}", error_text));
+            return Err(status_error(status, format!("Failed to report metrics: {}", error_text)));
         }
 
         Ok(())
@@ -299,12 +716,12 @@ pub async fn login(
     state: State<'_, AppState>,
     email: String,
     password: String,
-) -> Result<LoginResponse, String> {
+) -> Result<LoginResponse, PleError> {
     state.api_client.login(&email, &password).await
 }
 
 #[tauri::command]
-pub async fn verify_token(state: State<'_, AppState>, token: String) -> Result<User, String> {
+pub async fn verify_token(state: State<'_, AppState>, token: String) -> Result<User, PleError> {
     state.api_client.verify_token(&token).await
 }
 
@@ -312,18 +729,45 @@ pub async fn verify_token(state: State<'_, AppState>, token: String) -> Result<U
 pub async fn get_networks(
     app: tauri::AppHandle,
     state: State<'_, AppState>,
-) -> Result<Vec<Network>, String> {
-    let token = crate::config::get_stored_token_internal(&app).await?;
+) -> Result<Vec<Network>, PleError> {
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
     state.api_client.get_networks(&token).await
 }
 
+#[tauri::command]
+pub async fn create_network(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    name: String,
+    description: Option<String>,
+    ip_range: String,
+) -> Result<Network, PleError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(PleError::Parse("Network name cannot be empty".to_string()));
+    }
+    let description = description.as_deref().map(str::trim).filter(|d| !d.is_empty());
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
+    state.api_client.create_network(&token, name, description, &ip_range).await
+}
+
+#[tauri::command]
+pub async fn delete_network(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    network_id: String,
+) -> Result<(), PleError> {
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
+    state.api_client.delete_network(&token, &network_id).await
+}
+
 #[tauri::command]
 pub async fn get_devices(
     app: tauri::AppHandle,
     state: State<'_, AppState>,
     network_id: String,
-) -> Result<Vec<Device>, String> {
-    let token = crate::config::get_stored_token_internal(&app).await?;
+) -> Result<Vec<Device>, PleError> {
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
     state.api_client.get_devices(&token, &network_id).await
 }
 
@@ -332,8 +776,8 @@ pub async fn get_device_config(
     app: tauri::AppHandle,
     state: State<'_, AppState>,
     device_id: String,
-) -> Result<DeviceConfig, String> {
-    let token = crate::config::get_stored_token_internal(&app).await?;
+) -> Result<DeviceConfig, PleError> {
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
     state.api_client.get_device_config(&token, &device_id).await
 }
 
@@ -341,22 +785,69 @@ pub async fn get_device_config(
 pub async fn get_relays(
     app: tauri::AppHandle,
     state: State<'_, AppState>,
-) -> Result<Vec<Relay>, String> {
-    let token = crate::config::get_stored_token_internal(&app).await?;
+) -> Result<Vec<Relay>, PleError> {
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
     state.api_client.get_relays(&token).await
 }
 
+/// Probe all relays and return them sorted by round-trip time
+#[tauri::command]
+pub async fn measure_relays(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<Vec<RelayLatency>, PleError> {
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
+    let relays = state.api_client.get_relays(&token).await?;
+    Ok(probe_relays(&relays).await)
+}
+
+/// Probe relays concurrently, fastest first
+pub async fn probe_relays(relays: &[Relay]) -> Vec<RelayLatency> {
+    let endpoints: Vec<String> = relays.iter().map(|r| r.public_endpoint.clone()).collect();
+    let results = crate::stun::AsyncStunClient::new()
+        .measure_rtts(&endpoints, RELAY_PROBE_TIMEOUT)
+        .await;
+
+    let mut latencies: Vec<RelayLatency> = relays
+        .iter()
+        .cloned()
+        .zip(results)
+        .map(|(relay, result)| {
+            let latency_ms = match result {
+                Ok(rtt) => Some(rtt.as_millis() as u64),
+                Err(e) => {
+                    log::info!("[RELAY] No latency for {} ({}): {}", relay.name, relay.public_endpoint, e);
+                    None
+                }
+            };
+            RelayLatency {
+                id: relay.id,
+                name: relay.name,
+                country_code: relay.country_code,
+                public_endpoint: relay.public_endpoint,
+                latency_ms,
+            }
+        })
+        .collect();
+
+    sort_by_latency(&mut latencies);
+    latencies
+}
+
 #[tauri::command]
 pub async fn auto_register_device(
     app: tauri::AppHandle,
     state: State<'_, AppState>,
     network_id: String,
     device_name: String,
-) -> Result<Device, String> {
-    let token = crate::config::get_stored_token_internal(&app).await?;
+) -> Result<Device, PleError> {
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
+    state.api_client.auto_register_device(&token, &network_id, &device_name, device_platform()).await
+}
 
-    // Detect platform
-    let platform = if cfg!(target_os = "windows") {
+/// Platform reported when registering this machine
+pub fn device_platform() -> &'static str {
+    if cfg!(target_os = "windows") {
         "DESKTOP"
     } else if cfg!(target_os = "macos") {
         "DESKTOP"
@@ -364,9 +855,7 @@ pub async fn auto_register_device(
         "DESKTOP"
     } else {
         "UNKNOWN"
-    };
-
-    state.api_client.auto_register_device(&token, &network_id, &device_name, platform).await
+    }
 }
 
 #[tauri::command]
@@ -376,7 +865,107 @@ pub async fn set_exit_node(
     network_id: String,
     exit_type: String,
     exit_id: Option<String>,
-) -> Result<(), String> {
-    let token = crate::config::get_stored_token_internal(&app).await?;
+) -> Result<(), PleError> {
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
     state.api_client.set_exit_node(&token, &network_id, &exit_type, exit_id.as_deref()).await
 }
+
+#[tauri::command]
+pub async fn get_exit_node(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    network_id: String,
+) -> Result<ExitNodeOption, PleError> {
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
+    state.api_client.get_exit_node(&token, &network_id).await
+}
+
+#[tauri::command]
+pub async fn delete_device(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    device_id: String,
+) -> Result<(), PleError> {
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
+    state.api_client.delete_device(&token, &device_id).await?;
+    crate::config::clear_device_key(&app, &device_id);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn rename_device(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    device_id: String,
+    name: String,
+) -> Result<Device, PleError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(PleError::Parse("Device name cannot be empty".to_string()));
+    }
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
+    state.api_client.rename_device(&token, &device_id, name).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after(Some("30")), 30);
+        assert_eq!(parse_retry_after(Some(" 7 ")), 7);
+        assert_eq!(parse_retry_after(Some("Wed, 21 Oct 2026 07:28:00 GMT")), DEFAULT_RETRY_AFTER_SECS);
+        assert_eq!(parse_retry_after(None), DEFAULT_RETRY_AFTER_SECS);
+    }
+
+    #[test]
+    fn test_sort_by_latency() {
+        let relay = |id: &str, latency_ms| RelayLatency {
+            id: id.to_string(),
+            name: id.to_string(),
+            country_code: "US".to_string(),
+            public_endpoint: "relay.example.com:51820".to_string(),
+            latency_ms,
+        };
+        let mut latencies = vec![relay("a", None), relay("b", Some(80)), relay("c", Some(12))];
+        sort_by_latency(&mut latencies);
+        let order: Vec<&str> = latencies.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(order, ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn test_parse_exit_node() {
+        assert_eq!(parse_exit_node("").unwrap().node_type, "none");
+        assert_eq!(parse_exit_node("null").unwrap().node_type, "none");
+        assert_eq!(parse_exit_node(r#"{"type":"none"}"#).unwrap().node_type, "none");
+
+        let relay = parse_exit_node(r#"{"id":"r1","name":"Frankfurt","type":"relay","country_code":"DE"}"#).unwrap();
+        assert_eq!((relay.id.as_str(), relay.node_type.as_str()), ("r1", "relay"));
+        assert_eq!(relay.country_code.as_deref(), Some("DE"));
+
+        assert_eq!(parse_exit_node("{").unwrap_err().kind(), "parse");
+    }
+
+    #[test]
+    fn test_device_error_kinds() {
+        let kind = |status| device_error(status, "Failed".to_string()).kind();
+        assert_eq!(kind(reqwest::StatusCode::NOT_FOUND), "notFound");
+        assert_eq!(kind(reqwest::StatusCode::FORBIDDEN), "forbidden");
+        assert_eq!(kind(reqwest::StatusCode::UNAUTHORIZED), "auth");
+        assert_eq!(kind(reqwest::StatusCode::INTERNAL_SERVER_ERROR), "api");
+    }
+
+    #[test]
+    fn test_validate_ip_range() {
+        assert!(validate_ip_range("10.100.0.0/24").is_ok());
+        assert!(validate_ip_range(" 192.168.50.0/16 ").is_err());
+        assert!(validate_ip_range("10.100.0.0").is_err());
+        assert!(validate_ip_range("10.100.0.0/33").is_err());
+        assert!(validate_ip_range("fd00::/64").is_err());
+
+        let kind = |status| network_error(status, "Failed".to_string()).kind();
+        assert_eq!(kind(reqwest::StatusCode::CONFLICT), "api");
+        assert_eq!(kind(reqwest::StatusCode::FORBIDDEN), "forbidden");
+    }
+}
@@ -0,0 +1,159 @@
+//! Packet capture for support debugging
+//! Writes tunnel traffic as plaintext IP packets to a Wireshark-loadable pcap file.
+//! Off by default - a capture contains everything sent through the VPN unencrypted.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+
+/// Capture stops once the file reaches this size
+pub const MAX_CAPTURE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// pcap magic for microsecond timestamps
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+
+/// LINKTYPE_RAW - packets start at the IP header (what the TUN device carries)
+const LINKTYPE_RAW: u32 = 101;
+
+const SNAPLEN: u32 = 65535;
+
+/// Checked per packet so the data path only takes the lock while capturing
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+static CAPTURE: Mutex<Option<PcapWriter>> = Mutex::new(None);
+
+struct PcapWriter {
+    file: BufWriter<File>,
+    path: String,
+    written: u64,
+}
+
+/// pcap global header
+fn global_header() -> [u8; 24] {
+    let mut header = [0u8; 24];
+    header[0..4].copy_from_slice(&PCAP_MAGIC.to_le_bytes());
+    header[4..6].copy_from_slice(&2u16.to_le_bytes()); // version 2.4
+    header[6..8].copy_from_slice(&4u16.to_le_bytes());
+    // thiszone and sigfigs stay 0
+    header[16..20].copy_from_slice(&SNAPLEN.to_le_bytes());
+    header[20..24].copy_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    header
+}
+
+/// pcap per-packet record header
+fn record_header(timestamp: std::time::Duration, captured_len: u32, original_len: u32) -> [u8; 16] {
+    let mut header = [0u8; 16];
+    header[0..4].copy_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+    header[4..8].copy_from_slice(&timestamp.subsec_micros().to_le_bytes());
+    header[8..12].copy_from_slice(&captured_len.to_le_bytes());
+    header[12..16].copy_from_slice(&original_len.to_le_bytes());
+    header
+}
+
+/// Create (or truncate) `path` readable by the owner only, as it holds decrypted traffic
+fn create_private(path: &str) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        let file = options.open(path)?;
+        // mode() only applies to new files
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    options.open(path)
+}
+
+/// Start capturing to `path`, replacing any capture already running
+pub fn start(path: &str) -> Result<(), String> {
+    stop();
+
+    let mut file = BufWriter::new(create_private(path)
+        .map_err(|e| format!("Failed to create capture file {}: {}", path, e))?);
+    file.write_all(&global_header())
+        .map_err(|e| format!("Failed to write capture header: {}", e))?;
+
+    log::warn!("[CAPTURE] Writing decrypted tunnel traffic to {}", path);
+    *CAPTURE.lock() = Some(PcapWriter {
+        file,
+        path: path.to_string(),
+        written: global_header().len() as u64,
+    });
+    CAPTURING.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Stop capturing and flush the file. No-op when not capturing.
+pub fn stop() {
+    CAPTURING.store(false, Ordering::SeqCst);
+    if let Some(mut writer) = CAPTURE.lock().take() {
+        if let Err(e) = writer.file.flush() {
+            log::warn!("[CAPTURE] Failed to flush {}: {}", writer.path, e);
+        }
+        log::info!("[CAPTURE] Stopped, {} bytes written to {}", writer.written, writer.path);
+    }
+}
+
+/// Whether a capture is running
+pub fn is_capturing() -> bool {
+    CAPTURING.load(Ordering::Relaxed)
+}
+
+/// Append one plaintext IP packet to the capture, if one is running
+pub fn record(packet: &[u8]) {
+    if !is_capturing() {
+        return;
+    }
+
+    let mut capture = CAPTURE.lock();
+    let Some(writer) = capture.as_mut() else {
+        return;
+    };
+
+    let captured = &packet[..packet.len().min(SNAPLEN as usize)];
+    let record_len = 16 + captured.len() as u64;
+    if writer.written + record_len > MAX_CAPTURE_BYTES {
+        log::warn!("[CAPTURE] {} reached the {} byte limit", writer.path, MAX_CAPTURE_BYTES);
+        drop(capture);
+        stop();
+        return;
+    }
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let header = record_header(timestamp, captured.len() as u32, packet.len() as u32);
+    let result = writer.file.write_all(&header).and_then(|_| writer.file.write_all(captured));
+    match result {
+        Ok(()) => writer.written += record_len,
+        Err(e) => {
+            log::error!("[CAPTURE] Write to {} failed: {}", writer.path, e);
+            drop(capture);
+            stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pcap_headers() {
+        let global = global_header();
+        assert_eq!(&global[0..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(u16::from_le_bytes([global[4], global[5]]), 2);
+        assert_eq!(u16::from_le_bytes([global[6], global[7]]), 4);
+        assert_eq!(u32::from_le_bytes(global[20..24].try_into().unwrap()), LINKTYPE_RAW);
+
+        let record = record_header(std::time::Duration::from_micros(1_700_000_000_123_456), 60, 60);
+        assert_eq!(u32::from_le_bytes(record[0..4].try_into().unwrap()), 1_700_000_000);
+        assert_eq!(u32::from_le_bytes(record[4..8].try_into().unwrap()), 123_456);
+        assert_eq!(u32::from_le_bytes(record[8..12].try_into().unwrap()), 60);
+        assert_eq!(u32::from_le_bytes(record[12..16].try_into().unwrap()), 60);
+    }
+}
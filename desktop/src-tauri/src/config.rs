@@ -1,7 +1,51 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
 use tauri_plugin_store::StoreExt;
 
+use crate::flows;
+use crate::proxy;
+use crate::stun::{self, PreferredStunServer};
+use crate::tls::TlsSettings;
+use crate::tun_device;
+use crate::wireguard::{self, PowerProfile};
+
 const STORE_PATH: &str = ".ple7-config.json";
 const TOKEN_KEY: &str = "auth_token";
+const LAST_SESSION_KEY: &str = "last_session";
+const AUTO_CONNECT_KEY: &str = "auto_connect";
+const TLS_SETTINGS_KEY: &str = "tls_settings";
+const LISTEN_PORT_KEY: &str = "listen_port";
+const POWER_PROFILE_KEY: &str = "power_profile";
+const SOCKET_BUFFER_SIZE_KEY: &str = "socket_buffer_size";
+const RING_CAPACITY_KEY: &str = "ring_capacity";
+const DEVICE_KEYS_KEY: &str = "device_keys";
+const PENDING_DEVICE_KEYS_KEY: &str = "pending_device_keys";
+const BLOCK_IPV6_LEAKS_KEY: &str = "block_ipv6_leaks";
+const FLOW_SAMPLE_RATE_KEY: &str = "flow_sample_rate";
+const RELAY_ONLY_KEY: &str = "relay_only";
+const PROXY_KEY: &str = "proxy";
+const STUN_RTTS_KEY: &str = "stun_rtts";
+const PREFERRED_STUN_SERVER_KEY: &str = "preferred_stun_server";
+
+/// Parameters of the last successful connection, replayed by auto-connect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LastSession {
+    pub device_id: String,
+    pub network_id: String,
+    pub exit_node_type: Option<String>,
+    pub exit_node_id: Option<String>,
+    /// Local IP WireGuard was bound to, if the user picked one
+    #[serde(default)]
+    pub bind_address: Option<String>,
+    /// Whether path MTU discovery was on
+    #[serde(default)]
+    pub probe_mtu: bool,
+    /// Listen port the user pinned, if any
+    #[serde(default)]
+    pub listen_port: Option<u16>,
+}
 
 #[tauri::command]
 pub async fn store_token(app: tauri::AppHandle, token: String) -> Result<(), String> {
@@ -49,6 +93,8 @@ pub async fn clear_stored_token(app: tauri::AppHandle) -> Result<(), String> {
         .save()
         .map_err(|e| format!("Failed to save store: {}", e))?;
 
+    // Keys rotated on this machine belong to the account that's logging out
+    clear_device_keys(&app);
     Ok(())
 }
 
@@ -69,3 +115,625 @@ pub async fn get_stored_token_internal(app: &tauri::AppHandle) -> Result<String,
         None => Err("No token stored".to_string()),
     }
 }
+
+#[tauri::command]
+pub async fn get_auto_connect(app: tauri::AppHandle) -> Result<bool, String> {
+    get_auto_connect_internal(&app).await
+}
+
+#[tauri::command]
+pub async fn set_auto_connect(app: tauri::AppHandle, enabled: bool) -> Result<(), String> {
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    store.set(AUTO_CONNECT_KEY, serde_json::json!(enabled));
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    Ok(())
+}
+
+// Internal helper for reading the auto-connect setting (defaults to off)
+pub async fn get_auto_connect_internal(app: &tauri::AppHandle) -> Result<bool, String> {
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    Ok(store
+        .get(AUTO_CONNECT_KEY)
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false))
+}
+
+// Internal helper for remembering the last successful connection
+pub async fn store_last_session(app: &tauri::AppHandle, session: &LastSession) -> Result<(), String> {
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    let value = serde_json::to_value(session)
+        .map_err(|e| format!("Failed to serialize session: {}", e))?;
+    store.set(LAST_SESSION_KEY, value);
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    Ok(())
+}
+
+// Internal helper for loading the last successful connection, if any
+pub async fn get_last_session_internal(app: &tauri::AppHandle) -> Result<Option<LastSession>, String> {
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    match store.get(LAST_SESSION_KEY) {
+        Some(value) => serde_json::from_value(value)
+            .map(Some)
+            .map_err(|e| format!("Invalid stored session: {}", e)),
+        None => Ok(None),
+    }
+}
+
+#[tauri::command]
+pub async fn get_tls_settings(app: tauri::AppHandle) -> Result<TlsSettings, String> {
+    get_tls_settings_internal(&app)
+}
+
+/// Store the CA bundle path / verification toggle (applied on next launch)
+#[tauri::command]
+pub async fn set_tls_settings(app: tauri::AppHandle, settings: TlsSettings) -> Result<(), String> {
+    // Fail early on an unreadable or malformed bundle rather than at next launch
+    settings.http_client()?;
+
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    let value = serde_json::to_value(&settings)
+        .map_err(|e| format!("Failed to serialize TLS settings: {}", e))?;
+    store.set(TLS_SETTINGS_KEY, value);
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    Ok(())
+}
+
+// Internal helper for loading TLS settings (sync - used during app setup)
+pub fn get_tls_settings_internal(app: &tauri::AppHandle) -> Result<TlsSettings, String> {
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    match store.get(TLS_SETTINGS_KEY) {
+        Some(value) => serde_json::from_value(value)
+            .map_err(|e| format!("Invalid stored TLS settings: {}", e)),
+        None => Ok(TlsSettings::default()),
+    }
+}
+
+// Internal helper for remembering the WireGuard port so the next launch can rebind it
+pub async fn store_listen_port(app: &tauri::AppHandle, port: u16) -> Result<(), String> {
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    store.set(LISTEN_PORT_KEY, serde_json::json!(port));
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    Ok(())
+}
+
+// Internal helper for loading the persisted WireGuard port (sync - used during app setup)
+pub fn get_listen_port_internal(app: &tauri::AppHandle) -> Option<u16> {
+    let store = app.store(STORE_PATH).ok()?;
+    store
+        .get(LISTEN_PORT_KEY)
+        .and_then(|value| value.as_u64())
+        .and_then(|port| u16::try_from(port).ok())
+}
+
+// Internal helper for remembering STUN server round-trip times so the next run tries the fastest first
+pub async fn store_stun_rtts(app: &tauri::AppHandle, rtts: &BTreeMap<String, u64>) -> Result<(), String> {
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    store.set(STUN_RTTS_KEY, serde_json::json!(rtts));
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    Ok(())
+}
+
+// Internal helper for loading the persisted STUN round-trip times (sync - used during app setup)
+pub fn get_stun_rtts_internal(app: &tauri::AppHandle) -> BTreeMap<String, u64> {
+    app.store(STORE_PATH).ok()
+        .and_then(|store| store.get(STUN_RTTS_KEY))
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or_default()
+}
+
+#[tauri::command]
+pub async fn get_power_profile(app: tauri::AppHandle) -> Result<PowerProfile, String> {
+    Ok(get_power_profile_internal(&app))
+}
+
+/// Store the power profile (applied on next connect)
+#[tauri::command]
+pub async fn set_power_profile(app: tauri::AppHandle, profile: PowerProfile) -> Result<(), String> {
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    let value = serde_json::to_value(profile)
+        .map_err(|e| format!("Failed to serialize power profile: {}", e))?;
+    store.set(POWER_PROFILE_KEY, value);
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    Ok(())
+}
+
+// Internal helper for loading the power profile (defaults to balanced)
+pub fn get_power_profile_internal(app: &tauri::AppHandle) -> PowerProfile {
+    app.store(STORE_PATH)
+        .ok()
+        .and_then(|store| store.get(POWER_PROFILE_KEY))
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or_default()
+}
+
+#[tauri::command]
+pub async fn get_socket_buffer_size() -> Result<usize, String> {
+    Ok(wireguard::socket_buffer_size())
+}
+
+/// Store the WireGuard socket buffer size in bytes (applied on next connect)
+#[tauri::command]
+pub async fn set_socket_buffer_size(app: tauri::AppHandle, bytes: usize) -> Result<(), String> {
+    if !(wireguard::MIN_SOCKET_BUFFER_SIZE..=wireguard::MAX_SOCKET_BUFFER_SIZE).contains(&bytes) {
+        return Err(format!(
+            "Socket buffer size must be between {} and {} bytes",
+            wireguard::MIN_SOCKET_BUFFER_SIZE,
+            wireguard::MAX_SOCKET_BUFFER_SIZE
+        ));
+    }
+
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    store.set(SOCKET_BUFFER_SIZE_KEY, serde_json::json!(bytes));
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    wireguard::set_socket_buffer_size(bytes);
+    Ok(())
+}
+
+// Internal helper for loading the stored socket buffer size (sync - used during app setup)
+pub fn get_socket_buffer_size_internal(app: &tauri::AppHandle) -> Option<usize> {
+    let store = app.store(STORE_PATH).ok()?;
+    store
+        .get(SOCKET_BUFFER_SIZE_KEY)
+        .and_then(|value| value.as_u64())
+        .and_then(|bytes| usize::try_from(bytes).ok())
+}
+
+#[tauri::command]
+pub async fn get_block_ipv6_leaks(app: tauri::AppHandle) -> Result<bool, String> {
+    Ok(get_block_ipv6_leaks_internal(&app))
+}
+
+/// Block native IPv6 while an IPv4-only tunnel is the exit node (applied on next connect)
+#[tauri::command]
+pub async fn set_block_ipv6_leaks(app: tauri::AppHandle, enabled: bool) -> Result<(), String> {
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    store.set(BLOCK_IPV6_LEAKS_KEY, serde_json::json!(enabled));
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    Ok(())
+}
+
+// Internal helper for the IPv6 leak block setting - on unless the user turned it off
+pub fn get_block_ipv6_leaks_internal(app: &tauri::AppHandle) -> bool {
+    app.store(STORE_PATH)
+        .ok()
+        .and_then(|store| store.get(BLOCK_IPV6_LEAKS_KEY))
+        .and_then(|value| value.as_bool())
+        .unwrap_or(true)
+}
+
+#[tauri::command]
+pub async fn get_relay_only(app: tauri::AppHandle) -> Result<bool, String> {
+    Ok(get_relay_only_internal(&app))
+}
+
+/// Always go through the relay and skip STUN, for networks where direct paths
+/// never work (applied on next connect)
+#[tauri::command]
+pub async fn set_relay_only(app: tauri::AppHandle, enabled: bool) -> Result<(), String> {
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    store.set(RELAY_ONLY_KEY, serde_json::json!(enabled));
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    Ok(())
+}
+
+// Internal helper for the relay-only setting - off unless the user turned it on
+pub fn get_relay_only_internal(app: &tauri::AppHandle) -> bool {
+    app.store(STORE_PATH)
+        .ok()
+        .and_then(|store| store.get(RELAY_ONLY_KEY))
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false)
+}
+
+#[tauri::command]
+pub async fn get_proxy(app: tauri::AppHandle) -> Result<Option<String>, String> {
+    Ok(get_proxy_internal(&app))
+}
+
+/// Send API and WebSocket traffic through an http://, socks5:// or socks5h://
+/// proxy (None = use HTTPS_PROXY / ALL_PROXY). Applies to the next request; a
+/// running WebSocket keeps its connection until it reconnects.
+#[tauri::command]
+pub async fn set_proxy(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, crate::tunnel::AppState>,
+    url: Option<String>,
+) -> Result<(), String> {
+    let url = url.map(|url| url.trim().to_string()).filter(|url| !url.is_empty());
+    if let Some(url) = &url {
+        proxy::parse(url)?.check_reachable().await?;
+    }
+    // Built before anything is saved so a failure leaves the old proxy in place
+    let client = state.tls.http_client_via(url.as_deref())?;
+
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    match &url {
+        Some(url) => store.set(PROXY_KEY, serde_json::json!(url)),
+        None => {
+            store.delete(PROXY_KEY);
+        }
+    }
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    proxy::set_configured(url);
+    state.api_client.replace_client(client);
+    log::info!("[PROXY] Proxy setting changed, API client rebuilt");
+
+    Ok(())
+}
+
+// Internal helper for the proxy URL set in the app (sync - used during app setup)
+pub fn get_proxy_internal(app: &tauri::AppHandle) -> Option<String> {
+    app.store(STORE_PATH)
+        .ok()
+        .and_then(|store| store.get(PROXY_KEY))
+        .and_then(|value| value.as_str().map(str::to_string))
+}
+
+#[tauri::command]
+pub async fn get_preferred_stun_server(app: tauri::AppHandle) -> Result<Option<PreferredStunServer>, String> {
+    Ok(get_preferred_stun_server_internal(&app))
+}
+
+/// Try `server` (host, host:port or IP) before the built-in STUN servers, or only
+/// it with `exclusive`. An empty server goes back to rotating through the built-in ones.
+#[tauri::command]
+pub async fn set_preferred_stun_server(
+    app: tauri::AppHandle,
+    server: String,
+    exclusive: Option<bool>,
+) -> Result<(), String> {
+    let preferred = if server.trim().is_empty() {
+        None
+    } else {
+        let server = tokio::task::spawn_blocking(move || stun::validate_server(&server))
+            .await
+            .map_err(|e| format!("STUN server check failed: {}", e))??;
+        Some(PreferredStunServer { server, exclusive: exclusive.unwrap_or(false) })
+    };
+
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    match &preferred {
+        Some(preferred) => store.set(PREFERRED_STUN_SERVER_KEY, serde_json::json!(preferred)),
+        None => {
+            store.delete(PREFERRED_STUN_SERVER_KEY);
+        }
+    }
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    stun::set_preferred_server(preferred);
+    Ok(())
+}
+
+// Internal helper for loading the pinned STUN server (sync - used during app setup)
+pub fn get_preferred_stun_server_internal(app: &tauri::AppHandle) -> Option<PreferredStunServer> {
+    app.store(STORE_PATH).ok()
+        .and_then(|store| store.get(PREFERRED_STUN_SERVER_KEY))
+        .and_then(|value| serde_json::from_value(value).ok())
+}
+
+#[tauri::command]
+pub async fn get_ring_capacity() -> Result<u32, String> {
+    Ok(tun_device::ring_capacity())
+}
+
+/// Store the Wintun ring size in bytes, a power of two (Windows, applied on next connect)
+#[tauri::command]
+pub async fn set_ring_capacity(app: tauri::AppHandle, bytes: u32) -> Result<(), String> {
+    if !bytes.is_power_of_two() || !(tun_device::MIN_RING_CAPACITY..=tun_device::MAX_RING_CAPACITY).contains(&bytes) {
+        return Err(format!(
+            "Ring capacity must be a power of two between {} and {} bytes",
+            tun_device::MIN_RING_CAPACITY,
+            tun_device::MAX_RING_CAPACITY
+        ));
+    }
+
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    store.set(RING_CAPACITY_KEY, serde_json::json!(bytes));
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    tun_device::set_ring_capacity(bytes);
+    Ok(())
+}
+
+// Internal helper for loading the stored Wintun ring size (sync - used during app setup)
+pub fn get_ring_capacity_internal(app: &tauri::AppHandle) -> Option<u32> {
+    let store = app.store(STORE_PATH).ok()?;
+    store
+        .get(RING_CAPACITY_KEY)
+        .and_then(|value| value.as_u64())
+        .and_then(|bytes| u32::try_from(bytes).ok())
+}
+
+#[tauri::command]
+pub async fn get_flow_sample_rate() -> Result<u32, String> {
+    Ok(flows::sample_rate())
+}
+
+/// Sample 1 in `rate` outbound packets for get_active_flows (0 = off, applied immediately)
+#[tauri::command]
+pub async fn set_flow_sample_rate(app: tauri::AppHandle, rate: u32) -> Result<(), String> {
+    if rate > flows::MAX_SAMPLE_RATE {
+        return Err(format!("Flow sample rate must be between 0 and {}", flows::MAX_SAMPLE_RATE));
+    }
+
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|e| format!("Failed to open store: {}", e))?;
+
+    store.set(FLOW_SAMPLE_RATE_KEY, serde_json::json!(rate));
+
+    store
+        .save()
+        .map_err(|e| format!("Failed to save store: {}", e))?;
+
+    flows::set_sample_rate(rate);
+    Ok(())
+}
+
+// Internal helper for loading the flow sample rate (sync - used during app setup, 0 = off)
+pub fn get_flow_sample_rate_internal(app: &tauri::AppHandle) -> u32 {
+    app.store(STORE_PATH)
+        .ok()
+        .and_then(|store| store.get(FLOW_SAMPLE_RATE_KEY))
+        .and_then(|value| value.as_u64())
+        .and_then(|rate| u32::try_from(rate).ok())
+        .unwrap_or(0)
+}
+
+/// Keychain service the device private keys are filed under
+const KEYCHAIN_SERVICE: &str = "com.ple7.vpn";
+
+/// OS keychain entry holding a device's private key
+fn device_key_entry(device_id: &str) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYCHAIN_SERVICE, &format!("device-key-{}", device_id))
+        .map_err(|e| format!("Failed to open keychain entry: {}", e))
+}
+
+/// Ids of the devices with a stored key. Older versions kept the keys themselves
+/// here, in plaintext - those are moved to the keychain on first use. A key the
+/// keychain won't take stays in plaintext (see pending_device_keys) and the move
+/// is tried again on the next load, so a locked keychain doesn't lose it.
+fn device_key_ids(app: &tauri::AppHandle) -> Vec<String> {
+    let Ok(store) = app.store(STORE_PATH) else { return Vec::new() };
+    let mut pending = pending_device_keys(app);
+    let mut ids: Vec<String> = match store.get(DEVICE_KEYS_KEY) {
+        Some(serde_json::Value::Array(ids)) => ids.iter().filter_map(|id| id.as_str().map(str::to_string)).collect(),
+        Some(serde_json::Value::Object(keys)) => {
+            pending.extend(keys);
+            Vec::new()
+        }
+        _ => Vec::new(),
+    };
+    if pending.is_empty() {
+        return ids;
+    }
+
+    let mut kept = serde_json::Map::new();
+    for (device_id, key) in pending {
+        let moved = key.as_str()
+            .ok_or_else(|| "not a string".to_string())
+            .and_then(|key| device_key_entry(&device_id)?
+                .set_password(key)
+                .map_err(|e| e.to_string()));
+        match moved {
+            Ok(()) => log::info!("[KEYS] Moved the key for device {} to the keychain", device_id),
+            Err(e) if key.is_string() => {
+                log::warn!("[KEYS] Failed to move the key for device {} to the keychain, keeping it for now: {}", device_id, e);
+                kept.insert(device_id.clone(), key);
+            }
+            Err(e) => {
+                log::warn!("[KEYS] Dropping stored key for device {}: {}", device_id, e);
+                continue;
+            }
+        }
+        if !ids.contains(&device_id) {
+            ids.push(device_id);
+        }
+    }
+    set_pending_device_keys(app, kept);
+    set_device_key_ids(app, &ids);
+    ids
+}
+
+/// Plaintext keys from older versions that couldn't be moved to the keychain
+/// yet, by device id
+fn pending_device_keys(app: &tauri::AppHandle) -> serde_json::Map<String, serde_json::Value> {
+    match app.store(STORE_PATH).ok().and_then(|store| store.get(PENDING_DEVICE_KEYS_KEY)) {
+        Some(serde_json::Value::Object(keys)) => keys,
+        _ => serde_json::Map::new(),
+    }
+}
+
+/// Saved by the set_device_key_ids() that always follows
+fn set_pending_device_keys(app: &tauri::AppHandle, keys: serde_json::Map<String, serde_json::Value>) {
+    let Ok(store) = app.store(STORE_PATH) else { return };
+    if keys.is_empty() {
+        store.delete(PENDING_DEVICE_KEYS_KEY);
+    } else {
+        store.set(PENDING_DEVICE_KEYS_KEY, serde_json::Value::Object(keys));
+    }
+}
+
+fn set_device_key_ids(app: &tauri::AppHandle, ids: &[String]) {
+    let saved = app.store(STORE_PATH)
+        .map_err(|e| e.to_string())
+        .and_then(|store| {
+            store.set(DEVICE_KEYS_KEY, serde_json::json!(ids));
+            store.save().map_err(|e| e.to_string())
+        });
+    if let Err(e) = saved {
+        log::warn!("[KEYS] Failed to save store: {}", e);
+    }
+}
+
+// Internal helper for remembering a device's private key after it was rotated on this machine
+pub async fn store_device_key(app: &tauri::AppHandle, device_id: &str, private_key: &str) -> Result<(), String> {
+    device_key_entry(device_id)?
+        .set_password(private_key)
+        .map_err(|e| format!("Failed to save key to the keychain: {}", e))?;
+
+    let mut ids = device_key_ids(app);
+    // An old key still waiting to be moved would shadow the new one
+    let mut pending = pending_device_keys(app);
+    if pending.remove(device_id).is_some() {
+        set_pending_device_keys(app, pending);
+    }
+    if !ids.iter().any(|id| id == device_id) {
+        ids.push(device_id.to_string());
+    }
+    set_device_key_ids(app, &ids);
+    Ok(())
+}
+
+// Internal helper for loading a device's locally held private key, if it has one
+pub fn get_device_key_internal(app: &tauri::AppHandle, device_id: &str) -> Option<String> {
+    if !device_key_ids(app).iter().any(|id| id == device_id) {
+        return None;
+    }
+    if let Some(key) = pending_device_keys(app).get(device_id).and_then(|key| key.as_str()) {
+        return Some(key.to_string());
+    }
+    match device_key_entry(device_id).and_then(|entry| entry.get_password().map_err(|e| e.to_string())) {
+        Ok(key) => Some(key),
+        Err(e) => {
+            log::warn!("[KEYS] Failed to read key for device {} from the keychain: {}", device_id, e);
+            None
+        }
+    }
+}
+
+// Internal helper for telling whether a device has a locally held private key, without reading it
+pub fn has_device_key(app: &tauri::AppHandle, device_id: &str) -> bool {
+    device_key_ids(app).iter().any(|id| id == device_id)
+}
+
+// Internal helper for forgetting a device's locally held private key (no-op if it has none)
+pub fn clear_device_key(app: &tauri::AppHandle, device_id: &str) {
+    let mut ids = device_key_ids(app);
+    if !ids.iter().any(|id| id == device_id) {
+        return;
+    }
+    delete_device_key(device_id);
+    let mut pending = pending_device_keys(app);
+    if pending.remove(device_id).is_some() {
+        set_pending_device_keys(app, pending);
+    }
+    ids.retain(|id| id != device_id);
+    set_device_key_ids(app, &ids);
+}
+
+// Internal helper for forgetting every locally held private key, e.g. on logout
+pub fn clear_device_keys(app: &tauri::AppHandle) {
+    let ids = device_key_ids(app);
+    if ids.is_empty() {
+        return;
+    }
+    for device_id in &ids {
+        delete_device_key(device_id);
+    }
+    set_pending_device_keys(app, serde_json::Map::new());
+    set_device_key_ids(app, &[]);
+}
+
+fn delete_device_key(device_id: &str) {
+    let deleted = device_key_entry(device_id)
+        .and_then(|entry| match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        });
+    match deleted {
+        Ok(()) => log::info!("[KEYS] Forgot the local key for device {}", device_id),
+        Err(e) => log::warn!("[KEYS] Failed to delete key for device {} from the keychain: {}", device_id, e),
+    }
+}
@@ -0,0 +1,286 @@
+//! Which DNS resolvers the system is actually using
+//! Read from the OS (resolvectl or resolv.conf, scutil, netsh) rather than from
+//! what we configured, so the UI can show whether queries go through the tunnel.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::process::Command;
+
+use serde::Serialize;
+
+/// One resolver the system is configured to query
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DnsResolver {
+    pub address: String,
+    /// Interface the resolver is attached to, when the OS says
+    pub interface: Option<String>,
+    /// The resolver is one of the tunnel's DNS servers or sits on the tunnel interface
+    pub via_tunnel: bool,
+}
+
+/// Result of get_active_dns()
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveDns {
+    pub resolvers: Vec<DnsResolver>,
+    /// DNS servers from the tunnel config, empty when disconnected
+    pub tunnel_dns: Vec<String>,
+    /// Every resolver the system uses goes through the tunnel
+    pub all_via_tunnel: bool,
+    /// Port 53 to anything but the tunnel DNS is blocked, so a non-tunnel
+    /// resolver in the list can't actually leak queries
+    pub leak_block_active: bool,
+    /// Where the resolver list came from, e.g. "resolvectl"
+    pub source: &'static str,
+}
+
+/// Query the OS for its resolvers and compare them to the tunnel's DNS servers
+pub fn active_dns(tunnel_dns: &[Ipv4Addr], tun_name: Option<&str>, leak_block_active: bool) -> Result<ActiveDns, String> {
+    let (source, found) = read_resolvers()?;
+
+    let resolvers: Vec<DnsResolver> = found.into_iter()
+        .map(|(address, interface)| {
+            let via_tunnel = matches!(address, IpAddr::V4(v4) if tunnel_dns.contains(&v4))
+                || (interface.is_some() && interface.as_deref() == tun_name);
+            DnsResolver { address: address.to_string(), interface, via_tunnel }
+        })
+        .collect();
+
+    Ok(ActiveDns {
+        all_via_tunnel: !resolvers.is_empty() && resolvers.iter().all(|r| r.via_tunnel),
+        resolvers,
+        tunnel_dns: tunnel_dns.iter().map(Ipv4Addr::to_string).collect(),
+        leak_block_active,
+        source,
+    })
+}
+
+type Resolvers = Vec<(IpAddr, Option<String>)>;
+
+/// systemd-resolved knows the upstream servers behind its 127.0.0.53 stub
+#[cfg(target_os = "linux")]
+fn read_resolvers() -> Result<(&'static str, Resolvers), String> {
+    match run("resolvectl", &["status"]) {
+        Ok(output) => {
+            let resolvers = parse_resolvectl(&output);
+            if !resolvers.is_empty() {
+                return Ok(("resolvectl", resolvers));
+            }
+        }
+        Err(e) => log::debug!("[DNS] resolvectl unavailable, reading resolv.conf: {}", e),
+    }
+    read_resolv_conf()
+}
+
+/// scutil shows the resolvers mDNSResponder uses; resolv.conf is only a summary of them
+#[cfg(target_os = "macos")]
+fn read_resolvers() -> Result<(&'static str, Resolvers), String> {
+    match run("scutil", &["--dns"]) {
+        Ok(output) => {
+            let resolvers = parse_scutil_dns(&output);
+            if !resolvers.is_empty() {
+                return Ok(("scutil", resolvers));
+            }
+        }
+        Err(e) => log::warn!("[DNS] scutil --dns failed, reading resolv.conf: {}", e),
+    }
+    read_resolv_conf()
+}
+
+#[cfg(target_os = "windows")]
+fn read_resolvers() -> Result<(&'static str, Resolvers), String> {
+    let output = run("netsh", &["interface", "ip", "show", "dns"])?;
+    Ok(("netsh", parse_netsh_dns(&output)))
+}
+
+#[cfg(unix)]
+fn read_resolv_conf() -> Result<(&'static str, Resolvers), String> {
+    let contents = std::fs::read_to_string("/etc/resolv.conf")
+        .map_err(|e| format!("Failed to read /etc/resolv.conf: {}", e))?;
+    Ok(("resolv.conf", parse_resolv_conf(&contents)))
+}
+
+fn run(program: &str, args: &[&str]) -> Result<String, String> {
+    let mut command = Command::new(program);
+    command.args(args);
+
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    let output = command.output().map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Address of a resolver entry, without a DoT server name ("1.1.1.1#one.one.one.one")
+/// or scope ("fe80::1%en0")
+fn parse_server(value: &str) -> Option<IpAddr> {
+    value.split(['#', '%']).next()?.trim().parse().ok()
+}
+
+fn push_unique(resolvers: &mut Resolvers, address: IpAddr, interface: Option<&str>) {
+    let entry = (address, interface.map(str::to_string));
+    if !resolvers.contains(&entry) {
+        resolvers.push(entry);
+    }
+}
+
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+fn parse_resolv_conf(contents: &str) -> Resolvers {
+    let mut resolvers = Resolvers::new();
+    for line in contents.lines() {
+        let mut fields = line.split_whitespace();
+        if fields.next() == Some("nameserver") {
+            if let Some(address) = fields.next().and_then(parse_server) {
+                push_unique(&mut resolvers, address, None);
+            }
+        }
+    }
+    resolvers
+}
+
+/// "DNS Servers:" lines of the Global section and each "Link N (name)", including
+/// the continuation lines resolvectl wraps long lists onto
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_resolvectl(output: &str) -> Resolvers {
+    let mut resolvers = Resolvers::new();
+    let mut interface: Option<&str> = None;
+    let mut in_servers = false;
+
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if !line.starts_with(' ') && !trimmed.is_empty() {
+            // "Global" or "Link 2 (eth0)"
+            interface = trimmed.split_once('(').and_then(|(_, rest)| rest.strip_suffix(')'));
+            in_servers = false;
+            continue;
+        }
+
+        let values = match trimmed.split_once(": ") {
+            Some((key, value)) => {
+                in_servers = key == "DNS Servers";
+                value
+            }
+            None if in_servers => trimmed,
+            None => continue,
+        };
+        if in_servers {
+            for address in values.split_whitespace().filter_map(parse_server) {
+                push_unique(&mut resolvers, address, interface);
+            }
+        }
+    }
+    resolvers
+}
+
+/// Resolvers of the main "DNS configuration" block; the scoped-queries block
+/// after it repeats them per interface
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_scutil_dns(output: &str) -> Resolvers {
+    let mut resolvers = Resolvers::new();
+    let mut servers: Vec<IpAddr> = Vec::new();
+    let mut interface: Option<String> = None;
+
+    let mut flush = |servers: &mut Vec<IpAddr>, interface: &mut Option<String>| {
+        for address in servers.drain(..) {
+            push_unique(&mut resolvers, address, interface.as_deref());
+        }
+        *interface = None;
+    };
+
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("DNS configuration (") {
+            break;
+        }
+        if trimmed.starts_with("resolver #") {
+            flush(&mut servers, &mut interface);
+            continue;
+        }
+        let Some((key, value)) = trimmed.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if key.trim_end().starts_with("nameserver[") {
+            servers.extend(parse_server(value));
+        } else if key.trim_end() == "if_index" {
+            // "14 (utun5)"
+            interface = value.split_once('(').and_then(|(_, rest)| rest.strip_suffix(')')).map(str::to_string);
+        }
+    }
+    flush(&mut servers, &mut interface);
+    resolvers
+}
+
+/// Per-adapter "... DNS Servers ...:" entries and their continuation lines
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn parse_netsh_dns(output: &str) -> Resolvers {
+    let mut resolvers = Resolvers::new();
+    let mut interface: Option<&str> = None;
+    let mut in_servers = false;
+
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if let Some(rest) = trimmed.strip_prefix("Configuration for interface ") {
+            interface = Some(rest.trim_matches('"'));
+            in_servers = false;
+            continue;
+        }
+
+        let value = match trimmed.split_once(':') {
+            // An IPv6 resolver on a continuation line also contains ':'
+            _ if in_servers && parse_server(trimmed).is_some() => trimmed,
+            Some((key, value)) => {
+                in_servers = key.to_ascii_lowercase().contains("dns servers");
+                value.trim()
+            }
+            None => continue,
+        };
+        if in_servers {
+            if let Some(address) = parse_server(value) {
+                push_unique(&mut resolvers, address, interface);
+            }
+        }
+    }
+    resolvers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_resolver_output() {
+        let resolvectl = "Global\n           Protocols: +LLMNR -mDNS\n    resolv.conf mode: stub\n\n\
+            Link 2 (eth0)\n      Current Scopes: DNS\n  Current DNS Server: 192.168.1.1\n         DNS Servers: 192.168.1.1\n                    fe80::1%2\n        DNS Domain: lan\n\n\
+            Link 5 (ple7)\n       DNS Servers: 10.100.0.1#dns.ple7.test\n";
+        assert_eq!(parse_resolvectl(resolvectl), vec![
+            (ip("192.168.1.1"), Some("eth0".to_string())),
+            (ip("fe80::1"), Some("eth0".to_string())),
+            (ip("10.100.0.1"), Some("ple7".to_string())),
+        ]);
+
+        let scutil = "DNS configuration\n\nresolver #1\n  nameserver[0] : 10.100.0.1\n  if_index : 14 (utun5)\n  reach    : 0x00000003 (Reachable,Transient Connection)\n\n\
+            resolver #2\n  domain   : local\n  options  : mdns\n\n\
+            DNS configuration (for scoped queries)\n\nresolver #1\n  nameserver[0] : 192.168.1.1\n  if_index : 6 (en0)\n";
+        assert_eq!(parse_scutil_dns(scutil), vec![(ip("10.100.0.1"), Some("utun5".to_string()))]);
+
+        let netsh = "\nConfiguration for interface \"Ethernet\"\n    DNS servers configured through DHCP:  192.168.1.1\n                                          2001:db8::53\n    Register with which suffix:           Primary only\n\n\
+            Configuration for interface \"PLE7\"\n    Statically Configured DNS Servers:    10.100.0.1\n    Register with which suffix:           Primary only\n";
+        assert_eq!(parse_netsh_dns(netsh), vec![
+            (ip("192.168.1.1"), Some("Ethernet".to_string())),
+            (ip("2001:db8::53"), Some("Ethernet".to_string())),
+            (ip("10.100.0.1"), Some("PLE7".to_string())),
+        ]);
+
+        assert_eq!(parse_resolv_conf("# generated\nnameserver 127.0.0.53\noptions edns0\n"), vec![(ip("127.0.0.53"), None)]);
+    }
+}
@@ -0,0 +1,172 @@
+//! Typed errors for the API client, tunnel and STUN paths
+//!
+//! Serialized to the frontend as `{"kind", "message", "retryable"}` (plus
+//! `retryAfterSecs` when rate limited) so the UI can branch on the kind
+//! instead of matching error text.
+
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum PleError {
+    /// Request never got a response (DNS, connect, TLS, dropped connection)
+    #[error("Network error: {0}")]
+    Network(String),
+    /// Missing, invalid or expired credentials
+    #[error("{0}")]
+    Auth(String),
+    /// Control plane answered HTTP 429
+    #[error("Rate limited - try again in {retry_after_secs} seconds")]
+    RateLimited { retry_after_secs: u64 },
+    /// Control plane answered with a non-success status
+    #[error("{0}")]
+    Api(String),
+    /// Resource doesn't exist (or was already deleted)
+    #[error("{0}")]
+    NotFound(String),
+    /// Token is valid but doesn't grant access to this resource
+    #[error("{0}")]
+    Forbidden(String),
+    /// macOS helper daemon not installed, not running or not answering
+    #[error("{0}")]
+    HelperUnavailable(String),
+    /// Helper install or repair script failed or was cancelled
+    #[error("{0}")]
+    HelperInstallFailed(String),
+    /// Helper was installed but launchd hasn't started it yet
+    #[error("{0}")]
+    HelperStartSlow(String),
+    /// Running helper daemon is from a different app version
+    #[error("Helper version {helper} does not match app version {app}")]
+    HelperVersionMismatch { helper: String, app: String },
+    /// Windows: the app isn't running as Administrator and couldn't elevate
+    #[error("{0}")]
+    AdminRequired(String),
+    /// Tunnel didn't come up within the connect timeout
+    #[error("{0}")]
+    HandshakeTimeout(String),
+    /// Tunnel came up, but path MTU probing or routing setup ran past the connect timeout
+    #[error("{0}")]
+    SetupTimeout(String),
+    /// Adding or removing a route failed
+    #[error("{0}")]
+    RouteFailed(String),
+    /// WireGuard port (or the whole port range) taken, usually by another VPN
+    #[error("{0}")]
+    PortConflict(String),
+    /// Another app holds the TUN adapter
+    #[error("{0}")]
+    AdapterInUse(String),
+    /// Device config came without a private key and none is stored locally
+    #[error("{0}")]
+    MissingPrivateKey(String),
+    /// Server's certificate chain has none of the pinned public keys
+    #[error("{0}")]
+    CertificatePinMismatch(String),
+    /// Malformed config, key or server response
+    #[error("{0}")]
+    Parse(String),
+    /// Image file couldn't be read or isn't a supported image format
+    #[error("{0}")]
+    InvalidImage(String),
+    /// Image was read but has no QR code in it
+    #[error("No QR code found in the image")]
+    NoQrCode,
+    /// Image holds more than one QR code, so which config to import is ambiguous
+    #[error("Found {0} QR codes in the image - crop it to the one with the config")]
+    MultipleQrCodes(usize),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl PleError {
+    /// Stable identifier the frontend switches on
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Network(_) => "network",
+            Self::Auth(_) => "auth",
+            Self::RateLimited { .. } => "rateLimited",
+            Self::Api(_) => "api",
+            Self::NotFound(_) => "notFound",
+            Self::Forbidden(_) => "forbidden",
+            Self::HelperUnavailable(_) => "helperUnavailable",
+            Self::HelperInstallFailed(_) => "helperInstallFailed",
+            Self::HelperStartSlow(_) => "helperStartSlow",
+            Self::HelperVersionMismatch { .. } => "helperVersionMismatch",
+            Self::AdminRequired(_) => "adminRequired",
+            Self::HandshakeTimeout(_) => "handshakeTimeout",
+            Self::SetupTimeout(_) => "setupTimeout",
+            Self::RouteFailed(_) => "routeFailed",
+            Self::PortConflict(_) => "portConflict",
+            Self::AdapterInUse(_) => "adapterInUse",
+            Self::MissingPrivateKey(_) => "missingPrivateKey",
+            Self::CertificatePinMismatch(_) => "certificatePinMismatch",
+            Self::Parse(_) => "parse",
+            Self::InvalidImage(_) => "invalidImage",
+            Self::NoQrCode => "noQrCode",
+            Self::MultipleQrCodes(_) => "multipleQrCodes",
+            Self::Other(_) => "other",
+        }
+    }
+
+    /// Whether trying the same operation again later can succeed without user action
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::Network(_) | Self::RateLimited { .. } | Self::HandshakeTimeout(_) | Self::SetupTimeout(_)
+                | Self::HelperStartSlow(_)
+        )
+    }
+}
+
+impl Serialize for PleError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("PleError", 4)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("retryable", &self.is_retryable())?;
+        if let Self::RateLimited { retry_after_secs } = self {
+            state.serialize_field("retryAfterSecs", retry_after_secs)?;
+        } else {
+            state.skip_field("retryAfterSecs")?;
+        }
+        state.end()
+    }
+}
+
+/// Code that still returns `Result<_, String>` can use `?` on a PleError
+impl From<PleError> for String {
+    fn from(e: PleError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Unclassified String errors from lower layers
+impl From<String> for PleError {
+    fn from(e: String) -> Self {
+        Self::Other(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_shape() {
+        let json = serde_json::to_value(PleError::RateLimited { retry_after_secs: 7 }).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "kind": "rateLimited",
+            "message": "Rate limited - try again in 7 seconds",
+            "retryable": true,
+            "retryAfterSecs": 7,
+        }));
+
+        let json = serde_json::to_value(PleError::Auth("Invalid or expired token".to_string())).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "kind": "auth",
+            "message": "Invalid or expired token",
+            "retryable": false,
+        }));
+    }
+}
@@ -0,0 +1,219 @@
+//! Sampled "who am I talking to" view of outbound tunnel traffic.
+//! Every Nth packet is attributed to its destination address and port; counts are
+//! scaled back up by N, so they're estimates. Off by default.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+/// Destinations tracked at once; the least recently seen one makes room for a new one
+pub const MAX_FLOWS: usize = 256;
+
+/// Sample rates above this would make the view too coarse to be useful
+pub const MAX_SAMPLE_RATE: u32 = 10_000;
+
+/// Sample 1 in this many packets, 0 = sampler off. Checked per packet.
+static SAMPLE_RATE: AtomicU32 = AtomicU32::new(0);
+
+/// Packets seen since the last sample
+static SKIPPED: AtomicU32 = AtomicU32::new(0);
+
+static FLOWS: Mutex<Option<FlowTable>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FlowKey {
+    destination: IpAddr,
+    protocol: u8,
+    /// TCP/UDP destination port
+    port: Option<u16>,
+}
+
+struct FlowCounts {
+    packets: u64,
+    bytes: u64,
+    last_seen: Instant,
+}
+
+#[derive(Default)]
+struct FlowTable {
+    flows: HashMap<FlowKey, FlowCounts>,
+}
+
+impl FlowTable {
+    fn record(&mut self, key: FlowKey, packets: u64, bytes: u64, now: Instant) {
+        if !self.flows.contains_key(&key) && self.flows.len() >= MAX_FLOWS {
+            // Only runs for a new destination on a full table, and only on sampled packets
+            if let Some(oldest) = self.flows.iter().min_by_key(|(_, c)| c.last_seen).map(|(k, _)| *k) {
+                self.flows.remove(&oldest);
+            }
+        }
+
+        let counts = self.flows.entry(key).or_insert(FlowCounts { packets: 0, bytes: 0, last_seen: now });
+        counts.packets += packets;
+        counts.bytes += bytes;
+        counts.last_seen = now;
+    }
+}
+
+/// One destination in get_active_flows(), busiest first
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveFlow {
+    pub destination: String,
+    /// "tcp", "udp", "icmp" or "other"
+    pub protocol: &'static str,
+    pub port: Option<u16>,
+    /// Estimated from samples
+    pub packets: u64,
+    /// Estimated from samples
+    pub bytes: u64,
+    pub idle_secs: u64,
+}
+
+/// Sample 1 in `rate` outbound packets; 0 turns the sampler off and drops what it collected
+pub fn set_sample_rate(rate: u32) {
+    SAMPLE_RATE.store(rate.min(MAX_SAMPLE_RATE), Ordering::Relaxed);
+    if rate == 0 {
+        clear();
+    }
+}
+
+pub fn sample_rate() -> u32 {
+    SAMPLE_RATE.load(Ordering::Relaxed)
+}
+
+/// Forget every flow (on disconnect)
+pub fn clear() {
+    *FLOWS.lock() = None;
+}
+
+/// Count an outbound plaintext IP packet, if it's one of the sampled ones
+pub fn record(packet: &[u8]) {
+    let rate = sample_rate();
+    if rate == 0 {
+        return;
+    }
+    if SKIPPED.fetch_add(1, Ordering::Relaxed) + 1 < rate {
+        return;
+    }
+    SKIPPED.store(0, Ordering::Relaxed);
+
+    let Some(key) = flow_key(packet) else {
+        return;
+    };
+    FLOWS.lock()
+        .get_or_insert_with(FlowTable::default)
+        .record(key, rate as u64, packet.len() as u64 * rate as u64, Instant::now());
+}
+
+/// Current flows, busiest first
+pub fn snapshot() -> Vec<ActiveFlow> {
+    let now = Instant::now();
+    let mut flows: Vec<ActiveFlow> = FLOWS.lock()
+        .as_ref()
+        .map(|table| table.flows.iter().map(|(key, counts)| ActiveFlow {
+            destination: key.destination.to_string(),
+            protocol: protocol_name(key.protocol),
+            port: key.port,
+            packets: counts.packets,
+            bytes: counts.bytes,
+            idle_secs: now.duration_since(counts.last_seen).as_secs(),
+        }).collect())
+        .unwrap_or_default();
+    flows.sort_by_key(|flow| std::cmp::Reverse(flow.bytes));
+    flows
+}
+
+fn protocol_name(protocol: u8) -> &'static str {
+    match protocol {
+        6 => "tcp",
+        17 => "udp",
+        1 | 58 => "icmp",
+        _ => "other",
+    }
+}
+
+/// Destination, protocol and TCP/UDP port of an IP packet. As with the protocol
+/// counters, IPv6 extension headers aren't followed (the port is left out).
+fn flow_key(packet: &[u8]) -> Option<FlowKey> {
+    let (destination, protocol, header_len) = match packet.first()? >> 4 {
+        4 if packet.len() >= 20 => {
+            let dst: [u8; 4] = packet[16..20].try_into().ok()?;
+            let header_len = (packet[0] & 0x0f) as usize * 4;
+            // Later fragments don't start with a transport header
+            let fragment_offset = u16::from_be_bytes([packet[6], packet[7]]) & 0x1fff;
+            let header_len = if fragment_offset == 0 { Some(header_len) } else { None };
+            (IpAddr::V4(Ipv4Addr::from(dst)), packet[9], header_len)
+        }
+        6 if packet.len() >= 40 => {
+            let dst: [u8; 16] = packet[24..40].try_into().ok()?;
+            (IpAddr::V6(Ipv6Addr::from(dst)), packet[6], Some(40))
+        }
+        _ => return None,
+    };
+
+    let port = match (protocol, header_len) {
+        (6 | 17, Some(offset)) if packet.len() >= offset + 4 => {
+            Some(u16::from_be_bytes([packet[offset + 2], packet[offset + 3]]))
+        }
+        _ => None,
+    };
+    Some(FlowKey { destination, protocol, port })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn udp_packet(dst: [u8; 4], port: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; 28];
+        packet[0] = 0x45;
+        packet[9] = 17;
+        packet[16..20].copy_from_slice(&dst);
+        packet[22..24].copy_from_slice(&port.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_flow_key() {
+        let key = flow_key(&udp_packet([1, 1, 1, 1], 53)).unwrap();
+        assert_eq!(key.destination, IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)));
+        assert_eq!((key.protocol, key.port), (17, Some(53)));
+
+        let mut v6 = vec![0u8; 44];
+        v6[0] = 0x60;
+        v6[6] = 6;
+        v6[39] = 1;
+        v6[42..44].copy_from_slice(&443u16.to_be_bytes());
+        let key = flow_key(&v6).unwrap();
+        assert_eq!(key.destination, IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!((key.protocol, key.port), (6, Some(443)));
+
+        assert!(flow_key(&[0x45; 10]).is_none());
+    }
+
+    #[test]
+    fn test_flow_table_evicts_least_recent() {
+        let mut table = FlowTable::default();
+        let start = Instant::now();
+        for i in 0..MAX_FLOWS {
+            let key = flow_key(&udp_packet([10, 0, (i / 256) as u8, i as u8], 53)).unwrap();
+            table.record(key, 1, 100, start + std::time::Duration::from_millis(i as u64));
+        }
+        // Touch the oldest so the second oldest is the one to go
+        let first = flow_key(&udp_packet([10, 0, 0, 0], 53)).unwrap();
+        let later = start + std::time::Duration::from_secs(1);
+        table.record(first, 1, 100, later);
+
+        let new = flow_key(&udp_packet([192, 0, 2, 1], 443)).unwrap();
+        table.record(new, 1, 100, later);
+
+        assert_eq!(table.flows.len(), MAX_FLOWS);
+        assert_eq!(table.flows[&first].packets, 2);
+        assert!(table.flows.contains_key(&new));
+        assert!(!table.flows.contains_key(&flow_key(&udp_packet([10, 0, 0, 1], 53)).unwrap()));
+    }
+}
@@ -5,19 +5,38 @@
 //! - Installing helper with admin privileges
 //! - Sending commands to the helper daemon
 
-use std::io::{Read, Write, BufRead, BufReader};
+use std::io::{ErrorKind, Read, Write};
 use std::os::unix::net::UnixStream;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::PleError;
+
 const SOCKET_PATH: &str = "/var/run/ple7-helper.sock";
 const HELPER_PATH: &str = "/Library/PrivilegedHelperTools/ple7-helper";
 const PLIST_PATH: &str = "/Library/LaunchDaemons/com.ple7.vpn.helper.plist";
 const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// How long the helper gets to deliver a whole response
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Responses larger than this are treated as a broken stream
+const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// launchd service target for `launchctl kickstart`
+const LAUNCHD_SERVICE: &str = "system/com.ple7.vpn.helper";
+
+/// Written to stderr by the uninstall script when launchctl refuses to unload
+const LAUNCHCTL_FAILED_MARKER: &str = "PLE7_LAUNCHCTL_UNLOAD_FAILED";
+
+/// Set once the running helper answered with our version, cleared whenever the
+/// helper may have changed (reinstall, lost connection)
+static HELPER_VERIFIED: AtomicBool = AtomicBool::new(false);
+
 #[derive(Debug, Serialize)]
 #[serde(tag = "command")]
 pub enum HelperCommand {
@@ -47,6 +66,8 @@ pub enum HelperCommand {
         gateway: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         exclude_ip: Option<String>,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        include_ipv6: bool,
     },
     #[serde(rename = "restore_default_gateway")]
     RestoreDefaultGateway,
@@ -67,6 +88,45 @@ pub enum HelperCommand {
     Ping,
     #[serde(rename = "get_version")]
     GetVersion,
+    #[serde(rename = "get_metrics")]
+    GetMetrics,
+    #[serde(rename = "list_ple7_routes")]
+    ListPle7Routes,
+    #[serde(rename = "flush_ple7_routes")]
+    FlushPle7Routes,
+    #[serde(rename = "block_dns")]
+    BlockDns {
+        tun_name: String,
+        allowed_dns: String,
+    },
+    #[serde(rename = "unblock_dns")]
+    UnblockDns,
+    #[serde(rename = "set_dns")]
+    SetDns {
+        tun_name: String,
+        servers: String,
+    },
+    #[serde(rename = "restore_dns")]
+    RestoreDns,
+    #[serde(rename = "block_ipv6")]
+    BlockIpv6 {
+        tun_name: String,
+        allow_ips: Vec<String>,
+        udp_port: Option<u16>,
+    },
+    #[serde(rename = "unblock_ipv6")]
+    UnblockIpv6,
+    #[serde(rename = "set_mtu")]
+    SetMtu {
+        tun_name: String,
+        mtu: u16,
+    },
+    #[serde(rename = "set_ipv6_address")]
+    SetIpv6Address {
+        tun_name: String,
+        address: String,
+        prefix_len: u8,
+    },
 }
 
 #[derive(Debug, Deserialize)]
@@ -80,11 +140,93 @@ pub struct HelperClient {
     stream: Option<UnixStream>,
 }
 
+/// How long to wait for launchd to bring the helper up
+#[derive(Debug, Clone, Copy)]
+pub struct StartupWait {
+    pub poll_interval: Duration,
+    /// Total time allowed, including the wait after a kickstart
+    pub deadline: Duration,
+}
+
+impl StartupWait {
+    /// After an install - launchd can be slow on a busy machine
+    pub const AFTER_INSTALL: Self = Self {
+        poll_interval: Duration::from_millis(250),
+        deadline: Duration::from_secs(15),
+    };
+
+    /// After restarting an installed helper - give up sooner and reinstall instead
+    pub const AFTER_RESTART: Self = Self {
+        poll_interval: Duration::from_millis(250),
+        deadline: Duration::from_secs(5),
+    };
+}
+
 impl HelperClient {
     pub fn new() -> Self {
         Self { stream: None }
     }
 
+    /// Client for a helper known to match this app version - every command path goes through here
+    /// Checks the version once per helper process, later calls are free
+    pub fn verified() -> Result<Self, PleError> {
+        let mut client = Self::new();
+        if HELPER_VERIFIED.load(Ordering::Acquire) {
+            return Ok(client);
+        }
+
+        if !Self::is_running() {
+            return Err(PleError::HelperUnavailable("Helper daemon is not running".to_string()));
+        }
+
+        let response = client.send_command(HelperCommand::GetVersion)
+            .map_err(|e| PleError::HelperUnavailable(format!("Helper daemon is not responding: {}", e)))?;
+        check_version(&response)?;
+
+        HELPER_VERIFIED.store(true, Ordering::Release);
+        Ok(client)
+    }
+
+    /// Make sure a helper matching this app version is running, restarting or
+    /// (re)installing it if needed - may prompt for the admin password
+    pub async fn ensure_ready() -> Result<Self, PleError> {
+        let err = match Self::verified() {
+            Ok(client) => return Ok(client),
+            Err(e) => e,
+        };
+
+        match &err {
+            PleError::HelperVersionMismatch { helper, app } => {
+                log::info!("Helper version mismatch ({} != {}) - upgrading", helper, app);
+                // Force full reinstall for version upgrade
+                Self::install_helper().await?;
+            }
+            _ if Self::is_installed() => {
+                // Helper files exist but not responding - try to restart first
+                log::info!("Helper installed but not responding ({}), attempting to restart...", err);
+                let _ = Command::new("launchctl").args(["unload", PLIST_PATH]).output();
+                let _ = Command::new("launchctl").args(["load", PLIST_PATH]).output();
+
+                if Self::wait_until_ready(StartupWait::AFTER_RESTART).await.is_err() {
+                    // Restart failed, need full reinstall
+                    log::info!("Restart failed, performing full reinstall...");
+                    Self::install_helper().await?;
+                }
+            }
+            _ => {
+                log::info!("Helper daemon not installed, prompting for installation...");
+                Self::install_helper().await?;
+            }
+        }
+
+        Self::verified().map_err(|e| match e {
+            PleError::HelperUnavailable(msg) => PleError::HelperUnavailable(format!(
+                "Helper installation failed - please try again or restart your Mac: {}", msg
+            )),
+            e => e,
+        })
+    }
+
     /// Check if the helper daemon is installed and running
     pub fn is_installed() -> bool {
         Path::new(HELPER_PATH).exists() && Path::new(PLIST_PATH).exists()
@@ -95,11 +237,10 @@ impl HelperClient {
         Path::new(SOCKET_PATH).exists()
     }
 
-    /// Install the helper daemon (requires admin privileges)
-    /// Returns the AppleScript command to run with admin privileges
-    pub fn get_install_script(helper_binary_path: &str, plist_path: &str) -> String {
+    /// Shell commands that copy the bundled helper into place and load it
+    fn install_commands(helper_binary_path: &str, plist_path: &str) -> String {
         format!(
-            r#"do shell script "
+            r#"
 # Create directories
 mkdir -p /Library/PrivilegedHelperTools
 mkdir -p /Library/LaunchDaemons
@@ -119,16 +260,68 @@ launchctl unload /Library/LaunchDaemons/com.ple7.vpn.helper.plist 2>/dev/null ||
 launchctl load /Library/LaunchDaemons/com.ple7.vpn.helper.plist
 
 echo 'Helper installed successfully'
-" with administrator privileges"#,
+"#,
             helper_binary_path, plist_path
         )
     }
 
-    /// Install the helper using osascript (will prompt for admin password)
-    pub async fn install_helper() -> Result<(), String> {
-        log::info!("Installing PLE7 helper daemon...");
+    /// Shell commands that stop the daemon and remove every installed file
+    /// With `allow_unloaded` a daemon launchd can't unload (typically because it
+    /// isn't loaded - the state repair exists for) is not an error
+    fn uninstall_commands(allow_unloaded: bool) -> String {
+        let on_unload_failure = if allow_unloaded {
+            "true".to_string()
+        } else {
+            format!("{{ echo '{}' >&2; exit 3; }}", LAUNCHCTL_FAILED_MARKER)
+        };
+        format!(
+            r#"
+# Stop the daemon
+if [ -f {plist} ]; then
+  launchctl unload {plist} || {on_unload_failure}
+fi
+
+# Remove installed files
+rm -f {plist}
+rm -f {helper}
+rm -f {socket}
+
+echo 'Helper uninstalled successfully'
+"#,
+            plist = PLIST_PATH,
+            helper = HELPER_PATH,
+            socket = SOCKET_PATH,
+        )
+    }
 
-        // Get paths to bundled helper files
+    /// Install the helper daemon (requires admin privileges)
+    /// Returns the AppleScript command to run with admin privileges
+    pub fn get_install_script(helper_binary_path: &str, plist_path: &str) -> String {
+        format!(
+            r#"do shell script "{}" with administrator privileges"#,
+            Self::install_commands(helper_binary_path, plist_path)
+        )
+    }
+
+    /// AppleScript command that removes the helper daemon
+    pub fn get_uninstall_script() -> String {
+        format!(
+            r#"do shell script "{}" with administrator privileges"#,
+            Self::uninstall_commands(false)
+        )
+    }
+
+    /// AppleScript command that removes and reinstalls the helper under a single prompt
+    pub fn get_repair_script(helper_binary_path: &str, plist_path: &str) -> String {
+        format!(
+            r#"do shell script "{}{}" with administrator privileges"#,
+            Self::uninstall_commands(true),
+            Self::install_commands(helper_binary_path, plist_path)
+        )
+    }
+
+    /// Locate the helper binary and plist bundled in the app's Resources directory
+    fn bundled_helper_files() -> Result<(PathBuf, PathBuf), String> {
         let exe_path = std::env::current_exe()
             .map_err(|e| format!("Failed to get executable path: {}", e))?;
 
@@ -149,61 +342,144 @@ echo 'Helper installed successfully'
             return Err(format!("Plist file not found at {:?}", plist_file));
         }
 
-        let script = Self::get_install_script(
-            helper_binary.to_str().unwrap(),
-            plist_file.to_str().unwrap(),
-        );
+        Ok((helper_binary, plist_file))
+    }
 
-        log::debug!("Running install script via osascript");
+    /// Run an AppleScript via osascript, mapping failures to user-facing errors
+    fn run_admin_script(script: &str, action: &str) -> Result<(), String> {
+        log::debug!("Running {} script via osascript", action);
 
         let output = Command::new("osascript")
             .arg("-e")
-            .arg(&script)
+            .arg(script)
             .output()
             .map_err(|e| format!("Failed to run osascript: {}", e))?;
 
         if output.status.success() {
-            log::info!("Helper installed successfully, waiting for daemon to be ready...");
+            return Ok(());
+        }
 
-            // Wait for daemon to actually respond to ping (not just socket file existence)
-            for attempt in 1..=20 {
-                tokio::time::sleep(Duration::from_millis(250)).await;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
 
-                // First check if socket exists
-                if !Self::is_running() {
-                    log::debug!("Attempt {}/20: Socket not yet created", attempt);
-                    continue;
-                }
+        if stderr.contains("User canceled") || stdout.contains("User canceled") {
+            Err(format!("Helper {} cancelled by user", action))
+        } else if stderr.contains(LAUNCHCTL_FAILED_MARKER) {
+            Err(format!("launchctl failed to unload the helper daemon: {}", stderr.trim()))
+        } else {
+            Err(format!("Failed to {} helper: {} {}", action, stdout, stderr))
+        }
+    }
 
-                // Try to ping the daemon
-                let mut client = Self::new();
-                match client.ping() {
-                    Ok(true) => {
-                        log::info!("Helper daemon is ready (attempt {})", attempt);
-                        return Ok(());
-                    }
-                    Ok(false) => {
-                        log::debug!("Attempt {}/20: Ping returned false", attempt);
-                    }
-                    Err(e) => {
-                        log::debug!("Attempt {}/20: Ping failed: {}", attempt, e);
-                    }
-                }
+    /// Wait for a freshly loaded daemon to answer pings, kickstarting it once
+    /// halfway through the deadline if it still hasn't come up
+    async fn wait_until_ready(wait: StartupWait) -> Result<(), PleError> {
+        let started = std::time::Instant::now();
+        let kickstart_at = wait.deadline / 2;
+        let mut kickstarted = false;
+        let mut attempt = 0;
+
+        while started.elapsed() < wait.deadline {
+            tokio::time::sleep(wait.poll_interval).await;
+            attempt += 1;
+
+            if Self::ping_once(attempt) {
+                log::info!("Helper daemon is ready after {:?}", started.elapsed());
+                return Ok(());
             }
 
-            Err("Helper installed but daemon not responding after 5 seconds".to_string())
-        } else {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let stdout = String::from_utf8_lossy(&output.stdout);
+            if !kickstarted && started.elapsed() >= kickstart_at {
+                kickstarted = true;
+                Self::kickstart();
+            }
+        }
+
+        Err(PleError::HelperStartSlow(format!(
+            "Helper is installed but the daemon hasn't started after {} seconds - try again shortly",
+            wait.deadline.as_secs()
+        )))
+    }
+
+    /// One readiness probe: the socket exists and the daemon answers a ping
+    fn ping_once(attempt: u32) -> bool {
+        if !Self::is_running() {
+            log::debug!("Attempt {}: Socket not yet created", attempt);
+            return false;
+        }
 
-            if stderr.contains("User canceled") || stdout.contains("User canceled") {
-                Err("Installation cancelled by user".to_string())
-            } else {
-                Err(format!("Failed to install helper: {} {}", stdout, stderr))
+        match Self::new().ping() {
+            Ok(true) => true,
+            Ok(false) => {
+                log::debug!("Attempt {}: Ping returned false", attempt);
+                false
+            }
+            Err(e) => {
+                log::debug!("Attempt {}: Ping failed: {}", attempt, e);
+                false
             }
         }
     }
 
+    /// Ask launchd to (re)start the daemon now instead of whenever it gets to it.
+    /// Without root launchd may refuse, in which case waiting is all that's left.
+    fn kickstart() {
+        log::info!("Helper still not up, kickstarting {}", LAUNCHD_SERVICE);
+        match Command::new("launchctl").args(["kickstart", "-k", LAUNCHD_SERVICE]).output() {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => log::warn!("launchctl kickstart failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()),
+            Err(e) => log::warn!("Failed to run launchctl: {}", e),
+        }
+    }
+
+    /// Install the helper using osascript (will prompt for admin password)
+    pub async fn install_helper() -> Result<(), PleError> {
+        log::info!("Installing PLE7 helper daemon...");
+
+        let (helper_binary, plist_file) = Self::bundled_helper_files()
+            .map_err(PleError::HelperInstallFailed)?;
+        let script = Self::get_install_script(
+            helper_binary.to_str().unwrap(),
+            plist_file.to_str().unwrap(),
+        );
+
+        HELPER_VERIFIED.store(false, Ordering::Release);
+        Self::run_admin_script(&script, "install").map_err(PleError::HelperInstallFailed)?;
+
+        log::info!("Helper installed successfully, waiting for daemon to be ready...");
+        Self::wait_until_ready(StartupWait::AFTER_INSTALL).await
+    }
+
+    /// Unload the helper daemon and delete its binary, plist and socket
+    /// (will prompt for admin password)
+    pub async fn uninstall_helper() -> Result<(), String> {
+        log::info!("Uninstalling PLE7 helper daemon...");
+
+        HELPER_VERIFIED.store(false, Ordering::Release);
+        Self::run_admin_script(&Self::get_uninstall_script(), "uninstall")?;
+
+        log::info!("Helper uninstalled");
+        Ok(())
+    }
+
+    /// Force a clean reinstall of the helper daemon (will prompt for admin password)
+    pub async fn repair_helper() -> Result<(), PleError> {
+        log::info!("Repairing PLE7 helper daemon...");
+
+        let (helper_binary, plist_file) = Self::bundled_helper_files()
+            .map_err(PleError::HelperInstallFailed)?;
+        let script = Self::get_repair_script(
+            helper_binary.to_str().unwrap(),
+            plist_file.to_str().unwrap(),
+        );
+
+        HELPER_VERIFIED.store(false, Ordering::Release);
+        Self::run_admin_script(&script, "repair").map_err(PleError::HelperInstallFailed)?;
+
+        log::info!("Helper reinstalled, waiting for daemon to be ready...");
+        Self::wait_until_ready(StartupWait::AFTER_INSTALL).await
+    }
+
     /// Connect to the helper daemon with timeout
     pub fn connect(&mut self) -> Result<(), String> {
         self.connect_with_timeout(Duration::from_secs(5))
@@ -238,7 +514,7 @@ echo 'Helper installed successfully'
         };
 
         // Use shorter timeouts for read/write (2 seconds)
-        stream.set_read_timeout(Some(Duration::from_secs(2)))
+        stream.set_read_timeout(Some(RESPONSE_TIMEOUT))
             .map_err(|e| format!("Failed to set read timeout: {}", e))?;
         stream.set_write_timeout(Some(Duration::from_secs(2)))
             .map_err(|e| format!("Failed to set write timeout: {}", e))?;
@@ -249,22 +525,33 @@ echo 'Helper installed successfully'
 
     /// Send a command to the helper daemon
     pub fn send_command(&mut self, cmd: HelperCommand) -> Result<HelperResponse, String> {
+        let result = self.send_command_inner(cmd);
+        if result.is_err() {
+            // The helper may have been restarted or replaced - check its version again
+            HELPER_VERIFIED.store(false, Ordering::Release);
+        }
+        result
+    }
+
+    fn send_command_inner(&mut self, cmd: HelperCommand) -> Result<HelperResponse, String> {
         self.connect()?;
 
         let stream = self.stream.as_mut().unwrap();
 
-        // Send command
-        let cmd_json = serde_json::to_string(&cmd)
+        // Send command, newline-terminated so the helper knows where it ends.
+        // The connection id lets the helper tag its log lines for this request.
+        let mut request = serde_json::to_value(&cmd)
             .map_err(|e| format!("Failed to serialize command: {}", e))?;
+        if let (Some(fields), Some(conn_id)) = (request.as_object_mut(), crate::logging::connection_id()) {
+            fields.insert("conn_id".to_string(), conn_id.into());
+        }
+        let mut cmd_json = request.to_string();
+        cmd_json.push('\n');
 
         stream.write_all(cmd_json.as_bytes())
             .map_err(|e| format!("Failed to send command: {}", e))?;
 
-        // Read response
-        let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
-        let mut response_line = String::new();
-        reader.read_line(&mut response_line)
-            .map_err(|e| format!("Failed to read response: {}", e))?;
+        let response_line = read_response(stream, RESPONSE_TIMEOUT)?;
 
         serde_json::from_str(&response_line)
             .map_err(|e| format!("Failed to parse response: {}", e))
@@ -286,6 +573,19 @@ echo 'Helper installed successfully'
         })
     }
 
+    /// Names of the TUN devices the helper currently holds open
+    pub fn active_tuns(&mut self) -> Result<Vec<String>, String> {
+        let response = self.send_command(HelperCommand::Status)?;
+        if !response.success {
+            return Err(format!("Failed to get helper status: {}", response.message));
+        }
+        Ok(response.data
+            .as_ref()
+            .and_then(|data| data.get("active_tuns"))
+            .and_then(|tuns| serde_json::from_value(tuns.clone()).ok())
+            .unwrap_or_default())
+    }
+
     /// Add a route
     pub fn add_route(&mut self, destination: &str, prefix_len: u8, gateway: &str) -> Result<HelperResponse, String> {
         self.send_command(HelperCommand::AddRoute {
@@ -295,12 +595,22 @@ echo 'Helper installed successfully'
         })
     }
 
+    /// Remove a route
+    pub fn remove_route(&mut self, destination: &str, prefix_len: u8) -> Result<HelperResponse, String> {
+        self.send_command(HelperCommand::RemoveRoute {
+            destination: destination.to_string(),
+            prefix_len,
+        })
+    }
+
     /// Set default gateway for exit node
     /// exclude_ip: Optional IP to exclude from VPN routing (e.g., relay endpoint)
-    pub fn set_default_gateway(&mut self, gateway: &str, exclude_ip: Option<&str>) -> Result<HelperResponse, String> {
+    /// include_ipv6: Also route IPv6 traffic through the tunnel interface
+    pub fn set_default_gateway(&mut self, gateway: &str, exclude_ip: Option<&str>, include_ipv6: bool) -> Result<HelperResponse, String> {
         self.send_command(HelperCommand::SetDefaultGateway {
             gateway: gateway.to_string(),
             exclude_ip: exclude_ip.map(|s| s.to_string()),
+            include_ipv6,
         })
     }
 
@@ -309,74 +619,102 @@ echo 'Helper installed successfully'
         self.send_command(HelperCommand::RestoreDefaultGateway)
     }
 
+    /// Block port 53 everywhere except the comma-separated `allowed_dns` through the tunnel (pf anchor)
+    pub fn block_dns(&mut self, tun_name: &str, allowed_dns: &str) -> Result<HelperResponse, String> {
+        self.send_command(HelperCommand::BlockDns {
+            tun_name: tun_name.to_string(),
+            allowed_dns: allowed_dns.to_string(),
+        })
+    }
+
+    /// Remove the DNS leak block
+    pub fn unblock_dns(&mut self) -> Result<HelperResponse, String> {
+        self.send_command(HelperCommand::UnblockDns)
+    }
+
+    /// Point the system resolver at the comma-separated `servers` (scutil)
+    pub fn set_dns(&mut self, tun_name: &str, servers: &str) -> Result<HelperResponse, String> {
+        self.send_command(HelperCommand::SetDns {
+            tun_name: tun_name.to_string(),
+            servers: servers.to_string(),
+        })
+    }
+
+    /// Put back the DNS settings set_dns replaced
+    pub fn restore_dns(&mut self) -> Result<HelperResponse, String> {
+        self.send_command(HelperCommand::RestoreDns)
+    }
+
+    /// Reject internet IPv6 everywhere except through the tunnel, to `allow_ips`
+    /// and from `udp_port` (pf anchor)
+    pub fn block_ipv6(&mut self, tun_name: &str, allow_ips: &[std::net::Ipv6Addr], udp_port: Option<u16>) -> Result<HelperResponse, String> {
+        self.send_command(HelperCommand::BlockIpv6 {
+            tun_name: tun_name.to_string(),
+            allow_ips: allow_ips.iter().map(|ip| ip.to_string()).collect(),
+            udp_port,
+        })
+    }
+
+    /// Remove the IPv6 leak block
+    pub fn unblock_ipv6(&mut self) -> Result<HelperResponse, String> {
+        self.send_command(HelperCommand::UnblockIpv6)
+    }
+
+    /// Change a TUN device's MTU
+    pub fn set_mtu(&mut self, tun_name: &str, mtu: u16) -> Result<HelperResponse, String> {
+        self.send_command(HelperCommand::SetMtu {
+            tun_name: tun_name.to_string(),
+            mtu,
+        })
+    }
+
+    /// Give a TUN device an IPv6 address alongside its IPv4 one
+    pub fn set_ipv6_address(&mut self, tun_name: &str, address: &std::net::Ipv6Addr, prefix_len: u8) -> Result<HelperResponse, String> {
+        self.send_command(HelperCommand::SetIpv6Address {
+            tun_name: tun_name.to_string(),
+            address: address.to_string(),
+            prefix_len,
+        })
+    }
+
     /// Ping the helper to check if it's responsive
     pub fn ping(&mut self) -> Result<bool, String> {
         let response = self.send_command(HelperCommand::Ping)?;
         Ok(response.success && response.message == "pong")
     }
 
-    /// Get the helper version
-    pub fn get_version(&mut self) -> Result<String, String> {
-        let response = self.send_command(HelperCommand::GetVersion)?;
+    /// Per-TUN packet counters (read, written, read timeouts, short packets)
+    pub fn get_metrics(&mut self) -> Result<serde_json::Value, String> {
+        let response = self.send_command(HelperCommand::GetMetrics)?;
         if response.success {
-            Ok(response.message)
+            Ok(response.data.unwrap_or(serde_json::Value::Null))
         } else {
-            Err("Failed to get version".to_string())
+            Err(format!("Failed to get metrics: {}", response.message))
         }
     }
 
-    /// Check if helper version matches app version
-    pub fn version_matches(&mut self) -> bool {
-        match self.get_version() {
-            Ok(helper_version) => {
-                let matches = helper_version == APP_VERSION;
-                if !matches {
-                    log::info!("Helper version mismatch: helper={}, app={}", helper_version, APP_VERSION);
-                }
-                matches
-            }
-            Err(_) => {
-                // Old helper without version command - needs update
-                log::info!("Helper doesn't support version command - needs update");
-                false
-            }
+    /// Routes the helper believes it installed and hasn't removed yet
+    pub fn list_routes(&mut self) -> Result<serde_json::Value, String> {
+        let response = self.send_command(HelperCommand::ListPle7Routes)?;
+        if response.success {
+            Ok(response.data.unwrap_or(serde_json::Value::Null))
+        } else {
+            Err(format!("Failed to list routes: {}", response.message))
         }
     }
 
-    /// Get the app version (for comparison)
-    pub fn app_version() -> &'static str {
-        APP_VERSION
+    /// Delete every route the helper installed (recovery after an unclean exit)
+    pub fn flush_routes(&mut self) -> Result<HelperResponse, String> {
+        self.send_command(HelperCommand::FlushPle7Routes)
     }
 
     /// Read a packet from the TUN device
     pub fn read_packet(&mut self, tun_name: &str, timeout_ms: Option<u64>) -> Result<Option<Vec<u8>>, String> {
-        use base64::Engine as _;
-
         let response = self.send_command(HelperCommand::ReadPacket {
             tun_name: tun_name.to_string(),
             timeout_ms,
         })?;
-
-        if !response.success {
-            return Err(response.message);
-        }
-
-        // Check for timeout
-        if response.message == "timeout" {
-            return Ok(None);
-        }
-
-        // Extract packet data from response
-        if let Some(data) = response.data {
-            if let Some(packet_b64) = data.get("packet").and_then(|p| p.as_str()) {
-                let packet = base64::engine::general_purpose::STANDARD
-                    .decode(packet_b64)
-                    .map_err(|e| format!("Failed to decode packet: {}", e))?;
-                return Ok(Some(packet));
-            }
-        }
-
-        Err("No packet data in response".to_string())
+        packet_from_response(response)
     }
 
     /// Write a packet to the TUN device
@@ -403,3 +741,160 @@ impl Default for HelperClient {
         Self::new()
     }
 }
+
+/// Compare a get_version response with this app's version
+fn check_version(response: &HelperResponse) -> Result<(), PleError> {
+    if !response.success {
+        // Old helper without version command - needs update
+        return Err(PleError::HelperVersionMismatch {
+            helper: "unknown".to_string(),
+            app: APP_VERSION.to_string(),
+        });
+    }
+    if response.message != APP_VERSION {
+        return Err(PleError::HelperVersionMismatch {
+            helper: response.message.clone(),
+            app: APP_VERSION.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// The packet in a read_packet response, None if the read timed out
+fn packet_from_response(response: HelperResponse) -> Result<Option<Vec<u8>>, String> {
+    use base64::Engine as _;
+
+    if !response.success {
+        return Err(response.message);
+    }
+
+    // Check for timeout
+    if response.message == "timeout" {
+        return Ok(None);
+    }
+
+    // Extract packet data from response
+    if let Some(data) = response.data {
+        if let Some(packet_b64) = data.get("packet").and_then(|p| p.as_str()) {
+            let packet = base64::engine::general_purpose::STANDARD
+                .decode(packet_b64)
+                .map_err(|e| format!("Failed to decode packet: {}", e))?;
+            return Ok(Some(packet));
+        }
+    }
+
+    Err("No packet data in response".to_string())
+}
+
+/// Read one newline-terminated response, looping over partial reads.
+/// `timeout` covers the whole response, not each read.
+fn read_response(stream: &mut UnixStream, timeout: Duration) -> Result<String, String> {
+    let deadline = Instant::now() + timeout;
+    let mut response = Vec::new();
+    let mut chunk = [0u8; 8192];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(format!("Timed out after {:?} waiting for the helper response", timeout));
+        }
+        stream.set_read_timeout(Some(remaining))
+            .map_err(|e| format!("Failed to set read timeout: {}", e))?;
+
+        let n = match stream.read(&mut chunk) {
+            Ok(0) => return Err("Helper closed the connection before the response was complete".to_string()),
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(format!("Timed out after {:?} waiting for the helper response", timeout));
+            }
+            Err(e) => return Err(format!("Failed to read response: {}", e)),
+        };
+
+        // The helper answers one command at a time, so nothing follows the newline
+        if let Some(end) = chunk[..n].iter().position(|&b| b == b'\n') {
+            response.extend_from_slice(&chunk[..end]);
+            return String::from_utf8(response).map_err(|e| format!("Failed to read response: {}", e));
+        }
+        response.extend_from_slice(&chunk[..n]);
+        if response.len() > MAX_RESPONSE_BYTES {
+            return Err(format!("Helper response exceeded {} bytes", MAX_RESPONSE_BYTES));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version_response(success: bool, message: &str) -> HelperResponse {
+        HelperResponse { success, message: message.to_string(), data: None }
+    }
+
+    #[test]
+    fn test_repair_tolerates_unloaded_daemon() {
+        let repair = HelperClient::get_repair_script("/tmp/ple7-helper", "/tmp/helper.plist");
+        assert!(!repair.contains("exit 3"));
+        assert!(HelperClient::get_uninstall_script().contains("exit 3"));
+    }
+
+    #[test]
+    fn test_check_version_mismatch() {
+        assert!(check_version(&version_response(true, APP_VERSION)).is_ok());
+
+        let err = check_version(&version_response(true, "0.0.1")).unwrap_err();
+        assert_eq!(err.kind(), "helperVersionMismatch");
+        assert!(matches!(err, PleError::HelperVersionMismatch { ref helper, .. } if helper == "0.0.1"));
+
+        // Helpers from before get_version answer with an unknown-command error
+        let err = check_version(&version_response(false, "Unknown command")).unwrap_err();
+        assert!(matches!(err, PleError::HelperVersionMismatch { ref helper, .. } if helper == "unknown"));
+    }
+
+    #[test]
+    fn test_read_response_split_across_writes() {
+        let (mut client, mut helper) = UnixStream::pair().unwrap();
+        let writer = std::thread::spawn(move || {
+            helper.write_all(br#"{"success":true,"mess"#).unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            helper.write_all(b"age\":\"ok\",\"data\":null}\n").unwrap();
+            helper
+        });
+
+        let line = read_response(&mut client, RESPONSE_TIMEOUT).unwrap();
+        let response: HelperResponse = serde_json::from_str(&line).unwrap();
+        assert!(response.success);
+        assert_eq!(response.message, "ok");
+
+        // No newline within the timeout is an error, not a truncated response
+        let mut helper = writer.join().unwrap();
+        helper.write_all(b"{\"success\":").unwrap();
+        assert!(read_response(&mut client, Duration::from_millis(50)).is_err());
+    }
+
+    #[test]
+    fn test_read_response_max_size_packet() {
+        use base64::Engine as _;
+
+        // The largest IP packet is ~88KB of base64, far more than one socket read
+        let packet: Vec<u8> = (0..65535u32).map(|i| i as u8).collect();
+        let line = serde_json::json!({
+            "success": true,
+            "message": "ok",
+            "data": {
+                "packet": base64::engine::general_purpose::STANDARD.encode(&packet),
+                "length": packet.len(),
+            },
+        });
+
+        let (mut client, mut helper) = UnixStream::pair().unwrap();
+        let writer = std::thread::spawn(move || {
+            helper.write_all(format!("{}\n", line).as_bytes()).unwrap();
+        });
+
+        let response = read_response(&mut client, RESPONSE_TIMEOUT).unwrap();
+        writer.join().unwrap();
+        let response: HelperResponse = serde_json::from_str(&response).unwrap();
+        assert_eq!(packet_from_response(response).unwrap(), Some(packet));
+    }
+}
@@ -1,8 +1,19 @@
 // Library exports for Tauri
 pub mod api;
+pub mod capture;
 pub mod tunnel;
 pub mod config;
+pub mod dns;
+pub mod error;
+pub mod flows;
+pub mod logging;
+pub mod network_monitor;
+pub mod pmtu;
+pub mod proxy;
+pub mod qr;
+pub mod split_tunnel;
 pub mod stun;
+pub mod tls;
 pub mod tun_device;
 pub mod wireguard;
 pub mod websocket;
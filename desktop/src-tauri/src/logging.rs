@@ -0,0 +1,312 @@
+//! App logging and support bundles
+//! Logs go to stderr and to a log file, as plain text or - with PLE7_LOG_FORMAT=json -
+//! one JSON object per line. Each launch starts a new file and the previous ones are
+//! kept as `.1`, `.2`, so a crashed session's log survives the restart. Support
+//! bundles zip the logs up for attaching to issues.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::{Mutex, RwLock};
+
+/// Set to "json" to log one JSON object per line (the helper reads it too)
+pub const LOG_FORMAT_ENV: &str = "PLE7_LOG_FORMAT";
+
+/// Where launchd sends the macOS helper's output
+#[cfg(target_os = "macos")]
+const HELPER_LOG_PATH: &str = "/var/log/ple7-helper.log";
+
+/// A log file growing past this mid-session is rotated like at startup
+const MAX_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Log files kept: the current one plus `.1` and `.2`
+const KEPT_LOG_FILES: usize = 3;
+
+/// Id of the connection attempt in progress, included in every JSON log line
+static CONNECTION_ID: RwLock<Option<String>> = RwLock::new(None);
+
+struct AppLogger {
+    json: bool,
+    file: Mutex<Option<LogFile>>,
+}
+
+/// The current log file and how much has gone into it
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+}
+
+impl LogFile {
+    /// Rotate the existing files and start a new one with a session start marker
+    fn open(path: &Path) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        rotate_files(path);
+        // Left by versions that kept a single backup
+        let _ = fs::remove_file(path.with_extension("log.old"));
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut log_file = Self { path: path.to_path_buf(), file, written: 0 };
+        // Written directly - release builds only log errors
+        log_file.write_line(&format!(
+            "===== PLE7 VPN {} session start, pid {}, ts_ms {} =====",
+            env!("CARGO_PKG_VERSION"), std::process::id(), unix_millis()));
+        Ok(log_file)
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if writeln!(self.file, "{}", line).is_ok() {
+            self.written += line.len() as u64 + 1;
+        }
+        if self.written > MAX_LOG_BYTES {
+            match Self::open(&self.path) {
+                Ok(next) => *self = next,
+                Err(e) => eprintln!("Failed to rotate log file {}: {}", self.path.display(), e),
+            }
+        }
+    }
+}
+
+/// `path.N` for the Nth previous log
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Shift `path` to `path.1`, `path.1` to `path.2` and so on, dropping the oldest
+fn rotate_files(path: &Path) {
+    for n in (1..KEPT_LOG_FILES).rev() {
+        let from = if n == 1 { path.to_path_buf() } else { numbered(path, n - 1) };
+        if from.exists() {
+            let _ = fs::rename(&from, numbered(path, n));
+        }
+    }
+}
+
+impl log::Log for AppLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // In release: only errors. In debug: info and above
+        #[cfg(debug_assertions)]
+        { metadata.level() <= log::Level::Info }
+        #[cfg(not(debug_assertions))]
+        { metadata.level() <= log::Level::Error }
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = if self.json {
+            json_line(record)
+        } else {
+            text_line(record)
+        };
+        eprintln!("{}", line);
+        if let Some(file) = self.file.lock().as_mut() {
+            file.write_line(&line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(log_file) = self.file.lock().as_mut() {
+            let _ = log_file.file.flush();
+        }
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+fn text_line(record: &log::Record) -> String {
+    match CONNECTION_ID.read().as_deref() {
+        Some(id) => format!("[{}] [conn {}] {}", record.level(), id, record.args()),
+        None => format!("[{}] {}", record.level(), record.args()),
+    }
+}
+
+fn json_line(record: &log::Record) -> String {
+    serde_json::json!({
+        "ts_ms": unix_millis(),
+        "level": record.level().as_str(),
+        "module": record.target(),
+        "message": record.args().to_string(),
+        "conn_id": *CONNECTION_ID.read(),
+    })
+    .to_string()
+}
+
+/// Whether PLE7_LOG_FORMAT asks for JSON logs
+pub fn json_requested() -> bool {
+    std::env::var(LOG_FORMAT_ENV).is_ok_and(|format| format.eq_ignore_ascii_case("json"))
+}
+
+/// Where the app log file lives
+pub fn log_path() -> Option<PathBuf> {
+    #[cfg(target_os = "macos")]
+    { std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Logs/ple7-vpn.log")) }
+
+    #[cfg(target_os = "linux")]
+    {
+        std::env::var_os("XDG_STATE_HOME").map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+            .map(|dir| dir.join("ple7").join("ple7-vpn.log"))
+    }
+
+    #[cfg(target_os = "windows")]
+    { std::env::var_os("LOCALAPPDATA").map(|dir| PathBuf::from(dir).join("PLE7").join("ple7-vpn.log")) }
+}
+
+/// Install the app logger. Logging to the file is skipped if it can't be opened.
+pub fn init() -> Result<(), log::SetLoggerError> {
+    let file = log_path().and_then(|path| match LogFile::open(&path) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            eprintln!("Failed to open log file {}: {}", path.display(), e);
+            None
+        }
+    });
+
+    log::set_boxed_logger(Box::new(AppLogger {
+        json: json_requested(),
+        file: Mutex::new(file),
+    }))?;
+
+    #[cfg(debug_assertions)]
+    { log::set_max_level(log::LevelFilter::Info) }
+    #[cfg(not(debug_assertions))]
+    { log::set_max_level(log::LevelFilter::Error) }
+    Ok(())
+}
+
+/// Start tagging log lines with a fresh connection id, returning it
+pub fn start_connection() -> String {
+    let id = format!("{:016x}", rand::random::<u64>());
+    *CONNECTION_ID.write() = Some(id.clone());
+    id
+}
+
+/// The id of the connection currently being logged, if any
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+pub fn connection_id() -> Option<String> {
+    CONNECTION_ID.read().clone()
+}
+
+/// Stop tagging log lines with a connection id
+pub fn end_connection() {
+    *CONNECTION_ID.write() = None;
+}
+
+/// Zip the app log, the helper log and the given helper metrics into `dest`
+pub fn write_support_bundle(dest: &Path, helper_metrics: Option<serde_json::Value>) -> Result<(), String> {
+    use zip::write::SimpleFileOptions;
+
+    log::logger().flush();
+
+    let file = File::create(dest)
+        .map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let mut add = |name: &str, contents: &[u8]| -> Result<(), String> {
+        zip.start_file(name, options)
+            .and_then(|_| zip.write_all(contents).map_err(Into::into))
+            .map_err(|e| format!("Failed to add {} to the bundle: {}", name, e))
+    };
+
+    let about = serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "created_ms": unix_millis(),
+        "json_logs": json_requested(),
+    });
+    add("about.json", serde_json::to_string_pretty(&about).unwrap_or_default().as_bytes())?;
+
+    // A missing log isn't fatal - the rest of the bundle is still useful. The
+    // previous session's log is what explains a crash.
+    let logs = [
+        ("ple7-vpn.log", log_path()),
+        ("ple7-vpn.1.log", log_path().map(|path| numbered(&path, 1))),
+        #[cfg(target_os = "macos")]
+        ("ple7-helper.log", Some(PathBuf::from(HELPER_LOG_PATH))),
+        #[cfg(target_os = "macos")]
+        ("ple7-helper.1.log", Some(numbered(Path::new(HELPER_LOG_PATH), 1))),
+    ];
+    for (name, path) in logs {
+        match path.map(fs::read) {
+            Some(Ok(contents)) => add(name, &contents)?,
+            Some(Err(e)) => log::warn!("[SUPPORT] Skipping {}: {}", name, e),
+            None => log::warn!("[SUPPORT] Skipping {}: no log location", name),
+        }
+    }
+
+    if let Some(metrics) = helper_metrics {
+        add("helper-metrics.json", serde_json::to_string_pretty(&metrics).unwrap_or_default().as_bytes())?;
+    }
+
+    zip.finish().map_err(|e| format!("Failed to finish {}: {}", dest.display(), e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_line() {
+        *CONNECTION_ID.write() = Some("abc123".to_string());
+        let record = log::Record::builder()
+            .args(format_args!("Tunnel \"up\""))
+            .level(log::Level::Warn)
+            .target("ple7::tunnel")
+            .build();
+        let line = json_line(&record);
+        assert_eq!(text_line(&record), "[WARN] [conn abc123] Tunnel \"up\"");
+        end_connection();
+        assert_eq!(text_line(&record), "[WARN] Tunnel \"up\"");
+
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["module"], "ple7::tunnel");
+        assert_eq!(value["message"], "Tunnel \"up\"");
+        assert_eq!(value["conn_id"], "abc123");
+        assert!(value["ts_ms"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_log_rotation() {
+        let dir = std::env::temp_dir().join(format!("ple7-log-test-{}", std::process::id()));
+        let path = dir.join("ple7-vpn.log");
+        for session in 0..4 {
+            let mut log_file = LogFile::open(&path).unwrap();
+            log_file.write_line(&format!("session {}", session));
+        }
+
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert!(read(path.clone()).starts_with("===== PLE7 VPN"));
+        assert!(read(path.clone()).ends_with("session 3\n"));
+        assert!(read(numbered(&path, 1)).ends_with("session 2\n"));
+        assert!(read(numbered(&path, 2)).ends_with("session 1\n"));
+        assert!(!numbered(&path, 3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_support_bundle() {
+        let dest = std::env::temp_dir().join(format!("ple7-bundle-test-{}.zip", std::process::id()));
+        write_support_bundle(&dest, Some(serde_json::json!({ "utun3": { "read": 5 } }))).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&dest).unwrap()).unwrap();
+        assert!(archive.by_name("about.json").is_ok());
+        let mut metrics = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("helper-metrics.json").unwrap(), &mut metrics).unwrap();
+        assert!(metrics.contains("utun3"));
+        fs::remove_file(&dest).unwrap();
+    }
+}
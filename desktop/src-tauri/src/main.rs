@@ -2,9 +2,20 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod api;
+mod capture;
 mod tunnel;
 mod config;
+mod dns;
+mod error;
+mod flows;
+mod logging;
+mod network_monitor;
+mod pmtu;
+mod proxy;
+mod qr;
+mod split_tunnel;
 mod stun;
+mod tls;
 mod tun_device;
 mod wireguard;
 mod websocket;
@@ -17,44 +28,14 @@ use tauri::{Manager, Emitter};
 use tokio::sync::Mutex;
 use tunnel::{TunnelManager, AppState};
 
-/// Minimal logger - only prints errors to stderr in release builds
-struct MinimalLogger;
-
-impl log::Log for MinimalLogger {
-    fn enabled(&self, metadata: &log::Metadata) -> bool {
-        // In release: only errors. In debug: info and above
-        #[cfg(debug_assertions)]
-        { metadata.level() <= log::Level::Info }
-        #[cfg(not(debug_assertions))]
-        { metadata.level() <= log::Level::Error }
-    }
-
-    fn log(&self, record: &log::Record) {
-        if self.enabled(record.metadata()) {
-            eprintln!("[{}] {}", record.level(), record.args());
-        }
-    }
-
-    fn flush(&self) {}
-}
-
-static LOGGER: MinimalLogger = MinimalLogger;
-
 fn main() {
     // Set up panic hook
     std::panic::set_hook(Box::new(|panic_info| {
         eprintln!("PANIC: {}", panic_info);
     }));
 
-    // Initialize minimal logging
-    log::set_logger(&LOGGER)
-        .map(|()| {
-            #[cfg(debug_assertions)]
-            { log::set_max_level(log::LevelFilter::Info) }
-            #[cfg(not(debug_assertions))]
-            { log::set_max_level(log::LevelFilter::Error) }
-        })
-        .expect("Failed to set logger");
+    // Log to stderr and the log file (text, or JSON with PLE7_LOG_FORMAT=json)
+    logging::init().expect("Failed to set logger");
 
     log::info!("Starting PLE7 VPN...");
 
@@ -79,12 +60,51 @@ fn main() {
             }
 
             // Initialize app state
-            let tunnel_manager = Arc::new(Mutex::new(TunnelManager::new()));
-            let api_client = api::ApiClient::new("https://ple7.com".to_string());
+            proxy::set_configured(config::get_proxy_internal(app.handle()));
+            let tls = config::get_tls_settings_internal(app.handle()).unwrap_or_else(|e| {
+                log::error!("Failed to load TLS settings, using defaults: {}", e);
+                tls::TlsSettings::default()
+            });
+            // Never fall back to unpinned TLS - requests fail until the settings are fixed
+            let api_client = api::ApiClient::with_tls("https://ple7.com".to_string(), &tls)
+                .unwrap_or_else(|e| {
+                    log::error!("Failed to apply TLS settings, refusing API requests: {}", e);
+                    api::ApiClient::refusing("https://ple7.com".to_string(), &e)
+                });
+            let tunnel_manager = Arc::new(Mutex::new(
+                TunnelManager::new()
+                    .with_tls(tls.clone())
+                    .with_app_handle(app.handle().clone()),
+            ));
+
+            // Rebind last run's WireGuard port so peers' NAT mappings survive a restart
+            if let Some(port) = config::get_listen_port_internal(app.handle()) {
+                wireguard::set_preferred_listen_port(port);
+            }
+            if let Some(bytes) = config::get_socket_buffer_size_internal(app.handle()) {
+                wireguard::set_socket_buffer_size(bytes);
+            }
+            if let Some(bytes) = config::get_ring_capacity_internal(app.handle()) {
+                tun_device::set_ring_capacity(bytes);
+            }
+            flows::set_sample_rate(config::get_flow_sample_rate_internal(app.handle()));
+            stun::set_server_rtts(config::get_stun_rtts_internal(app.handle()));
+            if let Some(preferred) = config::get_preferred_stun_server_internal(app.handle()) {
+                stun::set_preferred_server(Some(preferred));
+            }
 
             app.manage(AppState {
                 tunnel_manager,
                 api_client,
+                tls,
+            });
+
+            // Clean up routes from an unclean exit, then reconnect to the last session
+            // if the user enabled auto-connect
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                tunnel::recover_stale_routes().await;
+                tunnel::auto_connect(handle).await;
             });
 
             // Check for deep link URL in command line args (Windows startup case)
@@ -107,18 +127,83 @@ fn main() {
             api::login,
             api::verify_token,
             api::get_networks,
+            api::create_network,
+            api::delete_network,
             api::get_devices,
             api::get_device_config,
             api::get_relays,
+            api::measure_relays,
             api::auto_register_device,
             api::set_exit_node,
+            api::get_exit_node,
+            api::delete_device,
+            api::rename_device,
             config::store_token,
             config::get_stored_token,
             config::clear_stored_token,
+            config::get_auto_connect,
+            config::set_auto_connect,
+            config::get_tls_settings,
+            config::set_tls_settings,
+            config::get_power_profile,
+            config::set_power_profile,
+            config::get_block_ipv6_leaks,
+            config::set_block_ipv6_leaks,
+            config::get_relay_only,
+            config::set_relay_only,
+            config::get_proxy,
+            config::set_proxy,
+            config::get_socket_buffer_size,
+            config::set_socket_buffer_size,
+            config::get_preferred_stun_server,
+            config::set_preferred_stun_server,
+            config::get_ring_capacity,
+            config::set_ring_capacity,
+            config::get_flow_sample_rate,
+            config::set_flow_sample_rate,
             tunnel::connect_vpn,
             tunnel::disconnect_vpn,
+            tunnel::force_reset,
+            tunnel::pause_vpn,
+            tunnel::resume_vpn,
             tunnel::get_connection_status,
             tunnel::get_connection_stats,
+            tunnel::get_detailed_stats,
+            tunnel::get_throughput_history,
+            tunnel::is_traffic_flowing,
+            tunnel::get_tunnel_health,
+            tunnel::get_protocol_stats,
+            tunnel::export_wg_config,
+            tunnel::get_device_public_key,
+            tunnel::rotate_device_keys,
+            tunnel::add_peer,
+            tunnel::remove_peer,
+            tunnel::update_peer_psk,
+            tunnel::set_peer_endpoint,
+            tunnel::generate_preshared_key,
+            tunnel::verify_config,
+            tunnel::import_config_from_qr,
+            tunnel::set_packet_capture,
+            tunnel::get_active_flows,
+            tunnel::get_active_dns,
+            tunnel::test_connectivity,
+            tunnel::set_default_gateway_manual,
+            tunnel::restore_default_gateway_manual,
+            tunnel::connect_best_exit,
+            tunnel::switch_network,
+            tunnel::get_capabilities,
+            tunnel::get_peers,
+            tunnel::get_devices_enriched,
+            tunnel::add_split_app,
+            tunnel::remove_split_app,
+            tunnel::refresh_stun_cache,
+            tunnel::set_stun_cache_ttl,
+            tunnel::check_prerequisites,
+            tunnel::uninstall_helper,
+            tunnel::repair_helper,
+            tunnel::get_helper_metrics,
+            tunnel::collect_support_bundle,
+            tunnel::list_helper_routes,
         ])
         .run(tauri::generate_context!());
 
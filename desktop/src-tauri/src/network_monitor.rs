@@ -0,0 +1,212 @@
+//! OS network change notifications
+//! A background thread watches interfaces and addresses come and go (Wi-Fi
+//! switches, wake from sleep) so the tunnel can refresh its NAT mapping instead
+//! of silently dying.
+
+use std::sync::OnceLock;
+
+use parking_lot::RwLock;
+use tokio::sync::broadcast;
+
+#[cfg(target_os = "linux")]
+use nix::libc;
+
+/// Fires once per interface or address change
+static CHANGES: OnceLock<broadcast::Sender<()>> = OnceLock::new();
+
+/// Our own TUN device - its address and MTU changes aren't network changes
+static IGNORED_INTERFACE: RwLock<Option<String>> = RwLock::new(None);
+
+/// Receive network changes, starting the watcher thread on first use
+pub fn subscribe() -> broadcast::Receiver<()> {
+    CHANGES.get_or_init(|| {
+        let (tx, _) = broadcast::channel(64);
+        let sender = tx.clone();
+        let spawned = std::thread::Builder::new()
+            .name("network-monitor".to_string())
+            .spawn(move || {
+                log::info!("[NETMON] Watching for network changes");
+                if let Err(e) = sys::watch(|interface| notify(&sender, interface)) {
+                    log::warn!("[NETMON] Network change detection stopped: {}", e);
+                }
+            });
+        if let Err(e) = spawned {
+            log::warn!("[NETMON] Failed to start network monitor: {}", e);
+        }
+        tx
+    }).subscribe()
+}
+
+/// Don't report changes on `name` (the tunnel's own interface)
+pub fn set_ignored_interface(name: Option<&str>) {
+    *IGNORED_INTERFACE.write() = name.map(str::to_string);
+}
+
+/// `interface` is None when the OS doesn't say which one changed
+fn notify(tx: &broadcast::Sender<()>, interface: Option<String>) {
+    if interface.is_some() && *IGNORED_INTERFACE.read() == interface {
+        return;
+    }
+    log::info!("[NETMON] Network change on {}", interface.as_deref().unwrap_or("an unknown interface"));
+    // No subscribers just means nothing is connected
+    let _ = tx.send(());
+}
+
+/// Name of the interface with `index`, None if it no longer exists
+#[cfg(unix)]
+fn interface_name(index: u32) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    let name = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+    if name.is_null() {
+        return None;
+    }
+    unsafe { std::ffi::CStr::from_ptr(name) }.to_str().ok().map(str::to_string)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::os::fd::AsRawFd;
+
+    use nix::errno::Errno;
+    use nix::libc;
+    use nix::sys::socket::{bind, recv, socket, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType};
+
+    /// Listen for link and address messages on a netlink route socket
+    pub fn watch(mut on_change: impl FnMut(Option<String>)) -> Result<(), String> {
+        let fd = socket(AddressFamily::Netlink, SockType::Raw, SockFlag::SOCK_CLOEXEC, SockProtocol::NetlinkRoute)
+            .map_err(|e| format!("Failed to open netlink socket: {}", e))?;
+        let groups = (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, groups))
+            .map_err(|e| format!("Failed to subscribe to netlink groups: {}", e))?;
+
+        let mut buf = vec![0u8; 16 * 1024];
+        loop {
+            let len = match recv(fd.as_raw_fd(), &mut buf, MsgFlags::empty()) {
+                Ok(len) => len,
+                Err(Errno::EINTR) => continue,
+                // The kernel dropped messages - something changed, we just don't know what
+                Err(Errno::ENOBUFS) => {
+                    on_change(None);
+                    continue;
+                }
+                Err(e) => return Err(format!("Failed to read netlink socket: {}", e)),
+            };
+            for index in changed_interfaces(&buf[..len]) {
+                on_change(super::interface_name(index));
+            }
+        }
+    }
+
+    /// Interface index of each link/address message in a netlink datagram.
+    /// ifinfomsg and ifaddrmsg both keep the index 4 bytes into the payload.
+    pub(super) fn changed_interfaces(mut data: &[u8]) -> Vec<u32> {
+        const HEADER_LEN: usize = 16;
+        let mut indexes = Vec::new();
+
+        while data.len() >= HEADER_LEN {
+            let len = u32::from_ne_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            let kind = u16::from_ne_bytes([data[4], data[5]]);
+            if len < HEADER_LEN || len > data.len() {
+                break;
+            }
+            let relevant = matches!(kind, libc::RTM_NEWLINK | libc::RTM_DELLINK | libc::RTM_NEWADDR | libc::RTM_DELADDR);
+            if relevant && len >= HEADER_LEN + 8 {
+                indexes.push(u32::from_ne_bytes([data[20], data[21], data[22], data[23]]));
+            }
+            // Messages are 4-byte aligned
+            data = &data[((len + 3) & !3).min(data.len())..];
+        }
+
+        indexes
+    }
+}
+
+/// Uses a PF_ROUTE socket rather than SCNetworkReachability: reachability only
+/// reports flag flips, so a move between two working Wi-Fi networks goes unseen
+#[cfg(target_os = "macos")]
+mod sys {
+    use std::io::Read;
+    use std::os::fd::FromRawFd;
+
+    pub fn watch(mut on_change: impl FnMut(Option<String>)) -> Result<(), String> {
+        let fd = unsafe { libc::socket(libc::PF_ROUTE, libc::SOCK_RAW, libc::AF_UNSPEC) };
+        if fd < 0 {
+            return Err(format!("Failed to open routing socket: {}", std::io::Error::last_os_error()));
+        }
+        let mut socket = unsafe { std::fs::File::from_raw_fd(fd) };
+
+        // Each read returns exactly one routing message
+        let mut buf = vec![0u8; 8 * 1024];
+        loop {
+            let len = match socket.read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("Failed to read routing socket: {}", e)),
+            };
+            if let Some(index) = changed_interface(&buf[..len]) {
+                on_change(super::interface_name(index));
+            }
+        }
+    }
+
+    /// Interface index of an interface/address message. Route messages (our own
+    /// route changes included) are skipped. if_msghdr and ifa_msghdr share the
+    /// layout up to the index: msglen u16, version u8, type u8, addrs i32, flags i32, index u16.
+    fn changed_interface(msg: &[u8]) -> Option<u32> {
+        if msg.len() < 14 {
+            return None;
+        }
+        match msg[3] as libc::c_int {
+            libc::RTM_IFINFO | libc::RTM_NEWADDR | libc::RTM_DELADDR => {
+                Some(u16::from_ne_bytes([msg[12], msg[13]]) as u32)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod sys {
+    use windows::Win32::NetworkManagement::IpHelper::NotifyAddrChange;
+
+    /// NotifyAddrChange doesn't say which adapter changed
+    pub fn watch(mut on_change: impl FnMut(Option<String>)) -> Result<(), String> {
+        loop {
+            // Without a handle or OVERLAPPED this blocks until an IPv4 address changes
+            let result = unsafe { NotifyAddrChange(std::ptr::null_mut(), std::ptr::null()) };
+            if result != 0 {
+                return Err(format!("NotifyAddrChange failed: {}", result));
+            }
+            on_change(None);
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    fn netlink_message(kind: u16, index: u32) -> Vec<u8> {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&26u32.to_ne_bytes()); // unaligned length, padded below
+        msg.extend_from_slice(&kind.to_ne_bytes());
+        msg.extend_from_slice(&[0; 10]);
+        msg.extend_from_slice(&[libc::AF_INET as u8, 24, 0, 0]);
+        msg.extend_from_slice(&index.to_ne_bytes());
+        msg.extend_from_slice(&[0; 2]);
+        msg.resize(28, 0);
+        msg
+    }
+
+    #[test]
+    fn test_netlink_changed_interfaces() {
+        let mut datagram = netlink_message(libc::RTM_NEWADDR, 3);
+        datagram.extend(netlink_message(libc::RTM_NEWROUTE, 4));
+        datagram.extend(netlink_message(libc::RTM_DELLINK, 5));
+        assert_eq!(sys::changed_interfaces(&datagram), vec![3, 5]);
+
+        // A truncated trailing message is ignored
+        datagram.extend_from_slice(&40u32.to_ne_bytes());
+        assert_eq!(sys::changed_interfaces(&datagram), vec![3, 5]);
+    }
+}
@@ -0,0 +1,339 @@
+//! Path MTU discovery for the tunnel
+//! Sends ICMP echoes through the tunnel with the Don't Fragment bit set on the
+//! outer UDP packets, and binary-searches the largest one that round-trips.
+//! Nested tunnels and PPPoE links black-hole anything bigger without telling us.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+
+/// Smallest MTU the probe will settle on (the IPv6 minimum link MTU)
+pub const MIN_TUNNEL_MTU: u16 = 1280;
+
+/// How long to wait for each echo reply
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(800);
+
+/// Probes sent per size before it's considered too big
+pub const PROBE_ATTEMPTS: usize = 2;
+
+/// The search stops once the working and failing sizes are this close
+const PROBE_GRANULARITY: u16 = 8;
+
+const IPV4_HEADER_LEN: usize = 20;
+const ICMP_HEADER_LEN: usize = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const PROTO_ICMP: u8 = 1;
+
+/// Discovered MTU per peer endpoint, so reconnects skip the probe
+static MTU_CACHE: Mutex<Vec<(SocketAddr, u16)>> = Mutex::new(Vec::new());
+
+/// Checked per received packet so the data path only takes the lock while probing
+static PROBING: AtomicBool = AtomicBool::new(false);
+
+/// Echo reply the running probe is waiting for: (identifier, sequence, waiter)
+static PENDING: Mutex<Option<(u16, u16, oneshot::Sender<()>)>> = Mutex::new(None);
+
+/// MTU previously discovered for this endpoint
+pub fn cached(endpoint: SocketAddr) -> Option<u16> {
+    MTU_CACHE.lock()
+        .iter()
+        .find(|(addr, _)| *addr == endpoint)
+        .map(|(_, mtu)| *mtu)
+}
+
+/// Remember the MTU discovered for this endpoint
+pub fn remember(endpoint: SocketAddr, mtu: u16) {
+    let mut cache = MTU_CACHE.lock();
+    cache.retain(|(addr, _)| *addr != endpoint);
+    cache.push((endpoint, mtu));
+}
+
+/// Address inside the tunnel to send probes to: a host route from the peer's
+/// AllowedIPs, otherwise the first host of its first network (usually the relay)
+pub fn probe_target(allowed_ips: &[(Ipv4Addr, u8)], own_address: Ipv4Addr) -> Option<Ipv4Addr> {
+    if let Some((addr, _)) = allowed_ips.iter().find(|(addr, prefix)| *prefix == 32 && *addr != own_address) {
+        return Some(*addr);
+    }
+
+    allowed_ips.iter()
+        .filter(|(_, prefix)| *prefix > 0 && *prefix < 31)
+        .map(|(addr, prefix)| {
+            let mask = u32::MAX << (32 - prefix);
+            Ipv4Addr::from((u32::from(*addr) & mask) + 1)
+        })
+        .find(|addr| *addr != own_address)
+}
+
+/// Binary search over tunnel MTUs. `good` is known to get through, `bad` is
+/// known (or assumed) not to.
+#[derive(Debug, Clone, Copy)]
+pub struct MtuSearch {
+    good: u16,
+    bad: u16,
+}
+
+impl MtuSearch {
+    pub fn new(good: u16, bad: u16) -> Self {
+        Self { good, bad }
+    }
+
+    /// Next size to probe, None once the search has converged
+    pub fn next(&self) -> Option<u16> {
+        if self.bad.saturating_sub(self.good) <= PROBE_GRANULARITY {
+            return None;
+        }
+        Some(self.good + (self.bad - self.good) / 2)
+    }
+
+    /// Record the outcome of probing `size`
+    pub fn record(&mut self, size: u16, got_through: bool) {
+        if got_through {
+            self.good = self.good.max(size);
+        } else {
+            self.bad = self.bad.min(size);
+        }
+    }
+
+    /// Largest size known to get through
+    pub fn result(&self) -> u16 {
+        self.good
+    }
+}
+
+/// Internet checksum (RFC 1071)
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data.chunks(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// IPv4 ICMP echo request padded to exactly `total_len` bytes
+pub fn echo_request(src: Ipv4Addr, dst: Ipv4Addr, ident: u16, seq: u16, total_len: u16) -> Vec<u8> {
+    let total = (total_len as usize).max(IPV4_HEADER_LEN + ICMP_HEADER_LEN);
+    let mut packet = vec![0u8; total];
+
+    packet[0] = 0x45; // version 4, 20-byte header
+    packet[2..4].copy_from_slice(&(total as u16).to_be_bytes());
+    packet[6] = 0x40; // Don't Fragment
+    packet[8] = 64; // TTL
+    packet[9] = PROTO_ICMP;
+    packet[12..16].copy_from_slice(&src.octets());
+    packet[16..20].copy_from_slice(&dst.octets());
+    let header_sum = checksum(&packet[..IPV4_HEADER_LEN]);
+    packet[10..12].copy_from_slice(&header_sum.to_be_bytes());
+
+    let icmp = &mut packet[IPV4_HEADER_LEN..];
+    icmp[0] = ICMP_ECHO_REQUEST;
+    icmp[4..6].copy_from_slice(&ident.to_be_bytes());
+    icmp[6..8].copy_from_slice(&seq.to_be_bytes());
+    for (i, byte) in icmp[ICMP_HEADER_LEN..].iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let icmp_sum = checksum(icmp);
+    icmp[2..4].copy_from_slice(&icmp_sum.to_be_bytes());
+
+    packet
+}
+
+/// Whether `packet` is the IPv4 echo reply for (ident, seq)
+pub fn is_echo_reply(packet: &[u8], ident: u16, seq: u16) -> bool {
+    if packet.len() < IPV4_HEADER_LEN + ICMP_HEADER_LEN || packet[0] >> 4 != 4 || packet[9] != PROTO_ICMP {
+        return false;
+    }
+    let header_len = ((packet[0] & 0x0f) as usize) * 4;
+    let Some(icmp) = packet.get(header_len..) else {
+        return false;
+    };
+    icmp.len() >= ICMP_HEADER_LEN
+        && icmp[0] == ICMP_ECHO_REPLY
+        && u16::from_be_bytes([icmp[4], icmp[5]]) == ident
+        && u16::from_be_bytes([icmp[6], icmp[7]]) == seq
+}
+
+/// Wait for the echo reply to (ident, seq). Replaces any earlier wait.
+pub fn expect_reply(ident: u16, seq: u16) -> oneshot::Receiver<()> {
+    let (tx, rx) = oneshot::channel();
+    *PENDING.lock() = Some((ident, seq, tx));
+    PROBING.store(true, Ordering::SeqCst);
+    rx
+}
+
+/// Stop waiting for probe replies
+pub fn cancel_reply() {
+    PROBING.store(false, Ordering::SeqCst);
+    PENDING.lock().take();
+}
+
+/// Called with every decrypted packet. Returns true if it was a probe reply,
+/// which is consumed here rather than written to the TUN device.
+pub fn intercept(packet: &[u8]) -> bool {
+    if !PROBING.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    let mut pending = PENDING.lock();
+    let matched = matches!(pending.as_ref(), Some((ident, seq, _)) if is_echo_reply(packet, *ident, *seq));
+    if matched {
+        PROBING.store(false, Ordering::SeqCst);
+        if let Some((_, _, tx)) = pending.take() {
+            let _ = tx.send(());
+        }
+    }
+    matched
+}
+
+/// Set or clear Don't Fragment on outgoing packets from `socket`.
+/// Dual-stack sockets get both the IPv4 and IPv6 option.
+pub fn set_dont_fragment(socket: &tokio::net::UdpSocket, enabled: bool) -> std::io::Result<()> {
+    let ipv6 = socket.local_addr()?.is_ipv6();
+    sys::set_dont_fragment(socket, ipv6, enabled)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use nix::libc;
+    use std::os::fd::AsRawFd;
+
+    fn set_int(socket: &impl AsRawFd, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> std::io::Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+    }
+
+    pub fn set_dont_fragment(socket: &impl AsRawFd, ipv6: bool, enabled: bool) -> std::io::Result<()> {
+        // PROBE sets DF without clamping to the kernel's cached path MTU, so
+        // oversized probes actually go out; WANT is the kernel default
+        let (v4, v6) = if enabled {
+            (libc::IP_PMTUDISC_PROBE, libc::IPV6_PMTUDISC_PROBE)
+        } else {
+            (libc::IP_PMTUDISC_WANT, libc::IPV6_PMTUDISC_WANT)
+        };
+        if ipv6 {
+            set_int(socket, libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, v6)?;
+            // IPv4-mapped destinations on a dual-stack socket use the IPv4 option
+            let _ = set_int(socket, libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, v4);
+            Ok(())
+        } else {
+            set_int(socket, libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, v4)
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use std::os::fd::AsRawFd;
+
+    /// From <netinet6/in6.h>, not exported by libc for Apple targets
+    const IPV6_DONTFRAG: libc::c_int = 62;
+
+    fn set_int(socket: &impl AsRawFd, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> std::io::Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+    }
+
+    pub fn set_dont_fragment(socket: &impl AsRawFd, ipv6: bool, enabled: bool) -> std::io::Result<()> {
+        let value = enabled as libc::c_int;
+        if ipv6 {
+            set_int(socket, libc::IPPROTO_IPV6, IPV6_DONTFRAG, value)?;
+            let _ = set_int(socket, libc::IPPROTO_IP, libc::IP_DONTFRAG, value);
+            Ok(())
+        } else {
+            set_int(socket, libc::IPPROTO_IP, libc::IP_DONTFRAG, value)
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod sys {
+    use std::os::windows::io::AsRawSocket;
+    use windows::Win32::Networking::WinSock::{
+        setsockopt, IPPROTO_IP, IPPROTO_IPV6, IPV6_DONTFRAG, IP_DONTFRAGMENT, SOCKET,
+    };
+
+    fn set_int(socket: &impl AsRawSocket, level: i32, name: i32, value: i32) -> std::io::Result<()> {
+        let socket = SOCKET(socket.as_raw_socket() as usize);
+        let ret = unsafe { setsockopt(socket, level, name, Some(&value.to_ne_bytes())) };
+        if ret == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+    }
+
+    pub fn set_dont_fragment(socket: &impl AsRawSocket, ipv6: bool, enabled: bool) -> std::io::Result<()> {
+        let value = enabled as i32;
+        if ipv6 {
+            set_int(socket, IPPROTO_IPV6.0, IPV6_DONTFRAG, value)?;
+            let _ = set_int(socket, IPPROTO_IP.0, IP_DONTFRAGMENT, value);
+            Ok(())
+        } else {
+            set_int(socket, IPPROTO_IP.0, IP_DONTFRAGMENT, value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mtu_search_converges() {
+        // Path carries 1372-byte tunnel packets and nothing bigger
+        let path_mtu = 1372;
+        let mut search = MtuSearch::new(MIN_TUNNEL_MTU, 1420);
+        let mut probes = 0;
+        while let Some(size) = search.next() {
+            search.record(size, size <= path_mtu);
+            probes += 1;
+        }
+        assert!(search.result() <= path_mtu);
+        assert!(path_mtu - search.result() <= PROBE_GRANULARITY);
+        assert!(probes <= 5, "took {} probes", probes);
+    }
+
+    #[test]
+    fn test_echo_request_roundtrip() {
+        let src = Ipv4Addr::new(10, 100, 0, 5);
+        let dst = Ipv4Addr::new(10, 100, 0, 1);
+        let mut packet = echo_request(src, dst, 0x1234, 7, 1300);
+        assert_eq!(packet.len(), 1300);
+        assert_eq!(checksum(&packet[..IPV4_HEADER_LEN]), 0);
+        assert_eq!(checksum(&packet[IPV4_HEADER_LEN..]), 0);
+        assert!(!is_echo_reply(&packet, 0x1234, 7));
+
+        // What the peer sends back: same ICMP body with type 0
+        packet[IPV4_HEADER_LEN] = ICMP_ECHO_REPLY;
+        assert!(is_echo_reply(&packet, 0x1234, 7));
+        assert!(!is_echo_reply(&packet, 0x1234, 8));
+    }
+
+    #[test]
+    fn test_probe_target() {
+        let own = Ipv4Addr::new(10, 100, 0, 5);
+        assert_eq!(probe_target(&[(Ipv4Addr::new(10, 100, 0, 0), 16)], own), Some(Ipv4Addr::new(10, 100, 0, 1)));
+        assert_eq!(
+            probe_target(&[(Ipv4Addr::new(10, 100, 0, 0), 16), (Ipv4Addr::new(10, 100, 0, 9), 32)], own),
+            Some(Ipv4Addr::new(10, 100, 0, 9)),
+        );
+        assert_eq!(probe_target(&[(Ipv4Addr::new(0, 0, 0, 0), 0)], own), None);
+    }
+}
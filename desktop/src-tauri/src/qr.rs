@@ -0,0 +1,76 @@
+//! Reading WireGuard configs from QR code images
+//! Mobile apps and dashboards hand configs out as QR codes; this decodes a
+//! screenshot or photo of one back to the config text.
+
+use std::path::Path;
+
+use crate::error::PleError;
+
+/// Text of the single QR code in the image at `path`
+pub fn read_config(path: &Path) -> Result<String, PleError> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| PleError::InvalidImage(format!("Failed to read {}: {}", path.display(), e)))?;
+    decode(&bytes)
+}
+
+/// Text of the single QR code in an encoded PNG or JPEG image
+pub fn decode(bytes: &[u8]) -> Result<String, PleError> {
+    let image = image::load_from_memory(bytes)
+        .map_err(|e| PleError::InvalidImage(format!("Not a PNG or JPEG image: {}", e)))?
+        .to_luma8();
+
+    let mut prepared = rqrr::PreparedImage::prepare(image);
+    let grids = prepared.detect_grids();
+    if grids.is_empty() {
+        return Err(PleError::NoQrCode);
+    }
+
+    let mut contents: Vec<String> = Vec::new();
+    let mut last_error = None;
+    for grid in &grids {
+        match grid.decode() {
+            Ok((_, text)) if !contents.contains(&text) => contents.push(text),
+            Ok(_) => {}
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    match contents.len() {
+        0 => Err(PleError::Parse(format!(
+            "QR code couldn't be decoded: {}",
+            last_error.map_or_else(|| "unknown error".to_string(), |e| e.to_string())
+        ))),
+        1 => Ok(contents.remove(0)),
+        n => Err(PleError::MultipleQrCodes(n)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(matches!(decode(b"not an image"), Err(PleError::InvalidImage(_))));
+
+        let mut png = Vec::new();
+        image::GrayImage::from_pixel(64, 64, image::Luma([255]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        assert!(matches!(decode(&png), Err(PleError::NoQrCode)));
+    }
+
+    #[test]
+    fn test_decode_config() {
+        let config = "[Interface]\nPrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\nAddress = 10.100.0.2/24\n\n\
+                      [Peer]\nPublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=\n\
+                      Endpoint = 198.51.100.1:51820\nAllowedIPs = 10.100.0.0/24\n";
+        let qr = qrcode::QrCode::new(config).unwrap()
+            .render::<image::Luma<u8>>()
+            .build();
+        let mut png = Vec::new();
+        qr.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+
+        assert_eq!(decode(&png).unwrap(), config);
+    }
+}
@@ -0,0 +1,339 @@
+//! Per-app split tunneling
+//! Routes traffic from selected processes through the VPN while everything
+//! else stays on the physical interface (Linux only for now)
+
+/// Firewall mark applied to packets from enrolled apps
+pub const SPLIT_FWMARK: u32 = 0x5037;
+
+/// Routing table that sends marked packets into the tunnel
+pub const SPLIT_TABLE: u32 = 5037;
+
+/// What to route through the tunnel: a single process or a whole cgroup
+#[derive(Debug, Clone, PartialEq)]
+pub enum SplitTarget {
+    Pid(u32),
+    /// cgroup v2 path relative to the cgroup root, e.g. "user.slice/app.scope"
+    Cgroup(String),
+}
+
+impl SplitTarget {
+    /// Parse a PID ("1234") or a cgroup path ("user.slice/app.scope")
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err("Empty split tunnel target".to_string());
+        }
+        match value.parse::<u32>() {
+            Ok(pid) => Ok(SplitTarget::Pid(pid)),
+            Err(_) => Ok(SplitTarget::Cgroup(value.trim_start_matches('/').to_string())),
+        }
+    }
+}
+
+/// Platform-independent split tunnel handle
+/// Rules live as long as this value - call teardown() when the tunnel goes down
+pub struct SplitTunnel {
+    #[cfg(target_os = "linux")]
+    inner: LinuxSplitTunnel,
+}
+
+impl SplitTunnel {
+    /// Install the mark/rule/table plumbing for the given TUN device
+    #[cfg(target_os = "linux")]
+    pub fn new(tun_name: &str) -> Result<Self, String> {
+        Ok(Self {
+            inner: LinuxSplitTunnel::new(tun_name)?,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(_tun_name: &str) -> Result<Self, String> {
+        Err("Per-app split tunneling is not supported on this platform yet".to_string())
+    }
+
+    /// Route a process or cgroup through the tunnel
+    pub fn add(&mut self, target: SplitTarget) -> Result<(), String> {
+        #[cfg(target_os = "linux")]
+        return self.inner.add(target);
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = target;
+            Err("Per-app split tunneling is not supported on this platform yet".to_string())
+        }
+    }
+
+    /// Stop routing a process or cgroup through the tunnel
+    pub fn remove(&mut self, target: &SplitTarget) -> Result<(), String> {
+        #[cfg(target_os = "linux")]
+        return self.inner.remove(target);
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = target;
+            Err("Per-app split tunneling is not supported on this platform yet".to_string())
+        }
+    }
+
+    /// Remove all rules and return enrolled processes to their original cgroups
+    pub fn teardown(&mut self) {
+        #[cfg(target_os = "linux")]
+        self.inner.teardown();
+    }
+
+    /// Whether new() can work here: Linux with a cgroup hierarchy and iptables
+    pub fn is_supported() -> bool {
+        #[cfg(target_os = "linux")]
+        return linux::is_supported();
+
+        #[cfg(not(target_os = "linux"))]
+        false
+    }
+}
+
+// ============================================================================
+// Linux Implementation (cgroup + fwmark + policy routing)
+// ============================================================================
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+    const CGROUP_NAME: &str = "ple7-split";
+
+    /// net_cls class ID for cgroup v1 systems (major 0x10, minor 0x5037)
+    const NET_CLS_CLASSID: u32 = 0x0010_5037;
+
+    enum CgroupMode {
+        /// Unified hierarchy - iptables matches on the cgroup path
+        V2,
+        /// Legacy net_cls controller - iptables matches on the class ID
+        V1NetCls,
+    }
+
+    pub struct LinuxSplitTunnel {
+        tun_name: String,
+        mode: CgroupMode,
+        cgroup_dir: PathBuf,
+        /// Enrolled PIDs and the cgroup they came from
+        pids: HashMap<u32, PathBuf>,
+        /// Enrolled external cgroups (v2 paths)
+        cgroups: Vec<String>,
+    }
+
+    impl LinuxSplitTunnel {
+        pub fn new(tun_name: &str) -> Result<Self, String> {
+            let (mode, cgroup_dir) = if Path::new(CGROUP_ROOT).join("cgroup.controllers").exists() {
+                (CgroupMode::V2, Path::new(CGROUP_ROOT).join(CGROUP_NAME))
+            } else {
+                (CgroupMode::V1NetCls, Path::new(CGROUP_ROOT).join("net_cls").join(CGROUP_NAME))
+            };
+
+            std::fs::create_dir_all(&cgroup_dir)
+                .map_err(|e| format!("Failed to create cgroup {:?}: {}", cgroup_dir, e))?;
+
+            if let CgroupMode::V1NetCls = mode {
+                std::fs::write(cgroup_dir.join("net_cls.classid"), format!("{:#x}", NET_CLS_CLASSID))
+                    .map_err(|e| format!("Failed to set net_cls classid: {}", e))?;
+            }
+
+            let split = Self {
+                tun_name: tun_name.to_string(),
+                mode,
+                cgroup_dir,
+                pids: HashMap::new(),
+                cgroups: Vec::new(),
+            };
+
+            if let Err(e) = split.install_rules() {
+                split.remove_rules();
+                return Err(e);
+            }
+
+            log::info!("Split tunneling enabled on {} (fwmark {:#x}, table {})",
+                tun_name, SPLIT_FWMARK, SPLIT_TABLE);
+            Ok(split)
+        }
+
+        /// iptables match arguments for our own cgroup
+        fn own_cgroup_match(&self) -> Vec<String> {
+            match self.mode {
+                CgroupMode::V2 => cgroup_path_match(CGROUP_NAME),
+                CgroupMode::V1NetCls => vec![
+                    "-m".to_string(), "cgroup".to_string(),
+                    "--cgroup".to_string(), format!("{:#x}", NET_CLS_CLASSID),
+                ],
+            }
+        }
+
+        fn install_rules(&self) -> Result<(), String> {
+            let mark = format!("{:#x}", SPLIT_FWMARK);
+            let table = SPLIT_TABLE.to_string();
+
+            // Mark packets leaving enrolled processes
+            iptables_mark("-A", &self.own_cgroup_match())?;
+
+            // Marked packets were source-addressed for the physical interface
+            run("iptables", &[
+                "-t", "nat", "-A", "POSTROUTING",
+                "-m", "mark", "--mark", &mark,
+                "-o", &self.tun_name, "-j", "MASQUERADE",
+            ])?;
+
+            // Dedicated table whose default route is the tunnel
+            run("ip", &["route", "replace", "default", "dev", &self.tun_name, "table", &table])?;
+
+            // Drop any stale rule from a crashed session before adding ours
+            let _ = run("ip", &["rule", "del", "fwmark", &mark, "lookup", &table]);
+            run("ip", &["rule", "add", "fwmark", &mark, "lookup", &table, "priority", &table])?;
+
+            Ok(())
+        }
+
+        fn remove_rules(&self) {
+            let mark = format!("{:#x}", SPLIT_FWMARK);
+            let table = SPLIT_TABLE.to_string();
+
+            let _ = iptables_mark("-D", &self.own_cgroup_match());
+            let _ = run("iptables", &[
+                "-t", "nat", "-D", "POSTROUTING",
+                "-m", "mark", "--mark", &mark,
+                "-o", &self.tun_name, "-j", "MASQUERADE",
+            ]);
+            let _ = run("ip", &["rule", "del", "fwmark", &mark, "lookup", &table]);
+            let _ = run("ip", &["route", "flush", "table", &table]);
+        }
+
+        pub fn add(&mut self, target: SplitTarget) -> Result<(), String> {
+            match target {
+                SplitTarget::Pid(pid) => {
+                    if self.pids.contains_key(&pid) {
+                        return Ok(());
+                    }
+                    let original = self.current_cgroup_dir(pid)?;
+                    move_pid(pid, &self.cgroup_dir)?;
+                    self.pids.insert(pid, original);
+                    log::info!("Split tunnel: routing PID {} through VPN", pid);
+                }
+                SplitTarget::Cgroup(path) => {
+                    if let CgroupMode::V1NetCls = self.mode {
+                        return Err("Cgroup targets require cgroup v2".to_string());
+                    }
+                    if self.cgroups.contains(&path) {
+                        return Ok(());
+                    }
+                    iptables_mark("-A", &cgroup_path_match(&path))?;
+                    log::info!("Split tunnel: routing cgroup {} through VPN", path);
+                    self.cgroups.push(path);
+                }
+            }
+            Ok(())
+        }
+
+        pub fn remove(&mut self, target: &SplitTarget) -> Result<(), String> {
+            match target {
+                SplitTarget::Pid(pid) => {
+                    let original = self.pids.remove(pid)
+                        .ok_or_else(|| format!("PID {} is not split tunneled", pid))?;
+                    move_pid(*pid, &original)?;
+                    log::info!("Split tunnel: PID {} back on physical interface", pid);
+                }
+                SplitTarget::Cgroup(path) => {
+                    let index = self.cgroups.iter().position(|c| c == path)
+                        .ok_or_else(|| format!("Cgroup {} is not split tunneled", path))?;
+                    iptables_mark("-D", &cgroup_path_match(path))?;
+                    self.cgroups.remove(index);
+                    log::info!("Split tunnel: cgroup {} back on physical interface", path);
+                }
+            }
+            Ok(())
+        }
+
+        pub fn teardown(&mut self) {
+            for (pid, original) in self.pids.drain() {
+                // The process may have exited already
+                if let Err(e) = move_pid(pid, &original) {
+                    log::debug!("Split tunnel: could not restore PID {}: {}", pid, e);
+                }
+            }
+            for path in self.cgroups.drain(..) {
+                let _ = iptables_mark("-D", &cgroup_path_match(&path));
+            }
+            self.remove_rules();
+
+            if let Err(e) = std::fs::remove_dir(&self.cgroup_dir) {
+                log::debug!("Split tunnel: could not remove {:?}: {}", self.cgroup_dir, e);
+            }
+            log::info!("Split tunneling disabled");
+        }
+
+        /// Directory of the cgroup a process currently belongs to
+        fn current_cgroup_dir(&self, pid: u32) -> Result<PathBuf, String> {
+            let contents = std::fs::read_to_string(format!("/proc/{}/cgroup", pid))
+                .map_err(|e| format!("Process {} not found: {}", pid, e))?;
+
+            // v2: "0::/user.slice/..."  v1: "N:net_cls,net_prio:/..."
+            let path = contents.lines()
+                .filter_map(|line| {
+                    let mut parts = line.splitn(3, ':');
+                    let (_, controllers, path) = (parts.next()?, parts.next()?, parts.next()?);
+                    let matches = match self.mode {
+                        CgroupMode::V2 => controllers.is_empty(),
+                        CgroupMode::V1NetCls => controllers.split(',').any(|c| c == "net_cls"),
+                    };
+                    matches.then(|| path.to_string())
+                })
+                .next()
+                .unwrap_or_else(|| "/".to_string());
+
+            Ok(self.cgroup_dir.parent()
+                .unwrap_or(Path::new(CGROUP_ROOT))
+                .join(path.trim_start_matches('/')))
+        }
+    }
+
+    fn cgroup_path_match(path: &str) -> Vec<String> {
+        vec![
+            "-m".to_string(), "cgroup".to_string(),
+            "--path".to_string(), path.to_string(),
+        ]
+    }
+
+    /// Add (-A) or delete (-D) a mangle rule marking packets that match
+    fn iptables_mark(action: &str, matcher: &[String]) -> Result<(), String> {
+        let mark = format!("{:#x}", SPLIT_FWMARK);
+        let mut args: Vec<&str> = vec!["-t", "mangle", action, "OUTPUT"];
+        args.extend(matcher.iter().map(|s| s.as_str()));
+        args.extend(["-j", "MARK", "--set-mark", &mark]);
+        run("iptables", &args)
+    }
+
+    fn move_pid(pid: u32, cgroup_dir: &Path) -> Result<(), String> {
+        std::fs::write(cgroup_dir.join("cgroup.procs"), pid.to_string())
+            .map_err(|e| format!("Failed to move PID {} to {:?}: {}", pid, cgroup_dir, e))
+    }
+
+    pub fn is_supported() -> bool {
+        Path::new(CGROUP_ROOT).exists() && run("iptables", &["--version"]).is_ok()
+    }
+
+    fn run(program: &str, args: &[&str]) -> Result<(), String> {
+        let output = Command::new(program)
+            .args(args)
+            .output()
+            .map_err(|e| format!("Failed to execute {}: {}", program, e))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("{} {} failed: {}", program, args.join(" "), stderr.trim()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+use linux::LinuxSplitTunnel;
@@ -1,14 +1,22 @@
 //! STUN client for NAT traversal
 //! Discovers public IP:port for direct peer-to-peer connections
 
-use std::net::{SocketAddr, UdpSocket};
-use std::time::Duration;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
 use stun_codec::rfc5389::attributes::XorMappedAddress;
 use stun_codec::rfc5389::methods::BINDING;
 use stun_codec::{Message, MessageClass, MessageDecoder, MessageEncoder, TransactionId};
 use bytecodec::{DecodeExt, EncodeExt};
 use rand::Rng;
 
+use crate::error::PleError;
+
 /// Public STUN servers for NAT traversal
 const STUN_SERVERS: &[&str] = &[
     "stun.l.google.com:19302",
@@ -18,117 +26,747 @@ const STUN_SERVERS: &[&str] = &[
     "stun.stunprotocol.org:3478",
 ];
 
+/// Per-server timeout of the first discovery pass. Most servers answer well
+/// within it; if none do, discovery runs again with the client's full timeout.
+const STUN_FAST_TIMEOUT: Duration = Duration::from_millis(750);
+
+/// Limit on a whole discovery, UDP passes and TCP fallback together, so connect
+/// falls back to the relay long before its own timeout
+const STUN_DISCOVERY_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Connect and response timeout of the single TCP fallback attempt
+const STUN_TCP_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Last round-trip time of each STUN server that answered, so the fastest is tried first
+static SERVER_RTTS: Mutex<BTreeMap<String, Duration>> = Mutex::new(BTreeMap::new());
+
+/// Load round-trip times saved by a previous run, in milliseconds per server
+pub fn set_server_rtts(rtts: BTreeMap<String, u64>) {
+    *SERVER_RTTS.lock() = rtts.into_iter()
+        .map(|(server, ms)| (server, Duration::from_millis(ms)))
+        .collect();
+}
+
+/// Recorded round-trip times in milliseconds per server, for saving
+pub fn server_rtts() -> BTreeMap<String, u64> {
+    SERVER_RTTS.lock().iter()
+        .map(|(server, rtt)| (server.clone(), rtt.as_millis() as u64))
+        .collect()
+}
+
+fn record_rtt(server: &str, rtt: Duration) {
+    log::debug!("[STUN] {} answered in {:?}", server, rtt);
+    SERVER_RTTS.lock().insert(server.to_string(), rtt);
+}
+
+/// STUN_SERVERS with the ones that answered fastest before first, minus the
+/// preferred server (tried separately)
+fn servers_by_rtt() -> Vec<&'static str> {
+    let preferred = preferred_server();
+    let mut servers = order_by_rtt(STUN_SERVERS, &SERVER_RTTS.lock());
+    servers.retain(|server| preferred.as_ref().is_none_or(|p| p.server != *server));
+    servers
+}
+
+/// `servers` sorted by recorded round-trip time, ones never heard from last in listed order
+fn order_by_rtt<'a>(servers: &[&'a str], rtts: &BTreeMap<String, Duration>) -> Vec<&'a str> {
+    let mut servers = servers.to_vec();
+    servers.sort_by_key(|server| rtts.get(*server).copied().unwrap_or(Duration::MAX));
+    servers
+}
+
+/// Port STUN servers listen on when none is given
+const STUN_DEFAULT_PORT: u16 = 3478;
+
+/// A STUN server the user pinned, so the mapping reported is always the one it sees
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreferredStunServer {
+    /// host:port
+    pub server: String,
+    /// Don't fall back to the built-in servers when it fails
+    pub exclusive: bool,
+}
+
+static PREFERRED_SERVER: RwLock<Option<PreferredStunServer>> = RwLock::new(None);
+
+/// Try `preferred` before the built-in servers (None = rotate through those only)
+pub fn set_preferred_server(preferred: Option<PreferredStunServer>) {
+    log::info!("[STUN] Preferred server set to {:?}", preferred);
+    *PREFERRED_SERVER.write() = preferred;
+    // Cached mappings may have come from another server
+    invalidate_stun_cache();
+}
+
+pub fn preferred_server() -> Option<PreferredStunServer> {
+    PREFERRED_SERVER.read().clone()
+}
+
+/// `server` as host:port, adding the default STUN port if it has none, once it resolves
+pub fn validate_server(server: &str) -> Result<String, String> {
+    let server = server.trim();
+    let normalized = match server.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, STUN_DEFAULT_PORT).to_string(),
+        Err(_) if server.parse::<SocketAddr>().is_ok() => server.to_string(),
+        Err(_) => match server.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => server.to_string(),
+            _ => format!("{}:{}", server, STUN_DEFAULT_PORT),
+        },
+    };
+
+    let mut addrs = std::net::ToSocketAddrs::to_socket_addrs(normalized.as_str())
+        .map_err(|e| format!("Could not resolve STUN server {}: {}", normalized, e))?;
+    if addrs.next().is_none() {
+        return Err(format!("Could not resolve STUN server {}: no addresses found", normalized));
+    }
+    Ok(normalized)
+}
+
+/// RFC 5389 section 7.2.1 initial retransmission timeout
+const STUN_INITIAL_RTO: Duration = Duration::from_millis(500);
+
+/// Cap on the doubling RTO, so a lossy link still gets several attempts per server
+const STUN_MAX_RTO: Duration = Duration::from_millis(1600);
+
+/// How long to wait on each attempt before retransmitting, doubling from
+/// STUN_INITIAL_RTO and fitting within `budget` (the last attempt gets what's left)
+fn retransmit_schedule(budget: Duration) -> Vec<Duration> {
+    let mut schedule = Vec::new();
+    let mut rto = STUN_INITIAL_RTO;
+    let mut remaining = budget;
+    while !remaining.is_zero() {
+        let wait = rto.min(remaining);
+        schedule.push(wait);
+        remaining -= wait;
+        rto = (rto * 2).min(STUN_MAX_RTO);
+    }
+    schedule
+}
+
+/// Bind a UDP socket on `port` (0 = any) that can reach both IPv4 and IPv6 hosts.
+/// Prefers a dual-stack `[::]` socket with IPV6_V6ONLY off, falling back to
+/// `0.0.0.0` on systems with IPv6 disabled.
+pub fn bind_udp(port: u16) -> std::io::Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let dual_stack = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP)).and_then(|socket| {
+        socket.set_only_v6(false)?;
+        socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+        Ok(socket)
+    });
+
+    match dual_stack {
+        Ok(socket) => Ok(socket.into()),
+        Err(e) => {
+            log::debug!("[STUN] Dual-stack bind on port {} failed ({}), using IPv4 only", port, e);
+            UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))
+        }
+    }
+}
+
+/// Like `bind_udp`, but bound to `local_ip` when given so traffic leaves through
+/// that address's interface. A specific address gives a single-stack socket.
+pub fn bind_udp_on(local_ip: Option<IpAddr>, port: u16) -> std::io::Result<UdpSocket> {
+    match local_ip {
+        Some(ip) => UdpSocket::bind(SocketAddr::new(ip, port)),
+        None => bind_udp(port),
+    }
+}
+
+/// Whether a socket from `bind_udp_on` is dual-stack - only an IPv6 socket on
+/// the unspecified address accepts v4-mapped destinations
+pub fn is_dual_stack(local_addr: SocketAddr) -> bool {
+    local_addr.is_ipv6() && local_addr.ip().is_unspecified()
+}
+
+/// Destination to hand to `send_to` on a socket from `bind_udp`. A dual-stack
+/// socket only takes IPv6 addresses, so IPv4 hosts become ::ffff:a.b.c.d.
+pub fn send_addr(dual_stack: bool, dest: SocketAddr) -> SocketAddr {
+    match dest {
+        SocketAddr::V4(v4) if dual_stack => {
+            SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port())
+        }
+        _ => dest,
+    }
+}
+
+/// IP version a STUN query went over, and so of the mapping it found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    pub fn of(addr: &SocketAddr) -> Self {
+        if addr.ip().to_canonical().is_ipv4() { Self::V4 } else { Self::V6 }
+    }
+
+    fn matches(self, addr: &SocketAddr) -> bool {
+        Self::of(addr) == self
+    }
+}
+
+impl std::fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::V4 => "IPv4",
+            Self::V6 => "IPv6",
+        })
+    }
+}
+
+/// Families a socket from `bind_udp_on` can send to, preferred first. A
+/// dual-stack socket tries both so IPv6-only networks still get a mapping.
+pub fn socket_families(local_addr: SocketAddr) -> &'static [AddressFamily] {
+    if is_dual_stack(local_addr) {
+        &[AddressFamily::V4, AddressFamily::V6]
+    } else if local_addr.is_ipv6() {
+        &[AddressFamily::V6]
+    } else {
+        &[AddressFamily::V4]
+    }
+}
+
+/// Undo v4-mapping on a received source address, so IPv4 peers compare equal
+/// to their configured endpoints
+pub fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// Default time a discovered mapping is reused before querying again
+pub const STUN_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Current cache TTL in seconds (0 disables the cache)
+static STUN_CACHE_TTL_SECS: AtomicU64 = AtomicU64::new(STUN_CACHE_TTL.as_secs());
+
+/// Change how long cached STUN results are reused
+pub fn set_stun_cache_ttl(ttl: Duration) {
+    STUN_CACHE_TTL_SECS.store(ttl.as_secs(), Ordering::Relaxed);
+    log::info!("[STUN] Cache TTL set to {:?}", ttl);
+}
+
+/// Last STUN result per local address and port - NAT mappings rarely change between quick reconnects
+static STUN_CACHE: OnceLock<Mutex<HashMap<StunCacheKey, CachedStunResult>>> = OnceLock::new();
+
+/// Local address the query was bound to (None = any) and local port
+type StunCacheKey = (Option<IpAddr>, u16);
+
+struct CachedStunResult {
+    result: StunResult,
+    discovered_at: Instant,
+}
+
+fn stun_cache() -> &'static Mutex<HashMap<StunCacheKey, CachedStunResult>> {
+    STUN_CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// When discovery from each local address (None = any) last got no UDP answer, so
+/// a discovery right after a failed one gives up at once instead of timing out again
+static UDP_UNANSWERED: Mutex<BTreeMap<Option<IpAddr>, Instant>> = Mutex::new(BTreeMap::new());
+
+/// Drop all cached STUN results (call when the network changes)
+pub fn invalidate_stun_cache() {
+    stun_cache().lock().clear();
+    UDP_UNANSWERED.lock().clear();
+    log::info!("[STUN] Cache invalidated");
+}
+
 /// Result of STUN query - our public endpoint as seen by the STUN server
 #[derive(Debug, Clone)]
 pub struct StunResult {
     pub public_addr: SocketAddr,
     pub local_addr: SocketAddr,
     pub stun_server: String,
+    /// Family of the query that produced `public_addr`
+    pub family: AddressFamily,
+    /// Found over the TCP fallback: the IP is right but the port is a TCP
+    /// mapping, so `public_addr` must not be used as a WireGuard endpoint
+    pub over_tcp: bool,
+}
+
+/// Outcome of a UDP reachability probe
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reachability {
+    /// Something answered
+    Reachable,
+    /// The OS reported the host or port unreachable (ICMP error, no route)
+    Unreachable(String),
+    /// No answer either way - normal for WireGuard, which ignores unknown packets
+    NoReply,
 }
 
 /// STUN client for discovering public IP:port
 pub struct StunClient {
     timeout: Duration,
+    /// Local address to query from (None = any interface)
+    local_ip: Option<IpAddr>,
 }
 
 impl StunClient {
     pub fn new() -> Self {
         Self {
             timeout: Duration::from_secs(3),
+            local_ip: None,
         }
     }
 
     pub fn with_timeout(timeout: Duration) -> Self {
-        Self { timeout }
+        Self { timeout, local_ip: None }
+    }
+
+    /// Query from `local_ip` so the mapping found is the one that interface gets
+    pub fn bound_to(mut self, local_ip: Option<IpAddr>) -> Self {
+        self.local_ip = local_ip;
+        self
     }
 
     /// Discover our public endpoint using STUN
-    /// Tries multiple servers until one succeeds
-    pub fn discover_public_endpoint(&self) -> Result<StunResult, String> {
+    /// Queries all servers in parallel, falling back to trying them one by one
+    pub fn discover_public_endpoint(&self) -> Result<StunResult, PleError> {
         // Bind to any available port
-        let socket = UdpSocket::bind("0.0.0.0:0")
-            .map_err(|e| format!("Failed to bind UDP socket: {}", e))?;
+        let socket = bind_udp_on(self.local_ip, 0)
+            .map_err(|e| PleError::Network(format!("Failed to bind UDP socket: {}", e)))?;
 
         socket.set_read_timeout(Some(self.timeout))
-            .map_err(|e| format!("Failed to set socket timeout: {}", e))?;
+            .map_err(|e| PleError::Network(format!("Failed to set socket timeout: {}", e)))?;
+
+        let local_addr = socket.local_addr()
+            .map_err(|e| PleError::Network(format!("Failed to get local address: {}", e)))?;
+
+        match self.discover_adaptive(&socket) {
+            Ok((public_addr, server, over_tcp)) => {
+                log::info!("[STUN] ✓ Success! {} -> {} (via {}{})",
+                    local_addr, public_addr, server, if over_tcp { " over TCP" } else { "" });
+                Ok(StunResult {
+                    public_addr,
+                    local_addr,
+                    family: AddressFamily::of(&public_addr),
+                    stun_server: server,
+                    over_tcp,
+                })
+            }
+            Err(e) => {
+                log::error!("[STUN] All servers failed. Details: {}", e);
+                Err(PleError::Network(format!("All STUN servers failed: {}", e)))
+            }
+        }
+    }
+
+    /// Discover public endpoint using a specific local port
+    /// This is important for WireGuard - we want to know the public mapping of our WG port
+    pub fn discover_for_port(&self, local_port: u16) -> Result<StunResult, PleError> {
+        let socket = bind_udp_on(self.local_ip, local_port)
+            .map_err(|e| PleError::Network(format!("Failed to bind to port {}: {}", local_port, e)))?;
+
+        socket.set_read_timeout(Some(self.timeout))
+            .map_err(|e| PleError::Network(format!("Failed to set socket timeout: {}", e)))?;
 
         let local_addr = socket.local_addr()
-            .map_err(|e| format!("Failed to get local address: {}", e))?;
+            .map_err(|e| PleError::Network(format!("Failed to get local address: {}", e)))?;
+
+        let (public_addr, server, over_tcp) = self.discover_adaptive(&socket).map_err(|e| {
+            log::debug!("STUN failed for port {}: {}", local_port, e);
+            PleError::Network(format!("All STUN servers failed for port {}", local_port))
+        })?;
+        log::info!("STUN discovery for port {}: {} -> {} (via {}{})",
+            local_port, local_addr, public_addr, server, if over_tcp { " over TCP" } else { "" });
+        Ok(StunResult {
+            public_addr,
+            local_addr,
+            family: AddressFamily::of(&public_addr),
+            stun_server: server,
+            over_tcp,
+        })
+    }
+
+    /// Discover with STUN_FAST_TIMEOUT per server, then once more with the full
+    /// timeout if nothing answered in time, and finally over TCP in case UDP
+    /// is blocked, all within STUN_DISCOVERY_DEADLINE. The flag is set when only
+    /// TCP answered.
+    fn discover_adaptive(&self, socket: &UdpSocket) -> Result<(SocketAddr, String, bool), String> {
+        let deadline = Instant::now() + STUN_DISCOVERY_DEADLINE;
+        // The UDP passes leave the TCP attempt its time
+        let udp_deadline = deadline - STUN_TCP_TIMEOUT;
+
+        let fast = StunClient { timeout: self.timeout.min(STUN_FAST_TIMEOUT), local_ip: self.local_ip };
+        let udp_result = match fast.discover_on(socket, udp_deadline) {
+            Err(e) if fast.timeout < self.timeout => {
+                log::warn!("[STUN] No server answered within {:?} ({}). Retrying with {:?}...",
+                    fast.timeout, e, self.timeout);
+                self.discover_on(socket, udp_deadline)
+            }
+            result => result,
+        };
+        let udp_error = match udp_result {
+            Ok((public_addr, server)) => {
+                UDP_UNANSWERED.lock().remove(&self.local_ip);
+                return Ok((public_addr, server, false));
+            }
+            Err(e) => e,
+        };
+        UDP_UNANSWERED.lock().insert(self.local_ip, Instant::now());
+
+        // UDP may be blocked outright - at least learn our public IP over TCP
+        log::info!("[STUN] No UDP answer ({}), trying TCP", udp_error);
+        self.discover_tcp(socket, deadline)
+            .map(|(public_addr, server)| (public_addr, server, true))
+            .map_err(|e| format!("{}; over TCP: {}", udp_error, e))
+    }
+
+    /// One TCP query to the server `discover_on` tries first, with STUN_TCP_TIMEOUT -
+    /// it only tells us the public IP, so it isn't worth waiting on several servers
+    fn discover_tcp(&self, socket: &UdpSocket, deadline: Instant) -> Result<(SocketAddr, String), String> {
+        let server = match preferred_server() {
+            Some(preferred) => preferred.server,
+            None => servers_by_rtt().first().ok_or("No STUN server configured")?.to_string(),
+        };
+        let addr = Self::families(socket).iter()
+            .find_map(|&family| Self::resolve_server(&server, family).ok())
+            .ok_or_else(|| format!("{}: no address found", server))?;
+        let client = StunClient { timeout: STUN_TCP_TIMEOUT, local_ip: self.local_ip }
+            .until(deadline)
+            .ok_or("Out of time")?;
+
+        client.query_stun_server_tcp(addr)
+            .map(|public_addr| (public_addr, server.clone()))
+            .map_err(|e| format!("{}: {}", server, e))
+    }
+
+    /// This client with its timeout cut to what's left before `deadline`, None once it passed
+    fn until(&self, deadline: Instant) -> Option<StunClient> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        (!remaining.is_zero()).then(|| StunClient { timeout: self.timeout.min(remaining), local_ip: self.local_ip })
+    }
+
+    /// Query the preferred server if one is set, then all servers in parallel,
+    /// then one by one, fastest first, those the parallel pass couldn't send to
+    fn discover_on(&self, socket: &UdpSocket, deadline: Instant) -> Result<(SocketAddr, String), String> {
+        if let Some(preferred) = preferred_server() {
+            match self.query_stun_server(socket, &preferred.server, deadline) {
+                Ok(public_addr) => return Ok((public_addr, preferred.server)),
+                Err(e) if preferred.exclusive => return Err(format!("{} (pinned): {}", preferred.server, e)),
+                Err(e) => log::warn!("[STUN] Preferred server {} failed: {}. Trying the others...", preferred.server, e),
+            }
+        }
+
+        let client = self.until(deadline).ok_or("Out of time")?;
+        let unsent = match client.query_parallel(socket) {
+            Ok(found) => return Ok(found),
+            // Every server already had the full timeout to answer
+            Err((e, unsent)) if unsent.is_empty() => return Err(e),
+            Err((e, unsent)) => {
+                log::debug!("[STUN] Parallel query failed: {}. Trying {} unsent server(s) one by one...", e, unsent.len());
+                unsent
+            }
+        };
 
-        // Try each STUN server until one works
         let mut errors = Vec::new();
-        for (i, server) in STUN_SERVERS.iter().enumerate() {
-            log::info!("[STUN] Trying server {}/{}: {}", i + 1, STUN_SERVERS.len(), server);
-            match self.query_stun_server(&socket, server) {
-                Ok(public_addr) => {
-                    log::info!("[STUN] ✓ Success! {} -> {} (via {})",
-                        local_addr, public_addr, server);
-                    return Ok(StunResult {
-                        public_addr,
-                        local_addr,
-                        stun_server: server.to_string(),
-                    });
-                }
+        for server in unsent {
+            match self.query_stun_server(socket, server, deadline) {
+                Ok(public_addr) => return Ok((public_addr, server.to_string())),
                 Err(e) => {
-                    log::warn!("[STUN] ✗ Server {} failed: {}", server, e);
+                    log::debug!("[STUN] ✗ Server {} failed: {}", server, e);
                     errors.push(format!("{}: {}", server, e));
-                    continue;
                 }
             }
         }
+        Err(errors.join("; "))
+    }
+
+    /// Send binding requests to all servers at once on the same socket and
+    /// return the first valid response, matched by transaction ID. On failure
+    /// also returns the servers no request could be sent to.
+    pub fn query_parallel(&self, socket: &UdpSocket) -> Result<(SocketAddr, String), (String, Vec<&'static str>)> {
+        let mut pending: Vec<(TransactionId, &str, Instant)> = Vec::new();
+        let mut unsent = Vec::new();
+        let dual_stack = Self::is_dual_stack(socket);
+
+        for server in servers_by_rtt() {
+            let sent_before = pending.len();
+            for &family in Self::families(socket) {
+                let server_addr = match Self::resolve_server(server, family) {
+                    Ok(addr) => addr,
+                    Err(e) => {
+                        log::debug!("[STUN] Skipping {} over {}: {}", server, family, e);
+                        continue;
+                    }
+                };
+
+                let (transaction_id, request_bytes) = self.encode_binding_request()
+                    .map_err(|e| (e, Vec::new()))?;
+                match socket.send_to(&request_bytes, send_addr(dual_stack, server_addr)) {
+                    Ok(_) => pending.push((transaction_id, server, Instant::now())),
+                    Err(e) => log::debug!("[STUN] Failed to send to {} over {}: {}", server, family, e),
+                }
+            }
+            if pending.len() == sent_before {
+                unsent.push(server);
+            }
+        }
+
+        if pending.is_empty() {
+            return Err(("No STUN server reachable".to_string(), unsent));
+        }
+
+        let deadline = std::time::Instant::now() + self.timeout;
+        let mut buf = [0u8; 1024];
+
+        let result = loop {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            if remaining.is_zero() {
+                break Err(format!("No response from {} STUN servers", pending.len()));
+            }
+
+            if let Err(e) = socket.set_read_timeout(Some(remaining)) {
+                break Err(format!("Failed to set socket timeout: {}", e));
+            }
+
+            let len = match socket.recv_from(&mut buf) {
+                Ok((len, _)) => len,
+                Err(e) => break Err(format!("Failed to receive STUN response: {}", e)),
+            };
+
+            // Ignore stray or malformed packets and keep waiting
+            match Self::decode_binding_response(&buf[..len]) {
+                Ok((transaction_id, public_addr)) => {
+                    if let Some((_, server, sent)) = pending.iter().find(|(id, _, _)| *id == transaction_id) {
+                        record_rtt(server, sent.elapsed());
+                        break Ok((canonical_addr(public_addr), server.to_string()));
+                    }
+                }
+                Err(e) => log::debug!("[STUN] Ignoring response: {}", e),
+            }
+        };
 
-        let error_summary = errors.join("; ");
-        log::error!("[STUN] All {} servers failed. Details: {}", STUN_SERVERS.len(), error_summary);
-        Err(format!("All STUN servers failed: {}", error_summary))
+        // Restore the per-server timeout for the serial fallback
+        socket.set_read_timeout(Some(self.timeout))
+            .map_err(|e| (format!("Failed to set socket timeout: {}", e), Vec::new()))?;
+
+        result.map_err(|e| (e, unsent))
     }
 
-    /// Discover public endpoint using a specific local port
-    /// This is important for WireGuard - we want to know the public mapping of our WG port
-    pub fn discover_for_port(&self, local_port: u16) -> Result<StunResult, String> {
-        let bind_addr = format!("0.0.0.0:{}", local_port);
-        let socket = UdpSocket::bind(&bind_addr)
-            .map_err(|e| format!("Failed to bind to port {}: {}", local_port, e))?;
+    /// Query `server` over each family the socket can use until one answers,
+    /// giving up at `deadline`
+    fn query_stun_server(&self, socket: &UdpSocket, server: &str, deadline: Instant) -> Result<SocketAddr, String> {
+        let mut errors = Vec::new();
+        for &family in Self::families(socket) {
+            let Some(client) = self.until(deadline) else {
+                errors.push(format!("{}: out of time", family));
+                break;
+            };
+            match client.query_stun_server_over(socket, server, family) {
+                Ok(public_addr) => return Ok(public_addr),
+                Err(e) => errors.push(format!("{}: {}", family, e)),
+            }
+        }
+        Err(errors.join(", "))
+    }
+
+    fn query_stun_server_over(&self, socket: &UdpSocket, server: &str, family: AddressFamily) -> Result<SocketAddr, String> {
+        let dual_stack = Self::is_dual_stack(socket);
+        let server_addr = Self::resolve_server(server, family)?;
 
+        // Retransmissions reuse the transaction ID, so a late reply to any attempt counts
+        let (transaction_id, request_bytes) = self.encode_binding_request()?;
+        let started = Instant::now();
+        let result = self.send_with_retransmits(
+            socket, &request_bytes, transaction_id, send_addr(dual_stack, server_addr),
+        );
+
+        // Restore the per-server timeout the attempts shortened
         socket.set_read_timeout(Some(self.timeout))
             .map_err(|e| format!("Failed to set socket timeout: {}", e))?;
 
-        let local_addr = socket.local_addr()
-            .map_err(|e| format!("Failed to get local address: {}", e))?;
-
-        for server in STUN_SERVERS {
-            match self.query_stun_server(&socket, server) {
-                Ok(public_addr) => {
-                    log::info!("STUN discovery for port {}: {} -> {} (via {})",
-                        local_port, local_addr, public_addr, server);
-                    return Ok(StunResult {
-                        public_addr,
-                        local_addr,
-                        stun_server: server.to_string(),
-                    });
+        match result? {
+            Some(public_addr) => {
+                record_rtt(server, started.elapsed());
+                Ok(canonical_addr(public_addr))
+            }
+            None => Err("Timed out".to_string()),
+        }
+    }
+
+    /// Send `request` on the RFC 5389 retransmission schedule until the response to
+    /// `transaction_id` arrives. Ok(None) if every attempt timed out.
+    fn send_with_retransmits(
+        &self,
+        socket: &UdpSocket,
+        request: &[u8],
+        transaction_id: TransactionId,
+        target: SocketAddr,
+    ) -> Result<Option<SocketAddr>, String> {
+        let mut buf = [0u8; 1024];
+
+        for (attempt, rto) in retransmit_schedule(self.timeout).into_iter().enumerate() {
+            socket.send_to(request, target)
+                .map_err(|e| format!("Failed to send STUN request: {}", e))?;
+
+            let attempt_deadline = Instant::now() + rto;
+            loop {
+                let remaining = attempt_deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
                 }
-                Err(e) => {
-                    log::debug!("STUN server {} failed for port {}: {}", server, local_port, e);
-                    continue;
+                socket.set_read_timeout(Some(remaining))
+                    .map_err(|e| format!("Failed to set socket timeout: {}", e))?;
+
+                let len = match socket.recv_from(&mut buf) {
+                    Ok((len, _)) => len,
+                    Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break,
+                    Err(e) => return Err(format!("Failed to receive STUN response: {}", e)),
+                };
+
+                // Ignore stray or malformed packets and keep waiting
+                match Self::decode_binding_response(&buf[..len]) {
+                    Ok((response_id, public_addr)) if response_id == transaction_id => {
+                        return Ok(Some(public_addr));
+                    }
+                    Ok(_) => log::debug!("[STUN] Ignoring response to another request"),
+                    Err(e) => log::debug!("[STUN] Ignoring response: {}", e),
                 }
             }
+
+            log::debug!("[STUN] No response to attempt {} after {:?}", attempt + 1, rto);
         }
 
-        Err(format!("All STUN servers failed for port {}", local_port))
+        Ok(None)
     }
 
-    fn query_stun_server(&self, socket: &UdpSocket, server: &str) -> Result<SocketAddr, String> {
-        // Resolve server address
-        let server_addr: SocketAddr = server
-            .parse()
-            .or_else(|_| {
-                // Try DNS resolution
-                std::net::ToSocketAddrs::to_socket_addrs(&server)
-                    .map_err(|e| format!("DNS resolution failed: {}", e))?
-                    .next()
-                    .ok_or_else(|| "No addresses found".to_string())
-            })?;
+    /// Round-trip time of a single binding request to `endpoint`
+    /// Only hosts that answer STUN on that port (e.g. relays) produce a measurement
+    pub fn measure_rtt(&self, endpoint: &str) -> Result<Duration, String> {
+        let socket = bind_udp(0)
+            .map_err(|e| format!("Failed to bind socket: {}", e))?;
+        socket.set_read_timeout(Some(self.timeout))
+            .map_err(|e| format!("Failed to set socket timeout: {}", e))?;
+
+        let dual_stack = Self::is_dual_stack(&socket);
+        let mut target = Err("No addresses found".to_string());
+        for &family in Self::families(&socket) {
+            target = Self::resolve_server(endpoint, family);
+            if target.is_ok() {
+                break;
+            }
+        }
+        let target = target?;
+        let (transaction_id, request_bytes) = self.encode_binding_request()?;
+
+        let started = Instant::now();
+        socket.send_to(&request_bytes, send_addr(dual_stack, target))
+            .map_err(|e| format!("Failed to send STUN request: {}", e))?;
+
+        let mut buf = [0u8; 1024];
+        loop {
+            let (len, src) = socket.recv_from(&mut buf)
+                .map_err(|e| format!("No STUN response from {}: {}", endpoint, e))?;
+            if canonical_addr(src) != target {
+                continue;
+            }
+            // Ignore stray or late responses to other requests
+            if let Ok((response_id, _)) = Self::decode_binding_response(&buf[..len]) {
+                if response_id == transaction_id {
+                    return Ok(started.elapsed());
+                }
+            }
+        }
+    }
+
+    /// Send a binding request to `target` and see what comes back. The socket is
+    /// connected so an ICMP port/host unreachable surfaces as a recv error.
+    pub fn probe_reachability(&self, target: SocketAddr) -> Reachability {
+        let socket = match bind_udp_on(self.local_ip, 0) {
+            Ok(socket) => socket,
+            Err(e) => {
+                log::warn!("[STUN] Failed to bind probe socket: {}", e);
+                return Reachability::NoReply;
+            }
+        };
+        let dual_stack = Self::is_dual_stack(&socket);
+        if let Err(e) = socket.connect(send_addr(dual_stack, target)) {
+            return Reachability::Unreachable(e.to_string());
+        }
+        if let Err(e) = socket.set_read_timeout(Some(self.timeout)) {
+            log::warn!("[STUN] Failed to set probe timeout: {}", e);
+            return Reachability::NoReply;
+        }
+
+        let request = match self.encode_binding_request() {
+            Ok((_, request)) => request,
+            Err(_) => return Reachability::NoReply,
+        };
+        if let Err(e) = socket.send(&request) {
+            return Reachability::Unreachable(e.to_string());
+        }
+
+        let mut buf = [0u8; 1024];
+        match socket.recv(&mut buf) {
+            Ok(_) => Reachability::Reachable,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+                Reachability::NoReply
+            }
+            Err(e) => Reachability::Unreachable(e.to_string()),
+        }
+    }
+
+    /// STUN over TCP (RFC 5389 section 7.2.2)
+    /// Messages are sent back to back on the stream, delimited by the
+    /// 2-byte length field in each STUN header. The mapped port is the TCP
+    /// one, so this is mainly useful for learning the public IP.
+    fn query_stun_server_tcp(&self, server_addr: SocketAddr) -> Result<SocketAddr, String> {
+        const STUN_HEADER_LEN: usize = 20;
+
+        let mut stream = TcpStream::connect_timeout(&server_addr, self.timeout)
+            .map_err(|e| format!("TCP connect failed: {}", e))?;
+        stream.set_read_timeout(Some(self.timeout))
+            .map_err(|e| format!("Failed to set socket timeout: {}", e))?;
+        stream.set_write_timeout(Some(self.timeout))
+            .map_err(|e| format!("Failed to set socket timeout: {}", e))?;
+
+        let (transaction_id, request_bytes) = self.encode_binding_request()?;
+        stream.write_all(&request_bytes)
+            .map_err(|e| format!("Failed to send STUN request over TCP: {}", e))?;
+
+        // Read the header first to learn the attribute length
+        let mut response = vec![0u8; STUN_HEADER_LEN];
+        stream.read_exact(&mut response)
+            .map_err(|e| format!("Failed to receive STUN header over TCP: {}", e))?;
+
+        let body_len = u16::from_be_bytes([response[2], response[3]]) as usize;
+        response.resize(STUN_HEADER_LEN + body_len, 0);
+        stream.read_exact(&mut response[STUN_HEADER_LEN..])
+            .map_err(|e| format!("Failed to receive STUN body over TCP: {}", e))?;
+
+        let (response_id, public_addr) = Self::decode_binding_response(&response)?;
+        if response_id != transaction_id {
+            return Err("Transaction ID mismatch".to_string());
+        }
+
+        let public_addr = canonical_addr(public_addr);
+        log::info!("[STUN] TCP query to {} returned {}", server_addr, public_addr);
+        Ok(public_addr)
+    }
+
+    /// Resolve a STUN server to an address of `family`
+    fn resolve_server(server: &str, family: AddressFamily) -> Result<SocketAddr, String> {
+        let addr = match server.parse::<SocketAddr>() {
+            Ok(addr) => Some(addr).filter(|addr| family.matches(addr)),
+            Err(_) => std::net::ToSocketAddrs::to_socket_addrs(&server)
+                .map_err(|e| format!("DNS resolution failed: {}", e))?
+                .find(|addr| family.matches(addr)),
+        };
+        addr.ok_or_else(|| format!("No {} address found", family))
+    }
+
+    fn is_dual_stack(socket: &UdpSocket) -> bool {
+        socket.local_addr().map(is_dual_stack).unwrap_or(false)
+    }
+
+    fn families(socket: &UdpSocket) -> &'static [AddressFamily] {
+        socket.local_addr().map(socket_families).unwrap_or(&[AddressFamily::V4])
+    }
 
-        // Create STUN binding request
+    fn encode_binding_request(&self) -> Result<(TransactionId, Vec<u8>), String> {
         let transaction_id = self.generate_transaction_id();
         let request = Message::<stun_codec::rfc5389::Attribute>::new(
             MessageClass::Request,
@@ -136,43 +774,36 @@ impl StunClient {
             transaction_id,
         );
 
-        // Encode and send
         let mut encoder = MessageEncoder::new();
         let request_bytes = encoder
             .encode_into_bytes(request)
             .map_err(|e| format!("Failed to encode STUN request: {}", e))?;
 
-        socket.send_to(&request_bytes, server_addr)
-            .map_err(|e| format!("Failed to send STUN request: {}", e))?;
-
-        // Receive response
-        let mut buf = [0u8; 1024];
-        let (len, _) = socket.recv_from(&mut buf)
-            .map_err(|e| format!("Failed to receive STUN response: {}", e))?;
+        Ok((transaction_id, request_bytes))
+    }
 
-        // Decode response
+    /// Decode a binding response into its transaction ID and mapped address
+    /// IPv6 mappings come back as `SocketAddr::V6` (v4-mapped ones are left for canonical_addr)
+    fn decode_binding_response(buf: &[u8]) -> Result<(TransactionId, SocketAddr), String> {
         let mut decoder = MessageDecoder::<stun_codec::rfc5389::Attribute>::new();
         let response = decoder
-            .decode_from_bytes(&buf[..len])
+            .decode_from_bytes(buf)
             .map_err(|e| format!("Failed to decode STUN response: {}", e))?
             .map_err(|e| format!("Incomplete STUN response: {:?}", e))?;
 
-        // Verify transaction ID
-        if response.transaction_id() != transaction_id {
-            return Err("Transaction ID mismatch".to_string());
-        }
+        let transaction_id = response.transaction_id();
 
         // Extract XOR-MAPPED-ADDRESS
         for attr in response.attributes() {
             if let stun_codec::rfc5389::Attribute::XorMappedAddress(xma) = attr {
-                return Ok(xma.address());
+                return Ok((transaction_id, xma.address()));
             }
         }
 
         // Try regular MAPPED-ADDRESS as fallback
         for attr in response.attributes() {
             if let stun_codec::rfc5389::Attribute::MappedAddress(ma) = attr {
-                return Ok(ma.address());
+                return Ok((transaction_id, ma.address()));
             }
         }
 
@@ -196,36 +827,117 @@ impl Default for StunClient {
 /// Async version of STUN client
 pub struct AsyncStunClient {
     timeout: Duration,
+    cache_ttl: Duration,
+    local_ip: Option<IpAddr>,
 }
 
 impl AsyncStunClient {
     pub fn new() -> Self {
         Self {
             timeout: Duration::from_secs(3),
+            cache_ttl: Duration::from_secs(STUN_CACHE_TTL_SECS.load(Ordering::Relaxed)),
+            local_ip: None,
         }
     }
 
+    /// Query from `local_ip` so the mapping found is the one that interface gets
+    pub fn bound_to(mut self, local_ip: Option<IpAddr>) -> Self {
+        self.local_ip = local_ip;
+        self
+    }
+
+    /// Wait up to `timeout` for each STUN server
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
     /// Discover public endpoint asynchronously
-    pub async fn discover_public_endpoint(&self) -> Result<StunResult, String> {
+    pub async fn discover_public_endpoint(&self) -> Result<StunResult, PleError> {
         // Run sync STUN client in blocking task
-        let timeout = self.timeout;
+        let (timeout, local_ip) = (self.timeout, self.local_ip);
         tokio::task::spawn_blocking(move || {
-            let client = StunClient::with_timeout(timeout);
+            let client = StunClient::with_timeout(timeout).bound_to(local_ip);
             client.discover_public_endpoint()
         })
         .await
-        .map_err(|e| format!("STUN task failed: {}", e))?
+        .map_err(|e| PleError::Other(format!("STUN task failed: {}", e)))?
     }
 
     /// Discover public endpoint for specific port asynchronously
-    pub async fn discover_for_port(&self, local_port: u16) -> Result<StunResult, String> {
-        let timeout = self.timeout;
-        tokio::task::spawn_blocking(move || {
-            let client = StunClient::with_timeout(timeout);
+    /// Reuses a cached result for the port if it is younger than the cache TTL,
+    /// and fails at once if no server answered over UDP within the TTL
+    pub async fn discover_for_port(&self, local_port: u16) -> Result<StunResult, PleError> {
+        if let Some(cached) = stun_cache().lock().get(&(self.local_ip, local_port)) {
+            if cached.discovered_at.elapsed() < self.cache_ttl {
+                log::info!("[STUN] Using cached mapping for port {}: {} ({:?} old)",
+                    local_port, cached.result.public_addr, cached.discovered_at.elapsed());
+                return Ok(cached.result.clone());
+            }
+        }
+        if let Some(at) = UDP_UNANSWERED.lock().get(&self.local_ip) {
+            if at.elapsed() < self.cache_ttl {
+                return Err(PleError::Network(format!(
+                    "No STUN server answered over UDP {:?} ago, not retrying yet", at.elapsed())));
+            }
+        }
+
+        self.refresh_for_port(local_port).await
+    }
+
+    /// Probe every endpoint concurrently, giving up on all of them after `limit`
+    /// Results are in the same order as `endpoints`
+    pub async fn measure_rtts(&self, endpoints: &[String], limit: Duration) -> Vec<Result<Duration, String>> {
+        let timeout = self.timeout.min(limit);
+        let probes = endpoints.iter().cloned().map(|endpoint| async move {
+            let task = tokio::task::spawn_blocking(move || {
+                StunClient::with_timeout(timeout).measure_rtt(&endpoint)
+            });
+            match tokio::time::timeout(limit, task).await {
+                Ok(Ok(result)) => result,
+                Ok(Err(e)) => Err(format!("STUN task failed: {}", e)),
+                Err(_) => Err("Timed out".to_string()),
+            }
+        });
+
+        futures::future::join_all(probes).await
+    }
+
+    /// Probe every target concurrently, each for up to the client timeout
+    /// Results are in the same order as `targets`
+    pub async fn probe_reachability(&self, targets: &[SocketAddr]) -> Vec<Reachability> {
+        let (timeout, local_ip) = (self.timeout, self.local_ip);
+        let probes = targets.iter().copied().map(|target| async move {
+            tokio::task::spawn_blocking(move || {
+                StunClient::with_timeout(timeout).bound_to(local_ip).probe_reachability(target)
+            })
+            .await
+            .unwrap_or(Reachability::NoReply)
+        });
+
+        futures::future::join_all(probes).await
+    }
+
+    /// Discover public endpoint for specific port, bypassing the cache
+    pub async fn refresh_for_port(&self, local_port: u16) -> Result<StunResult, PleError> {
+        let (timeout, local_ip) = (self.timeout, self.local_ip);
+        let result = tokio::task::spawn_blocking(move || {
+            let client = StunClient::with_timeout(timeout).bound_to(local_ip);
             client.discover_for_port(local_port)
         })
         .await
-        .map_err(|e| format!("STUN task failed: {}", e))?
+        .map_err(|e| PleError::Other(format!("STUN task failed: {}", e)))??;
+
+        // A TCP mapping isn't a usable endpoint - try UDP again next time
+        if result.over_tcp {
+            return Ok(result);
+        }
+        stun_cache().lock().insert((self.local_ip, local_port), CachedStunResult {
+            result: result.clone(),
+            discovered_at: Instant::now(),
+        });
+
+        Ok(result)
     }
 }
 
@@ -239,6 +951,115 @@ impl Default for AsyncStunClient {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_dual_stack_addr_mapping() {
+        let v4: SocketAddr = "203.0.113.7:51820".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:51820".parse().unwrap();
+
+        let mapped = send_addr(true, v4);
+        assert_eq!(mapped, "[::ffff:203.0.113.7]:51820".parse::<SocketAddr>().unwrap());
+        assert_eq!(canonical_addr(mapped), v4);
+
+        assert_eq!(send_addr(false, v4), v4);
+        assert_eq!(send_addr(true, v6), v6);
+        assert_eq!(canonical_addr(v6), v6);
+
+        assert!(is_dual_stack("[::]:51820".parse().unwrap()));
+        assert!(!is_dual_stack("[2001:db8::1]:51820".parse().unwrap()));
+        assert!(!is_dual_stack("192.0.2.10:51820".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_address_families() {
+        use AddressFamily::{V4, V6};
+
+        assert_eq!(socket_families("[::]:0".parse().unwrap()), &[V4, V6]);
+        assert_eq!(socket_families("[2001:db8::1]:0".parse().unwrap()), &[V6]);
+        assert_eq!(socket_families("0.0.0.0:0".parse().unwrap()), &[V4]);
+        assert_eq!(AddressFamily::of(&"[::ffff:203.0.113.7]:3478".parse().unwrap()), V4);
+
+        assert_eq!(StunClient::resolve_server("[2001:db8::1]:3478", V6).unwrap(), "[2001:db8::1]:3478".parse().unwrap());
+        assert!(StunClient::resolve_server("[2001:db8::1]:3478", V4).is_err());
+        assert!(StunClient::resolve_server("203.0.113.7:3478", V6).is_err());
+    }
+
+    #[test]
+    fn test_retransmit_schedule() {
+        let ms = |v: &[u64]| v.iter().map(|&m| Duration::from_millis(m)).collect::<Vec<_>>();
+
+        assert_eq!(retransmit_schedule(Duration::from_secs(3)), ms(&[500, 1000, 1500]));
+        assert_eq!(retransmit_schedule(Duration::from_secs(6)), ms(&[500, 1000, 1600, 1600, 1300]));
+        assert_eq!(retransmit_schedule(Duration::from_millis(300)), ms(&[300]));
+        assert!(retransmit_schedule(Duration::ZERO).is_empty());
+    }
+
+    #[test]
+    fn test_until_deadline() {
+        let client = StunClient::with_timeout(Duration::from_secs(3));
+        let soon = client.until(Instant::now() + Duration::from_secs(1)).unwrap();
+        assert!(soon.timeout <= Duration::from_secs(1));
+        let later = client.until(Instant::now() + Duration::from_secs(60)).unwrap();
+        assert_eq!(later.timeout, Duration::from_secs(3));
+        assert!(client.until(Instant::now()).is_none());
+    }
+
+    #[test]
+    fn test_order_by_rtt() {
+        let rtts = BTreeMap::from([
+            ("c:3478".to_string(), Duration::from_millis(40)),
+            ("d:3478".to_string(), Duration::from_millis(15)),
+        ]);
+        assert_eq!(
+            order_by_rtt(&["a:3478", "b:3478", "c:3478", "d:3478"], &rtts),
+            ["d:3478", "c:3478", "a:3478", "b:3478"],
+        );
+    }
+
+    #[test]
+    fn test_validate_server() {
+        assert_eq!(validate_server(" 198.51.100.7 ").unwrap(), "198.51.100.7:3478");
+        assert_eq!(validate_server("2001:db8::1").unwrap(), "[2001:db8::1]:3478");
+        assert_eq!(validate_server("[2001:db8::1]:19302").unwrap(), "[2001:db8::1]:19302");
+        assert_eq!(validate_server("localhost").unwrap(), "localhost:3478");
+        assert_eq!(validate_server("localhost:19302").unwrap(), "localhost:19302");
+        assert!(validate_server("localhost:notaport").unwrap_err().starts_with("Could not resolve STUN server"));
+    }
+
+    #[test]
+    fn test_probe_reachability() {
+        let client = StunClient::with_timeout(Duration::from_millis(200)).bound_to(Some(Ipv4Addr::LOCALHOST.into()));
+
+        // A bound socket that never answers looks like a WireGuard server
+        let silent = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        assert_eq!(client.probe_reachability(silent.local_addr().unwrap()), Reachability::NoReply);
+
+        // Nothing listening: the ICMP port unreachable comes back as an error
+        let closed = silent.local_addr().unwrap();
+        drop(silent);
+        assert!(matches!(client.probe_reachability(closed), Reachability::Unreachable(_)));
+    }
+
+    #[test]
+    fn test_decode_ipv6_xor_mapped_address() {
+        let public: SocketAddr = "[2001:db8::42]:40000".parse().unwrap();
+        let transaction_id = TransactionId::new([7; 12]);
+
+        let mut response = Message::<stun_codec::rfc5389::Attribute>::new(
+            MessageClass::SuccessResponse,
+            BINDING,
+            transaction_id,
+        );
+        response.add_attribute(stun_codec::rfc5389::Attribute::XorMappedAddress(
+            XorMappedAddress::new(public),
+        ));
+        let bytes = MessageEncoder::new().encode_into_bytes(response).unwrap();
+
+        let (decoded_id, decoded_addr) = StunClient::decode_binding_response(&bytes).unwrap();
+        assert_eq!(decoded_id, transaction_id);
+        assert_eq!(decoded_addr, public);
+        assert!(canonical_addr(decoded_addr).is_ipv6());
+    }
+
     #[test]
     fn test_stun_discovery() {
         let client = StunClient::new();
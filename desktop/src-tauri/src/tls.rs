@@ -0,0 +1,376 @@
+//! TLS settings for self-hosted control planes
+//! Lets the API client and WebSocket trust a private CA in addition to the system roots,
+//! and optionally pin the server's public key
+
+use std::sync::Arc;
+
+use base64::Engine as _;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio_tungstenite::Connector;
+
+/// Pins baked in at build time: comma-separated base64 SHA-256 SPKI hashes
+const SHIPPED_PINS: Option<&str> = option_env!("PLE7_SPKI_PINS");
+
+/// Error text of a handshake rejected by pinning, matched to tell it apart from other TLS failures
+pub const PIN_MISMATCH: &str = "certificate pin mismatch";
+
+/// TLS trust configuration shared by the API client and WebSocket
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsSettings {
+    /// PEM bundle with extra CA certificates to trust
+    pub ca_cert_path: Option<String>,
+    /// Skip certificate verification entirely - lab use only
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+    /// Base64 SHA-256 hashes of SubjectPublicKeyInfo; the leaf or an intermediate
+    /// must match one of these (or a shipped pin) or the connection is refused
+    #[serde(default)]
+    pub pinned_spki_sha256: Vec<String>,
+}
+
+impl TlsSettings {
+    /// Build an HTTP client trusting the system roots plus the configured CA
+    pub fn http_client(&self) -> Result<reqwest::Client, String> {
+        self.http_client_via(crate::proxy::configured().as_deref())
+    }
+
+    /// http_client, but through `proxy` instead of the one set in the app
+    pub fn http_client_via(&self, proxy: Option<&str>) -> Result<reqwest::Client, String> {
+        let mut builder = crate::proxy::apply(reqwest::Client::builder(), proxy)?;
+
+        // Pinning needs our own verifier, which reqwest only takes as a full rustls config
+        if !self.pins()?.is_empty() {
+            let config = self.client_config()?;
+            return builder
+                .use_preconfigured_tls(config)
+                .build()
+                .map_err(|e| format!("Failed to build HTTP client: {}", e));
+        }
+
+        if let Some(path) = &self.ca_cert_path {
+            let pem = read_pem(path)?;
+            let certs = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| format!("Invalid CA bundle {}: {}", path, e))?;
+            log::info!("[TLS] Trusting {} extra CA certificate(s) from {}", certs.len(), path);
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        if self.danger_accept_invalid_certs {
+            log::warn!("[TLS] Certificate verification DISABLED for API requests");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        builder
+            .build()
+            .map_err(|e| format!("Failed to build HTTP client: {}", e))
+    }
+
+    /// WebSocket TLS connector, or None when the default (system roots) is enough
+    pub fn ws_connector(&self) -> Result<Option<Connector>, String> {
+        if self.ca_cert_path.is_none() && !self.danger_accept_invalid_certs && self.pins()?.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Connector::Rustls(Arc::new(self.client_config()?))))
+    }
+
+    /// Configured and shipped pins, decoded
+    fn pins(&self) -> Result<Vec<[u8; 32]>, String> {
+        let shipped = SHIPPED_PINS.unwrap_or_default().split(',');
+        shipped
+            .chain(self.pinned_spki_sha256.iter().map(String::as_str))
+            .map(str::trim)
+            .filter(|pin| !pin.is_empty())
+            .map(|pin| {
+                base64::engine::general_purpose::STANDARD.decode(pin).ok()
+                    .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+                    .ok_or_else(|| format!("Invalid certificate pin {} - expected a base64 SHA-256 hash", pin))
+            })
+            .collect()
+    }
+
+    fn client_config(&self) -> Result<ClientConfig, String> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("Failed to configure TLS: {}", e))?;
+
+        let pins = self.pins()?;
+        let verifier: Arc<dyn ServerCertVerifier> = if self.danger_accept_invalid_certs {
+            log::warn!("[TLS] Certificate chain verification DISABLED");
+            Arc::new(AcceptAnyCert(provider.clone()))
+        } else {
+            let mut roots = RootCertStore::empty();
+            let (added, _) = roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+            log::debug!("[TLS] Loaded {} system root certificates", added);
+
+            if let Some(path) = &self.ca_cert_path {
+                let pem = read_pem(path)?;
+                for cert in CertificateDer::pem_slice_iter(&pem) {
+                    let cert = cert.map_err(|e| format!("Invalid CA bundle {}: {}", path, e))?;
+                    roots.add(cert)
+                        .map_err(|e| format!("Invalid CA certificate in {}: {}", path, e))?;
+                }
+            }
+
+            WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| format!("Failed to configure TLS: {}", e))?
+        };
+
+        let verifier = if pins.is_empty() {
+            verifier
+        } else {
+            log::info!("[TLS] Pinning {} server public key(s)", pins.len());
+            Arc::new(PinnedKeys { inner: verifier, pins })
+        };
+
+        Ok(builder
+            .dangerous()
+            .with_custom_certificate_verifier(verifier)
+            .with_no_client_auth())
+    }
+}
+
+/// HTTP client whose every TLS handshake fails with `reason`, for when the
+/// configured trust settings can't be applied - falling back to the system
+/// roots would quietly drop the pins
+pub fn refusing_http_client(reason: &str) -> reqwest::Client {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map(|builder| builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(RefuseAll { reason: reason.to_string(), provider }))
+            .with_no_client_auth());
+
+    config.map_err(|e| e.to_string())
+        .and_then(|config| reqwest::Client::builder().use_preconfigured_tls(config).build().map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            // Without any trusted roots no handshake can succeed either
+            log::error!("[TLS] Failed to build refusing client ({}), using one without roots", e);
+            reqwest::Client::builder()
+                .tls_built_in_root_certs(false)
+                .https_only(true)
+                .build()
+                .unwrap_or_default()
+        })
+}
+
+/// Whether an error (or anything in its source chain) is a pin rejection
+pub fn is_pin_mismatch(error: &(dyn std::error::Error + 'static)) -> bool {
+    std::iter::successors(Some(error), |e| e.source())
+        .any(|e| e.to_string().contains(PIN_MISMATCH))
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Failed to read CA bundle {}: {}", path, e))
+}
+
+/// Runs the normal verification, then requires a pinned key in the presented chain
+#[derive(Debug)]
+struct PinnedKeys {
+    inner: Arc<dyn ServerCertVerifier>,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedKeys {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|cert| spki_sha256(cert))
+            .any(|hash| self.pins.contains(&hash));
+        if !pinned {
+            log::error!("[TLS] No certificate presented by {:?} matches a pinned key", server_name);
+            return Err(rustls::Error::General(PIN_MISMATCH.to_string()));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// SHA-256 of a certificate's DER SubjectPublicKeyInfo, the value HPKP-style pins use
+fn spki_sha256(cert: &[u8]) -> Option<[u8; 32]> {
+    let (_, certificate, _) = der_element(cert)?;
+    let (_, mut tbs, _) = der_element(certificate)?;
+
+    // version [0] is optional; then serial, signature algorithm, issuer, validity, subject
+    let (tag, _, rest) = der_element(tbs)?;
+    if tag == 0xa0 {
+        tbs = rest;
+    }
+    for _ in 0..5 {
+        tbs = der_element(tbs)?.2;
+    }
+
+    // The pin covers the whole element, tag and length included
+    let (_, _, rest) = der_element(tbs)?;
+    Some(Sha256::digest(&tbs[..tbs.len() - rest.len()]).into())
+}
+
+/// Split one DER element off the front: (tag, contents, remainder)
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, input) = input.split_first()?;
+    let (len, input) = if first < 0x80 {
+        (first as usize, input)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || input.len() < count {
+            return None;
+        }
+        let len = input[..count].iter().fold(0usize, |len, b| len << 8 | *b as usize);
+        (len, &input[count..])
+    };
+    (input.len() >= len).then(|| (tag, &input[..len], &input[len..]))
+}
+
+/// Verifier that rejects every server certificate, see refusing_http_client
+#[derive(Debug)]
+struct RefuseAll {
+    reason: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for RefuseAll {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Err(rustls::Error::General(format!("TLS settings could not be applied: {}", self.reason)))
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Verifier that accepts any server certificate but still checks handshake signatures
+#[derive(Debug)]
+struct AcceptAnyCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIBezCCASGgAwIBAgIUAsFsq7CwYuv9hs0GkSCKcFsl9acwCgYIKoZIzj0EAwIw
+EzERMA8GA1UEAwwIcGluLnRlc3QwHhcNMjYxMDE3MTIxOTU1WhcNMzYxMDE0MTIx
+OTU1WjATMREwDwYDVQQDDAhwaW4udGVzdDBZMBMGByqGSM49AgEGCCqGSM49AwEH
+A0IABECZoHoIhTN5YfaTHnipI0eSG9ECTTs52+WH0aOzOzNLaVzXQgMxZ/FA0Isz
+x2WHmTJzy0yv6QQWdumXcdmzEA6jUzBRMB0GA1UdDgQWBBTDuNCXxC0wlX8W1ido
+KRY+wuct/zAfBgNVHSMEGDAWgBTDuNCXxC0wlX8W1idoKRY+wuct/zAPBgNVHRMB
+Af8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIQCyNA1H2amFWdIV45gImiiIcPlY
+gFn5axiFrmsMJApREAIgNGK2FIBXxPa6yPbhvUqBNx2nj7M7sLps3d5miDKWUT8=
+-----END CERTIFICATE-----";
+
+    #[test]
+    fn test_spki_pin() {
+        // openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64
+        let cert = CertificateDer::from_pem_slice(CERT.as_bytes()).unwrap();
+        let pin = base64::engine::general_purpose::STANDARD.encode(spki_sha256(&cert).unwrap());
+        assert_eq!(pin, "V1ONcsG11RPw2gbiLQVYYk5WIUtHBbOlwXoov5qf2PQ=");
+
+        assert!(spki_sha256(&cert[..40]).is_none());
+
+        let settings = TlsSettings { pinned_spki_sha256: vec!["not-a-pin".to_string()], ..Default::default() };
+        assert!(settings.pins().is_err());
+    }
+}
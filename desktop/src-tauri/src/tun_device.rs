@@ -1,13 +1,42 @@
 //! TUN device management for all platforms
 //! Creates virtual network interface for VPN traffic
 
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use parking_lot::Mutex;
 
+use crate::error::PleError;
+
 /// MTU for the TUN device
 pub const TUN_MTU: usize = 1420; // WireGuard recommended MTU
 
+/// Name given to the VPN's TUN device
+pub const TUN_NAME: &str = "ple7";
+
+/// Default size of the Wintun packet rings (Windows only)
+pub const DEFAULT_RING_CAPACITY: u32 = 0x40_0000; // 4 MiB
+
+/// Accepted range for set_ring_capacity() - Wintun's own limits
+pub const MIN_RING_CAPACITY: u32 = 0x2_0000; // 128 KiB
+pub const MAX_RING_CAPACITY: u32 = 0x400_0000; // 64 MiB
+
+/// Ring size the next Windows TUN device starts its session with
+static RING_CAPACITY: AtomicU32 = AtomicU32::new(DEFAULT_RING_CAPACITY);
+
+/// Set the Wintun ring size the next TUN device uses. Wintun needs a power of
+/// two, so it's rounded up, then clamped to the accepted range.
+pub fn set_ring_capacity(bytes: u32) {
+    let bytes = bytes.checked_next_power_of_two().unwrap_or(MAX_RING_CAPACITY)
+        .clamp(MIN_RING_CAPACITY, MAX_RING_CAPACITY);
+    RING_CAPACITY.store(bytes, Ordering::Relaxed);
+}
+
+/// Wintun ring size the next TUN device will use
+pub fn ring_capacity() -> u32 {
+    RING_CAPACITY.load(Ordering::Relaxed)
+}
+
 /// Packet received from TUN device (outbound traffic)
 #[derive(Debug)]
 pub struct TunPacket {
@@ -19,13 +48,20 @@ pub struct TunDevice {
     name: String,
     address: Ipv4Addr,
     netmask: Ipv4Addr,
-    mtu: usize,
-    #[cfg(target_os = "linux")]
+    /// Current MTU - lowered at runtime by path MTU discovery
+    mtu: AtomicUsize,
+    /// Whether set_ipv6_address() succeeded - without it the OS drops inbound IPv6
+    ipv6: AtomicBool,
+    /// The address set by set_ipv6_address(), so a reused device can be checked against a new config
+    ipv6_address: Mutex<Option<(Ipv6Addr, u8)>>,
+    #[cfg(all(target_os = "linux", not(test)))]
     inner: LinuxTun,
-    #[cfg(target_os = "macos")]
+    #[cfg(all(target_os = "macos", not(test)))]
     inner: MacOsTun,
-    #[cfg(target_os = "windows")]
+    #[cfg(all(target_os = "windows", not(test)))]
     inner: WindowsTun,
+    #[cfg(test)]
+    inner: LoopbackTun,
 }
 
 impl TunDevice {
@@ -34,27 +70,46 @@ impl TunDevice {
         name: &str,
         address: Ipv4Addr,
         netmask: Ipv4Addr,
-    ) -> Result<Self, String> {
+    ) -> Result<Self, PleError> {
         log::info!("Creating TUN device: {} with address {}/{}", name, address, netmask);
 
-        #[cfg(target_os = "linux")]
+        #[cfg(all(target_os = "linux", not(test)))]
         let inner = LinuxTun::create(name, address, netmask).await?;
+        #[cfg(all(target_os = "linux", not(test)))]
+        let name = inner.name();
 
-        #[cfg(target_os = "macos")]
+        #[cfg(all(target_os = "macos", not(test)))]
         let inner = MacOsTun::create(name, address, netmask).await?;
 
-        #[cfg(target_os = "windows")]
+        #[cfg(all(target_os = "windows", not(test)))]
         let inner = WindowsTun::create(name, address, netmask).await?;
 
+        #[cfg(test)]
+        let inner = LoopbackTun::create();
+
         Ok(Self {
             name: name.to_string(),
             address,
             netmask,
-            mtu: TUN_MTU,
+            mtu: AtomicUsize::new(TUN_MTU),
+            ipv6: AtomicBool::new(false),
+            ipv6_address: Mutex::new(None),
             inner,
         })
     }
 
+    /// Test side of the loopback device - only the first call gets it
+    #[cfg(test)]
+    pub fn loopback_handle(&self) -> Option<LoopbackHandle> {
+        self.inner.take_handle()
+    }
+
+    /// IPv4 routes added through the loopback device and not removed since
+    #[cfg(test)]
+    pub fn loopback_routes(&self) -> Vec<(Ipv4Addr, u8)> {
+        self.inner.routes()
+    }
+
     /// Get the device name
     pub fn name(&self) -> &str {
         &self.name
@@ -65,6 +120,33 @@ impl TunDevice {
         self.address
     }
 
+    /// Get the device netmask
+    pub fn netmask(&self) -> Ipv4Addr {
+        self.netmask
+    }
+
+    /// IPv6 address and prefix length set on the device, if any
+    pub fn ipv6_address(&self) -> Option<(Ipv6Addr, u8)> {
+        *self.ipv6_address.lock()
+    }
+
+    /// Get the device MTU
+    pub fn mtu(&self) -> usize {
+        self.mtu.load(Ordering::Relaxed)
+    }
+
+    /// Change the device MTU. Only ever lowered below TUN_MTU, so the read
+    /// buffers sized for TUN_MTU stay big enough.
+    pub async fn set_mtu(&self, mtu: usize) -> Result<(), String> {
+        if mtu > TUN_MTU {
+            return Err(format!("MTU {} is above the maximum of {}", mtu, TUN_MTU));
+        }
+        self.inner.set_mtu(mtu).await?;
+        self.mtu.store(mtu, Ordering::Relaxed);
+        log::info!("{} MTU set to {}", self.name, mtu);
+        Ok(())
+    }
+
     /// Read a packet from the TUN device (outbound traffic from apps)
     pub async fn read(&self) -> Result<TunPacket, String> {
         self.inner.read().await
@@ -80,24 +162,288 @@ impl TunDevice {
         self.inner.add_route(destination, prefix_len).await
     }
 
+    /// Remove a route previously added through this TUN device
+    pub async fn remove_route(&self, destination: Ipv4Addr, prefix_len: u8) -> Result<(), String> {
+        self.inner.remove_route(destination, prefix_len).await
+    }
+
+    /// Whether the device has an IPv6 address to deliver v6 packets to
+    pub fn has_ipv6(&self) -> bool {
+        self.ipv6.load(Ordering::Relaxed)
+    }
+
+    /// Give the device an IPv6 address alongside its IPv4 one
+    pub async fn set_ipv6_address(&self, address: Ipv6Addr, prefix_len: u8) -> Result<(), String> {
+        self.inner.set_ipv6_address(address, prefix_len).await?;
+        self.ipv6.store(true, Ordering::Relaxed);
+        *self.ipv6_address.lock() = Some((address, prefix_len));
+        log::info!("{} IPv6 address set to {}/{}", self.name, address, prefix_len);
+        Ok(())
+    }
+
+    /// Add an IPv6 route through this TUN device
+    pub async fn add_route_v6(&self, destination: Ipv6Addr, prefix_len: u8) -> Result<(), String> {
+        self.inner.add_route_v6(destination, prefix_len).await
+    }
+
+    /// Remove an IPv6 route previously added through this TUN device
+    pub async fn remove_route_v6(&self, destination: Ipv6Addr, prefix_len: u8) -> Result<(), String> {
+        self.inner.remove_route_v6(destination, prefix_len).await
+    }
+
     /// Set the default gateway (for exit node functionality)
     /// exclude_ip: Optional IP to exclude from VPN routing (e.g., relay endpoint to prevent routing loop)
     pub async fn set_default_gateway(&self, exclude_ip: Option<&str>) -> Result<(), String> {
         self.inner.set_default_gateway(exclude_ip).await
     }
+
+    /// Remove the default gateway routes so traffic uses the physical interface again
+    pub async fn clear_default_gateway(&self) -> Result<(), String> {
+        self.inner.clear_default_gateway().await
+    }
+
+    /// Block DNS (port 53) to everything but the `dns` servers over this device, so
+    /// apps with hardcoded resolvers can't leak queries over the physical interface
+    pub async fn block_dns_leaks(&self, dns: &[Ipv4Addr]) -> Result<(), String> {
+        self.inner.block_dns_leaks(dns).await
+    }
+
+    /// Reject internet IPv6 (2000::/3) everywhere but this device, so apps that
+    /// prefer native IPv6 can't bypass a tunnel that only carries IPv4. WireGuard
+    /// itself stays reachable: traffic to the `endpoints` and from `udp_port`
+    /// (the WireGuard socket) is let through.
+    pub async fn block_ipv6_leaks(&self, endpoints: &[Ipv6Addr], udp_port: Option<u16>) -> Result<(), String> {
+        self.inner.block_ipv6_leaks(endpoints, udp_port).await
+    }
+
+    /// Remove the IPv6 leak block installed by block_ipv6_leaks()
+    pub async fn unblock_ipv6_leaks(&self) -> Result<(), String> {
+        tokio::task::spawn_blocking(Self::remove_ipv6_block)
+            .await
+            .map_err(|e| format!("IPv6 unblock task failed: {}", e))?
+    }
+
+    /// Point the system's resolvers at the tunnel DNS servers (first is primary):
+    /// per adapter on Windows, through systemd-resolved or resolv.conf on Linux and
+    /// the helper (scutil) on macOS. The DNS leak block needs this - it drops
+    /// queries to any other resolver.
+    pub async fn set_dns(&self, servers: &[Ipv4Addr]) -> Result<(), String> {
+        self.inner.set_dns(servers).await
+    }
+
+    /// Put the system's resolvers back the way they were before set_dns()
+    pub async fn restore_dns(&self) -> Result<(), String> {
+        self.inner.restore_dns().await
+    }
+
+    /// Remove the DNS leak block installed by block_dns_leaks()
+    pub async fn unblock_dns_leaks(&self) -> Result<(), String> {
+        tokio::task::spawn_blocking(Self::remove_dns_block)
+            .await
+            .map_err(|e| format!("DNS unblock task failed: {}", e))?
+    }
+
+    /// Remove a TUN device (and the routes through it) left behind by a session
+    /// this process no longer tracks. A missing device is not an error.
+    pub fn destroy_stale(name: &str) -> Result<(), String> {
+        #[cfg(target_os = "linux")]
+        { LinuxTun::destroy_stale(name) }
+
+        #[cfg(target_os = "macos")]
+        { MacOsTun::destroy_stale(name) }
+
+        #[cfg(target_os = "windows")]
+        { WindowsTun::destroy_stale(name) }
+    }
+
+    /// Remove a DNS leak block whether or not this process installed it
+    /// (e.g. one left behind by a crashed session). A missing block is not an error.
+    pub fn remove_dns_block() -> Result<(), String> {
+        #[cfg(target_os = "linux")]
+        { LinuxTun::remove_dns_block() }
+
+        #[cfg(target_os = "macos")]
+        { MacOsTun::remove_dns_block() }
+
+        #[cfg(target_os = "windows")]
+        { WindowsTun::remove_dns_block() }
+    }
+
+    /// Undo a set_dns() left behind by a crashed session. Only Linux needs this - on
+    /// macOS the helper's route flush restores DNS, and Windows' per-adapter
+    /// servers go with the adapter. Nothing to undo is not an error.
+    pub fn restore_stale_dns() -> Result<(), String> {
+        #[cfg(target_os = "linux")]
+        { LinuxTun::restore_resolv_conf() }
+
+        #[cfg(not(target_os = "linux"))]
+        { Ok(()) }
+    }
+
+    /// Remove an IPv6 leak block whether or not this process installed it.
+    /// A missing block is not an error.
+    pub fn remove_ipv6_block() -> Result<(), String> {
+        #[cfg(target_os = "linux")]
+        { LinuxTun::remove_ipv6_block() }
+
+        #[cfg(target_os = "macos")]
+        { MacOsTun::remove_ipv6_block() }
+
+        #[cfg(target_os = "windows")]
+        { WindowsTun::remove_ipv6_block() }
+    }
 }
 
+// ============================================================================
+// Loopback TUN Implementation (tests only)
+// ============================================================================
+
+/// In-memory device standing in for the platform one in tests, so the WireGuard
+/// data path runs without root or a real interface
+#[cfg(test)]
+mod loopback {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    /// Test side of a loopback device - plays the apps behind the TUN
+    pub struct LoopbackHandle {
+        outbound: mpsc::UnboundedSender<Vec<u8>>,
+        inbound: mpsc::UnboundedReceiver<Vec<u8>>,
+    }
+
+    impl LoopbackHandle {
+        /// Queue a packet for the tunnel to read, as if an app sent it
+        pub fn send(&self, packet: &[u8]) {
+            let _ = self.outbound.send(packet.to_vec());
+        }
+
+        /// Next packet the tunnel wrote to the device (None once it's gone)
+        pub async fn recv(&mut self) -> Option<Vec<u8>> {
+            self.inbound.recv().await
+        }
+    }
+
+    pub struct LoopbackTun {
+        outbound: tokio::sync::Mutex<mpsc::UnboundedReceiver<Vec<u8>>>,
+        inbound: mpsc::UnboundedSender<Vec<u8>>,
+        handle: Mutex<Option<LoopbackHandle>>,
+        /// IPv4 routes currently added through the device
+        routes: Mutex<Vec<(Ipv4Addr, u8)>>,
+    }
+
+    impl LoopbackTun {
+        pub fn create() -> Self {
+            let (outbound_tx, outbound_rx) = mpsc::unbounded_channel();
+            let (inbound_tx, inbound_rx) = mpsc::unbounded_channel();
+            Self {
+                outbound: tokio::sync::Mutex::new(outbound_rx),
+                inbound: inbound_tx,
+                handle: Mutex::new(Some(LoopbackHandle { outbound: outbound_tx, inbound: inbound_rx })),
+                routes: Mutex::new(Vec::new()),
+            }
+        }
+
+        pub fn take_handle(&self) -> Option<LoopbackHandle> {
+            self.handle.lock().take()
+        }
+
+        pub async fn read(&self) -> Result<TunPacket, String> {
+            self.outbound.lock().await.recv().await
+                .map(|data| TunPacket { data })
+                .ok_or_else(|| "Loopback handle dropped".to_string())
+        }
+
+        pub async fn write(&self, packet: &[u8]) -> Result<(), String> {
+            self.inbound.send(packet.to_vec())
+                .map_err(|_| "Loopback handle dropped".to_string())
+        }
+
+        pub fn routes(&self) -> Vec<(Ipv4Addr, u8)> {
+            self.routes.lock().clone()
+        }
+
+        pub async fn add_route(&self, destination: Ipv4Addr, prefix_len: u8) -> Result<(), String> {
+            let mut routes = self.routes.lock();
+            if !routes.contains(&(destination, prefix_len)) {
+                routes.push((destination, prefix_len));
+            }
+            Ok(())
+        }
+
+        pub async fn remove_route(&self, destination: Ipv4Addr, prefix_len: u8) -> Result<(), String> {
+            self.routes.lock().retain(|route| *route != (destination, prefix_len));
+            Ok(())
+        }
+
+        pub async fn set_ipv6_address(&self, _address: Ipv6Addr, _prefix_len: u8) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub async fn add_route_v6(&self, _destination: Ipv6Addr, _prefix_len: u8) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub async fn remove_route_v6(&self, _destination: Ipv6Addr, _prefix_len: u8) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub async fn set_default_gateway(&self, _exclude_ip: Option<&str>) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub async fn clear_default_gateway(&self) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub async fn set_mtu(&self, _mtu: usize) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub async fn block_dns_leaks(&self, _dns: &[Ipv4Addr]) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub async fn block_ipv6_leaks(&self, _endpoints: &[Ipv6Addr], _udp_port: Option<u16>) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub async fn set_dns(&self, _servers: &[Ipv4Addr]) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub async fn restore_dns(&self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+pub use loopback::LoopbackHandle;
+#[cfg(test)]
+use loopback::LoopbackTun;
+
 // ============================================================================
 // Linux TUN Implementation
 // ============================================================================
 
 #[cfg(target_os = "linux")]
+#[cfg_attr(test, allow(dead_code))]
 mod linux {
     use super::*;
     use tun::{Configuration, AbstractDevice};
+    use std::path::Path;
     use std::process::Command;
     use std::io::{Read, Write};
 
+    /// nftables table holding the DNS leak block
+    const DNS_BLOCK_TABLE: &str = "ple7_dns";
+    /// nftables table holding the IPv6 leak block
+    const IPV6_BLOCK_TABLE: &str = "ple7_ipv6";
+
+    const RESOLV_CONF: &str = "/etc/resolv.conf";
+    /// Where set_dns moves the original resolv.conf (or its symlink) when it has to rewrite it
+    const RESOLV_CONF_BACKUP: &str = "/etc/resolv.conf.ple7-backup";
+
     pub struct LinuxTun {
         device: Arc<Mutex<tun::Device>>,
         name: String,
@@ -109,16 +455,27 @@ mod linux {
             address: Ipv4Addr,
             netmask: Ipv4Addr,
         ) -> Result<Self, String> {
-            let mut config = Configuration::default();
-            config
-                .tun_name(name)
-                .address(address)
-                .netmask(netmask)
-                .mtu(TUN_MTU as u16)
-                .up();
-
-            let device = tun::create(&config)
-                .map_err(|e| format!("Failed to create TUN device: {}", e))?;
+            let device = match Self::open(name, address, netmask) {
+                Ok(device) => device,
+                // Usually left over from a crashed session - clear it like the
+                // Windows backend does, or take the next free ple7N
+                Err(e) if Path::new("/sys/class/net").join(name).exists() => {
+                    log::warn!("{} already exists ({})", name, e);
+                    let reclaimed = if Path::new("/sys/class/net").join(name).join("tun_flags").exists() {
+                        Self::destroy_stale(name).and_then(|()| Self::open(name, address, netmask))
+                    } else {
+                        Err(format!("{} isn't a TUN device, leaving it alone", name))
+                    };
+                    match reclaimed {
+                        Ok(device) => device,
+                        Err(e) => {
+                            log::warn!("Couldn't reclaim {} ({}), letting the kernel pick a name", name, e);
+                            Self::open(&format!("{}%d", name), address, netmask)?
+                        }
+                    }
+                }
+                Err(e) => return Err(e),
+            };
 
             let actual_name = device.tun_name()
                 .map_err(|e| format!("Failed to get device name: {}", e))?;
@@ -131,6 +488,25 @@ mod linux {
             })
         }
 
+        fn open(name: &str, address: Ipv4Addr, netmask: Ipv4Addr) -> Result<tun::Device, String> {
+            let mut config = Configuration::default();
+            config
+                .tun_name(name)
+                .address(address)
+                .netmask(netmask)
+                .mtu(TUN_MTU as u16)
+                .up();
+
+            tun::create(&config)
+                .map_err(|e| format!("Failed to create TUN device {}: {}", name, e))
+        }
+
+        /// Name the kernel gave the device, which differs from the requested one
+        /// if that was taken
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
         pub async fn read(&self) -> Result<TunPacket, String> {
             let device = self.device.clone();
 
@@ -161,83 +537,450 @@ mod linux {
             .map_err(|e| format!("Write task failed: {}", e))?
         }
 
-        pub async fn add_route(&self, destination: Ipv4Addr, prefix_len: u8) -> Result<(), String> {
+        pub async fn add_route(&self, destination: Ipv4Addr, prefix_len: u8) -> Result<(), String> {
+            let name = self.name.clone();
+
+            tokio::task::spawn_blocking(move || {
+                let output = Command::new("ip")
+                    .args([
+                        "route", "add",
+                        &format!("{}/{}", destination, prefix_len),
+                        "dev", &name,
+                    ])
+                    .output()
+                    .map_err(|e| format!("Failed to execute ip route: {}", e))?;
+
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    if !stderr.contains("File exists") {
+                        return Err(format!("Failed to add route: {}", stderr));
+                    }
+                }
+                Ok(())
+            })
+            .await
+            .map_err(|e| format!("Route task failed: {}", e))?
+        }
+
+        pub async fn remove_route(&self, destination: Ipv4Addr, prefix_len: u8) -> Result<(), String> {
+            let name = self.name.clone();
+
+            tokio::task::spawn_blocking(move || {
+                let output = Command::new("ip")
+                    .args([
+                        "route", "del",
+                        &format!("{}/{}", destination, prefix_len),
+                        "dev", &name,
+                    ])
+                    .output()
+                    .map_err(|e| format!("Failed to execute ip route: {}", e))?;
+
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    if !stderr.contains("No such process") {
+                        return Err(format!("Failed to remove route: {}", stderr));
+                    }
+                }
+                Ok(())
+            })
+            .await
+            .map_err(|e| format!("Route task failed: {}", e))?
+        }
+
+        pub async fn set_ipv6_address(&self, address: Ipv6Addr, prefix_len: u8) -> Result<(), String> {
+            let name = self.name.clone();
+
+            tokio::task::spawn_blocking(move || {
+                // Some distros disable IPv6 on new interfaces, which rejects the address
+                let sysctl = format!("/proc/sys/net/ipv6/conf/{}/disable_ipv6", name);
+                if let Err(e) = std::fs::write(&sysctl, "0") {
+                    log::warn!("Failed to enable IPv6 on {}: {}", name, e);
+                }
+
+                let output = Command::new("ip")
+                    .args([
+                        "-6", "addr", "add",
+                        &format!("{}/{}", address, prefix_len),
+                        "dev", &name,
+                    ])
+                    .output()
+                    .map_err(|e| format!("Failed to execute ip addr: {}", e))?;
+
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    if !stderr.contains("File exists") {
+                        return Err(format!("Failed to add IPv6 address: {}", stderr));
+                    }
+                }
+                Ok(())
+            })
+            .await
+            .map_err(|e| format!("Address task failed: {}", e))?
+        }
+
+        pub async fn add_route_v6(&self, destination: Ipv6Addr, prefix_len: u8) -> Result<(), String> {
+            let name = self.name.clone();
+
+            tokio::task::spawn_blocking(move || {
+                let output = Command::new("ip")
+                    .args([
+                        "-6", "route", "add",
+                        &format!("{}/{}", destination, prefix_len),
+                        "dev", &name,
+                    ])
+                    .output()
+                    .map_err(|e| format!("Failed to execute ip route: {}", e))?;
+
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    if !stderr.contains("File exists") {
+                        return Err(format!("Failed to add IPv6 route: {}", stderr));
+                    }
+                }
+                Ok(())
+            })
+            .await
+            .map_err(|e| format!("Route task failed: {}", e))?
+        }
+
+        pub async fn remove_route_v6(&self, destination: Ipv6Addr, prefix_len: u8) -> Result<(), String> {
+            let name = self.name.clone();
+
+            tokio::task::spawn_blocking(move || {
+                let output = Command::new("ip")
+                    .args([
+                        "-6", "route", "del",
+                        &format!("{}/{}", destination, prefix_len),
+                        "dev", &name,
+                    ])
+                    .output()
+                    .map_err(|e| format!("Failed to execute ip route: {}", e))?;
+
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    if !stderr.contains("No such process") {
+                        return Err(format!("Failed to remove IPv6 route: {}", stderr));
+                    }
+                }
+                Ok(())
+            })
+            .await
+            .map_err(|e| format!("Route task failed: {}", e))?
+        }
+
+        pub async fn set_default_gateway(&self, exclude_ip: Option<&str>) -> Result<(), String> {
+            let name = self.name.clone();
+            let exclude = exclude_ip.map(|s| s.to_string());
+
+            tokio::task::spawn_blocking(move || {
+                // Get original default gateway for bypass route
+                if let Some(ref ip) = exclude {
+                    // Get current default gateway
+                    let output = Command::new("ip")
+                        .args(["route", "show", "default"])
+                        .output()
+                        .map_err(|e| format!("Failed to get default route: {}", e))?;
+
+                    let stdout = String::from_utf8_lossy(&output.stdout);
+                    // Parse "default via X.X.X.X dev ..."
+                    if let Some(gw) = stdout.split_whitespace().skip_while(|&s| s != "via").nth(1) {
+                        // Add bypass route for relay endpoint
+                        log::info!("Adding bypass route for {} via {}", ip, gw);
+                        Command::new("ip")
+                            .args(["route", "add", ip, "via", gw])
+                            .output()
+                            .ok(); // Ignore errors (may already exist)
+                    }
+                }
+
+                // Add split routes for default gateway
+                Command::new("ip")
+                    .args(["route", "add", "0.0.0.0/1", "dev", &name])
+                    .output()
+                    .map_err(|e| format!("Failed to add route: {}", e))?;
+
+                Command::new("ip")
+                    .args(["route", "add", "128.0.0.0/1", "dev", &name])
+                    .output()
+                    .map_err(|e| format!("Failed to add route: {}", e))?;
+
+                Ok(())
+            })
+            .await
+            .map_err(|e| format!("Default gateway task failed: {}", e))?
+        }
+
+        pub async fn clear_default_gateway(&self) -> Result<(), String> {
+            self.remove_route(Ipv4Addr::new(0, 0, 0, 0), 1).await?;
+            self.remove_route(Ipv4Addr::new(128, 0, 0, 0), 1).await
+        }
+
+        pub async fn set_mtu(&self, mtu: usize) -> Result<(), String> {
+            let name = self.name.clone();
+
+            tokio::task::spawn_blocking(move || {
+                let output = Command::new("ip")
+                    .args(["link", "set", "dev", &name, "mtu", &mtu.to_string()])
+                    .output()
+                    .map_err(|e| format!("Failed to execute ip link: {}", e))?;
+
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    Err(format!("Failed to set MTU: {}", String::from_utf8_lossy(&output.stderr).trim()))
+                }
+            })
+            .await
+            .map_err(|e| format!("MTU task failed: {}", e))?
+        }
+
+        pub async fn block_dns_leaks(&self, dns: &[Ipv4Addr]) -> Result<(), String> {
+            let name = self.name.clone();
+            let dns = dns.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(", ");
+
+            tokio::task::spawn_blocking(move || {
+                // Replace rather than stack on a table from an earlier connect
+                Self::remove_dns_block()?;
+
+                let ruleset = format!(
+                    "table inet {table} {{\n\
+                     \tchain output {{\n\
+                     \t\ttype filter hook output priority 0; policy accept;\n\
+                     \t\toifname \"lo\" accept\n\
+                     \t\toifname \"{name}\" ip daddr {{ {dns} }} accept\n\
+                     \t\tudp dport 53 drop\n\
+                     \t\ttcp dport 53 drop\n\
+                     \t}}\n\
+                     }}\n",
+                    table = DNS_BLOCK_TABLE,
+                    name = name,
+                    dns = dns,
+                );
+
+                log::info!("Blocking DNS except {} via {} (nftables)", dns, name);
+                Self::load_ruleset(&ruleset)
+                    .map_err(|e| format!("Failed to install DNS block: {}", e))
+            })
+            .await
+            .map_err(|e| format!("DNS block task failed: {}", e))?
+        }
+
+        /// systemd-resolved gets the servers on this link plus the `~.` routing domain,
+        /// so every query goes over the tunnel; without it resolv.conf is rewritten
+        pub async fn set_dns(&self, servers: &[Ipv4Addr]) -> Result<(), String> {
+            let name = self.name.clone();
+            let servers: Vec<String> = servers.iter().map(|ip| ip.to_string()).collect();
+
+            tokio::task::spawn_blocking(move || {
+                let mut dns_args = vec!["dns".to_string(), name.clone()];
+                dns_args.extend(servers.iter().cloned());
+                let resolved = Self::resolvectl(&dns_args)
+                    .and_then(|()| Self::resolvectl(&["domain".to_string(), name.clone(), "~.".to_string()]));
+                match resolved {
+                    Ok(()) => {
+                        log::info!("DNS set to {} on {} (systemd-resolved)", servers.join(", "), name);
+                        Ok(())
+                    }
+                    Err(e) => {
+                        log::info!("systemd-resolved not usable ({}), rewriting {}", e, RESOLV_CONF);
+                        Self::write_resolv_conf(&servers)
+                    }
+                }
+            })
+            .await
+            .map_err(|e| format!("DNS task failed: {}", e))?
+        }
+
+        pub async fn restore_dns(&self) -> Result<(), String> {
+            let name = self.name.clone();
+
+            tokio::task::spawn_blocking(move || {
+                if Path::new(RESOLV_CONF_BACKUP).exists() {
+                    return Self::restore_resolv_conf();
+                }
+                log::info!("Reverting DNS on {} (systemd-resolved)", name);
+                Self::resolvectl(&["revert".to_string(), name])
+            })
+            .await
+            .map_err(|e| format!("DNS restore task failed: {}", e))?
+        }
+
+        fn resolvectl(args: &[String]) -> Result<(), String> {
+            let output = Command::new("resolvectl")
+                .args(args)
+                .output()
+                .map_err(|e| format!("Failed to run resolvectl: {}", e))?;
+
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+            }
+        }
+
+        /// Move the original resolv.conf aside (once - a backup left by a crash is
+        /// the real original) and write one listing only `servers`
+        fn write_resolv_conf(servers: &[String]) -> Result<(), String> {
+            if !Path::new(RESOLV_CONF_BACKUP).exists() {
+                std::fs::rename(RESOLV_CONF, RESOLV_CONF_BACKUP)
+                    .map_err(|e| format!("Failed to back up {}: {}", RESOLV_CONF, e))?;
+            }
+
+            let contents: String = std::iter::once("# Written by PLE7 while connected\n".to_string())
+                .chain(servers.iter().map(|ip| format!("nameserver {}\n", ip)))
+                .collect();
+            if let Err(e) = std::fs::write(RESOLV_CONF, contents) {
+                let _ = Self::restore_resolv_conf();
+                return Err(format!("Failed to write {}: {}", RESOLV_CONF, e));
+            }
+            log::info!("DNS set to {} in {}", servers.join(", "), RESOLV_CONF);
+            Ok(())
+        }
+
+        /// Put back the resolv.conf write_resolv_conf moved aside; no backup is not an error
+        pub fn restore_resolv_conf() -> Result<(), String> {
+            if !Path::new(RESOLV_CONF_BACKUP).exists() {
+                return Ok(());
+            }
+            log::info!("Restoring {}", RESOLV_CONF);
+            std::fs::rename(RESOLV_CONF_BACKUP, RESOLV_CONF)
+                .map_err(|e| format!("Failed to restore {}: {}", RESOLV_CONF, e))
+        }
+
+        pub async fn block_ipv6_leaks(&self, endpoints: &[Ipv6Addr], udp_port: Option<u16>) -> Result<(), String> {
             let name = self.name.clone();
+            let ruleset = ipv6_block_ruleset(&name, endpoints, udp_port);
 
             tokio::task::spawn_blocking(move || {
-                let output = Command::new("ip")
-                    .args([
-                        "route", "add",
-                        &format!("{}/{}", destination, prefix_len),
-                        "dev", &name,
-                    ])
-                    .output()
-                    .map_err(|e| format!("Failed to execute ip route: {}", e))?;
+                Self::remove_ipv6_block()?;
 
-                if !output.status.success() {
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    if !stderr.contains("File exists") {
-                        return Err(format!("Failed to add route: {}", stderr));
-                    }
-                }
-                Ok(())
+                log::info!("Blocking IPv6 egress outside {} (nftables)", name);
+                Self::load_ruleset(&ruleset)
+                    .map_err(|e| format!("Failed to install IPv6 block: {}", e))
             })
             .await
-            .map_err(|e| format!("Route task failed: {}", e))?
+            .map_err(|e| format!("IPv6 block task failed: {}", e))?
         }
 
-        pub async fn set_default_gateway(&self, exclude_ip: Option<&str>) -> Result<(), String> {
-            let name = self.name.clone();
-            let exclude = exclude_ip.map(|s| s.to_string());
+        /// Feed a ruleset to `nft -f -`
+        fn load_ruleset(ruleset: &str) -> Result<(), String> {
+            use std::process::Stdio;
 
-            tokio::task::spawn_blocking(move || {
-                // Get original default gateway for bypass route
-                if let Some(ref ip) = exclude {
-                    // Get current default gateway
-                    let output = Command::new("ip")
-                        .args(["route", "show", "default"])
-                        .output()
-                        .map_err(|e| format!("Failed to get default route: {}", e))?;
+            let mut child = Command::new("nft")
+                .args(["-f", "-"])
+                .stdin(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| format!("Failed to run nft: {}", e))?;
 
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    // Parse "default via X.X.X.X dev ..."
-                    if let Some(gw) = stdout.split_whitespace().skip_while(|&s| s != "via").nth(1) {
-                        // Add bypass route for relay endpoint
-                        log::info!("Adding bypass route for {} via {}", ip, gw);
-                        Command::new("ip")
-                            .args(["route", "add", ip, "via", gw])
-                            .output()
-                            .ok(); // Ignore errors (may already exist)
-                    }
-                }
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(ruleset.as_bytes())
+                    .map_err(|e| format!("Failed to write nftables rules: {}", e))?;
+            }
 
-                // Add split routes for default gateway
-                Command::new("ip")
-                    .args(["route", "add", "0.0.0.0/1", "dev", &name])
-                    .output()
-                    .map_err(|e| format!("Failed to add route: {}", e))?;
+            let output = child.wait_with_output()
+                .map_err(|e| format!("nft failed: {}", e))?;
 
-                Command::new("ip")
-                    .args(["route", "add", "128.0.0.0/1", "dev", &name])
-                    .output()
-                    .map_err(|e| format!("Failed to add route: {}", e))?;
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+            }
+        }
+
+        /// Delete one of our nftables tables; a missing table is not an error
+        fn delete_table(family: &str, table: &str) -> Result<(), String> {
+            let output = Command::new("nft")
+                .args(["delete", "table", family, table])
+                .output()
+                .map_err(|e| format!("Failed to run nft: {}", e))?;
 
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if output.status.success() || stderr.contains("No such file or directory") {
                 Ok(())
-            })
-            .await
-            .map_err(|e| format!("Default gateway task failed: {}", e))?
+            } else {
+                Err(stderr.trim().to_string())
+            }
+        }
+
+        pub fn destroy_stale(name: &str) -> Result<(), String> {
+            // Routes through the device go with it
+            let output = Command::new("ip")
+                .args(["link", "delete", name])
+                .output()
+                .map_err(|e| format!("Failed to run ip: {}", e))?;
+
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if output.status.success() || stderr.contains("Cannot find device") {
+                Ok(())
+            } else {
+                Err(format!("Failed to delete {}: {}", name, stderr.trim()))
+            }
+        }
+
+        pub fn remove_dns_block() -> Result<(), String> {
+            Self::delete_table("inet", DNS_BLOCK_TABLE)
+                .map_err(|e| format!("Failed to remove DNS block: {}", e))
+        }
+
+        pub fn remove_ipv6_block() -> Result<(), String> {
+            Self::delete_table("ip6", IPV6_BLOCK_TABLE)
+                .map_err(|e| format!("Failed to remove IPv6 block: {}", e))
+        }
+    }
+
+    /// nftables ruleset for block_ipv6_leaks
+    pub(super) fn ipv6_block_ruleset(name: &str, endpoints: &[Ipv6Addr], udp_port: Option<u16>) -> String {
+        let mut exemptions = String::new();
+        if let Some(port) = udp_port {
+            exemptions.push_str(&format!("\t\tudp sport {} accept\n", port));
         }
+        if !endpoints.is_empty() {
+            let endpoints: Vec<String> = endpoints.iter().map(|ip| ip.to_string()).collect();
+            exemptions.push_str(&format!("\t\tip6 daddr {{ {} }} accept\n", endpoints.join(", ")));
+        }
+
+        format!(
+            "table ip6 {table} {{\n\
+             \tchain output {{\n\
+             \t\ttype filter hook output priority 0; policy accept;\n\
+             \t\toifname \"{name}\" accept\n\
+             {exemptions}\
+             \t\tip6 daddr 2000::/3 reject\n\
+             \t}}\n\
+             }}\n",
+            table = IPV6_BLOCK_TABLE,
+        )
     }
 }
 
 #[cfg(target_os = "linux")]
 use linux::LinuxTun;
 
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipv6_block_ruleset() {
+        let endpoint: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let ruleset = linux::ipv6_block_ruleset("ple7", &[endpoint], Some(51820));
+        assert!(ruleset.contains("\t\tudp sport 51820 accept\n"), "{}", ruleset);
+        assert!(ruleset.contains("\t\tip6 daddr { 2001:db8::1 } accept\n"), "{}", ruleset);
+        // Exemptions come before the reject
+        assert!(ruleset.find("2001:db8::1").unwrap() < ruleset.find("reject").unwrap());
+
+        let ruleset = linux::ipv6_block_ruleset("ple7", &[], None);
+        assert!(!ruleset.contains("sport") && !ruleset.contains("{  }"), "{}", ruleset);
+    }
+}
+
 // ============================================================================
 // macOS TUN Implementation (via privileged helper daemon)
 // ============================================================================
 
 #[cfg(target_os = "macos")]
+#[cfg_attr(test, allow(dead_code))]
 mod macos {
     use super::*;
     use crate::helper_client::HelperClient;
@@ -245,6 +988,9 @@ mod macos {
     pub struct MacOsTun {
         name: String,
         address: Ipv4Addr,
+        /// A peer takes ::/0 - routed by set_default_gateway() as ::/1 and 8000::/1,
+        /// like IPv4, rather than next to the physical default route
+        default_route_v6: AtomicBool,
     }
 
     impl MacOsTun {
@@ -256,70 +1002,8 @@ mod macos {
             log::info!("macOS: Creating TUN device via helper daemon");
             log::info!("macOS: Address: {}, Netmask: {}", address, netmask);
 
-            // Try to connect to helper and check version
-            let mut client = HelperClient::new();
-            let helper_responsive = client.ping().is_ok();
-            let version_ok = if helper_responsive { client.version_matches() } else { false };
-
-            if !helper_responsive || !version_ok {
-                let needs_upgrade = helper_responsive && !version_ok;
-
-                if needs_upgrade {
-                    log::info!("Helper version mismatch - upgrading to {}", HelperClient::app_version());
-                    // Force full reinstall for version upgrade
-                    HelperClient::install_helper().await?;
-                } else {
-                    log::info!("Helper daemon not responding, checking installation status...");
-
-                    // Clean up stale socket if it exists
-                    if HelperClient::is_running() {
-                        log::info!("Stale socket found, will reinstall helper");
-                    }
-
-                    if HelperClient::is_installed() {
-                        // Helper files exist but not responding - try to restart first
-                        log::info!("Helper installed but not responding, attempting to restart...");
-
-                        // Unload first (ignore errors)
-                        let _ = std::process::Command::new("launchctl")
-                            .args(["unload", "/Library/LaunchDaemons/com.ple7.vpn.helper.plist"])
-                            .output();
-
-                        // Try to load
-                        let _ = std::process::Command::new("launchctl")
-                            .args(["load", "/Library/LaunchDaemons/com.ple7.vpn.helper.plist"])
-                            .output();
-
-                        // Wait for it to start
-                        let mut started = false;
-                        for _ in 0..10 {
-                            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-                            let mut test_client = HelperClient::new();
-                            if test_client.ping().is_ok() {
-                                started = true;
-                                break;
-                            }
-                        }
-
-                        if !started {
-                            // Restart failed, need full reinstall
-                            log::info!("Restart failed, performing full reinstall...");
-                            HelperClient::install_helper().await?;
-                        }
-                    } else {
-                        // Helper not installed at all
-                        log::info!("Helper daemon not installed, prompting for installation...");
-                        HelperClient::install_helper().await?;
-                    }
-                }
-
-                // Verify helper is now working
-                let mut verify_client = HelperClient::new();
-                if let Err(e) = verify_client.ping() {
-                    return Err(format!("Helper installation failed - please try again or restart your Mac: {}", e));
-                }
-                client = verify_client;
-            }
+            // Installs, upgrades or restarts the helper as needed
+            let mut client = HelperClient::ensure_ready().await?;
 
             log::info!("Connected to helper daemon");
 
@@ -350,6 +1034,7 @@ mod macos {
             Ok(Self {
                 name: actual_name,
                 address,
+                default_route_v6: AtomicBool::new(false),
             })
         }
 
@@ -357,7 +1042,7 @@ mod macos {
             let name = self.name.clone();
 
             tokio::task::spawn_blocking(move || {
-                let mut client = HelperClient::new();
+                let mut client = HelperClient::verified()?;
 
                 // Use 5ms timeout for responsive packet processing
                 match client.read_packet(&name, Some(5)) {
@@ -375,7 +1060,7 @@ mod macos {
             let packet = packet.to_vec();
 
             tokio::task::spawn_blocking(move || {
-                let mut client = HelperClient::new();
+                let mut client = HelperClient::verified()?;
                 client.write_packet(&name, &packet)
             })
             .await
@@ -388,7 +1073,7 @@ mod macos {
 
             log::info!("Adding route {}/{} via helper", dest, prefix_len);
 
-            let mut client = HelperClient::new();
+            let mut client = HelperClient::verified()?;
             let response = client.add_route(&dest, prefix_len, &address)?;
 
             if response.success {
@@ -398,6 +1083,21 @@ mod macos {
             }
         }
 
+        pub async fn remove_route(&self, destination: Ipv4Addr, prefix_len: u8) -> Result<(), String> {
+            let dest = destination.to_string();
+
+            log::info!("Removing route {}/{} via helper", dest, prefix_len);
+
+            let mut client = HelperClient::verified()?;
+            let response = client.remove_route(&dest, prefix_len)?;
+
+            if response.success {
+                Ok(())
+            } else {
+                Err(format!("Failed to remove route: {}", response.message))
+            }
+        }
+
         pub async fn set_default_gateway(&self, exclude_ip: Option<&str>) -> Result<(), String> {
             let address = self.address.to_string();
 
@@ -406,8 +1106,10 @@ mod macos {
                 log::info!("Excluding {} from VPN routing (bypass route)", ip);
             }
 
-            let mut client = HelperClient::new();
-            let response = client.set_default_gateway(&address, exclude_ip)?;
+            // IPv6 stays on the physical interface unless the tunnel carries ::/0
+            let include_ipv6 = self.default_route_v6.load(Ordering::Relaxed);
+            let mut client = HelperClient::verified()?;
+            let response = client.set_default_gateway(&address, exclude_ip, include_ipv6)?;
 
             if response.success {
                 Ok(())
@@ -415,6 +1117,187 @@ mod macos {
                 Err(format!("Failed to set default gateway: {}", response.message))
             }
         }
+
+        pub async fn clear_default_gateway(&self) -> Result<(), String> {
+            log::info!("Restoring original default gateway via helper");
+
+            let mut client = HelperClient::verified()?;
+            let response = client.restore_default_gateway()?;
+
+            if response.success {
+                Ok(())
+            } else {
+                Err(format!("Failed to restore default gateway: {}", response.message))
+            }
+        }
+
+        pub async fn set_ipv6_address(&self, address: Ipv6Addr, prefix_len: u8) -> Result<(), String> {
+            log::info!("Setting IPv6 address {}/{} via helper", address, prefix_len);
+
+            let mut client = HelperClient::verified()?;
+            let response = client.set_ipv6_address(&self.name, &address, prefix_len)?;
+
+            if response.success {
+                Ok(())
+            } else {
+                Err(format!("Failed to set IPv6 address: {}", response.message))
+            }
+        }
+
+        /// The helper finds the utun by its IPv4 address and adds an -inet6 route to it
+        pub async fn add_route_v6(&self, destination: Ipv6Addr, prefix_len: u8) -> Result<(), String> {
+            if prefix_len == 0 {
+                log::info!("IPv6 default route deferred to set_default_gateway");
+                self.default_route_v6.store(true, Ordering::Relaxed);
+                return Ok(());
+            }
+            let address = self.address.to_string();
+            let dest = destination.to_string();
+
+            log::info!("Adding route {}/{} via helper", dest, prefix_len);
+
+            let mut client = HelperClient::verified()?;
+            let response = client.add_route(&dest, prefix_len, &address)?;
+
+            if response.success {
+                Ok(())
+            } else {
+                Err(format!("Failed to add IPv6 route: {}", response.message))
+            }
+        }
+
+        pub async fn remove_route_v6(&self, destination: Ipv6Addr, prefix_len: u8) -> Result<(), String> {
+            if prefix_len == 0 {
+                self.default_route_v6.store(false, Ordering::Relaxed);
+                return Ok(());
+            }
+            let dest = destination.to_string();
+
+            log::info!("Removing route {}/{} via helper", dest, prefix_len);
+
+            let mut client = HelperClient::verified()?;
+            let response = client.remove_route(&dest, prefix_len)?;
+
+            if response.success {
+                Ok(())
+            } else {
+                Err(format!("Failed to remove IPv6 route: {}", response.message))
+            }
+        }
+
+        pub async fn set_mtu(&self, mtu: usize) -> Result<(), String> {
+            let mut client = HelperClient::verified()?;
+            let response = client.set_mtu(&self.name, mtu as u16)?;
+
+            if response.success {
+                Ok(())
+            } else {
+                Err(format!("Failed to set MTU: {}", response.message))
+            }
+        }
+
+        pub async fn block_dns_leaks(&self, dns: &[Ipv4Addr]) -> Result<(), String> {
+            let dns = dns.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(",");
+            log::info!("Blocking DNS except {} via helper (pf)", dns);
+
+            let mut client = HelperClient::verified()?;
+            let response = client.block_dns(&self.name, &dns)?;
+
+            if response.success {
+                Ok(())
+            } else {
+                Err(format!("Failed to block DNS leaks: {}", response.message))
+            }
+        }
+
+        pub async fn set_dns(&self, servers: &[Ipv4Addr]) -> Result<(), String> {
+            let servers = servers.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(",");
+            log::info!("Setting DNS to {} via helper (scutil)", servers);
+
+            let mut client = HelperClient::verified()?;
+            let response = client.set_dns(&self.name, &servers)?;
+
+            if response.success {
+                Ok(())
+            } else {
+                Err(format!("Failed to set DNS: {}", response.message))
+            }
+        }
+
+        pub async fn restore_dns(&self) -> Result<(), String> {
+            log::info!("Restoring DNS via helper");
+
+            let mut client = HelperClient::verified()?;
+            let response = client.restore_dns()?;
+
+            if response.success {
+                Ok(())
+            } else {
+                Err(format!("Failed to restore DNS: {}", response.message))
+            }
+        }
+
+        pub async fn block_ipv6_leaks(&self, endpoints: &[Ipv6Addr], udp_port: Option<u16>) -> Result<(), String> {
+            log::info!("Blocking IPv6 egress outside {} via helper (pf)", self.name);
+
+            let mut client = HelperClient::verified()?;
+            let response = client.block_ipv6(&self.name, endpoints, udp_port)?;
+
+            if response.success {
+                Ok(())
+            } else {
+                Err(format!("Failed to block IPv6 leaks: {}", response.message))
+            }
+        }
+
+        /// The kernel picks the utun name, so `_name` can't identify the device -
+        /// every TUN the helper holds belongs to this app and gets destroyed
+        pub fn destroy_stale(_name: &str) -> Result<(), String> {
+            if !HelperClient::is_running() {
+                return Ok(());
+            }
+
+            let mut client = HelperClient::verified()?;
+            let response = client.restore_default_gateway()?;
+            if !response.success {
+                return Err(format!("Failed to restore default gateway: {}", response.message));
+            }
+
+            for tun in client.active_tuns()? {
+                log::info!("Destroying stale TUN device: {}", tun);
+                let response = client.destroy_tun(&tun)?;
+                if !response.success {
+                    log::warn!("Failed to destroy {}: {}", tun, response.message);
+                }
+            }
+            Ok(())
+        }
+
+        pub fn remove_dns_block() -> Result<(), String> {
+            if !HelperClient::is_running() {
+                return Ok(());
+            }
+
+            let response = HelperClient::verified()?.unblock_dns()?;
+            if response.success {
+                Ok(())
+            } else {
+                Err(format!("Failed to remove DNS block: {}", response.message))
+            }
+        }
+
+        pub fn remove_ipv6_block() -> Result<(), String> {
+            if !HelperClient::is_running() {
+                return Ok(());
+            }
+
+            let response = HelperClient::verified()?.unblock_ipv6()?;
+            if response.success {
+                Ok(())
+            } else {
+                Err(format!("Failed to remove IPv6 block: {}", response.message))
+            }
+        }
     }
 
     impl Drop for MacOsTun {
@@ -448,13 +1331,25 @@ use macos::MacOsTun;
 // ============================================================================
 
 #[cfg(target_os = "windows")]
+#[cfg_attr(test, allow(dead_code))]
 mod windows {
     use super::*;
     use wintun::{Adapter, Session};
     use std::sync::Arc;
 
     const WINTUN_POOL: &str = "PLE7";
-    const RING_CAPACITY: u32 = 0x400000; // 4MB ring buffer
+    /// Allocation attempts in a full send ring before the packet is dropped,
+    /// backing off a little longer each time for Windows to drain it
+    const SEND_ATTEMPTS: u32 = 4;
+    const SEND_BACKOFF: std::time::Duration = std::time::Duration::from_micros(250);
+    /// WintunAllocateSendPacket's error when the ring is full
+    const ERROR_BUFFER_OVERFLOW: i32 = 111;
+    /// Shortest gap between drop-rate log lines while the ring overflows
+    const DROP_LOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+    /// Windows Firewall rule name for the DNS leak block
+    const DNS_BLOCK_RULE: &str = "PLE7 DNS leak block";
+    /// Windows Firewall rule name for the IPv6 leak block
+    const IPV6_BLOCK_RULE: &str = "PLE7 IPv6 leak block";
 
     pub struct WindowsTun {
         session: Arc<Session>,
@@ -467,6 +1362,53 @@ mod windows {
         interface_index: u32,
         /// Original default gateway saved before VPN routes are added
         original_gateway: Option<String>,
+        ring_capacity: u32,
+        send_stats: Arc<SendStats>,
+    }
+
+    /// Packets written to the send ring and dropped because it stayed full
+    struct SendStats {
+        sent: std::sync::atomic::AtomicU64,
+        dropped: std::sync::atomic::AtomicU64,
+        /// When the drop rate was last logged, and (sent, dropped) at that point
+        last_report: Mutex<(std::time::Instant, u64, u64)>,
+    }
+
+    impl SendStats {
+        fn new() -> Self {
+            Self {
+                sent: Default::default(),
+                dropped: Default::default(),
+                last_report: Mutex::new((std::time::Instant::now(), 0, 0)),
+            }
+        }
+
+        /// Count a dropped packet and log the drop rate at most every DROP_LOG_INTERVAL
+        fn record_drop(&self, ring_capacity: u32) {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            let sent = self.sent.load(Ordering::Relaxed);
+
+            let mut last = self.last_report.lock();
+            let (at, last_sent, last_dropped) = *last;
+            if at.elapsed() < DROP_LOG_INTERVAL {
+                return;
+            }
+            // Another writer may have logged (and moved the baseline) since the loads above
+            let (window_sent, window_dropped) = (sent.saturating_sub(last_sent), dropped.saturating_sub(last_dropped));
+            if window_dropped == 0 {
+                return;
+            }
+            log::warn!(
+                "[TUN] Wintun send ring full: dropped {} of {} packets ({:.1}%) in the last {}s, {} total (ring {} KiB)",
+                window_dropped,
+                window_sent + window_dropped,
+                window_dropped as f64 * 100.0 / (window_sent + window_dropped) as f64,
+                at.elapsed().as_secs(),
+                dropped,
+                ring_capacity / 1024,
+            );
+            *last = (std::time::Instant::now(), sent, dropped);
+        }
     }
 
     impl WindowsTun {
@@ -512,7 +1454,7 @@ mod windows {
             name: &str,
             address: Ipv4Addr,
             netmask: Ipv4Addr,
-        ) -> Result<Self, String> {
+        ) -> Result<Self, PleError> {
             // CRITICAL: Capture original default gateway BEFORE any Wintun operations
             // Must be done first because creating adapter can leave stale routes
             let original_gateway = Self::get_original_gateway();
@@ -555,39 +1497,83 @@ mod windows {
                             adapter
                         }
                         Err(e2) => {
-                            return Err(format!(
-                                "Failed to create or open Wintun adapter. \
-                                Create error: {}. Open error: {}. \
-                                Please ensure you're running as Administrator and no other VPN is using Wintun.",
-                                e, e2
+                            return Err(Self::adapter_in_use(
+                                name,
+                                format!("create error: {}, open error: {}", e, e2),
                             ));
                         }
                     }
                 }
             };
 
-            // Configure IP address using netsh
-            Self::configure_address(&adapter, name, address, netmask)?;
-
-            // Get interface index for routing
-            let interface_index = Self::get_interface_index(name)?;
+            // Get interface index for routing - also what netsh is pointed at, as
+            // the adapter may not have ended up with the name we asked for
+            let interface_index = Self::get_interface_index(&adapter, name)?;
             log::info!("Wintun adapter interface index: {}", interface_index);
 
+            // Configure IP address using netsh
+            Self::configure_address(interface_index, address, netmask)?;
+
             // Start session
-            let session = adapter.start_session(RING_CAPACITY)
-                .map_err(|e| format!("Failed to start Wintun session: {}", e))?;
+            // Only one session per adapter - failing here means someone else has it open
+            let ring_capacity = ring_capacity();
+            let session = adapter.start_session(ring_capacity)
+                .map_err(|e| Self::adapter_in_use(name, format!("failed to start session: {}", e)))?;
+
+            log::info!("Windows TUN device created: {} (IF {}, ring {} KiB)", name, interface_index, ring_capacity / 1024);
+
+            Ok(Self {
+                session: Arc::new(session),
+                adapter, // Already Arc<Adapter>
+                name: name.to_string(),
+                address,
+                netmask,
+                interface_index,
+                original_gateway,
+                ring_capacity,
+                send_stats: Arc::new(SendStats::new()),
+            })
+        }
+
+        fn adapter_in_use(name: &str, detail: String) -> PleError {
+            let holders = Self::wintun_holders();
+            let holder = if holders.is_empty() { "another app".to_string() } else { holders.join(", ") };
+            PleError::AdapterInUse(format!(
+                "Wintun adapter '{}' is in use by {} ({}). Close the other VPN and try again - \
+                if none is running, make sure the app runs as Administrator.",
+                name, holder, detail
+            ))
+        }
+
+        /// Other processes with wintun.dll loaded, as "name (pid)". Wintun doesn't
+        /// say who owns an adapter, so this is the best guess; empty if unknown.
+        fn wintun_holders() -> Vec<String> {
+            use std::process::Command;
+            use std::os::windows::process::CommandExt;
 
-            log::info!("Windows TUN device created: {} (IF {})", name, interface_index);
+            const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+            let script = "Get-Process | Where-Object { $_.Modules.ModuleName -contains 'wintun.dll' } | \
+                ForEach-Object { \"$($_.ProcessName) ($($_.Id))\" }";
+            let output = match Command::new("powershell")
+                .args(["-NoProfile", "-NonInteractive", "-Command", script])
+                .creation_flags(CREATE_NO_WINDOW)
+                .output()
+            {
+                Ok(output) => output,
+                Err(e) => {
+                    log::warn!("Failed to list Wintun users: {}", e);
+                    return Vec::new();
+                }
+            };
 
-            Ok(Self {
-                session: Arc::new(session),
-                adapter, // Already Arc<Adapter>
-                name: name.to_string(),
-                address,
-                netmask,
-                interface_index,
-                original_gateway,
-            })
+            let own = format!("({})", std::process::id());
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.ends_with(&own))
+                .map(str::to_string)
+                .collect()
         }
 
         /// Get the original default gateway before VPN routes are added
@@ -657,14 +1643,34 @@ mod windows {
             }
         }
 
-        /// Get interface index by name using multiple methods for reliability
-        fn get_interface_index(name: &str) -> Result<u32, String> {
+        /// Get the adapter's interface index, preferring the IP Helper API over parsing tool output
+        fn get_interface_index(adapter: &Adapter, name: &str) -> Result<u32, String> {
             use std::process::Command;
             use std::os::windows::process::CommandExt;
+            use windows::Win32::NetworkManagement::Ndis::NET_LUID_LH;
 
             const CREATE_NO_WINDOW: u32 = 0x08000000;
 
-            // Method 1: Try PowerShell (most reliable)
+            // Method 1: LUID reported by Wintun for this exact adapter
+            let luid = NET_LUID_LH { Value: unsafe { adapter.get_luid().Value } };
+            match Self::luid_to_index(&luid) {
+                Ok(idx) => {
+                    log::info!("Adapter LUID: interface index = {}", idx);
+                    return Ok(idx);
+                }
+                Err(e) => log::warn!("Adapter LUID lookup failed: {}", e),
+            }
+
+            // Method 2: resolve the interface alias to a LUID
+            match Self::alias_to_luid(name).and_then(|luid| Self::luid_to_index(&luid)) {
+                Ok(idx) => {
+                    log::info!("Alias lookup: interface index = {}", idx);
+                    return Ok(idx);
+                }
+                Err(e) => log::warn!("Interface alias lookup failed: {}", e),
+            }
+
+            // Method 3: Try PowerShell
             log::info!("Getting interface index for '{}' via PowerShell...", name);
             let ps_output = Command::new("powershell")
                 .args([
@@ -682,7 +1688,7 @@ mod windows {
                 }
             }
 
-            // Method 2: Try netsh interface show interface
+            // Method 4: Try netsh interface show interface
             log::info!("Trying netsh method...");
             let output = Command::new("netsh")
                 .args(["interface", "ipv4", "show", "interfaces"])
@@ -708,7 +1714,7 @@ mod windows {
                 }
             }
 
-            // Method 3: Try route print to find interface by IP address
+            // Method 5: Try route print to find interface by IP address
             log::info!("Trying route print method...");
             let route_output = Command::new("route")
                 .args(["print"])
@@ -735,19 +1741,52 @@ mod windows {
                 }
             }
 
-            // Default: return 0 and log warning
-            log::warn!("Could not find interface index for '{}', routing may fail", name);
-            Ok(0)
+            // Routes added against index 0 would silently go nowhere
+            Err(format!("Could not find interface index for adapter '{}'", name))
+        }
+
+        fn luid_to_index(luid: &windows::Win32::NetworkManagement::Ndis::NET_LUID_LH) -> Result<u32, String> {
+            use windows::Win32::NetworkManagement::IpHelper::ConvertInterfaceLuidToIndex;
+
+            let mut index = 0u32;
+            let result = unsafe { ConvertInterfaceLuidToIndex(luid, &mut index) };
+            if result.is_err() {
+                return Err(format!("ConvertInterfaceLuidToIndex failed: error {}", result.0));
+            }
+            if index == 0 {
+                return Err("ConvertInterfaceLuidToIndex returned index 0".to_string());
+            }
+            Ok(index)
+        }
+
+        fn alias_to_luid(name: &str) -> Result<windows::Win32::NetworkManagement::Ndis::NET_LUID_LH, String> {
+            use std::ffi::OsStr;
+            use std::os::windows::ffi::OsStrExt;
+            use windows::Win32::NetworkManagement::IpHelper::ConvertInterfaceAliasToLuid;
+            use windows::Win32::NetworkManagement::Ndis::NET_LUID_LH;
+            use windows::core::PCWSTR;
+
+            let alias: Vec<u16> = OsStr::new(name)
+                .encode_wide()
+                .chain(std::iter::once(0))
+                .collect();
+
+            let mut luid = NET_LUID_LH::default();
+            let result = unsafe { ConvertInterfaceAliasToLuid(PCWSTR::from_raw(alias.as_ptr()), &mut luid) };
+            if result.is_err() {
+                return Err(format!("ConvertInterfaceAliasToLuid failed: error {}", result.0));
+            }
+            Ok(luid)
         }
 
-        fn configure_address(_adapter: &Adapter, name: &str, address: Ipv4Addr, netmask: Ipv4Addr) -> Result<(), String> {
+        fn configure_address(if_index: u32, address: Ipv4Addr, netmask: Ipv4Addr) -> Result<(), String> {
             use std::process::Command;
 
             // Use netsh to set IP address
             let output = Command::new("netsh")
                 .args([
                     "interface", "ip", "set", "address",
-                    &format!("name={}", name),
+                    &format!("name={}", if_index),
                     "static",
                     &address.to_string(),
                     &netmask.to_string(),
@@ -777,16 +1816,32 @@ mod windows {
             .map_err(|e| format!("Read task failed: {}", e))?
         }
 
+        /// A full send ring is retried briefly, then the packet is dropped (and
+        /// counted) like a full NIC queue would - only other failures are errors
         pub async fn write(&self, packet: &[u8]) -> Result<(), String> {
             let session = self.session.clone();
+            let send_stats = self.send_stats.clone();
+            let ring_capacity = self.ring_capacity;
             let packet_data = packet.to_vec();
 
             tokio::task::spawn_blocking(move || {
-                let mut write_packet = session.allocate_send_packet(packet_data.len() as u16)
-                    .map_err(|e| format!("Failed to allocate packet: {}", e))?;
-
-                write_packet.bytes_mut().copy_from_slice(&packet_data);
-                session.send_packet(write_packet);
+                for attempt in 1..=SEND_ATTEMPTS {
+                    match session.allocate_send_packet(packet_data.len() as u16) {
+                        Ok(mut write_packet) => {
+                            write_packet.bytes_mut().copy_from_slice(&packet_data);
+                            session.send_packet(write_packet);
+                            send_stats.sent.fetch_add(1, Ordering::Relaxed);
+                            return Ok(());
+                        }
+                        Err(wintun::Error::Io(e)) if e.raw_os_error() == Some(ERROR_BUFFER_OVERFLOW) => {
+                            if attempt < SEND_ATTEMPTS {
+                                std::thread::sleep(SEND_BACKOFF * attempt);
+                            }
+                        }
+                        Err(e) => return Err(format!("Failed to allocate packet: {}", e)),
+                    }
+                }
+                send_stats.record_drop(ring_capacity);
                 Ok(())
             })
             .await
@@ -835,6 +1890,42 @@ mod windows {
             .map_err(|e| format!("Route task failed: {}", e))?
         }
 
+        pub async fn remove_route(&self, destination: Ipv4Addr, prefix_len: u8) -> Result<(), String> {
+            let if_index = self.interface_index;
+
+            tokio::task::spawn_blocking(move || {
+                use std::process::Command;
+                use std::os::windows::process::CommandExt;
+
+                const CREATE_NO_WINDOW: u32 = 0x08000000;
+                let mask = Self::prefix_to_mask(prefix_len);
+
+                log::info!("Removing route: {}/{} IF {}", destination, prefix_len, if_index);
+
+                let output = Command::new("route")
+                    .args([
+                        "delete",
+                        &destination.to_string(),
+                        "mask",
+                        &mask.to_string(),
+                        "IF",
+                        &if_index.to_string(),
+                    ])
+                    .creation_flags(CREATE_NO_WINDOW)
+                    .output()
+                    .map_err(|e| format!("Failed to execute route: {}", e))?;
+
+                if !output.status.success() {
+                    let stdout = String::from_utf8_lossy(&output.stdout);
+                    log::warn!("Route delete warning: {}", stdout);
+                }
+
+                Ok(())
+            })
+            .await
+            .map_err(|e| format!("Route task failed: {}", e))?
+        }
+
         pub async fn set_default_gateway(&self, exclude_ip: Option<&str>) -> Result<(), String> {
             let address = self.address;
             let exclude = exclude_ip.map(|s| s.to_string());
@@ -938,6 +2029,417 @@ mod windows {
             .map_err(|e| format!("Default gateway task failed: {}", e))?
         }
 
+        pub async fn clear_default_gateway(&self) -> Result<(), String> {
+            self.remove_route(Ipv4Addr::new(0, 0, 0, 0), 1).await?;
+            self.remove_route(Ipv4Addr::new(128, 0, 0, 0), 1).await
+        }
+
+        pub async fn set_ipv6_address(&self, address: Ipv6Addr, prefix_len: u8) -> Result<(), String> {
+            let if_index = self.interface_index;
+
+            tokio::task::spawn_blocking(move || {
+                Self::netsh_ipv6(&[
+                    "add", "address",
+                    &format!("interface={}", if_index),
+                    &format!("address={}/{}", address, prefix_len),
+                    "store=active",
+                ])
+                .map_err(|e| format!("Failed to add IPv6 address: {}", e))
+            })
+            .await
+            .map_err(|e| format!("Address task failed: {}", e))?
+        }
+
+        pub async fn add_route_v6(&self, destination: Ipv6Addr, prefix_len: u8) -> Result<(), String> {
+            let if_index = self.interface_index;
+
+            tokio::task::spawn_blocking(move || {
+                log::info!("Adding route: {}/{} IF {}", destination, prefix_len, if_index);
+                Self::netsh_ipv6(&[
+                    "add", "route",
+                    &format!("prefix={}/{}", destination, prefix_len),
+                    &format!("interface={}", if_index),
+                    "metric=1",
+                    "store=active",
+                ])
+                .map_err(|e| format!("Failed to add IPv6 route: {}", e))
+            })
+            .await
+            .map_err(|e| format!("Route task failed: {}", e))?
+        }
+
+        pub async fn remove_route_v6(&self, destination: Ipv6Addr, prefix_len: u8) -> Result<(), String> {
+            let if_index = self.interface_index;
+
+            tokio::task::spawn_blocking(move || {
+                log::info!("Removing route: {}/{} IF {}", destination, prefix_len, if_index);
+                Self::netsh_ipv6(&[
+                    "delete", "route",
+                    &format!("prefix={}/{}", destination, prefix_len),
+                    &format!("interface={}", if_index),
+                ])
+                .map_err(|e| format!("Failed to remove IPv6 route: {}", e))
+            })
+            .await
+            .map_err(|e| format!("Route task failed: {}", e))?
+        }
+
+        /// Run `netsh interface ipv6 <args>`. An object that already exists counts as success.
+        fn netsh_ipv6(args: &[&str]) -> Result<(), String> {
+            use std::process::Command;
+            use std::os::windows::process::CommandExt;
+
+            const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+            let output = Command::new("netsh")
+                .args(["interface", "ipv6"])
+                .args(args)
+                .creation_flags(CREATE_NO_WINDOW)
+                .output()
+                .map_err(|e| format!("Failed to run netsh: {}", e))?;
+
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            if output.status.success() || stdout.contains("already exists") {
+                Ok(())
+            } else {
+                Err(stdout.trim().to_string())
+            }
+        }
+
+        pub async fn set_mtu(&self, mtu: usize) -> Result<(), String> {
+            let if_index = self.interface_index;
+
+            tokio::task::spawn_blocking(move || {
+                use std::process::Command;
+                use std::os::windows::process::CommandExt;
+
+                const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+                let output = Command::new("netsh")
+                    .args([
+                        "interface", "ipv4", "set", "subinterface",
+                        &if_index.to_string(),
+                        &format!("mtu={}", mtu),
+                        "store=active",
+                    ])
+                    .creation_flags(CREATE_NO_WINDOW)
+                    .output()
+                    .map_err(|e| format!("Failed to run netsh: {}", e))?;
+
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    Err(format!("Failed to set MTU: {}", String::from_utf8_lossy(&output.stdout).trim()))
+                }
+            })
+            .await
+            .map_err(|e| format!("MTU task failed: {}", e))?
+        }
+
+        /// Set static resolvers on the adapter. Addressed by interface index, since
+        /// Windows may have given the Wintun adapter a different name than requested.
+        pub async fn set_dns(&self, servers: &[Ipv4Addr]) -> Result<(), String> {
+            let if_index = self.interface_index;
+            let servers = servers.to_vec();
+
+            tokio::task::spawn_blocking(move || {
+                use std::process::Command;
+                use std::os::windows::process::CommandExt;
+
+                const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+                let Some((primary, secondaries)) = servers.split_first() else {
+                    return Ok(());
+                };
+                log::info!("Setting DNS on IF {} to {:?}", if_index, servers);
+
+                let output = Command::new("netsh")
+                    .args([
+                        "interface", "ip", "set", "dns",
+                        &format!("name={}", if_index),
+                        "static",
+                        &primary.to_string(),
+                        "register=none",
+                        "validate=no",
+                    ])
+                    .creation_flags(CREATE_NO_WINDOW)
+                    .output()
+                    .map_err(|e| format!("Failed to run netsh: {}", e))?;
+
+                if !output.status.success() {
+                    let stdout = String::from_utf8_lossy(&output.stdout);
+                    return Err(format!("Failed to set DNS server {}: {}", primary, stdout.trim()));
+                }
+
+                for (i, server) in secondaries.iter().enumerate() {
+                    let output = Command::new("netsh")
+                        .args([
+                            "interface", "ip", "add", "dns",
+                            &format!("name={}", if_index),
+                            &server.to_string(),
+                            &format!("index={}", i + 2),
+                            "validate=no",
+                        ])
+                        .creation_flags(CREATE_NO_WINDOW)
+                        .output()
+                        .map_err(|e| format!("Failed to run netsh: {}", e))?;
+
+                    if !output.status.success() {
+                        let stdout = String::from_utf8_lossy(&output.stdout);
+                        log::warn!("Failed to add DNS server {}: {}", server, stdout.trim());
+                    }
+                }
+
+                Ok(())
+            })
+            .await
+            .map_err(|e| format!("DNS task failed: {}", e))?
+        }
+
+        /// Switch the adapter's resolvers back to DHCP
+        pub async fn restore_dns(&self) -> Result<(), String> {
+            let if_index = self.interface_index;
+
+            tokio::task::spawn_blocking(move || {
+                use std::process::Command;
+                use std::os::windows::process::CommandExt;
+
+                const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+                log::info!("Restoring DHCP DNS on IF {}", if_index);
+                let output = Command::new("netsh")
+                    .args(["interface", "ip", "set", "dns", &format!("name={}", if_index), "dhcp"])
+                    .creation_flags(CREATE_NO_WINDOW)
+                    .output()
+                    .map_err(|e| format!("Failed to run netsh: {}", e))?;
+
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    let stdout = String::from_utf8_lossy(&output.stdout);
+                    Err(format!("Failed to restore DNS: {}", stdout.trim()))
+                }
+            })
+            .await
+            .map_err(|e| format!("DNS task failed: {}", e))?
+        }
+
+        /// Windows Firewall block rules always beat allow rules, so instead of
+        /// allowing the tunnel DNS we block every remote address except it
+        pub async fn block_dns_leaks(&self, dns: &[Ipv4Addr]) -> Result<(), String> {
+            let dns = dns.to_vec();
+
+            tokio::task::spawn_blocking(move || {
+                use std::process::Command;
+                use std::os::windows::process::CommandExt;
+
+                const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+                Self::remove_dns_block()?;
+
+                // The tunnel DNS servers are IPv4, so IPv6 DNS is blocked outright
+                let remote_ips = [Self::all_ipv4_except(&dns), "::/0".to_string()];
+                log::info!("Blocking DNS except {:?} (Windows Firewall)", dns);
+
+                for remote_ip in &remote_ips {
+                    for protocol in ["UDP", "TCP"] {
+                        let output = Command::new("netsh")
+                            .args([
+                                "advfirewall", "firewall", "add", "rule",
+                                &format!("name={}", DNS_BLOCK_RULE),
+                                "dir=out",
+                                "action=block",
+                                &format!("protocol={}", protocol),
+                                "remoteport=53",
+                                &format!("remoteip={}", remote_ip),
+                            ])
+                            .creation_flags(CREATE_NO_WINDOW)
+                            .output()
+                            .map_err(|e| format!("Failed to run netsh: {}", e))?;
+
+                        if !output.status.success() {
+                            let stdout = String::from_utf8_lossy(&output.stdout);
+                            return Err(format!("Failed to add {} DNS block rule for {}: {}", protocol, remote_ip, stdout.trim()));
+                        }
+                    }
+                }
+
+                Ok(())
+            })
+            .await
+            .map_err(|e| format!("DNS block task failed: {}", e))?
+        }
+
+        /// The tunnel adapter has no IPv6 address when this is used, so rules covering
+        /// every interface are enough. Block rules beat allow rules, so WireGuard's
+        /// traffic is carved out of the blocked ranges and ports instead.
+        pub async fn block_ipv6_leaks(&self, endpoints: &[Ipv6Addr], udp_port: Option<u16>) -> Result<(), String> {
+            let remote_ips = Self::internet_ipv6_except(endpoints);
+
+            tokio::task::spawn_blocking(move || {
+                use std::process::Command;
+                use std::os::windows::process::CommandExt;
+
+                const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+                Self::remove_ipv6_block()?;
+
+                // Without a WireGuard port to spare one rule covers every protocol;
+                // ports can only be given per protocol
+                let rules: Vec<(&str, Option<String>)> = match udp_port {
+                    None => vec![("any", None)],
+                    Some(port) => vec![
+                        ("TCP", None),
+                        ("ICMPv6", None),
+                        ("UDP", Some(Self::ports_except(port))),
+                    ],
+                };
+
+                log::info!("Blocking IPv6 egress (Windows Firewall)");
+                for (protocol, local_ports) in rules {
+                    let mut args = vec![
+                        "advfirewall".to_string(), "firewall".to_string(), "add".to_string(), "rule".to_string(),
+                        format!("name={}", IPV6_BLOCK_RULE),
+                        "dir=out".to_string(),
+                        "action=block".to_string(),
+                        format!("protocol={}", protocol),
+                        format!("remoteip={}", remote_ips),
+                    ];
+                    if let Some(ports) = local_ports {
+                        args.push(format!("localport={}", ports));
+                    }
+
+                    let output = Command::new("netsh")
+                        .args(&args)
+                        .creation_flags(CREATE_NO_WINDOW)
+                        .output()
+                        .map_err(|e| format!("Failed to run netsh: {}", e))?;
+
+                    if !output.status.success() {
+                        let stdout = String::from_utf8_lossy(&output.stdout);
+                        return Err(format!("Failed to add {} IPv6 block rule: {}", protocol, stdout.trim()));
+                    }
+                }
+                Ok(())
+            })
+            .await
+            .map_err(|e| format!("IPv6 block task failed: {}", e))?
+        }
+
+        /// Closing an opened adapter only releases our handle - Wintun removes an
+        /// adapter when the handle that created it closes. Creating one under the
+        /// same name makes Wintun clean up adapters whose owning process is gone,
+        /// and dropping that new one removes it.
+        pub fn destroy_stale(name: &str) -> Result<(), String> {
+            let wintun = Self::load_wintun()?;
+            match Adapter::open(&wintun, name) {
+                Ok(adapter) => drop(adapter),
+                Err(e) => {
+                    log::debug!("No stale Wintun adapter '{}': {}", name, e);
+                    return Ok(());
+                }
+            }
+
+            log::info!("Removing stale Wintun adapter '{}'", name);
+            match Adapter::create(&wintun, WINTUN_POOL, name, None) {
+                Ok(adapter) => drop(adapter),
+                Err(e) => log::warn!("Failed to take over stale Wintun adapter '{}': {}", name, e),
+            }
+
+            if Adapter::open(&wintun, name).is_ok() {
+                log::warn!("Stale Wintun adapter '{}' is still present", name);
+                return Err(format!("Could not remove stale network adapter '{}' - is another VPN session still running?", name));
+            }
+            log::info!("Stale Wintun adapter '{}' removed", name);
+            Ok(())
+        }
+
+        pub fn remove_dns_block() -> Result<(), String> {
+            use std::process::Command;
+            use std::os::windows::process::CommandExt;
+
+            // Deletes every rule with this name; fails harmlessly if there are none
+            Command::new("netsh")
+                .args(["advfirewall", "firewall", "delete", "rule", &format!("name={}", DNS_BLOCK_RULE)])
+                .creation_flags(0x08000000)
+                .output()
+                .map_err(|e| format!("Failed to run netsh: {}", e))?;
+
+            Ok(())
+        }
+
+        pub fn remove_ipv6_block() -> Result<(), String> {
+            use std::process::Command;
+            use std::os::windows::process::CommandExt;
+
+            Command::new("netsh")
+                .args(["advfirewall", "firewall", "delete", "rule", &format!("name={}", IPV6_BLOCK_RULE)])
+                .creation_flags(0x08000000)
+                .output()
+                .map_err(|e| format!("Failed to run netsh: {}", e))?;
+
+            Ok(())
+        }
+
+        /// netsh remoteip ranges covering all of IPv4 except the `excluded` addresses
+        fn all_ipv4_except(excluded: &[Ipv4Addr]) -> String {
+            let mut excluded: Vec<u32> = excluded.iter().map(|ip| u32::from(*ip)).collect();
+            excluded.sort_unstable();
+            excluded.dedup();
+
+            let mut ranges = Vec::new();
+            // First address not yet covered, None once past 255.255.255.255
+            let mut next = Some(0u32);
+            for n in excluded {
+                if let Some(from) = next.filter(|from| *from < n) {
+                    ranges.push(format!("{}-{}", Ipv4Addr::from(from), Ipv4Addr::from(n - 1)));
+                }
+                next = n.checked_add(1);
+            }
+            if let Some(from) = next {
+                ranges.push(format!("{}-255.255.255.255", Ipv4Addr::from(from)));
+            }
+            ranges.join(",")
+        }
+
+        /// netsh remoteip ranges covering internet IPv6 (2000::/3) except the `excluded` addresses
+        fn internet_ipv6_except(excluded: &[Ipv6Addr]) -> String {
+            const FIRST: u128 = 0x2000 << 112;
+            const LAST: u128 = (0x4000 << 112) - 1;
+
+            let mut excluded: Vec<u128> = excluded.iter()
+                .map(|ip| u128::from(*ip))
+                .filter(|n| (FIRST..=LAST).contains(n))
+                .collect();
+            excluded.sort_unstable();
+            excluded.dedup();
+
+            let mut ranges = Vec::new();
+            let mut next = FIRST;
+            for n in excluded {
+                if next < n {
+                    ranges.push(format!("{}-{}", Ipv6Addr::from(next), Ipv6Addr::from(n - 1)));
+                }
+                next = n + 1;
+            }
+            if next <= LAST {
+                ranges.push(format!("{}-{}", Ipv6Addr::from(next), Ipv6Addr::from(LAST)));
+            }
+            ranges.join(",")
+        }
+
+        /// netsh localport ranges covering every port but `port`
+        fn ports_except(port: u16) -> String {
+            let mut ranges = Vec::new();
+            if port > 1 {
+                ranges.push(format!("1-{}", port - 1));
+            }
+            if port < u16::MAX {
+                ranges.push(format!("{}-{}", port + 1, u16::MAX));
+            }
+            ranges.join(",")
+        }
+
         fn prefix_to_mask(prefix_len: u8) -> Ipv4Addr {
             let mask: u32 = if prefix_len == 0 {
                 0
@@ -1,26 +1,72 @@
 //! Tunnel manager - coordinates VPN connection lifecycle
 //! Integrates WireGuard, STUN, WebSocket, and TUN device
 
-use std::net::SocketAddr;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
-use tauri::State;
+use tauri::{Emitter, Manager, State};
 use tokio::sync::Mutex;
 use base64::Engine as _;
 use parking_lot::RwLock;
 
-use crate::api::ApiClient;
-use crate::stun::AsyncStunClient;
-use crate::wireguard::{WgTunnel, WgConfig, parse_wg_config};
+use crate::api::{ApiClient, ConnectionMetrics, Device, DeviceConfig, Relay, RelayLatency};
+use crate::error::PleError;
+use crate::network_monitor;
+use crate::split_tunnel::{SplitTarget, SplitTunnel};
+use crate::stun::{AsyncStunClient, Reachability};
+use crate::tls::TlsSettings;
+use crate::tun_device::{TunDevice, TUN_NAME};
+use crate::wireguard::{WgTunnel, WgConfig, WgPeer, PeerInfo, SecretKey, PowerProfile, ProtocolStats, parse_wg_config, parse_allowed_ips, parse_allowed_ips_v6, resolve_peer_endpoints, derive_public_key, decode_secret, generate_keypair, replace_private_key};
 use crate::websocket::{ManagedWsClient, WsConfig, WsEvent};
 
+/// How often connection quality is reported to the control plane
+const METRICS_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default upper bound for the whole connect flow
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Quiet period before refetching config after a NetworkConfigUpdate
+const CONFIG_UPDATE_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How long to wait on each peer endpoint's reachability probe before connecting
+const ENDPOINT_PROBE_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Quiet period after the last OS network change before refreshing the tunnel
+const NETWORK_CHANGE_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How long the status shows Handshaking after a network change before giving
+/// the tunnel's own retransmits the rest of the job
+const NETWORK_CHANGE_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long after connecting health checks give the first handshake and a
+/// direct path to come up before reporting them missing
+const HEALTH_GRACE_PERIOD: Duration = Duration::from_secs(20);
+
+/// A handshake older than this means the session keys have expired (WireGuard's
+/// Reject-After-Time) and the tunnel failed to rekey
+const STALE_HANDSHAKE_AGE: Duration = Duration::from_secs(180);
+
+/// Number of stats-updater samples kept for throughput graphs (one per stats
+/// interval - a minute with the default power profile)
+const THROUGHPUT_HISTORY_LEN: usize = 60;
+
+/// Default window for is_traffic_flowing(), and the longest one it accepts
+const TRAFFIC_FLOW_WINDOW: Duration = Duration::from_secs(10);
+const MAX_TRAFFIC_FLOW_WINDOW: Duration = Duration::from_secs(120);
+
+/// Error status while every peer has gone silent; cleared when one answers again
+const NO_LIVE_PEERS: &str = "No peer is responding";
+
 /// App state type for Tauri commands
 pub struct AppState {
     pub tunnel_manager: Arc<Mutex<TunnelManager>>,
     pub api_client: ApiClient,
+    /// TLS settings in effect since launch (changes to the stored ones wait for a restart)
+    pub tls: TlsSettings,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -30,10 +76,99 @@ pub enum ConnectionStatus {
     DiscoveringEndpoint,
     Handshaking,
     Connected,
+    /// Tunnel kept alive but not forwarding traffic
+    Paused,
     Disconnecting,
     Error(String),
 }
 
+/// Why a connect attempt failed, so the UI can offer the right fix.
+/// Sent with the "connection-failed" event.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ConnectionFailure {
+    /// Windows: restart the app as Administrator
+    NeedsAdmin,
+    /// macOS: helper daemon missing or not answering - install it
+    HelperNotInstalled,
+    /// macOS: helper install was cancelled or failed
+    HelperInstallFailed,
+    /// macOS: helper installed but not started yet - retry shortly
+    HelperStarting,
+    /// macOS: helper is from another app version - reinstall it
+    HelperOutdated,
+    /// Tunnel didn't come up within the connect timeout
+    HandshakeTimeout,
+    /// Tunnel was up but routing setup didn't finish within the connect timeout
+    SetupTimeout,
+    /// Token missing or expired - log in again
+    AuthExpired,
+    /// Relay or control plane unreachable
+    Network,
+    /// Control plane refused the request (not found, forbidden, rate limited)
+    ServerRejected,
+    /// WireGuard port or TUN adapter held by another app
+    ResourceInUse,
+    /// Routes couldn't be installed
+    RouteFailed,
+    /// Control plane certificate didn't match a pinned key - possible interception
+    UntrustedServer,
+    /// Device has no private key - re-register it with generated keys
+    MissingPrivateKey,
+    /// Malformed config or server response
+    InvalidConfig,
+    Other,
+}
+
+impl From<&PleError> for ConnectionFailure {
+    fn from(e: &PleError) -> Self {
+        match e {
+            PleError::AdminRequired(_) => Self::NeedsAdmin,
+            PleError::HelperUnavailable(_) => Self::HelperNotInstalled,
+            PleError::HelperInstallFailed(_) => Self::HelperInstallFailed,
+            PleError::HelperStartSlow(_) => Self::HelperStarting,
+            PleError::HelperVersionMismatch { .. } => Self::HelperOutdated,
+            PleError::HandshakeTimeout(_) => Self::HandshakeTimeout,
+            PleError::SetupTimeout(_) => Self::SetupTimeout,
+            PleError::Auth(_) => Self::AuthExpired,
+            PleError::Network(_) => Self::Network,
+            PleError::RateLimited { .. } | PleError::Api(_) | PleError::NotFound(_) | PleError::Forbidden(_) => Self::ServerRejected,
+            PleError::PortConflict(_) | PleError::AdapterInUse(_) => Self::ResourceInUse,
+            PleError::RouteFailed(_) => Self::RouteFailed,
+            PleError::MissingPrivateKey(_) => Self::MissingPrivateKey,
+            PleError::CertificatePinMismatch(_) => Self::UntrustedServer,
+            PleError::Parse(_) => Self::InvalidConfig,
+            PleError::InvalidImage(_) | PleError::NoQrCode | PleError::MultipleQrCodes(_) | PleError::Other(_) => Self::Other,
+        }
+    }
+}
+
+/// Payload of the "connection-failed" event
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionFailedEvent {
+    pub reason: ConnectionFailure,
+    pub error: PleError,
+}
+
+/// Per-connection choices made by the user
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// Route all traffic through the VPN
+    pub use_exit_node: bool,
+    /// Local address to send WireGuard traffic from (None = let the OS pick)
+    pub bind_address: Option<IpAddr>,
+    /// Probe the path MTU after the handshake and lower the TUN MTU to fit
+    pub probe_mtu: bool,
+    /// Keepalive, handshake retry, STUN and stats timings
+    pub power_profile: PowerProfile,
+    /// With an exit node, block native IPv6 the tunnel doesn't carry
+    pub block_ipv6_leaks: bool,
+    /// UDP port to listen on, overriding the config's ListenPort and the auto-pick
+    pub listen_port: Option<u16>,
+    /// Skip STUN and stay on the relay, for networks where direct paths never work
+    pub relay_only: bool,
+}
+
 /// Connection statistics
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectionStats {
@@ -42,17 +177,259 @@ pub struct ConnectionStats {
     pub connected_peers: usize,
     pub public_endpoint: Option<String>,
     pub connection_type: String, // "direct" or "relay"
+    /// Unix timestamp (seconds) of the last transition to Connected
+    pub connected_since: Option<u64>,
+    pub uptime_secs: u64,
+    /// MTU of the TUN device
+    pub mtu: Option<u16>,
+    /// MTU found by path MTU discovery, None if it didn't run or was inconclusive
+    pub discovered_mtu: Option<u16>,
+    /// UDP port WireGuard is bound to
+    pub listen_port: Option<u16>,
+    /// Datagrams on the listen port dropped for not being WireGuard messages
+    pub rejected_datagrams: u64,
+}
+
+/// Traffic rates over one stats-updater interval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThroughputSample {
+    /// Unix timestamp (milliseconds) at the end of the interval
+    pub timestamp_ms: u64,
+    pub tx_bytes_per_sec: u64,
+    pub rx_bytes_per_sec: u64,
+}
+
+/// Per-peer latency
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerStats {
+    pub public_key: String,
+    pub latest_rtt_ms: Option<u64>,
+    pub average_rtt_ms: Option<u64>,
+    pub handshake_rtt_ms: Option<u64>,
+    /// Effective keepalive interval for this peer's path, None if disabled
+    pub keepalive_interval_secs: Option<u64>,
+}
+
+/// Connection statistics with per-peer detail
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetailedStats {
+    #[serde(flatten)]
+    pub stats: ConnectionStats,
+    pub peers: Vec<PeerStats>,
+}
+
+/// Relay connect_best_exit() chose as the exit node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BestExit {
+    pub relay_id: String,
+    pub name: String,
+    pub location: String,
+    pub country_code: String,
+    /// None when no relay answered and the server's first pick was used instead
+    pub latency_ms: Option<u64>,
+}
+
+/// Fastest healthy relay that answered the probe, otherwise the first healthy
+/// relay in the order the server lists them
+fn pick_best_exit(relays: &[Relay], latencies: &[RelayLatency]) -> Option<BestExit> {
+    let choose = |relay: &Relay, latency_ms| BestExit {
+        relay_id: relay.id.clone(),
+        name: relay.name.clone(),
+        location: relay.location.clone(),
+        country_code: relay.country_code.clone(),
+        latency_ms,
+    };
+    let healthy = |id: &str| relays.iter().find(|r| r.id == id && r.is_healthy());
+
+    latencies.iter()
+        .find_map(|l| Some(choose(healthy(&l.id)?, Some(l.latency_ms?))))
+        .or_else(|| relays.iter().find(|r| r.is_healthy()).map(|r| choose(r, None)))
+}
+
+/// Result of test_connectivity()
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectivityTest {
+    pub target: String,
+    /// Peer the echoes went to, and where it was reached
+    pub peer_public_key: String,
+    pub peer_endpoint: String,
+    pub sent: u32,
+    pub received: u32,
+    /// At least one echo came back through the tunnel
+    pub success: bool,
+    pub min_rtt_ms: Option<f64>,
+    pub average_rtt_ms: Option<f64>,
+    pub max_rtt_ms: Option<f64>,
+}
+
+/// Echoes sent by test_connectivity
+const CONNECTIVITY_TEST_ECHOES: u16 = 3;
+
+/// Server device with what the running tunnel knows about it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnrichedDevice {
+    #[serde(flatten)]
+    pub device: Device,
+    /// The device is a peer of the running tunnel, so the fields below are local
+    pub is_tunnel_peer: bool,
+    /// Handshaked and answering on this tunnel, or the server's is_online otherwise
+    pub online: bool,
+    pub last_handshake_secs: Option<u64>,
+    /// Endpoint the tunnel sends to for this peer
+    pub endpoint: Option<String>,
+    /// "direct" or "relay", None when not a tunnel peer
+    pub connection_type: Option<String>,
+}
+
+/// Merge tunnel peer state into the server's device list by public key
+fn enrich_devices(devices: Vec<Device>, peers: &[PeerInfo]) -> Vec<EnrichedDevice> {
+    devices.into_iter()
+        .map(|device| {
+            let key = base64::engine::general_purpose::STANDARD.decode(device.public_key.trim()).ok();
+            match peers.iter().find(|peer| key.as_deref() == Some(peer.public_key.as_slice())) {
+                Some(peer) => EnrichedDevice {
+                    is_tunnel_peer: true,
+                    online: peer.responding && peer.last_handshake_age.is_some(),
+                    last_handshake_secs: peer.last_handshake_age.map(|age| age.as_secs()),
+                    endpoint: peer.endpoint.map(|endpoint| endpoint.to_string()),
+                    connection_type: Some(if peer.direct { "direct" } else { "relay" }.to_string()),
+                    device,
+                },
+                None => EnrichedDevice {
+                    is_tunnel_peer: false,
+                    online: device.is_online,
+                    last_handshake_secs: None,
+                    endpoint: None,
+                    connection_type: None,
+                    device,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Last known presence of a device in the network, from WebSocket events
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerPresence {
+    pub device_id: String,
+    pub public_key: Option<String>,
+    pub online: bool,
+    /// Direct endpoint the peer last reported
+    pub endpoint: Option<String>,
+    /// Unix timestamp (seconds) of the last event for this peer
+    pub updated_at: u64,
+}
+
+/// Cumulative tunnel byte counters at one stats-updater tick
+#[derive(Debug, Clone, Copy)]
+struct CounterSample {
+    at: Instant,
+    tx_bytes: u64,
+    rx_bytes: u64,
+}
+
+/// Whether (tx, rx) grew over the last `window`, against the newest sample at
+/// least that old. None until the history covers the window.
+fn traffic_movement(samples: &VecDeque<CounterSample>, window: Duration) -> Option<(bool, bool)> {
+    let latest = samples.back()?;
+    let baseline = samples.iter().rev().find(|s| latest.at.duration_since(s.at) >= window)?;
+    Some((latest.tx_bytes > baseline.tx_bytes, latest.rx_bytes > baseline.rx_bytes))
+}
+
+/// One-glance tunnel health for the tray/menu bar
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum TunnelHealth {
+    Healthy,
+    /// Up, but something is off (no traffic, stuck on the relay, ...)
+    Degraded { reason: String },
+    Down { reason: String },
+}
+
+/// Existing connection state that tunnel health is derived from
+#[derive(Debug, Clone)]
+struct HealthInputs {
+    status: ConnectionStatus,
+    /// Time since connecting, None when not connected
+    uptime: Option<Duration>,
+    last_handshake_age: Option<Duration>,
+    /// Summed rates over the throughput window, None until the window has filled
+    recent_traffic: Option<(u64, u64)>,
+    /// Whether (tx, rx) moved over TRAFFIC_FLOW_WINDOW, None until it has passed
+    traffic_movement: Option<(bool, bool)>,
+    connection_type: String,
+    /// STUN found a public endpoint and the WebSocket is up to signal it, so
+    /// peers should be reachable directly
+    direct_expected: bool,
+}
+
+fn derive_health(inputs: &HealthInputs) -> TunnelHealth {
+    let degraded = |reason: &str| TunnelHealth::Degraded { reason: reason.to_string() };
+    let down = |reason: &str| TunnelHealth::Down { reason: reason.to_string() };
+
+    match &inputs.status {
+        ConnectionStatus::Connected => {}
+        ConnectionStatus::Disconnected => return down("Disconnected"),
+        ConnectionStatus::Disconnecting => return down("Disconnecting"),
+        ConnectionStatus::Error(e) => return down(e),
+        ConnectionStatus::Paused => return degraded("Paused"),
+        ConnectionStatus::Connecting
+        | ConnectionStatus::DiscoveringEndpoint
+        | ConnectionStatus::Handshaking => return degraded("Connecting"),
+    }
+
+    let settled = inputs.uptime.is_some_and(|uptime| uptime >= HEALTH_GRACE_PERIOD);
+    match inputs.last_handshake_age {
+        None if settled => return down("No handshake with any peer"),
+        Some(age) if age >= STALE_HANDSHAKE_AGE => {
+            return degraded(&format!("Last handshake {} minutes ago", age.as_secs() / 60));
+        }
+        _ => {}
+    }
+
+    // Packets go out but nothing comes back: handshakes work, the data path doesn't
+    if inputs.traffic_movement == Some((true, false)) {
+        return degraded("Handshake OK but no data coming back");
+    }
+
+    match inputs.recent_traffic {
+        Some((tx, 0)) if tx > 0 => return degraded("Sending but receiving nothing"),
+        Some((0, 0)) => return degraded("No recent traffic"),
+        _ => {}
+    }
+
+    if settled && inputs.direct_expected && inputs.connection_type == "relay" {
+        return degraded("Using the relay, direct connection failed");
+    }
+
+    TunnelHealth::Healthy
 }
 
 /// Tunnel manager - handles the VPN connection lifecycle
 pub struct TunnelManager {
     status: Arc<RwLock<ConnectionStatus>>,
     stats: Arc<RwLock<ConnectionStats>>,
+    /// Recent per-second throughput, oldest first
+    throughput: Arc<RwLock<VecDeque<ThroughputSample>>>,
+    /// Raw counters covering MAX_TRAFFIC_FLOW_WINDOW, oldest first
+    counters: Arc<RwLock<VecDeque<CounterSample>>>,
     wg_tunnel: Arc<Mutex<Option<WgTunnel>>>,
+    /// TUN device kept by disconnect(keep_device), picked up by the next connect
+    kept_device: Arc<Mutex<Option<Arc<TunDevice>>>>,
     ws_client: Arc<Mutex<Option<ManagedWsClient>>>,
+    split_tunnel: Arc<Mutex<Option<SplitTunnel>>>,
     is_running: Arc<AtomicBool>,
     current_device_id: Arc<RwLock<Option<String>>>,
     current_network_id: Arc<RwLock<Option<String>>>,
+    /// Monotonic start of the current connection, for uptime
+    connected_at: Arc<RwLock<Option<Instant>>>,
+    /// TLS trust settings for the WebSocket
+    tls: TlsSettings,
+    /// Used to notify the UI of changes detected in the background
+    app_handle: Option<tauri::AppHandle>,
+    /// Peers seen over the WebSocket this session, by device id
+    presence: Arc<RwLock<HashMap<String, PeerPresence>>>,
+    /// Background loops of the current connection, aborted on teardown
+    tasks: parking_lot::Mutex<Vec<tokio::task::JoinHandle<()>>>,
 }
 
 impl TunnelManager {
@@ -65,16 +442,43 @@ impl TunnelManager {
                 connected_peers: 0,
                 public_endpoint: None,
                 connection_type: "unknown".to_string(),
+                connected_since: None,
+                uptime_secs: 0,
+                mtu: None,
+                discovered_mtu: None,
+                listen_port: None,
+                rejected_datagrams: 0,
             })),
+            throughput: Arc::new(RwLock::new(VecDeque::with_capacity(THROUGHPUT_HISTORY_LEN))),
+            counters: Arc::new(RwLock::new(VecDeque::new())),
             wg_tunnel: Arc::new(Mutex::new(None)),
+            kept_device: Arc::new(Mutex::new(None)),
             ws_client: Arc::new(Mutex::new(None)),
+            split_tunnel: Arc::new(Mutex::new(None)),
             is_running: Arc::new(AtomicBool::new(false)),
             current_device_id: Arc::new(RwLock::new(None)),
             current_network_id: Arc::new(RwLock::new(None)),
+            connected_at: Arc::new(RwLock::new(None)),
+            tls: TlsSettings::default(),
+            app_handle: None,
+            presence: Arc::new(RwLock::new(HashMap::new())),
+            tasks: parking_lot::Mutex::new(Vec::new()),
         }
     }
 
-    /// Connect to VPN using the device configuration
+    /// Emit background status changes (e.g. connection type) to the UI
+    pub fn with_app_handle(mut self, app_handle: tauri::AppHandle) -> Self {
+        self.app_handle = Some(app_handle);
+        self
+    }
+
+    /// Use custom TLS trust settings for the control-plane API and WebSocket
+    pub fn with_tls(mut self, tls: TlsSettings) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Connect to VPN using the device configuration, returning the id its log lines carry
     pub async fn connect(
         &self,
         config_str: &str,
@@ -82,64 +486,137 @@ impl TunnelManager {
         network_id: &str,
         api_base_url: &str,
         token: &str,
-        use_exit_node: bool,
-    ) -> Result<(), String> {
+        options: ConnectOptions,
+    ) -> Result<String, PleError> {
         if self.is_running.load(Ordering::SeqCst) {
             log::warn!("[TUNNEL] Already connected, rejecting new connection");
-            return Err("Already connected".to_string());
+            return Err(PleError::Other("Already connected".to_string()));
+        }
+
+        let result = self.establish(config_str, device_id, network_id, api_base_url, token, options).await;
+        // Whichever step failed, don't leave the UI showing Connecting/Handshaking
+        if result.is_err() {
+            *self.status.write() = ConnectionStatus::Disconnected;
+        }
+        result
+    }
+
+    async fn establish(
+        &self,
+        config_str: &str,
+        device_id: &str,
+        network_id: &str,
+        api_base_url: &str,
+        token: &str,
+        options: ConnectOptions,
+    ) -> Result<String, PleError> {
+        let ConnectOptions { use_exit_node, bind_address, probe_mtu, power_profile, block_ipv6_leaks, listen_port, relay_only } = options;
+        let timings = power_profile.timings();
+        if let Some(port) = listen_port {
+            crate::wireguard::check_listen_port(bind_address, port)?;
         }
 
+        // The background API calls carry the token, so they get the same pinning as the rest
+        let api_client = Arc::new(ApiClient::with_tls(api_base_url.to_string(), &self.tls)
+            .map_err(|e| PleError::Other(format!("Failed to apply TLS settings: {}", e)))?);
+
+        let connection_id = crate::logging::start_connection();
         log::info!("[TUNNEL] ========== TUNNEL CONNECT START ==========");
+        log::info!("[TUNNEL] Connection id: {}", connection_id);
         log::info!("[TUNNEL] Device: {}, Network: {}", device_id, network_id);
         log::info!("[TUNNEL] API URL: {}", api_base_url);
         *self.status.write() = ConnectionStatus::Connecting;
 
         // Parse WireGuard configuration
         log::info!("[TUNNEL] Phase 0: Parsing WireGuard config...");
-        let wg_config = match parse_wg_config(config_str) {
+        let mut wg_config = match parse_wg_config(config_str) {
             Ok(c) => {
                 log::info!("[TUNNEL] ✓ WireGuard config parsed successfully");
                 c
             }
             Err(e) => {
                 log::error!("[TUNNEL] ✗ Failed to parse WireGuard config: {}", e);
-                return Err(e);
+                return Err(PleError::Parse(e));
             }
         };
         log::info!("[TUNNEL] Parsed WireGuard config with {} peers", wg_config.peers.len());
+        wg_config.bind_address = bind_address;
+        wg_config.power_profile = power_profile;
+        wg_config.block_ipv6_leaks = block_ipv6_leaks;
+        wg_config.relay_only = relay_only;
+        if let Some(port) = listen_port {
+            log::info!("[TUNNEL] Listen port pinned to {} (config had {:?})", port, wg_config.listen_port);
+            wg_config.listen_port = Some(port);
+        }
+        log::info!("[TUNNEL] Power profile: {:?}", power_profile);
+        if let Some(ip) = bind_address {
+            log::info!("[TUNNEL] Binding WireGuard and STUN to local address {}", ip);
+        }
+        resolve_peer_endpoints(&mut wg_config.peers).await.map_err(PleError::Network)?;
         for (i, peer) in wg_config.peers.iter().enumerate() {
             log::info!("[TUNNEL]   Peer {}: endpoint={:?}, allowed_ips={:?}",
                 i, peer.endpoint, peer.allowed_ips);
         }
 
+        // Fail fast on a dead relay instead of running out the connect timeout
+        let endpoints: Vec<SocketAddr> = wg_config.peers.iter().filter_map(|peer| peer.endpoint).collect();
+        if !endpoints.is_empty() {
+            log::info!("[TUNNEL] Probing {} peer endpoint(s)...", endpoints.len());
+            let results = AsyncStunClient::new()
+                .bound_to(bind_address)
+                .with_timeout(ENDPOINT_PROBE_TIMEOUT)
+                .probe_reachability(&endpoints)
+                .await;
+            for (endpoint, result) in endpoints.iter().zip(&results) {
+                log::info!("[TUNNEL]   {}: {:?}", endpoint, result);
+            }
+            if let Some(reason) = unreachable_endpoints(&endpoints, &results) {
+                log::error!("[TUNNEL] ✗ {}", reason);
+                return Err(PleError::Network(reason));
+            }
+        }
+
         // Store current session info
         *self.current_device_id.write() = Some(device_id.to_string());
         *self.current_network_id.write() = Some(network_id.to_string());
 
         // Phase 1: Discover our public endpoint via STUN
-        log::info!("[TUNNEL] Phase 1: STUN endpoint discovery...");
-        *self.status.write() = ConnectionStatus::DiscoveringEndpoint;
-        let stun_client = AsyncStunClient::new();
-        log::info!("[TUNNEL]   Contacting STUN servers (timeout: 3s each)...");
-        log::info!("[TUNNEL]   STUN servers: stun.l.google.com:19302, stun.cloudflare.com:3478, ...");
-        let public_endpoint = match stun_client.discover_public_endpoint().await {
-            Ok(result) => {
-                log::info!("[TUNNEL] ✓ STUN discovery successful!");
-                log::info!("[TUNNEL]   Public endpoint: {} (this is your NAT-mapped address)", result.public_addr);
-                log::info!("[TUNNEL]   Local endpoint: {}", result.local_addr);
-                log::info!("[TUNNEL]   STUN server used: {}", result.stun_server);
-                self.stats.write().public_endpoint = Some(result.public_addr.to_string());
-                Some(result.public_addr)
-            }
-            Err(e) => {
-                log::warn!("[TUNNEL] ⚠ STUN discovery FAILED: {}", e);
-                log::warn!("[TUNNEL]   This means P2P is not available - traffic will go through relay");
-                log::warn!("[TUNNEL]   Common causes:");
-                log::warn!("[TUNNEL]     - Firewall blocking UDP to ports 19302/3478");
-                log::warn!("[TUNNEL]     - Network (hotspot/corporate) restricts STUN");
-                log::warn!("[TUNNEL]     - Symmetric NAT that doesn't allow STUN");
-                log::warn!("[TUNNEL]   VPN will still work via relay, just with higher latency");
-                None
+        let public_endpoint = if relay_only {
+            log::info!("[TUNNEL] Phase 1: Relay-only mode, skipping STUN endpoint discovery");
+            None
+        } else {
+            log::info!("[TUNNEL] Phase 1: STUN endpoint discovery...");
+            *self.status.write() = ConnectionStatus::DiscoveringEndpoint;
+            let stun_client = AsyncStunClient::new().bound_to(bind_address).with_timeout(timings.stun_timeout);
+            log::info!("[TUNNEL]   Contacting STUN servers (timeout: {:?} each)...", timings.stun_timeout);
+            log::info!("[TUNNEL]   STUN servers: stun.l.google.com:19302, stun.cloudflare.com:3478, ...");
+            match stun_client.discover_public_endpoint().await {
+                Ok(result) => {
+                    log::info!("[TUNNEL] ✓ STUN discovery successful!");
+                    log::info!("[TUNNEL]   Public endpoint: {} (this is your NAT-mapped address)", result.public_addr);
+                    log::info!("[TUNNEL]   Local endpoint: {}", result.local_addr);
+                    log::info!("[TUNNEL]   STUN server used: {} over {}", result.stun_server, result.family);
+                    if result.over_tcp {
+                        // Only TCP answered - the IP is ours but the port isn't a UDP mapping
+                        log::warn!("[TUNNEL]   UDP to STUN is blocked, public IP is {} - traffic will go through relay",
+                            result.public_addr.ip());
+                        self.stats.write().public_endpoint = Some(result.public_addr.ip().to_string());
+                        None
+                    } else {
+                        self.stats.write().public_endpoint = Some(result.public_addr.to_string());
+                        Some(result.public_addr)
+                    }
+                }
+                Err(e) => {
+                    log::warn!("[TUNNEL] ⚠ STUN discovery FAILED: {}", e);
+                    log::warn!("[TUNNEL]   This means P2P is not available - traffic will go through relay");
+                    log::warn!("[TUNNEL]   Common causes:");
+                    log::warn!("[TUNNEL]     - Firewall blocking UDP to ports 19302/3478");
+                    log::warn!("[TUNNEL]     - Network (hotspot/corporate) restricts STUN");
+                    log::warn!("[TUNNEL]     - Symmetric NAT that doesn't allow STUN");
+                    log::warn!("[TUNNEL]   VPN will still work via relay, just with higher latency");
+                    None
+                }
             }
         };
 
@@ -147,17 +624,44 @@ impl TunnelManager {
         log::info!("[TUNNEL] Phase 2: Creating WireGuard tunnel...");
         *self.status.write() = ConnectionStatus::Handshaking;
 
-        let tunnel = WgTunnel::new(wg_config).await?;
+        let kept_device = self.kept_device.lock().await.take();
+        let tunnel = WgTunnel::new(wg_config, kept_device).await.map_err(tunnel_setup_error)?;
 
         // Update stats with public endpoint from tunnel
         if let Some(endpoint) = tunnel.public_endpoint() {
             self.stats.write().public_endpoint = Some(endpoint.to_string());
         }
 
-        tunnel.start().await?;
+        // start() installs the AllowedIPs routes
+        tunnel.start().await.map_err(PleError::RouteFailed)?;
+
+        // Held by the manager from here on, so the teardown() after a connect
+        // timeout can undo the routes, gateway and leak blocks set up below
+        network_monitor::set_ignored_interface(Some(tunnel.tun_name()));
+        *self.wg_tunnel.lock().await = Some(tunnel);
+        let guard = self.wg_tunnel.lock().await;
+        let tunnel = guard.as_ref()
+            .ok_or_else(|| PleError::Other("Tunnel was torn down while connecting".to_string()))?;
+
+        if probe_mtu {
+            log::info!("[TUNNEL] Probing path MTU...");
+            let discovered = tunnel.discover_mtu().await;
+            self.stats.write().discovered_mtu = discovered;
+        }
+        {
+            let mut stats = self.stats.write();
+            stats.mtu = Some(tunnel.mtu());
+            stats.listen_port = tunnel.listen_port();
+        }
 
-        // If exit node is selected, route all traffic through VPN
-        if use_exit_node {
+        // If exit node is selected, route all traffic through VPN. A peer with
+        // AllowedIPs 0.0.0.0/0 asks for the same thing, and its route is only
+        // installed this way (see wireguard::routed_ips)
+        let full_tunnel = !use_exit_node && tunnel.is_full_tunnel();
+        if full_tunnel {
+            log::info!("[TUNNEL] A peer takes 0.0.0.0/0, routing all traffic through it");
+        }
+        if use_exit_node || full_tunnel {
             log::info!("[TUNNEL] Exit node enabled, setting default gateway through VPN");
             if let Err(e) = tunnel.set_default_gateway().await {
                 log::warn!("[TUNNEL] Failed to set default gateway: {}", e);
@@ -165,7 +669,7 @@ impl TunnelManager {
             }
         }
 
-        *self.wg_tunnel.lock().await = Some(tunnel);
+        drop(guard);
         self.is_running.store(true, Ordering::SeqCst);
 
         // Phase 3: Connect WebSocket for real-time peer updates (optional - VPN works via relay without it)
@@ -175,18 +679,34 @@ impl TunnelManager {
             token: token.to_string(),
             device_id: device_id.to_string(),
             reconnect_interval: Duration::from_secs(5),
+            tls: self.tls.clone(),
         };
 
         let ws_client = ManagedWsClient::new(ws_config);
 
         // Clone the tunnel Arc for use in the callback
         let tunnel_for_callback = self.wg_tunnel.clone();
+        let presence = self.presence.clone();
+        let presence_app = self.app_handle.clone();
+
+        // Config refetch on NetworkConfigUpdate (debounced via generation counter)
+        let config_api_client = api_client.clone();
+        let config_token = token.to_string();
+        // Shared so an in-place switch_network moves the refetch to the new device
+        let config_device_id = self.current_device_id.clone();
+        let config_network_id = self.current_network_id.clone();
+        let config_generation = Arc::new(AtomicU64::new(0));
 
         // Try to start WebSocket with callback that updates peer endpoints
         // Pass endpoint and network_id so they're registered after connection
         log::info!("[TUNNEL]   Attempting WebSocket connection...");
         let ws_connected = match ws_client.start_with_registration(
             Box::new(move |event| {
+            if record_presence(&mut presence.write(), &event) {
+                if let Some(app) = &presence_app {
+                    let _ = app.emit("peer-event", &event);
+                }
+            }
             match event {
                 WsEvent::PeerEndpointUpdate { device_id, public_key, endpoint } => {
                     log::info!("[P2P] Peer endpoint update: {} ({}) -> {}", device_id, public_key, endpoint);
@@ -217,6 +737,32 @@ impl TunnelManager {
                 WsEvent::PeerOffline { device_id } => {
                     log::info!("[P2P] Peer went offline: {}", device_id);
                 }
+                WsEvent::NetworkConfigUpdate { network_id }
+                    if config_network_id.read().as_deref() == Some(network_id.as_str()) => {
+                    let Some(device_id) = config_device_id.read().clone() else {
+                        return;
+                    };
+                    log::info!("[CONFIG] Network config changed, refetching...");
+
+                    let generation = config_generation.fetch_add(1, Ordering::SeqCst) + 1;
+                    let config_generation = config_generation.clone();
+                    let tunnel = tunnel_for_callback.clone();
+                    let api_client = config_api_client.clone();
+                    let token = config_token.clone();
+
+                    tokio::spawn(async move {
+                        tokio::time::sleep(CONFIG_UPDATE_DEBOUNCE).await;
+
+                        // A newer update arrived while we waited - let that one apply
+                        if config_generation.load(Ordering::SeqCst) != generation {
+                            return;
+                        }
+
+                        if let Err(e) = apply_config_update(&tunnel, &api_client, &token, &device_id).await {
+                            log::warn!("[CONFIG] Failed to apply config update: {}", e);
+                        }
+                    });
+                }
                 _ => {}
             }
         }),
@@ -238,84 +784,413 @@ impl TunnelManager {
             *self.ws_client.lock().await = Some(ws_client);
         }
 
-        // Determine connection type
-        let connection_type = if public_endpoint.is_some() {
-            "direct".to_string()
-        } else {
-            "relay".to_string()
-        };
-        self.stats.write().connection_type = connection_type;
+        // Traffic starts on the configured (relay) endpoints - the stats updater
+        // switches to "direct" once a P2P path is verified by a handshake
+        {
+            let mut stats = self.stats.write();
+            stats.connection_type = "relay".to_string();
+            stats.connected_since = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_secs());
+        }
+        *self.connected_at.write() = Some(Instant::now());
 
         *self.status.write() = ConnectionStatus::Connected;
         log::info!("VPN connection established");
 
         // Start stats update task
-        self.start_stats_updater();
+        self.start_stats_updater(timings.stats_interval);
 
-        Ok(())
+        // Start quality reporting for server-side relay selection
+        self.start_metrics_reporter(api_client, token);
+
+        // Re-establish the tunnel when the laptop moves networks or wakes up
+        self.start_network_watcher();
+
+        Ok(connection_id)
     }
 
     /// Start background task to update connection statistics
-    fn start_stats_updater(&self) {
+    fn start_stats_updater(&self, tick: Duration) {
+        let status = self.status.clone();
         let stats = self.stats.clone();
+        let throughput = self.throughput.clone();
+        let counters = self.counters.clone();
         let tunnel = self.wg_tunnel.clone();
         let running = self.is_running.clone();
+        let app_handle = self.app_handle.clone();
 
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(1));
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            // Cumulative (tx, rx) at the previous tick, to turn counters into rates
+            let mut previous: Option<(u64, u64, Instant)> = None;
 
             while running.load(Ordering::SeqCst) {
                 interval.tick().await;
 
                 if let Some(tun) = tunnel.lock().await.as_ref() {
+                    // Track relay <-> direct transitions as P2P endpoints come and go
+                    tun.expire_direct_paths();
+                    let connection_type = tun.connection_type();
+
                     let peer_stats = tun.get_stats();
+                    let tx_bytes: u64 = peer_stats.iter().map(|(_, tx, _)| tx).sum();
+                    let rx_bytes: u64 = peer_stats.iter().map(|(_, _, rx)| rx).sum();
+
+                    let now = Instant::now();
+                    if let Some((prev_tx, prev_rx, prev_at)) = previous {
+                        let elapsed = now.duration_since(prev_at).as_secs_f64().max(0.001);
+                        let rate = |delta: u64| (delta as f64 / elapsed) as u64;
+                        let mut history = throughput.write();
+                        if history.len() == THROUGHPUT_HISTORY_LEN {
+                            history.pop_front();
+                        }
+                        history.push_back(ThroughputSample {
+                            timestamp_ms: SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .map(|d| d.as_millis() as u64)
+                                .unwrap_or(0),
+                            // Counters can go backwards when a peer is removed
+                            tx_bytes_per_sec: rate(tx_bytes.saturating_sub(prev_tx)),
+                            rx_bytes_per_sec: rate(rx_bytes.saturating_sub(prev_rx)),
+                        });
+                    }
+                    previous = Some((tx_bytes, rx_bytes, now));
+
+                    {
+                        let mut counters = counters.write();
+                        counters.push_back(CounterSample { at: now, tx_bytes, rx_bytes });
+                        // Keep one sample older than the longest window as its baseline
+                        while counters.get(1).is_some_and(|s| now.duration_since(s.at) >= MAX_TRAFFIC_FLOW_WINDOW) {
+                            counters.pop_front();
+                        }
+                    }
+
+                    let live_peers = tun.live_peer_count();
+                    // Only ever flips Connected <-> our own error, never over Paused and friends
+                    {
+                        let mut current = status.write();
+                        if live_peers == 0 && !peer_stats.is_empty() && *current == ConnectionStatus::Connected {
+                            log::warn!("[TUNNEL] No peer is responding");
+                            *current = ConnectionStatus::Error(NO_LIVE_PEERS.to_string());
+                        } else if live_peers > 0 && matches!(&*current, ConnectionStatus::Error(reason) if reason == NO_LIVE_PEERS) {
+                            log::info!("[TUNNEL] Peers are responding again");
+                            *current = ConnectionStatus::Connected;
+                        }
+                    }
+
                     let mut s = stats.write();
-                    s.tx_bytes = peer_stats.iter().map(|(_, tx, _)| tx).sum();
-                    s.rx_bytes = peer_stats.iter().map(|(_, _, rx)| rx).sum();
-                    s.connected_peers = peer_stats.len();
+                    s.tx_bytes = tx_bytes;
+                    s.rx_bytes = rx_bytes;
+                    s.connected_peers = live_peers;
+                    s.rejected_datagrams = tun.rejected_datagrams();
+
+                    if s.connection_type != connection_type {
+                        log::info!("[TUNNEL] Connection type changed: {} -> {}", s.connection_type, connection_type);
+                        s.connection_type = connection_type.to_string();
+                        if let Some(app) = &app_handle {
+                            let _ = app.emit("connection-type-changed", connection_type);
+                        }
+                    }
+                }
+            }
+        });
+        self.tasks.lock().push(task);
+    }
+
+    /// Start background task that reports connection quality to the control plane
+    fn start_metrics_reporter(&self, api_client: Arc<ApiClient>, token: &str) {
+        let status = self.status.clone();
+        let stats = self.stats.clone();
+        let tunnel = self.wg_tunnel.clone();
+        let running = self.is_running.clone();
+        let current_device_id = self.current_device_id.clone();
+        let token = token.to_string();
+
+        let task = tokio::spawn(async move {
+            // First report after a full interval so the handshake has settled
+            let start = tokio::time::Instant::now() + METRICS_REPORT_INTERVAL;
+            let mut interval = tokio::time::interval_at(start, METRICS_REPORT_INTERVAL);
+            // Handshake counters at the previous report, to measure loss per interval
+            let mut previous = (0, 0);
+
+            while running.load(Ordering::SeqCst) {
+                interval.tick().await;
+
+                if !running.load(Ordering::SeqCst) || *status.read() != ConnectionStatus::Connected {
+                    continue;
+                }
+
+                // Follows the device across an in-place switch_network
+                let Some(device_id) = current_device_id.read().clone() else {
+                    continue;
+                };
+                let quality = match tunnel.lock().await.as_ref() {
+                    Some(tun) => tun.quality_metrics(),
+                    None => continue,
+                };
+
+                let counters = (quality.handshake_attempts, quality.handshakes_completed);
+                let metrics = ConnectionMetrics {
+                    handshake_rtt_ms: quality.handshake_rtt_ms,
+                    keepalive_rtt_ms: quality.keepalive_rtt_ms,
+                    packet_loss: interval_loss(previous, counters),
+                    connection_type: stats.read().connection_type.clone(),
+                };
+                previous = counters;
+
+                log::debug!("[METRICS] Reporting {:?}", metrics);
+                if let Err(e) = api_client.report_metrics(&token, &device_id, &metrics).await {
+                    log::warn!("[METRICS] Failed to report metrics: {}", e);
+                }
+            }
+        });
+        self.tasks.lock().push(task);
+    }
+
+    /// Start background task that refreshes the tunnel after OS network changes
+    fn start_network_watcher(&self) {
+        let status = self.status.clone();
+        let stats = self.stats.clone();
+        let tunnel = self.wg_tunnel.clone();
+        let ws_client = self.ws_client.clone();
+        let running = self.is_running.clone();
+        let app_handle = self.app_handle.clone();
+        let mut changes = network_monitor::subscribe();
+
+        let task = tokio::spawn(async move {
+            use tokio::sync::broadcast::error::RecvError;
+
+            while running.load(Ordering::SeqCst) {
+                // Wake up every second to notice a disconnect
+                match tokio::time::timeout(Duration::from_secs(1), changes.recv()).await {
+                    Err(_) => continue,
+                    Ok(Err(RecvError::Closed)) => return,
+                    // A lagged receiver still means something changed
+                    Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => {}
+                }
+
+                // Switching networks produces a burst of link and address changes
+                while let Ok(Ok(_) | Err(RecvError::Lagged(_))) =
+                    tokio::time::timeout(NETWORK_CHANGE_DEBOUNCE, changes.recv()).await
+                {}
+
+                if !running.load(Ordering::SeqCst) {
+                    return;
                 }
+                refresh_after_network_change(&status, &stats, &tunnel, &ws_client, app_handle.as_ref()).await;
             }
         });
+        self.tasks.lock().push(task);
+    }
+
+    /// Device the tunnel is currently up for, if any
+    pub fn connected_device(&self) -> Option<String> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return None;
+        }
+        self.current_device_id.read().clone()
+    }
+
+    /// Network the tunnel is currently up for, if any
+    pub fn connected_network(&self) -> Option<String> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return None;
+        }
+        self.current_network_id.read().clone()
+    }
+
+    /// The running tunnel's private key if `config` could be switched to in place
+    /// once it uses that key (see WgTunnel::can_adopt_peers)
+    pub async fn key_for_switch(&self, config: &WgConfig) -> Option<SecretKey> {
+        let guard = self.wg_tunnel.lock().await;
+        let tunnel = guard.as_ref()?;
+        tunnel.can_adopt_peers(config).then(|| tunnel.private_key().clone())
+    }
+
+    /// Move the running tunnel to `network_id`, where this machine is `device_id`,
+    /// by swapping its peers for those in `config`, keeping the socket, TUN device
+    /// and WebSocket. False if the caller has to reconnect instead: `config` needs
+    /// a different key, address, DNS or exit node (nothing changed then), or some
+    /// peers failed to swap and the tunnel is left with a mix of both networks.
+    pub async fn switch_network(&self, device_id: &str, network_id: &str, config: WgConfig) -> Result<bool, PleError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return Err(PleError::Other("Not connected".to_string()));
+        }
+        {
+            let guard = self.wg_tunnel.lock().await;
+            let tunnel = guard.as_ref().ok_or_else(|| PleError::Other("Not connected".to_string()))?;
+            if !tunnel.can_adopt(&config) {
+                return Ok(false);
+            }
+            if let Err(e) = apply_peer_changes(tunnel, config.peers).await {
+                log::warn!("[TUNNEL] Swapping peers for network {} failed: {}", network_id, e);
+                return Ok(false);
+            }
+        }
+
+        // Devices are per network, so the device changes along with it
+        *self.current_device_id.write() = Some(device_id.to_string());
+        let old_network_id = self.current_network_id.write().replace(network_id.to_string());
+        self.presence.write().clear();
+        if let Some(ws) = self.ws_client.lock().await.as_ref() {
+            if let Some(old) = old_network_id.filter(|old| old != network_id) {
+                ws.unsubscribe(&old).await?;
+            }
+            ws.switch_device(device_id).await?;
+            ws.subscribe(network_id).await?;
+        }
+        log::info!("[TUNNEL] Switched to network {} (device {}) in place", network_id, device_id);
+        Ok(true)
     }
 
     /// Disconnect from VPN
-    pub async fn disconnect(&self) -> Result<(), String> {
+    /// With `keep_device` the TUN device stays up for the next connect to reuse,
+    /// which saves recreating it (and the helper round trips) on a quick reconnect
+    pub async fn disconnect(&self, keep_device: bool) -> Result<(), String> {
         if !self.is_running.load(Ordering::SeqCst) {
             return Err("Not connected".to_string());
         }
 
-        log::info!("Disconnecting VPN");
+        log::info!("Disconnecting VPN (keep TUN device: {})", keep_device);
+        self.teardown_keeping_device(keep_device).await?;
+
+        log::info!("VPN disconnected");
+        Ok(())
+    }
+
+    /// Tear down any active or partially established connection state, including
+    /// a kept TUN device. Safe to call after an aborted connect - every step
+    /// tolerates missing state.
+    pub async fn teardown(&self) -> Result<(), String> {
+        self.teardown_keeping_device(false).await
+    }
+
+    async fn teardown_keeping_device(&self, keep_device: bool) -> Result<(), String> {
         *self.status.write() = ConnectionStatus::Disconnecting;
 
-        // Stop WireGuard tunnel
-        if let Some(tunnel) = self.wg_tunnel.lock().await.as_ref() {
-            tunnel.stop().await?;
+        // Stop the background loops now rather than at their next tick, so a
+        // quick reconnect doesn't run two of each
+        for task in self.tasks.lock().drain(..) {
+            task.abort();
+        }
+
+        // Split tunnel rules point at the TUN device - remove them first
+        if let Some(mut split) = self.split_tunnel.lock().await.take() {
+            split.teardown();
         }
-        *self.wg_tunnel.lock().await = None;
 
-        // Stop WebSocket
+        // Say goodbye over WebSocket while the endpoint is still bound
         if let Some(ws) = self.ws_client.lock().await.as_ref() {
-            ws.stop();
+            ws.shutdown().await;
         }
         *self.ws_client.lock().await = None;
 
+        // Stop WireGuard tunnel
+        let mut kept = None;
+        if let Some(tunnel) = self.wg_tunnel.lock().await.as_ref() {
+            tunnel.stop().await?;
+            if keep_device {
+                kept = Some(tunnel.release_device().await);
+            }
+        }
+        *self.wg_tunnel.lock().await = None;
+        *self.kept_device.lock().await = kept;
+
         // Clear session info
         *self.current_device_id.write() = None;
         *self.current_network_id.write() = None;
+        *self.connected_at.write() = None;
 
         self.is_running.store(false, Ordering::SeqCst);
         *self.status.write() = ConnectionStatus::Disconnected;
 
         // Reset stats
+        self.throughput.write().clear();
+        self.counters.write().clear();
+        crate::flows::clear();
+        self.presence.write().clear();
         *self.stats.write() = ConnectionStats {
             tx_bytes: 0,
             rx_bytes: 0,
             connected_peers: 0,
             public_endpoint: None,
             connection_type: "unknown".to_string(),
+            connected_since: None,
+            uptime_secs: 0,
+            mtu: None,
+            discovered_mtu: None,
+            listen_port: None,
+            rejected_datagrams: 0,
         };
 
-        log::info!("VPN disconnected");
+        crate::logging::end_connection();
+        Ok(())
+    }
+
+    /// Forcibly return to a clean Disconnected state when a crash or failed connect
+    /// left a TUN device, routes or a DNS block behind. Runs whether or not a
+    /// connection is active and keeps going past failed steps.
+    pub async fn force_reset(&self) -> Result<(), String> {
+        log::info!("[RESET] Forcing VPN reset");
+        let mut failures = Vec::new();
+
+        if let Err(e) = self.teardown().await {
+            log::warn!("[RESET] Teardown failed, dropping tunnel anyway: {}", e);
+            *self.wg_tunnel.lock().await = None;
+            // Nothing left that can fail - this just clears the remaining state
+            let _ = self.teardown().await;
+            failures.push(e);
+        }
+
+        match tokio::task::spawn_blocking(|| TunDevice::destroy_stale(TUN_NAME)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => failures.push(e),
+            Err(e) => failures.push(format!("TUN cleanup task failed: {}", e)),
+        }
+
+        recover_stale_routes().await;
+
+        *self.status.write() = ConnectionStatus::Disconnected;
+
+        if failures.is_empty() {
+            log::info!("[RESET] VPN reset complete");
+            Ok(())
+        } else {
+            Err(format!("Reset finished with errors: {}", failures.join("; ")))
+        }
+    }
+
+    /// Temporarily stop routing traffic through the VPN without tearing it down
+    pub async fn pause(&self) -> Result<(), String> {
+        if *self.status.read() != ConnectionStatus::Connected {
+            return Err("Not connected".to_string());
+        }
+
+        match self.wg_tunnel.lock().await.as_ref() {
+            Some(tunnel) => tunnel.pause().await?,
+            None => return Err("Not connected".to_string()),
+        }
+
+        *self.status.write() = ConnectionStatus::Paused;
+        log::info!("VPN paused");
+        Ok(())
+    }
+
+    /// Resume routing traffic through the existing tunnel
+    pub async fn resume(&self) -> Result<(), String> {
+        if *self.status.read() != ConnectionStatus::Paused {
+            return Err("VPN is not paused".to_string());
+        }
+
+        match self.wg_tunnel.lock().await.as_ref() {
+            Some(tunnel) => tunnel.resume().await?,
+            None => return Err("Not connected".to_string()),
+        }
+
+        *self.status.write() = ConnectionStatus::Connected;
+        log::info!("VPN resumed");
         Ok(())
     }
 
@@ -326,29 +1201,434 @@ impl TunnelManager {
 
     /// Get connection statistics
     pub fn get_stats(&self) -> ConnectionStats {
-        self.stats.read().clone()
+        let mut stats = self.stats.read().clone();
+        stats.uptime_secs = self.connected_at.read()
+            .map(|at| at.elapsed().as_secs())
+            .unwrap_or(0);
+        stats
+    }
+
+    /// Recent per-second throughput samples, oldest first
+    pub fn get_throughput_history(&self) -> Vec<ThroughputSample> {
+        self.throughput.read().iter().cloned().collect()
+    }
+
+    /// Whether data moved both ways over `window` - a completed handshake with a
+    /// stalled data path reads as false here while still Connected
+    pub fn is_traffic_flowing(&self, window: Duration) -> bool {
+        traffic_movement(&self.counters.read(), window) == Some((true, true))
+    }
+
+    /// Summarize status, handshake age and recent traffic into one health signal
+    pub async fn get_health(&self) -> TunnelHealth {
+        let last_handshake_age = self.wg_tunnel.lock().await.as_ref()
+            .and_then(|tunnel| tunnel.last_handshake_age());
+        let ws_connected = self.ws_client.lock().await.is_some();
+
+        let recent_traffic = {
+            let history = self.throughput.read();
+            (history.len() == THROUGHPUT_HISTORY_LEN).then(|| {
+                history.iter().fold((0, 0), |(tx, rx), sample| {
+                    (tx + sample.tx_bytes_per_sec, rx + sample.rx_bytes_per_sec)
+                })
+            })
+        };
+        let stats = self.stats.read();
+
+        derive_health(&HealthInputs {
+            status: self.get_status(),
+            uptime: self.connected_at.read().map(|at| at.elapsed()),
+            last_handshake_age,
+            recent_traffic,
+            traffic_movement: traffic_movement(&self.counters.read(), TRAFFIC_FLOW_WINDOW),
+            connection_type: stats.connection_type.clone(),
+            direct_expected: ws_connected && stats.public_endpoint.is_some(),
+        })
+    }
+
+    /// Traffic of the current tunnel split by protocol
+    pub async fn get_protocol_stats(&self) -> ProtocolStats {
+        self.wg_tunnel.lock().await.as_ref()
+            .map(|tunnel| tunnel.protocol_stats())
+            .unwrap_or_default()
+    }
+
+    /// Tunnel DNS servers, TUN name and whether the DNS leak block is up (empty when disconnected)
+    pub async fn dns_setup(&self) -> (Vec<std::net::Ipv4Addr>, Option<String>, bool) {
+        match self.wg_tunnel.lock().await.as_ref() {
+            Some(tunnel) => (tunnel.dns_servers().to_vec(), Some(tunnel.tun_name().to_string()), tunnel.dns_leak_block_active()),
+            None => (Vec::new(), None, false),
+        }
+    }
+
+    /// Get connection statistics including per-peer latency
+    pub async fn get_detailed_stats(&self) -> DetailedStats {
+        let ms = |rtt: Option<Duration>| rtt.map(|d| d.as_millis() as u64);
+        let peers = match self.wg_tunnel.lock().await.as_ref() {
+            Some(tunnel) => tunnel.peer_latencies().into_iter()
+                .map(|latency| PeerStats {
+                    public_key: base64::engine::general_purpose::STANDARD.encode(latency.public_key),
+                    latest_rtt_ms: ms(latency.latest_rtt),
+                    average_rtt_ms: ms(latency.average_rtt),
+                    handshake_rtt_ms: ms(latency.handshake_rtt),
+                    keepalive_interval_secs: latency.keepalive_interval.map(|d| d.as_secs()),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        DetailedStats {
+            stats: self.get_stats(),
+            peers,
+        }
     }
 
     /// Update peer endpoint for direct P2P connection
     pub async fn update_peer_endpoint(&self, public_key: &str, endpoint: SocketAddr) -> Result<(), String> {
         if let Some(tunnel) = self.wg_tunnel.lock().await.as_ref() {
-            let key_bytes: [u8; 32] = base64::engine::general_purpose::STANDARD
-                .decode(public_key)
-                .map_err(|e| format!("Invalid public key: {}", e))?
-                .try_into()
-                .map_err(|_| "Public key must be 32 bytes")?;
-
-            tunnel.update_peer_endpoint(&key_bytes, endpoint);
-            Ok(())
+            let key_bytes = decode_key(public_key, "public key")?;
+            if tunnel.update_peer_endpoint(&key_bytes, endpoint) {
+                Ok(())
+            } else {
+                Err("Unknown peer".to_string())
+            }
         } else {
             Err("Not connected".to_string())
         }
     }
-}
 
-impl Default for TunnelManager {
-    fn default() -> Self {
-        Self::new()
+    /// Add a peer to the running tunnel
+    pub async fn add_peer(&self, peer: WgPeer) -> Result<(), String> {
+        match self.wg_tunnel.lock().await.as_ref() {
+            Some(tunnel) => tunnel.add_peer(peer).await,
+            None => Err("Not connected".to_string()),
+        }
+    }
+
+    /// The running tunnel as a wg-quick .conf
+    pub async fn export_wg_config(&self, include_secrets: bool) -> Result<String, String> {
+        match self.wg_tunnel.lock().await.as_ref() {
+            Some(tunnel) => Ok(tunnel.to_wg_quick_string(include_secrets)),
+            None => Err("Not connected".to_string()),
+        }
+    }
+
+    /// Remove a peer from the running tunnel
+    pub async fn remove_peer(&self, public_key: &str) -> Result<(), String> {
+        let key_bytes = decode_key(public_key, "public key")?;
+        match self.wg_tunnel.lock().await.as_ref() {
+            Some(tunnel) => tunnel.remove_peer(&key_bytes).await,
+            None => Err("Not connected".to_string()),
+        }
+    }
+
+    /// Change a running peer's preshared key; false if it already had this one
+    pub async fn update_peer_psk(&self, public_key: &str, preshared_key: Option<crate::wireguard::SecretKey>) -> Result<bool, String> {
+        let key_bytes = decode_key(public_key, "public key")?;
+        match self.wg_tunnel.lock().await.as_ref() {
+            Some(tunnel) => tunnel.update_peer_psk(&key_bytes, preshared_key).await,
+            None => Err("Not connected".to_string()),
+        }
+    }
+
+    /// Peers seen over the WebSocket this session, by device id
+    pub fn peers(&self) -> Vec<PeerPresence> {
+        let mut peers: Vec<PeerPresence> = self.presence.read().values().cloned().collect();
+        peers.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        peers
+    }
+
+    /// Whether a WireGuard tunnel is held, connected or still being set up
+    pub async fn has_tunnel(&self) -> bool {
+        self.wg_tunnel.lock().await.is_some()
+    }
+
+    /// Snapshot of the running tunnel's peers, empty when not connected
+    pub async fn peer_info(&self) -> Vec<PeerInfo> {
+        self.wg_tunnel.lock().await.as_ref()
+            .map(|tunnel| tunnel.peers())
+            .unwrap_or_default()
+    }
+
+    /// Force the default route through the active tunnel (see WgTunnel::set_default_gateway_manual)
+    pub async fn set_default_gateway_manual(&self, exclude_ip: Option<IpAddr>) -> Result<(), String> {
+        match self.wg_tunnel.lock().await.as_ref() {
+            Some(tunnel) => tunnel.set_default_gateway_manual(exclude_ip).await,
+            None => Err("Not connected".to_string()),
+        }
+    }
+
+    /// Give the default route back to the physical interface while staying connected
+    pub async fn restore_default_gateway_manual(&self) -> Result<(), String> {
+        match self.wg_tunnel.lock().await.as_ref() {
+            Some(tunnel) => tunnel.restore_default_gateway_manual().await,
+            None => Err("Not connected".to_string()),
+        }
+    }
+
+    /// Ping a tunnel address through the WireGuard data path
+    pub async fn test_connectivity(&self, target: std::net::Ipv4Addr) -> Result<ConnectivityTest, String> {
+        let result = match self.wg_tunnel.lock().await.as_ref() {
+            Some(tunnel) => tunnel.echo(target, CONNECTIVITY_TEST_ECHOES).await?,
+            None => return Err("Not connected".to_string()),
+        };
+
+        let ms = |rtt: &Duration| rtt.as_secs_f64() * 1000.0;
+        let received = result.rtts.len() as u32;
+        Ok(ConnectivityTest {
+            target: target.to_string(),
+            peer_public_key: base64::engine::general_purpose::STANDARD.encode(result.public_key),
+            peer_endpoint: result.endpoint.to_string(),
+            sent: result.sent,
+            received,
+            success: received > 0,
+            min_rtt_ms: result.rtts.iter().min().map(ms),
+            average_rtt_ms: (received > 0).then(|| result.rtts.iter().map(ms).sum::<f64>() / received as f64),
+            max_rtt_ms: result.rtts.iter().max().map(ms),
+        })
+    }
+
+    /// Route an app (PID or cgroup path) through the tunnel
+    pub async fn add_split_app(&self, target: &str) -> Result<(), String> {
+        let target = SplitTarget::parse(target)?;
+
+        let tunnel = self.wg_tunnel.lock().await;
+        let tunnel = tunnel.as_ref().ok_or("Not connected")?;
+
+        let mut split = self.split_tunnel.lock().await;
+        if split.is_none() {
+            *split = Some(SplitTunnel::new(tunnel.tun_name())?);
+        }
+        split.as_mut().unwrap().add(target)
+    }
+
+    /// Stop routing an app through the tunnel
+    pub async fn remove_split_app(&self, target: &str) -> Result<(), String> {
+        let target = SplitTarget::parse(target)?;
+
+        match self.split_tunnel.lock().await.as_mut() {
+            Some(split) => split.remove(&target),
+            None => Err("Split tunneling is not active".to_string()),
+        }
+    }
+}
+
+/// Refetch the device config and apply peer changes to the running tunnel
+async fn apply_config_update(
+    tunnel: &Mutex<Option<WgTunnel>>,
+    api_client: &ApiClient,
+    token: &str,
+    device_id: &str,
+) -> Result<(), String> {
+    let config_response = api_client.get_device_config(token, device_id).await?;
+    let mut new_config = parse_wg_config(&config_response.config)?;
+    resolve_peer_endpoints(&mut new_config.peers).await?;
+
+    let guard = tunnel.lock().await;
+    let tunnel = guard.as_ref().ok_or("Not connected")?;
+    apply_peer_changes(tunnel, new_config.peers).await?;
+
+    log::info!("[CONFIG] Config update applied");
+    Ok(())
+}
+
+/// Bring the running tunnel's peers in line with `peers`, leaving unchanged ones alone
+/// A peer that fails to apply doesn't stop the others; the failures are reported together
+async fn apply_peer_changes(tunnel: &WgTunnel, peers: Vec<WgPeer>) -> Result<(), String> {
+    let running = tunnel.peers();
+    let mut errors = Vec::new();
+
+    // Remove peers that are gone
+    for current in &running {
+        if !peers.iter().any(|p| p.public_key == current.public_key) {
+            if let Err(e) = tunnel.remove_peer(&current.public_key).await {
+                errors.push(e);
+            }
+        }
+    }
+
+    // Add peers that are new. Kept peers get their allowed IPs and PSK updated in
+    // place, so their session survives unless the PSK rotated.
+    for peer in peers {
+        let result = if running.iter().any(|p| p.public_key == peer.public_key) {
+            let public_key = peer.public_key;
+            match tunnel.update_peer_allowed_ips(&public_key, peer.allowed_ips, peer.allowed_ips_v6).await {
+                Ok(_) => tunnel.update_peer_psk(&public_key, peer.preshared_key).await.map(|_| ()),
+                Err(e) => Err(e),
+            }
+        } else {
+            tunnel.add_peer(peer).await
+        };
+        if let Err(e) = result {
+            errors.push(e);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        log::warn!("[CONFIG] {} peer change(s) failed, the others were applied", errors.len());
+        Err(errors.join("; "))
+    }
+}
+
+/// Share of the handshake initiations sent between two (attempts, completed)
+/// readings that got no response. Counters that went down (peers were replaced)
+/// start over from zero. Responses to initiations from the previous interval can
+/// outnumber this one's, which counts as no loss.
+fn interval_loss(previous: (u32, u32), now: (u32, u32)) -> f32 {
+    let previous = if now.0 < previous.0 || now.1 < previous.1 { (0, 0) } else { previous };
+    let sent = now.0 - previous.0;
+    if sent == 0 {
+        return 0.0;
+    }
+    let answered = now.1 - previous.1;
+    sent.saturating_sub(answered) as f32 / sent as f32
+}
+
+/// Update the presence map from a WebSocket event. True if it was a peer event.
+fn record_presence(presence: &mut HashMap<String, PeerPresence>, event: &WsEvent) -> bool {
+    let (device_id, public_key, online, endpoint) = match event {
+        WsEvent::PeerOnline { device_id, public_key } => (device_id, Some(public_key), true, None),
+        WsEvent::PeerOffline { device_id } => (device_id, None, false, None),
+        // A peer announcing an endpoint is up
+        WsEvent::PeerEndpointUpdate { device_id, public_key, endpoint } => (device_id, Some(public_key), true, Some(endpoint)),
+        _ => return false,
+    };
+
+    let entry = presence.entry(device_id.clone()).or_insert_with(|| PeerPresence {
+        device_id: device_id.clone(),
+        public_key: None,
+        online,
+        endpoint: None,
+        updated_at: 0,
+    });
+    entry.online = online;
+    if let Some(public_key) = public_key {
+        entry.public_key = Some(public_key.clone());
+    }
+    if let Some(endpoint) = endpoint {
+        entry.endpoint = Some(endpoint.clone());
+    }
+    entry.updated_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    true
+}
+
+/// Re-handshake every peer and re-discover our public endpoint after the network
+/// changed, since the old NAT mapping (and any direct path) is gone
+async fn refresh_after_network_change(
+    status: &RwLock<ConnectionStatus>,
+    stats: &RwLock<ConnectionStats>,
+    tunnel: &Mutex<Option<WgTunnel>>,
+    ws_client: &Mutex<Option<ManagedWsClient>>,
+    app_handle: Option<&tauri::AppHandle>,
+) {
+    // A paused tunnel is refreshed too, but keeps showing Paused
+    let paused = match &*status.read() {
+        ConnectionStatus::Connected => false,
+        ConnectionStatus::Paused => true,
+        _ => return,
+    };
+
+    log::info!("[NETWORK] Network changed, refreshing the tunnel...");
+    if let Some(app) = app_handle {
+        let _ = app.emit("network-changed", ());
+    }
+    if !paused {
+        *status.write() = ConnectionStatus::Handshaking;
+    }
+
+    // A relay behind a hostname may have moved along with us. The lookups can take
+    // a while, so the tunnel stays unlocked for disconnect and stats meanwhile.
+    let hosts = match tunnel.lock().await.as_ref() {
+        Some(tun) => tun.endpoint_hosts(),
+        None => return,
+    };
+    let mut resolved = Vec::new();
+    for (public_key, host) in hosts {
+        match crate::wireguard::resolve_endpoint(&host).await {
+            Ok(addr) => resolved.push((public_key, addr)),
+            Err(e) => log::warn!("[NETWORK] {}", e),
+        }
+    }
+
+    let (bind_address, stun_timeout, handshakes_before, relay_only) = match tunnel.lock().await.as_ref() {
+        Some(tun) => {
+            tun.apply_resolved_endpoints(&resolved).await;
+            tun.refresh_paths();
+            (tun.bind_address(), tun.power_profile().timings().stun_timeout, tun.quality_metrics().handshakes_completed, tun.relay_only())
+        }
+        None => return,
+    };
+
+    // The cached mapping belongs to the old network
+    crate::stun::invalidate_stun_cache();
+    if relay_only {
+        log::info!("[NETWORK] Relay-only mode, skipping STUN discovery");
+    } else {
+        let stun_client = AsyncStunClient::new().bound_to(bind_address).with_timeout(stun_timeout);
+        match stun_client.discover_public_endpoint().await {
+            Ok(result) if result.over_tcp => {
+                log::warn!("[NETWORK] Only TCP reached STUN, public IP is now {} - not registering an endpoint",
+                    result.public_addr.ip());
+                stats.write().public_endpoint = Some(result.public_addr.ip().to_string());
+            }
+            Ok(result) => {
+                log::info!("[NETWORK] Public endpoint is now {}", result.public_addr);
+                stats.write().public_endpoint = Some(result.public_addr.to_string());
+                if let Some(ws) = ws_client.lock().await.as_ref() {
+                    if let Err(e) = ws.register_endpoint(result.public_addr).await {
+                        log::warn!("[NETWORK] Failed to register new endpoint: {}", e);
+                    }
+                }
+            }
+            Err(e) => {
+                log::warn!("[NETWORK] STUN discovery after network change failed: {}", e);
+                stats.write().public_endpoint = None;
+            }
+        }
+    }
+
+    let deadline = Instant::now() + NETWORK_CHANGE_HANDSHAKE_TIMEOUT;
+    loop {
+        let completed = match tunnel.lock().await.as_ref() {
+            Some(tun) => tun.quality_metrics().handshakes_completed,
+            None => return,
+        };
+        if completed > handshakes_before {
+            log::info!("[NETWORK] ✓ Tunnel re-established after network change");
+            break;
+        }
+        if Instant::now() >= deadline {
+            log::warn!("[NETWORK] No handshake within {:?} of the network change, still retrying",
+                NETWORK_CHANGE_HANDSHAKE_TIMEOUT);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    // Leave the status alone if the user disconnected meanwhile
+    let mut status = status.write();
+    if *status == ConnectionStatus::Handshaking {
+        *status = ConnectionStatus::Connected;
+    }
+}
+
+/// Decode a base64 WireGuard key
+fn decode_key(key: &str, what: &str) -> Result<[u8; 32], String> {
+    base64::engine::general_purpose::STANDARD
+        .decode(key)
+        .map_err(|e| format!("Invalid {}: {}", what, e))?
+        .try_into()
+        .map_err(|_| format!("{} must be 32 bytes", what))
+}
+
+impl Default for TunnelManager {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -356,7 +1636,10 @@ impl Default for TunnelManager {
 // Tauri Commands
 // ============================================================================
 
+/// Returns the connection id that tags this session's log lines. Failures are
+/// also emitted as "connection-failed" with a ConnectionFailure reason.
 #[tauri::command]
+#[allow(clippy::too_many_arguments)] // each is a separate optional argument from the frontend
 pub async fn connect_vpn(
     app: tauri::AppHandle,
     state: State<'_, AppState>,
@@ -364,9 +1647,274 @@ pub async fn connect_vpn(
     network_id: String,
     exit_node_type: Option<String>,
     exit_node_id: Option<String>,
-) -> Result<(), String> {
+    connect_timeout_secs: Option<u64>,
+    bind_address: Option<String>,
+    probe_mtu: Option<bool>,
+    listen_port: Option<u16>,
+    auto_register: Option<bool>,
+    relay_only: Option<bool>,
+) -> Result<String, PleError> {
+    let result = try_connect_vpn(
+        app.clone(), state, device_id, network_id, exit_node_type, exit_node_id,
+        connect_timeout_secs, bind_address, probe_mtu, listen_port, auto_register.unwrap_or(false), relay_only,
+    ).await;
+
+    if let Err(error) = &result {
+        let reason = ConnectionFailure::from(error);
+        log::info!("[CONNECT] Failure reason: {:?}", reason);
+        let _ = app.emit("connection-failed", ConnectionFailedEvent { reason, error: error.clone() });
+    }
+    result
+}
+
+/// Probe the relays, make the fastest one the network's exit node and connect
+/// through it. Falls back to the server's first healthy relay if none answer.
+#[tauri::command]
+pub async fn connect_best_exit(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    device_id: String,
+    network_id: String,
+) -> Result<BestExit, PleError> {
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
+    let relays = state.api_client.get_relays(&token).await?;
+    let latencies = crate::api::probe_relays(&relays).await;
+
+    let best = pick_best_exit(&relays, &latencies)
+        .ok_or_else(|| PleError::NotFound("No healthy relay available as an exit node".to_string()))?;
+    match best.latency_ms {
+        Some(ms) => log::info!("[EXIT] Fastest relay: {} ({}) at {}ms", best.name, best.relay_id, ms),
+        None => log::warn!("[EXIT] No relay answered the probe, using {} ({})", best.name, best.relay_id),
+    }
+
+    state.api_client.set_exit_node(&token, &network_id, "relay", Some(&best.relay_id)).await?;
+    connect_vpn(
+        app, state, device_id, network_id, Some("relay".to_string()), Some(best.relay_id.clone()),
+        None, None, None, None, None, None,
+    ).await?;
+    Ok(best)
+}
+
+/// Name of `device_id` in `network_id`, "Desktop" if it can't be looked up
+async fn device_name(api_client: &ApiClient, token: &str, network_id: &str, device_id: &str) -> String {
+    api_client.get_devices(token, network_id).await.ok()
+        .and_then(|devices| devices.into_iter().find(|d| d.id == device_id))
+        .map_or_else(|| "Desktop".to_string(), |d| d.name)
+}
+
+/// Whether `stored_key` is the private half of `public_key`
+fn key_matches(stored_key: &str, public_key: &str) -> bool {
+    decode_secret(stored_key, "stored private key")
+        .is_ok_and(|private_key| derive_public_key(private_key.as_bytes()) == public_key.trim())
+}
+
+/// `stored_key` unless the server has another public key for the device (it was
+/// rotated elsewhere) - then the stale key is forgotten and the server's config
+/// applies. Kept if the server's key couldn't be looked up.
+fn checked_device_key(app: &tauri::AppHandle, device_id: &str, stored_key: String, public_key: Option<&str>) -> Option<String> {
+    match public_key {
+        Some(public_key) if !key_matches(&stored_key, public_key) => {
+            log::warn!("[KEYS] Stored key for device {} doesn't match its public key on the server, forgetting it", device_id);
+            crate::config::clear_device_key(app, device_id);
+            None
+        }
+        Some(_) => Some(stored_key),
+        None => {
+            log::warn!("[KEYS] Couldn't look up device {} on the server, using the stored key unchecked", device_id);
+            Some(stored_key)
+        }
+    }
+}
+
+/// The device's config with the private key this machine uses for it: a locally
+/// rotated key replaces the server's. None if neither has one.
+fn config_with_key(
+    config_response: &DeviceConfig,
+    stored_key: Option<&str>,
+) -> Result<Option<zeroize::Zeroizing<String>>, PleError> {
+    match stored_key {
+        Some(encoded) => {
+            let private_key = decode_secret(encoded, "stored private key").map_err(PleError::Parse)?;
+            Ok(Some(replace_private_key(&config_response.config, &private_key)))
+        }
+        None if config_response.has_private_key => Ok(Some(zeroize::Zeroizing::new(config_response.config.clone()))),
+        None => Ok(None),
+    }
+}
+
+/// Move the running connection to another network, returning this machine's
+/// device there - the one it already has, registered if there's none yet. Peers
+/// are swapped in place when the addresses fit, with that device taking over the
+/// running key; otherwise it reconnects, keeping the TUN device if it fits.
+/// `auto_register` is passed on to that reconnect (see connect_vpn).
+#[tauri::command]
+pub async fn switch_network(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    new_network_id: String,
+    auto_register: Option<bool>,
+) -> Result<Device, PleError> {
+    let (device_id, network_id) = {
+        let manager = state.tunnel_manager.lock().await;
+        manager.connected_device().zip(manager.connected_network())
+            .ok_or_else(|| PleError::Other("Not connected".to_string()))?
+    };
+    log::info!("[SWITCH] Switching from network {} to {}", network_id, new_network_id);
+
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
+    let name = device_name(&state.api_client, &token, &network_id, &device_id).await;
+    let device = match own_device(&app, &state.api_client, &token, &new_network_id, &name).await? {
+        Some(device) => device,
+        None => {
+            log::info!("[SWITCH] No device on network {} yet, registering '{}'", new_network_id, name);
+            state.api_client
+                .auto_register_device(&token, &new_network_id, &name, crate::api::device_platform())
+                .await?
+        }
+    };
+    let config_response = state.api_client.get_device_config(&token, &device.id).await?;
+
+    let stored_key = crate::config::get_device_key_internal(&app, &device.id)
+        .and_then(|key| checked_device_key(&app, &device.id, key, Some(&device.public_key)));
+    let switched = match config_with_key(&config_response, stored_key.as_deref())? {
+        Some(config) => {
+            let mut config = parse_wg_config(&config).map_err(PleError::Parse)?;
+            resolve_peer_endpoints(&mut config.peers).await.map_err(PleError::Network)?;
+            let running_key = state.tunnel_manager.lock().await.key_for_switch(&config).await;
+            match running_key {
+                Some(private_key) if adopt_running_key(&app, &state.api_client, &token, &device, &private_key).await => {
+                    config.private_key = private_key;
+                    state.tunnel_manager.lock().await.switch_network(&device.id, &new_network_id, config).await?
+                }
+                _ => false,
+            }
+        }
+        None => false,
+    };
+
+    let session = crate::config::get_last_session_internal(&app).await.ok().flatten();
+    // Exit node choices belong to the old network, so they aren't carried over -
+    // an in-place switch only happens without one
+    if switched {
+        if let Some(mut session) = session {
+            session.device_id = device.id.clone();
+            session.network_id = new_network_id;
+            session.exit_node_type = None;
+            session.exit_node_id = None;
+            if let Err(e) = crate::config::store_last_session(&app, &session).await {
+                log::warn!("Failed to remember session for auto-connect: {}", e);
+            }
+        }
+        return Ok(device);
+    }
+
+    log::info!("[SWITCH] Device {} can't be switched to in place - reconnecting", device.id);
+    state.tunnel_manager.lock().await.disconnect(true).await.map_err(PleError::Other)?;
+    let (bind_address, probe_mtu, listen_port) = session
+        .map_or((None, None, None), |s| (s.bind_address, Some(s.probe_mtu), s.listen_port));
+    connect_vpn(
+        app, state, device.id.clone(), new_network_id, None, None,
+        None, bind_address, probe_mtu, listen_port, auto_register, None,
+    ).await?;
+    Ok(device)
+}
+
+/// This machine's device on `network_id`: the one it holds a key for, else the
+/// one named `name`. None if it has none there.
+async fn own_device(
+    app: &tauri::AppHandle,
+    api_client: &ApiClient,
+    token: &str,
+    network_id: &str,
+    name: &str,
+) -> Result<Option<Device>, PleError> {
+    let mut devices = api_client.get_devices(token, network_id).await?;
+    let keyed = devices.iter().position(|d| crate::config::has_device_key(app, &d.id));
+    let index = keyed.or_else(|| devices.iter().position(|d| d.name == name));
+    Ok(index.map(|index| devices.swap_remove(index)))
+}
+
+/// Have `device` use the running tunnel's key, so the tunnel can move to its
+/// network without a new handshake identity: the public half goes to the server
+/// and the private half is stored for the device. False if the device keeps its
+/// own key.
+async fn adopt_running_key(
+    app: &tauri::AppHandle,
+    api_client: &ApiClient,
+    token: &str,
+    device: &Device,
+    private_key: &SecretKey,
+) -> bool {
+    let public_key = derive_public_key(private_key.as_bytes());
+    if device.public_key.trim() == public_key {
+        return true;
+    }
+    if let Err(e) = api_client.rotate_device_key(token, &device.id, &public_key).await {
+        log::warn!("[SWITCH] Server rejected the running key for device {}: {}", device.id, e);
+        return false;
+    }
+
+    let encoded = zeroize::Zeroizing::new(base64::engine::general_purpose::STANDARD.encode(private_key.as_bytes()));
+    if let Err(e) = crate::config::store_device_key(app, &device.id, &encoded).await {
+        log::warn!("[SWITCH] Failed to store the running key for device {}, restoring its own: {}", device.id, e);
+        if let Err(rollback) = api_client.rotate_device_key(token, &device.id, &device.public_key).await {
+            log::error!("[SWITCH] Rollback failed: {}", rollback);
+        }
+        return false;
+    }
+    log::info!("[SWITCH] Device {} now uses public key {}", device.id, public_key);
+    true
+}
+
+/// Register a replacement for a device whose config has no private key and fetch
+/// its config, once. The new device is announced with a "device-reregistered" event.
+async fn reregister_device(
+    app: &tauri::AppHandle,
+    api_client: &ApiClient,
+    token: &str,
+    network_id: &str,
+    device_id: &str,
+) -> Result<(String, DeviceConfig), PleError> {
+    // Keep the old name so the device list doesn't gain an anonymous entry
+    let name = device_name(api_client, token, network_id, device_id).await;
+
+    let device = api_client.auto_register_device(token, network_id, &name, crate::api::device_platform()).await?;
+    log::info!("[STEP 3/6] Registered device {} ('{}') in place of {}", device.id, name, device_id);
+
+    let config = api_client.get_device_config(token, &device.id).await?;
+    if !config.has_private_key {
+        return Err(PleError::MissingPrivateKey(format!(
+            "Re-registered device {} still has no private key - the server didn't generate one", device.id)));
+    }
+    let _ = app.emit("device-reregistered", &device);
+    Ok((device.id, config))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn try_connect_vpn(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    device_id: String,
+    network_id: String,
+    exit_node_type: Option<String>,
+    exit_node_id: Option<String>,
+    connect_timeout_secs: Option<u64>,
+    bind_address: Option<String>,
+    probe_mtu: Option<bool>,
+    listen_port: Option<u16>,
+    auto_register: bool,
+    relay_only: Option<bool>,
+) -> Result<String, PleError> {
     log::info!("========== VPN CONNECTION START ==========");
 
+    // Local address to send WireGuard traffic from, for multi-homed machines
+    let bind_ip = bind_address.as_deref()
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| addr.parse::<IpAddr>()
+            .map_err(|_| PleError::Parse(format!("Bind address must be a local IP address, got {}", addr))))
+        .transpose()?;
+
     // Windows: Check if running as Administrator, request elevation if not
     #[cfg(target_os = "windows")]
     {
@@ -375,10 +1923,10 @@ pub async fn connect_vpn(
             // Try to re-launch with admin privileges
             if let Err(e) = request_elevation() {
                 log::error!("Failed to request elevation: {}", e);
-                return Err("Administrator privileges required. Please right-click the app and select 'Run as administrator'.".to_string());
+                return Err(PleError::AdminRequired("Administrator privileges required. Please right-click the app and select 'Run as administrator'.".to_string()));
             }
             // If we get here, elevation was requested but process didn't exit (shouldn't happen)
-            return Err("Elevation requested. Please restart the app.".to_string());
+            return Err(PleError::AdminRequired("Elevation requested. Please restart the app.".to_string()));
         }
         log::info!("[ADMIN] ✓ Running as Administrator");
     }
@@ -387,6 +1935,11 @@ pub async fn connect_vpn(
     log::info!("[STEP 1/6] Exit node: type={:?}, id={:?}", exit_node_type, exit_node_id);
     log::info!("[STEP 1/6] API base URL: {}", state.api_client.base_url);
 
+    // Read at every connect, so a changed profile applies without a restart
+    let power_profile = crate::config::get_power_profile_internal(&app);
+    let block_ipv6_leaks = crate::config::get_block_ipv6_leaks_internal(&app);
+    let relay_only = relay_only.unwrap_or_else(|| crate::config::get_relay_only_internal(&app));
+
     // Get stored token
     log::info!("[STEP 2/6] Retrieving stored auth token...");
     let token = match crate::config::get_stored_token_internal(&app).await {
@@ -396,7 +1949,7 @@ pub async fn connect_vpn(
         }
         Err(e) => {
             log::error!("[STEP 2/6] ✗ FAILED to get token: {}", e);
-            return Err(format!("Failed to get auth token: {}", e));
+            return Err(PleError::Auth(format!("Failed to get auth token: {}", e)));
         }
     };
 
@@ -411,18 +1964,42 @@ pub async fn connect_vpn(
         }
         Err(e) => {
             log::error!("[STEP 3/6] ✗ FAILED to get device config: {}", e);
-            return Err(format!("Failed to get device config: {}", e));
+            return Err(e);
         }
     };
 
-    if !config_response.has_private_key {
-        log::error!("[STEP 3/6] ✗ Device config missing private key");
-        return Err("Device configuration does not include private key. Please use a device with auto-generated keys.".to_string());
+    // A device registered without generated keys can't connect from here; swap it
+    // for one that has them if the caller allowed it
+    let stored_key = match crate::config::get_device_key_internal(&app, &device_id) {
+        Some(key) => {
+            let public_key = state.api_client.get_devices(&token, &network_id).await.ok()
+                .and_then(|devices| devices.into_iter().find(|d| d.id == device_id))
+                .map(|d| d.public_key);
+            checked_device_key(&app, &device_id, key, public_key.as_deref())
+        }
+        None => None,
+    };
+    let (device_id, config_response) = if !config_response.has_private_key && stored_key.is_none() && auto_register {
+        log::warn!("[STEP 3/6] Device config missing private key, re-registering...");
+        reregister_device(&app, &state.api_client, &token, &network_id, &device_id).await?
+    } else {
+        (device_id, config_response)
+    };
+
+    // A key rotated on this machine replaces whatever the server handed out
+    if stored_key.is_some() {
+        log::info!("[STEP 3/6]   - using locally stored private key");
     }
+    let Some(config) = config_with_key(&config_response, stored_key.as_deref())? else {
+        log::error!("[STEP 3/6] ✗ Device config missing private key");
+        return Err(PleError::MissingPrivateKey(format!(
+            "Device {} has no private key on this machine. Connect with auto-register enabled or use a device with auto-generated keys.",
+            device_id)));
+    };
 
     // Log WireGuard config details (without secrets)
     log::info!("[STEP 4/6] Parsing WireGuard config...");
-    for line in config_response.config.lines() {
+    for line in config.lines() {
         let line = line.trim();
         if line.starts_with("[") || line.starts_with("Address") || line.starts_with("DNS") ||
            line.starts_with("Endpoint") || line.starts_with("AllowedIPs") || line.starts_with("PersistentKeepalive") {
@@ -441,32 +2018,250 @@ pub async fn connect_vpn(
 
     // Determine if we should route all traffic through VPN (exit node)
     let use_exit_node = exit_node_type.as_deref() == Some("relay") || exit_node_type.as_deref() == Some("device");
-    log::info!("[STEP 6/6] Calling tunnel_manager.connect() with exit_node={}...", use_exit_node);
-    match tunnel_manager.connect(
-        &config_response.config,
+    let connect_timeout = connect_timeout_secs
+        .map(Duration::from_secs)
+        .unwrap_or(CONNECT_TIMEOUT);
+    log::info!("[STEP 6/6] Calling tunnel_manager.connect() with exit_node={} (timeout {:?})...",
+        use_exit_node, connect_timeout);
+    let result = tokio::time::timeout(connect_timeout, tunnel_manager.connect(
+        &config,
         &device_id,
         &network_id,
         &state.api_client.base_url,
         &token,
-        use_exit_node,
-    ).await {
-        Ok(()) => {
+        ConnectOptions {
+            use_exit_node,
+            bind_address: bind_ip,
+            probe_mtu: probe_mtu.unwrap_or(false),
+            power_profile,
+            block_ipv6_leaks,
+            listen_port,
+            relay_only,
+        },
+    )).await;
+
+    match result {
+        Ok(Ok(connection_id)) => {
             log::info!("========== VPN CONNECTION SUCCESS ==========");
-            Ok(())
+            let session = crate::config::LastSession {
+                device_id,
+                network_id,
+                exit_node_type,
+                exit_node_id,
+                bind_address: bind_ip.map(|ip| ip.to_string()),
+                probe_mtu: probe_mtu.unwrap_or(false),
+                listen_port,
+            };
+            if let Err(e) = crate::config::store_last_session(&app, &session).await {
+                log::warn!("Failed to remember session for auto-connect: {}", e);
+            }
+            if let Some(port) = crate::wireguard::preferred_listen_port() {
+                if let Err(e) = crate::config::store_listen_port(&app, port).await {
+                    log::warn!("Failed to remember listen port: {}", e);
+                }
+            }
+            if let Err(e) = crate::config::store_stun_rtts(&app, &crate::stun::server_rtts()).await {
+                log::warn!("Failed to remember STUN server round-trip times: {}", e);
+            }
+            Ok(connection_id)
         }
-        Err(e) => {
+        Ok(Err(e)) => {
             log::error!("[STEP 6/6] ✗ tunnel_manager.connect() FAILED: {}", e);
             log::error!("========== VPN CONNECTION FAILED ==========");
             Err(e)
         }
+        Err(_) => {
+            log::error!("[STEP 6/6] ✗ tunnel_manager.connect() timed out after {:?}", connect_timeout);
+            // Past start() the tunnel is up and it was the MTU probe or routing setup that hung
+            let tunnel_up = tunnel_manager.has_tunnel().await;
+            // Undo whatever the aborted connect left behind (routes, TUN device, WebSocket)
+            if let Err(e) = tunnel_manager.teardown().await {
+                log::warn!("[STEP 6/6] Cleanup after timeout failed: {}", e);
+            }
+            log::error!("========== VPN CONNECTION TIMED OUT ==========");
+            let message = format!("Connection timed out after {} seconds", connect_timeout.as_secs());
+            Err(if tunnel_up {
+                PleError::SetupTimeout(format!("{} setting up routes", message))
+            } else {
+                PleError::HandshakeTimeout(message)
+            })
+        }
+    }
+}
+
+/// Error when every probed endpoint is definitely unreachable. Silence isn't
+/// enough - relays needn't answer probes to accept WireGuard.
+fn unreachable_endpoints(endpoints: &[SocketAddr], results: &[Reachability]) -> Option<String> {
+    let unreachable: Vec<(SocketAddr, &str)> = endpoints.iter().zip(results)
+        .filter_map(|(endpoint, result)| match result {
+            Reachability::Unreachable(reason) => Some((*endpoint, reason.as_str())),
+            _ => None,
+        })
+        .collect();
+
+    if unreachable.is_empty() || unreachable.len() < endpoints.len() {
+        return None;
+    }
+    match unreachable.as_slice() {
+        [(endpoint, reason)] => Some(format!("Relay {} is unreachable: {}", endpoint, reason)),
+        all => {
+            let details: Vec<String> = all.iter().map(|(endpoint, reason)| format!("{} ({})", endpoint, reason)).collect();
+            Some(format!("Relays are unreachable: {}", details.join(", ")))
+        }
+    }
+}
+
+/// Classify an unclassified WgTunnel::new failure - on macOS a missing or stale
+/// helper daemon is the usual cause
+fn tunnel_setup_error(e: PleError) -> PleError {
+    let PleError::Other(e) = e else {
+        return e;
+    };
+
+    #[cfg(target_os = "macos")]
+    match crate::helper_client::HelperClient::verified() {
+        Err(PleError::HelperUnavailable(_)) => return PleError::HelperUnavailable(e),
+        Err(mismatch @ PleError::HelperVersionMismatch { .. }) => return mismatch,
+        _ => {}
+    }
+
+    PleError::Other(e)
+}
+
+/// Remove routes and the DNS/IPv6 leak blocks a previous session left behind if the
+/// app exited without disconnecting. Must run before anything brings a tunnel up.
+pub async fn recover_stale_routes() {
+    // On macOS the helper's route flush below also clears both blocks
+    #[cfg(not(target_os = "macos"))]
+    {
+        let result = tokio::task::spawn_blocking(crate::tun_device::TunDevice::remove_dns_block).await;
+        if let Ok(Err(e)) = result {
+            log::debug!("[ROUTES] No stale DNS block removed: {}", e);
+        }
+        let result = tokio::task::spawn_blocking(crate::tun_device::TunDevice::remove_ipv6_block).await;
+        if let Ok(Err(e)) = result {
+            log::debug!("[ROUTES] No stale IPv6 block removed: {}", e);
+        }
+        let result = tokio::task::spawn_blocking(crate::tun_device::TunDevice::restore_stale_dns).await;
+        if let Ok(Err(e)) = result {
+            log::warn!("[ROUTES] Failed to restore stale DNS settings: {}", e);
+        }
     }
+
+    #[cfg(target_os = "macos")]
+    {
+        use crate::helper_client::HelperClient;
+
+        if !HelperClient::is_running() {
+            return;
+        }
+
+        let result = tokio::task::spawn_blocking(|| -> Result<_, String> {
+            Ok(HelperClient::verified()?.flush_routes()?)
+        }).await;
+        match result {
+            Ok(Ok(response)) if response.success => {
+                log::info!("[ROUTES] {}", response.message);
+            }
+            Ok(Ok(response)) => log::warn!("[ROUTES] {}", response.message),
+            Ok(Err(e)) => log::warn!("[ROUTES] Failed to flush stale routes: {}", e),
+            Err(e) => log::warn!("[ROUTES] Flush task failed: {}", e),
+        }
+    }
+}
+
+/// Reconnect to the last session on launch when auto-connect is enabled
+/// Emits "login-required" instead of connecting if the stored token is missing or expired
+pub async fn auto_connect(app: tauri::AppHandle) {
+    match crate::config::get_auto_connect_internal(&app).await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            log::warn!("[AUTO-CONNECT] Failed to read setting: {}", e);
+            return;
+        }
+    }
+
+    let session = match crate::config::get_last_session_internal(&app).await {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            log::info!("[AUTO-CONNECT] No previous session, skipping");
+            return;
+        }
+        Err(e) => {
+            log::warn!("[AUTO-CONNECT] {}", e);
+            return;
+        }
+    };
+
+    let state = app.state::<AppState>();
+
+    let verified = match crate::config::get_stored_token_internal(&app).await {
+        Ok(token) => state.api_client.verify_token(&token).await.map(|_| ()),
+        Err(e) => Err(PleError::Auth(e)),
+    };
+    match verified {
+        Ok(()) => {}
+        Err(PleError::Auth(_)) => {
+            log::warn!("[AUTO-CONNECT] Token missing or expired, asking UI to prompt login");
+            let _ = app.emit("login-required", ());
+            return;
+        }
+        // Offline or server trouble doesn't mean the token is bad - don't force a re-login
+        Err(e) => {
+            log::warn!("[AUTO-CONNECT] Couldn't verify token, skipping: {}", e);
+            return;
+        }
+    }
+
+    log::info!("[AUTO-CONNECT] Reconnecting to network {} as device {}",
+        session.network_id, session.device_id);
+    if let Err(e) = connect_vpn(
+        app.clone(),
+        state,
+        session.device_id,
+        session.network_id,
+        session.exit_node_type,
+        session.exit_node_id,
+        None,
+        session.bind_address,
+        Some(session.probe_mtu),
+        session.listen_port,
+        None,
+        None,
+    ).await {
+        log::error!("[AUTO-CONNECT] Failed: {}", e);
+    }
+}
+
+/// `keep_device` leaves the TUN device up for an immediate reconnect
+#[tauri::command]
+pub async fn disconnect_vpn(state: State<'_, AppState>, keep_device: Option<bool>) -> Result<(), String> {
+    log::info!("disconnect_vpn command (keep_device={:?})", keep_device);
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.disconnect(keep_device.unwrap_or(false)).await
+}
+
+/// Clean up stuck VPN state (TUN device, routes, default gateway) even when not connected
+#[tauri::command]
+pub async fn force_reset(state: State<'_, AppState>) -> Result<(), String> {
+    log::info!("force_reset command");
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.force_reset().await
+}
+
+#[tauri::command]
+pub async fn pause_vpn(state: State<'_, AppState>) -> Result<(), String> {
+    log::info!("pause_vpn command");
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.pause().await
 }
 
 #[tauri::command]
-pub async fn disconnect_vpn(state: State<'_, AppState>) -> Result<(), String> {
-    log::info!("disconnect_vpn command");
+pub async fn resume_vpn(state: State<'_, AppState>) -> Result<(), String> {
+    log::info!("resume_vpn command");
     let tunnel_manager = state.tunnel_manager.lock().await;
-    tunnel_manager.disconnect().await
+    tunnel_manager.resume().await
 }
 
 #[tauri::command]
@@ -481,85 +2276,515 @@ pub async fn get_connection_stats(state: State<'_, AppState>) -> Result<Connecti
     Ok(tunnel_manager.get_stats())
 }
 
-/// Legacy config parser (kept for compatibility)
-pub fn parse_wireguard_config(config_str: &str) -> Result<WireGuardConfig, String> {
-    let mut private_key = String::new();
-    let mut address = String::new();
-    let mut dns = None;
-    let mut peers = Vec::new();
-    let mut current_peer: Option<PeerConfig> = None;
+#[tauri::command]
+pub async fn get_detailed_stats(state: State<'_, AppState>) -> Result<DetailedStats, String> {
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    Ok(tunnel_manager.get_detailed_stats().await)
+}
 
-    for line in config_str.lines() {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
+/// Tunnel traffic split by protocol, all zero when not connected
+#[tauri::command]
+pub async fn get_protocol_stats(state: State<'_, AppState>) -> Result<ProtocolStats, String> {
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    Ok(tunnel_manager.get_protocol_stats().await)
+}
 
-        if line == "[Interface]" {
-            continue;
-        }
+#[tauri::command]
+pub async fn get_tunnel_health(state: State<'_, AppState>) -> Result<TunnelHealth, String> {
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    Ok(tunnel_manager.get_health().await)
+}
 
-        if line == "[Peer]" {
-            if let Some(peer) = current_peer.take() {
-                peers.push(peer);
-            }
-            current_peer = Some(PeerConfig {
-                public_key: String::new(),
-                endpoint: None,
-                allowed_ips: Vec::new(),
-                persistent_keepalive: None,
-            });
-            continue;
-        }
+/// Whether data moved in both directions over the last `window_secs` (default 10).
+/// False while connected means idle or, if get_tunnel_health says so, stalled.
+#[tauri::command]
+pub async fn is_traffic_flowing(state: State<'_, AppState>, window_secs: Option<u64>) -> Result<bool, String> {
+    let window = window_secs.map(Duration::from_secs).unwrap_or(TRAFFIC_FLOW_WINDOW);
+    if window.is_zero() || window > MAX_TRAFFIC_FLOW_WINDOW {
+        return Err(format!("Window must be 1-{} seconds", MAX_TRAFFIC_FLOW_WINDOW.as_secs()));
+    }
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    Ok(tunnel_manager.is_traffic_flowing(window))
+}
 
-        if let Some((key, value)) = line.split_once('=') {
-            let key = key.trim();
-            let value = value.trim();
+#[tauri::command]
+pub async fn get_throughput_history(state: State<'_, AppState>) -> Result<Vec<ThroughputSample>, String> {
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    Ok(tunnel_manager.get_throughput_history())
+}
 
-            match key {
-                "PrivateKey" => private_key = value.to_string(),
-                "Address" => address = value.to_string(),
-                "DNS" => dns = Some(value.to_string()),
-                "PublicKey" => {
-                    if let Some(ref mut peer) = current_peer {
-                        peer.public_key = value.to_string();
-                    }
-                }
-                "Endpoint" => {
-                    if let Some(ref mut peer) = current_peer {
-                        peer.endpoint = Some(value.to_string());
-                    }
-                }
-                "AllowedIPs" => {
-                    if let Some(ref mut peer) = current_peer {
-                        peer.allowed_ips = value.split(',').map(|s| s.trim().to_string()).collect();
-                    }
-                }
-                "PersistentKeepalive" => {
-                    if let Some(ref mut peer) = current_peer {
-                        peer.persistent_keepalive = value.parse().ok();
-                    }
-                }
-                _ => {}
-            }
+/// Export the running tunnel as a wg-quick .conf. The private and preshared
+/// keys are only included when `include_secrets` is set (the UI confirms first).
+#[tauri::command]
+pub async fn export_wg_config(state: State<'_, AppState>, include_secrets: Option<bool>) -> Result<String, String> {
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.export_wg_config(include_secrets.unwrap_or(false)).await
+}
+
+/// Add a peer to the running tunnel without reconnecting
+#[tauri::command]
+pub async fn add_peer(
+    state: State<'_, AppState>,
+    public_key: String,
+    endpoint: Option<String>,
+    allowed_ips: String,
+    persistent_keepalive: Option<u16>,
+    preshared_key: Option<String>,
+) -> Result<(), String> {
+    let (endpoint, endpoint_host) = endpoint
+        .map(|e| crate::wireguard::parse_endpoint(e.trim()))
+        .transpose()?
+        .unzip();
+    let preshared_key = preshared_key
+        .map(|k| crate::wireguard::decode_secret(&zeroize::Zeroizing::new(k), "preshared key"))
+        .transpose()?;
+
+    let mut peer = WgPeer {
+        public_key: decode_key(&public_key, "public key")?,
+        endpoint: endpoint.flatten(),
+        endpoint_host: endpoint_host.flatten(),
+        allowed_ips: parse_allowed_ips(&allowed_ips),
+        allowed_ips_v6: parse_allowed_ips_v6(&allowed_ips),
+        persistent_keepalive,
+        preshared_key,
+    };
+    resolve_peer_endpoints(std::slice::from_mut(&mut peer)).await?;
+
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.add_peer(peer).await
+}
+
+/// Point a peer at `endpoint` directly, bypassing P2P signalling - for testing
+/// NAT traversal and forcing a relay-to-direct switch by hand
+#[tauri::command]
+pub async fn set_peer_endpoint(state: State<'_, AppState>, public_key: String, endpoint: String) -> Result<(), String> {
+    let endpoint = endpoint.trim().parse::<SocketAddr>()
+        .map_err(|e| format!("Invalid endpoint: {}", e))?;
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.update_peer_endpoint(&public_key, endpoint).await
+}
+
+/// Rotate (or with None, remove) a running peer's preshared key without
+/// reconnecting. Returns false if the peer already used this key.
+#[tauri::command]
+pub async fn update_peer_psk(
+    state: State<'_, AppState>,
+    public_key: String,
+    preshared_key: Option<String>,
+) -> Result<bool, String> {
+    let preshared_key = preshared_key
+        .map(|k| crate::wireguard::decode_secret(&zeroize::Zeroizing::new(k), "preshared key"))
+        .transpose()?;
+
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.update_peer_psk(&public_key, preshared_key).await
+}
+
+/// Remove a peer from the running tunnel without reconnecting
+#[tauri::command]
+pub async fn remove_peer(state: State<'_, AppState>, public_key: String) -> Result<(), String> {
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.remove_peer(&public_key).await
+}
+
+/// Route only the given app (PID or cgroup path) through the VPN
+#[tauri::command]
+pub async fn add_split_app(state: State<'_, AppState>, target: String) -> Result<(), String> {
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.add_split_app(&target).await
+}
+
+/// Stop routing an app through the VPN
+#[tauri::command]
+pub async fn remove_split_app(state: State<'_, AppState>, target: String) -> Result<(), String> {
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.remove_split_app(&target).await
+}
+
+/// What connecting needs from the system. Fields that don't apply on this
+/// platform are None.
+#[derive(Debug, Clone, Serialize)]
+pub struct Prerequisites {
+    /// macOS: helper binary and launchd plist are in place
+    pub helper_installed: Option<bool>,
+    /// macOS: helper socket exists
+    pub helper_running: Option<bool>,
+    /// macOS: running helper answered with this app's version
+    pub helper_version_ok: Option<bool>,
+    /// Windows: process is elevated
+    pub is_admin: Option<bool>,
+    /// Everything that applies is satisfied
+    pub ready: bool,
+}
+
+/// Check the helper (macOS) or elevation (Windows) without connecting, so the UI
+/// can guide the user before a connect fails
+#[tauri::command]
+pub async fn check_prerequisites() -> Result<Prerequisites, String> {
+    #[cfg(target_os = "macos")]
+    let (helper_installed, helper_running, helper_version_ok) = {
+        use crate::helper_client::HelperClient;
+
+        let running = HelperClient::is_running();
+        (Some(HelperClient::is_installed()), Some(running), Some(running && HelperClient::verified().is_ok()))
+    };
+    #[cfg(not(target_os = "macos"))]
+    let (helper_installed, helper_running, helper_version_ok) = (None, None, None);
+
+    #[cfg(target_os = "windows")]
+    let is_admin = Some(is_running_as_admin());
+    #[cfg(not(target_os = "windows"))]
+    let is_admin = None;
+
+    let ready = [helper_installed, helper_running, helper_version_ok, is_admin]
+        .iter()
+        .all(|met| met.unwrap_or(true));
+    let prerequisites = Prerequisites { helper_installed, helper_running, helper_version_ok, is_admin, ready };
+
+    log::info!("check_prerequisites command: {:?}", prerequisites);
+    Ok(prerequisites)
+}
+
+/// Features the running platform supports, so the UI can hide toggles that
+/// would only error out
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    /// Block all traffic outside the tunnel when it drops - no platform has one yet
+    pub kill_switch: bool,
+    /// Route selected apps through the tunnel (Linux with cgroups and iptables)
+    pub per_app_split: bool,
+    /// Carry IPv6 through the tunnel (not yet through the macOS helper)
+    pub ipv6: bool,
+    /// Point the system's resolvers at the tunnel DNS
+    pub dns_config: bool,
+    /// Tunnel setup goes through the privileged helper (macOS)
+    pub helper_required: bool,
+}
+
+/// What this build supports on this machine
+#[tauri::command]
+pub async fn get_capabilities() -> Result<Capabilities, String> {
+    let capabilities = tokio::task::spawn_blocking(|| Capabilities {
+        kill_switch: false,
+        per_app_split: crate::split_tunnel::SplitTunnel::is_supported(),
+        // Linux can run with IPv6 disabled in the kernel
+        ipv6: cfg!(target_os = "windows")
+            || (cfg!(target_os = "linux") && std::path::Path::new("/proc/net/if_inet6").exists()),
+        dns_config: true,
+        helper_required: cfg!(target_os = "macos"),
+    })
+    .await
+    .map_err(|e| format!("Capability check failed: {}", e))?;
+
+    log::info!("get_capabilities command: {:?}", capabilities);
+    Ok(capabilities)
+}
+
+/// Remove the macOS privileged helper daemon (prompts for admin password)
+#[tauri::command]
+pub async fn uninstall_helper() -> Result<(), String> {
+    #[cfg(target_os = "macos")]
+    return crate::helper_client::HelperClient::uninstall_helper().await;
+
+    #[cfg(not(target_os = "macos"))]
+    Err("The helper daemon is only used on macOS".to_string())
+}
+
+/// Clean reinstall of the macOS privileged helper daemon (prompts for admin password)
+#[tauri::command]
+pub async fn repair_helper() -> Result<(), PleError> {
+    #[cfg(target_os = "macos")]
+    return crate::helper_client::HelperClient::repair_helper().await;
+
+    #[cfg(not(target_os = "macos"))]
+    Err(PleError::Other("The helper daemon is only used on macOS".to_string()))
+}
+
+/// Helper daemon packet counters, for telling "helper isn't reading" apart from "no traffic"
+#[tauri::command]
+pub async fn get_helper_metrics() -> Result<serde_json::Value, String> {
+    #[cfg(target_os = "macos")]
+    return crate::helper_client::HelperClient::verified()?.get_metrics();
+
+    #[cfg(not(target_os = "macos"))]
+    Err("The helper daemon is only used on macOS".to_string())
+}
+
+/// Zip the app log, helper log and helper metrics for attaching to a support issue.
+/// Written to `path`, or a timestamped file in the temp directory; returns where it went.
+#[tauri::command]
+pub async fn collect_support_bundle(path: Option<String>) -> Result<String, String> {
+    let dest = match path.filter(|p| !p.trim().is_empty()) {
+        Some(path) => std::path::PathBuf::from(path),
+        None => {
+            let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            std::env::temp_dir().join(format!("ple7-support-{}.zip", secs))
         }
+    };
+
+    #[cfg(target_os = "macos")]
+    let helper_metrics = match get_helper_metrics().await {
+        Ok(metrics) => Some(metrics),
+        Err(e) => Some(serde_json::json!({ "error": e })),
+    };
+    #[cfg(not(target_os = "macos"))]
+    let helper_metrics = None;
+
+    let bundle_path = dest.clone();
+    tokio::task::spawn_blocking(move || crate::logging::write_support_bundle(&bundle_path, helper_metrics))
+        .await
+        .map_err(|e| format!("Support bundle task failed: {}", e))??;
+
+    log::info!("[SUPPORT] Wrote support bundle to {}", dest.display());
+    Ok(dest.display().to_string())
+}
+
+/// Routes the helper daemon installed and hasn't removed, for diagnosing broken networking
+#[tauri::command]
+pub async fn list_helper_routes() -> Result<serde_json::Value, String> {
+    #[cfg(target_os = "macos")]
+    return crate::helper_client::HelperClient::verified()?.list_routes();
+
+    #[cfg(not(target_os = "macos"))]
+    Err("The helper daemon is only used on macOS".to_string())
+}
+
+/// Check a pasted or imported WireGuard config for problems before connecting with it
+#[tauri::command]
+pub async fn verify_config(config: String) -> Result<crate::wireguard::ConfigReport, String> {
+    Ok(crate::wireguard::verify_wg_config(&config))
+}
+
+/// Read a WireGuard config from a QR code image, checked with parse_wg_config,
+/// for the UI to save or connect with
+#[tauri::command]
+pub async fn import_config_from_qr(image_path: String) -> Result<String, PleError> {
+    log::info!("import_config_from_qr command: {}", image_path);
+    let config = tokio::task::spawn_blocking(move || crate::qr::read_config(std::path::Path::new(&image_path)))
+        .await
+        .map_err(|e| PleError::Other(format!("QR decode task failed: {}", e)))??;
+    crate::wireguard::parse_wg_config(&config)
+        .map_err(|e| PleError::Parse(format!("QR code isn't a WireGuard config: {}", e)))?;
+    Ok(config)
+}
+
+/// Start or stop writing tunnel traffic to a pcap file for support debugging.
+/// The capture holds decrypted traffic, so it is never on unless the user turns it on.
+#[tauri::command]
+pub async fn set_packet_capture(enabled: bool, path: Option<String>) -> Result<(), String> {
+    log::info!("set_packet_capture command: enabled={}", enabled);
+    if !enabled {
+        crate::capture::stop();
+        return Ok(());
     }
 
-    if let Some(peer) = current_peer {
-        peers.push(peer);
+    let path = path.filter(|p| !p.trim().is_empty())
+        .ok_or_else(|| "A capture file path is required".to_string())?;
+    crate::capture::start(&path)
+}
+
+/// Destinations the sampler has seen outbound traffic to, busiest first.
+/// Empty unless a flow sample rate is set.
+#[tauri::command]
+pub async fn get_active_flows() -> Result<Vec<crate::flows::ActiveFlow>, String> {
+    Ok(crate::flows::snapshot())
+}
+
+/// Ping an IPv4 address through the tunnel to prove traffic flows end to end
+#[tauri::command]
+pub async fn test_connectivity(state: State<'_, AppState>, target_ip: String) -> Result<ConnectivityTest, String> {
+    let target = match target_ip.trim().parse::<IpAddr>() {
+        Ok(IpAddr::V4(target)) => target,
+        Ok(IpAddr::V6(_)) => return Err("Only IPv4 targets can be tested".to_string()),
+        Err(_) => return Err(format!("Invalid IP address: {}", target_ip)),
+    };
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.test_connectivity(target).await
+}
+
+/// Support tool: route all traffic through the active tunnel, keeping `exclude_ip`
+/// (default: the relay endpoint) on the physical interface
+#[tauri::command]
+pub async fn set_default_gateway_manual(state: State<'_, AppState>, exclude_ip: Option<String>) -> Result<(), String> {
+    let exclude_ip = exclude_ip.as_deref()
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(|ip| ip.parse::<IpAddr>().map_err(|_| format!("Invalid IP address: {}", ip)))
+        .transpose()?;
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.set_default_gateway_manual(exclude_ip).await
+}
+
+/// Support tool: undo the default route through the tunnel without disconnecting
+#[tauri::command]
+pub async fn restore_default_gateway_manual(state: State<'_, AppState>) -> Result<(), String> {
+    let tunnel_manager = state.tunnel_manager.lock().await;
+    tunnel_manager.restore_default_gateway_manual().await
+}
+
+/// The network's devices with handshake, endpoint and direct/relay state from the
+/// running tunnel merged in - plain server data when not connected
+#[tauri::command]
+pub async fn get_devices_enriched(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    network_id: String,
+) -> Result<Vec<EnrichedDevice>, PleError> {
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
+    let devices = state.api_client.get_devices(&token, &network_id).await?;
+    let peers = state.tunnel_manager.lock().await.peer_info().await;
+    Ok(enrich_devices(devices, &peers))
+}
+
+/// Peers seen over the WebSocket this session and whether they're online.
+/// Changes arrive as "peer-event" events.
+#[tauri::command]
+pub async fn get_peers(state: State<'_, AppState>) -> Result<Vec<PeerPresence>, String> {
+    Ok(state.tunnel_manager.lock().await.peers())
+}
+
+/// Resolvers the system will actually query, and whether they go through the tunnel
+#[tauri::command]
+pub async fn get_active_dns(state: State<'_, AppState>) -> Result<crate::dns::ActiveDns, String> {
+    let (tunnel_dns, tun_name, leak_block_active) = state.tunnel_manager.lock().await.dns_setup().await;
+    tokio::task::spawn_blocking(move || crate::dns::active_dns(&tunnel_dns, tun_name.as_deref(), leak_block_active))
+        .await
+        .map_err(|e| format!("DNS check task failed: {}", e))?
+}
+
+/// Generate a random base64 preshared key for a new peer
+#[tauri::command]
+pub async fn generate_preshared_key() -> Result<String, String> {
+    Ok(crate::wireguard::generate_preshared_key())
+}
+
+/// Forget cached STUN mappings so the next connect re-discovers the public endpoint
+#[tauri::command]
+pub async fn refresh_stun_cache() -> Result<(), String> {
+    crate::stun::invalidate_stun_cache();
+    Ok(())
+}
+
+/// Set how long STUN results are reused across reconnects (0 disables caching)
+#[tauri::command]
+pub async fn set_stun_cache_ttl(ttl_secs: u64) -> Result<(), String> {
+    crate::stun::set_stun_cache_ttl(Duration::from_secs(ttl_secs));
+    Ok(())
+}
+
+/// Get this device's WireGuard public key, derived from its private key
+/// Useful for spotting key mismatches when handshakes fail
+#[tauri::command]
+pub async fn get_device_public_key(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    device_id: String,
+) -> Result<String, String> {
+    if let Some(encoded) = crate::config::get_device_key_internal(&app, &device_id) {
+        let private_key = decode_secret(&encoded, "stored private key")?;
+        return Ok(derive_public_key(private_key.as_bytes()));
+    }
+
+    let token = crate::config::get_stored_token_internal(&app).await?;
+    let config_response = state.api_client.get_device_config(&token, &device_id).await?;
+
+    if !config_response.has_private_key {
+        return Err("Device configuration does not include private key".to_string());
+    }
+
+    let wg_config = parse_wg_config(&config_response.config)?;
+    Ok(derive_public_key(wg_config.private_key.as_bytes()))
+}
+
+/// Replace this device's WireGuard keypair. The new public key is registered
+/// with the server and the private key kept locally; a live tunnel for the
+/// device is reconnected so it handshakes with the new key.
+#[tauri::command]
+pub async fn rotate_device_keys(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    device_id: String,
+) -> Result<String, PleError> {
+    log::info!("[KEYS] rotate_device_keys command: device={}", device_id);
+
+    // Needed to roll the server back if the new key can't be kept
+    let old_public_key = get_device_public_key(app.clone(), state.clone(), device_id.clone())
+        .await
+        .map_err(PleError::Other)?;
+    let token = crate::config::get_stored_token_internal(&app).await.map_err(PleError::Auth)?;
+
+    let (private_key, public_key) = generate_keypair();
+    if let Err(e) = state.api_client.rotate_device_key(&token, &device_id, &public_key).await {
+        log::error!("[KEYS] Server rejected the new key: {}", e);
+        return Err(e);
     }
 
-    if private_key.is_empty() {
-        return Err("Missing PrivateKey".to_string());
+    let encoded = zeroize::Zeroizing::new(base64::engine::general_purpose::STANDARD.encode(private_key.as_bytes()));
+    if let Err(e) = crate::config::store_device_key(&app, &device_id, &encoded).await {
+        log::error!("[KEYS] Failed to store new key, restoring the old one: {}", e);
+        if let Err(rollback) = state.api_client.rotate_device_key(&token, &device_id, &old_public_key).await {
+            log::error!("[KEYS] Rollback failed: {}", rollback);
+        }
+        return Err(PleError::Other(format!("Failed to store new key: {}", e)));
     }
-    if address.is_empty() {
-        return Err("Missing Address".to_string());
+    log::info!("[KEYS] Device {} now uses public key {}", device_id, public_key);
+
+    // The running tunnel still holds the old key - reconnect with the new one
+    let connected = state.tunnel_manager.lock().await.connected_device();
+    if connected.as_deref() == Some(device_id.as_str()) {
+        match crate::config::get_last_session_internal(&app).await {
+            Ok(Some(session)) if session.device_id == device_id => {
+                log::info!("[KEYS] Reconnecting with the new key");
+                state.tunnel_manager.lock().await.disconnect(true).await.map_err(PleError::Other)?;
+                connect_vpn(
+                    app.clone(),
+                    state,
+                    session.device_id,
+                    session.network_id,
+                    session.exit_node_type,
+                    session.exit_node_id,
+                    None,
+                    session.bind_address,
+                    Some(session.probe_mtu),
+                    session.listen_port,
+                    None,
+                    None,
+                ).await?;
+            }
+            _ => log::warn!("[KEYS] No session to replay, reconnect manually to use the new key"),
+        }
     }
 
+    Ok(public_key)
+}
+
+/// Legacy config parser (kept for compatibility) - parses with parse_wg_config and
+/// renders the result as strings, so both accept and reject the same configs.
+/// Values come back canonical: keys re-encoded, Address with its prefix length;
+/// hostname endpoints are kept as written.
+pub fn parse_wireguard_config(config_str: &str) -> Result<WireGuardConfig, String> {
+    let config = parse_wg_config(config_str)?;
+    let b64 = |bytes: &[u8]| base64::engine::general_purpose::STANDARD.encode(bytes);
+
+    let mut addresses = vec![format!("{}/{}", config.address, u32::from(config.netmask).count_ones())];
+    addresses.extend(config.address_v6.map(|(addr, prefix)| format!("{}/{}", addr, prefix)));
+    let dns = config.dns.iter().map(|server| server.to_string()).collect::<Vec<_>>();
+
+    let peers = config.peers.iter()
+        .map(|peer| PeerConfig {
+            public_key: b64(&peer.public_key),
+            endpoint: peer.endpoint_host.clone().or_else(|| peer.endpoint.map(|endpoint| endpoint.to_string())),
+            allowed_ips: peer.allowed_ips.iter().map(|(addr, prefix)| format!("{}/{}", addr, prefix))
+                .chain(peer.allowed_ips_v6.iter().map(|(addr, prefix)| format!("{}/{}", addr, prefix)))
+                .collect(),
+            persistent_keepalive: peer.persistent_keepalive,
+        })
+        .collect();
+
     Ok(WireGuardConfig {
-        private_key,
-        address,
-        dns,
+        private_key: b64(config.private_key.as_bytes()),
+        address: addresses.join(", "),
+        dns: (!dns.is_empty()).then(|| dns.join(", ")),
         peers,
     })
 }
@@ -671,3 +2896,328 @@ fn request_elevation() -> Result<(), String> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connected() -> HealthInputs {
+        HealthInputs {
+            status: ConnectionStatus::Connected,
+            uptime: Some(Duration::from_secs(120)),
+            last_handshake_age: Some(Duration::from_secs(30)),
+            recent_traffic: Some((4096, 8192)),
+            traffic_movement: Some((true, true)),
+            connection_type: "direct".to_string(),
+            direct_expected: true,
+        }
+    }
+
+    fn reason(health: TunnelHealth) -> String {
+        match health {
+            TunnelHealth::Degraded { reason } | TunnelHealth::Down { reason } => reason,
+            TunnelHealth::Healthy => "healthy".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_unreachable_endpoints() {
+        let a: SocketAddr = "203.0.113.1:51820".parse().unwrap();
+        let b: SocketAddr = "203.0.113.2:51820".parse().unwrap();
+        let refused = || Reachability::Unreachable("Connection refused".to_string());
+
+        // Silence isn't failure, and one working relay is enough
+        assert_eq!(unreachable_endpoints(&[a], &[Reachability::NoReply]), None);
+        assert_eq!(unreachable_endpoints(&[a, b], &[refused(), Reachability::Reachable]), None);
+
+        assert_eq!(
+            unreachable_endpoints(&[a], &[refused()]).as_deref(),
+            Some("Relay 203.0.113.1:51820 is unreachable: Connection refused"),
+        );
+        assert!(unreachable_endpoints(&[a, b], &[refused(), refused()]).unwrap().starts_with("Relays are unreachable"));
+    }
+
+    #[test]
+    fn test_connection_failure_event() {
+        let event = ConnectionFailedEvent {
+            reason: ConnectionFailure::from(&PleError::AdminRequired("Administrator privileges required".to_string())),
+            error: PleError::AdminRequired("Administrator privileges required".to_string()),
+        };
+        assert_eq!(serde_json::to_value(&event).unwrap(), serde_json::json!({
+            "reason": "needsAdmin",
+            "error": { "kind": "adminRequired", "message": "Administrator privileges required", "retryable": false },
+        }));
+
+        let reason = |e: PleError| ConnectionFailure::from(&e);
+        assert_eq!(reason(PleError::HelperUnavailable(String::new())), ConnectionFailure::HelperNotInstalled);
+        assert_eq!(reason(PleError::HandshakeTimeout(String::new())), ConnectionFailure::HandshakeTimeout);
+        assert_eq!(reason(PleError::SetupTimeout(String::new())), ConnectionFailure::SetupTimeout);
+        assert_eq!(reason(PleError::Auth(String::new())), ConnectionFailure::AuthExpired);
+        assert_eq!(reason(PleError::MissingPrivateKey(String::new())), ConnectionFailure::MissingPrivateKey);
+    }
+
+    #[test]
+    fn test_derive_health() {
+        assert_eq!(derive_health(&connected()), TunnelHealth::Healthy);
+
+        let disconnected = HealthInputs { status: ConnectionStatus::Disconnected, ..connected() };
+        assert!(matches!(derive_health(&disconnected), TunnelHealth::Down { .. }));
+
+        let no_handshake = HealthInputs { last_handshake_age: None, ..connected() };
+        assert!(matches!(derive_health(&no_handshake), TunnelHealth::Down { .. }));
+        // Still within the grace period
+        let just_connected = HealthInputs { uptime: Some(Duration::from_secs(2)), recent_traffic: None, ..no_handshake };
+        assert_eq!(derive_health(&just_connected), TunnelHealth::Healthy);
+
+        let idle = HealthInputs { recent_traffic: Some((0, 0)), ..connected() };
+        assert_eq!(reason(derive_health(&idle)), "No recent traffic");
+        // Idle for the short window is fine, one-way isn't
+        let quiet = HealthInputs { traffic_movement: Some((false, false)), ..connected() };
+        assert_eq!(derive_health(&quiet), TunnelHealth::Healthy);
+        let stalled = HealthInputs { traffic_movement: Some((true, false)), ..connected() };
+        assert_eq!(reason(derive_health(&stalled)), "Handshake OK but no data coming back");
+        let one_way = HealthInputs { recent_traffic: Some((512, 0)), ..connected() };
+        assert_eq!(reason(derive_health(&one_way)), "Sending but receiving nothing");
+
+        let relayed = HealthInputs { connection_type: "relay".to_string(), ..connected() };
+        assert!(matches!(derive_health(&relayed), TunnelHealth::Degraded { .. }));
+        let relay_only = HealthInputs { direct_expected: false, ..relayed };
+        assert_eq!(derive_health(&relay_only), TunnelHealth::Healthy);
+
+        let stale = HealthInputs { last_handshake_age: Some(Duration::from_secs(300)), ..connected() };
+        assert_eq!(reason(derive_health(&stale)), "Last handshake 5 minutes ago");
+    }
+
+    #[test]
+    fn test_interval_loss() {
+        assert_eq!(interval_loss((0, 0), (0, 0)), 0.0);
+        // 4 initiations this interval, 3 answered
+        assert_eq!(interval_loss((2, 2), (6, 5)), 0.25);
+        // A late response to the previous interval's initiation isn't negative loss
+        assert_eq!(interval_loss((3, 2), (4, 4)), 0.0);
+        // Peers were replaced, counting starts over
+        assert_eq!(interval_loss((10, 9), (2, 1)), 0.5);
+    }
+
+    #[test]
+    fn test_record_presence() {
+        let mut presence = HashMap::new();
+        let online = WsEvent::PeerOnline { device_id: "dev-1".to_string(), public_key: "key-1".to_string() };
+        assert!(record_presence(&mut presence, &online));
+
+        let endpoint = WsEvent::PeerEndpointUpdate {
+            device_id: "dev-1".to_string(),
+            public_key: "key-1".to_string(),
+            endpoint: "203.0.113.5:51820".to_string(),
+        };
+        assert!(record_presence(&mut presence, &endpoint));
+        assert!(record_presence(&mut presence, &WsEvent::PeerOffline { device_id: "dev-1".to_string() }));
+
+        let peer = &presence["dev-1"];
+        assert!(!peer.online);
+        assert_eq!(peer.public_key.as_deref(), Some("key-1"));
+        assert_eq!(peer.endpoint.as_deref(), Some("203.0.113.5:51820"));
+
+        assert!(!record_presence(&mut presence, &WsEvent::Ping));
+        assert_eq!(presence.len(), 1);
+    }
+
+    #[test]
+    fn test_pick_best_exit() {
+        let relay = |id: &str, status: &str| Relay {
+            id: id.to_string(),
+            name: id.to_string(),
+            location: "Frankfurt".to_string(),
+            country_code: "DE".to_string(),
+            public_endpoint: "relay.example.com:51820".to_string(),
+            status: status.to_string(),
+        };
+        let latency = |id: &str, latency_ms| RelayLatency {
+            id: id.to_string(),
+            name: id.to_string(),
+            country_code: "DE".to_string(),
+            public_endpoint: "relay.example.com:51820".to_string(),
+            latency_ms,
+        };
+        let relays = vec![relay("near", "online"), relay("fast", "offline"), relay("ok", "online")];
+
+        // The fastest relay is skipped while the server reports it offline
+        let latencies = vec![latency("fast", Some(5)), latency("ok", Some(40)), latency("near", None)];
+        let best = pick_best_exit(&relays, &latencies).unwrap();
+        assert_eq!((best.relay_id.as_str(), best.latency_ms), ("ok", Some(40)));
+
+        // Nothing answered - the server's first healthy relay
+        let latencies = vec![latency("near", None), latency("ok", None)];
+        let best = pick_best_exit(&relays, &latencies).unwrap();
+        assert_eq!((best.relay_id.as_str(), best.latency_ms), ("near", None));
+
+        assert!(pick_best_exit(&[relay("fast", "offline")], &[]).is_none());
+    }
+
+    #[test]
+    fn test_legacy_parser_matches_parse_wg_config() {
+        let encode = |key: [u8; 32]| base64::engine::general_purpose::STANDARD.encode(key);
+        let fixture = format!(
+            "[Interface]\nPrivateKey = {}\nAddress = 10.100.0.2/16, fd00:100::2/64\nDNS = 1.1.1.1, 9.9.9.9\n\n\
+             [Peer]\nPublicKey = {}\nEndpoint = 203.0.113.5:51820\nAllowedIPs = 10.100.0.0/16, fd00:100::/64\nPersistentKeepalive = 25\n\n\
+             [Peer]\nPublicKey = {}\nAllowedIPs = 0.0.0.0/0\n",
+            encode([7; 32]), encode([8; 32]), encode([9; 32]),
+        );
+        let config = parse_wg_config(&fixture).unwrap();
+        let legacy = parse_wireguard_config(&fixture).unwrap();
+
+        assert_eq!(legacy.private_key, encode([7; 32]));
+        assert_eq!(legacy.address, "10.100.0.2/16, fd00:100::2/64");
+        assert_eq!(legacy.dns.as_deref(), Some("1.1.1.1, 9.9.9.9"));
+
+        let expected = vec![
+            (encode([8; 32]), Some("203.0.113.5:51820".to_string()),
+                vec!["10.100.0.0/16".to_string(), "fd00:100::/64".to_string()], Some(25)),
+            (encode([9; 32]), None, vec!["0.0.0.0/0".to_string()], None),
+        ];
+        let legacy_peers: Vec<_> = legacy.peers.into_iter()
+            .map(|p| (p.public_key, p.endpoint, p.allowed_ips, p.persistent_keepalive))
+            .collect();
+        let peers: Vec<_> = config.peers.iter()
+            .map(|p| (
+                encode(p.public_key),
+                p.endpoint.map(|e| e.to_string()),
+                p.allowed_ips.iter().map(|(a, n)| format!("{}/{}", a, n))
+                    .chain(p.allowed_ips_v6.iter().map(|(a, n)| format!("{}/{}", a, n)))
+                    .collect::<Vec<_>>(),
+                p.persistent_keepalive,
+            ))
+            .collect();
+        assert_eq!(legacy_peers, expected);
+        assert_eq!(peers, expected);
+
+        // Both reject what either rejects
+        let broken = fixture.replace("203.0.113.5:51820", "not an endpoint");
+        assert!(parse_wg_config(&broken).is_err() && parse_wireguard_config(&broken).is_err());
+    }
+
+    #[test]
+    fn test_traffic_movement() {
+        let start = Instant::now();
+        let sample = |secs, tx_bytes, rx_bytes| CounterSample { at: start + Duration::from_secs(secs), tx_bytes, rx_bytes };
+        let window = Duration::from_secs(10);
+
+        let mut samples = VecDeque::from([sample(0, 100, 100), sample(5, 200, 300)]);
+        assert_eq!(traffic_movement(&samples, window), None);
+
+        // Compared against the newest sample at least a window old (t=5)
+        samples.extend([sample(15, 400, 300), sample(16, 400, 300)]);
+        assert_eq!(traffic_movement(&samples, window), Some((true, false)));
+        samples.push_back(sample(20, 500, 900));
+        assert_eq!(traffic_movement(&samples, window), Some((true, true)));
+    }
+
+    #[test]
+    fn test_enrich_devices() {
+        let device = |id: &str, key: [u8; 32], is_online| Device {
+            id: id.to_string(),
+            name: id.to_string(),
+            ip_address: "10.100.0.2".to_string(),
+            public_key: base64::engine::general_purpose::STANDARD.encode(key),
+            is_online,
+            is_exit_node: false,
+            platform: "DESKTOP".to_string(),
+        };
+        let peer = PeerInfo {
+            public_key: [1; 32],
+            endpoint: Some("203.0.113.5:51820".parse().unwrap()),
+            last_handshake_age: Some(Duration::from_secs(42)),
+            direct: true,
+            responding: true,
+        };
+
+        // The server still says offline, but the tunnel just handshaked with it
+        let devices = enrich_devices(vec![device("a", [1; 32], false), device("b", [2; 32], true)], &[peer]);
+        assert!(devices[0].is_tunnel_peer && devices[0].online);
+        assert_eq!(devices[0].last_handshake_secs, Some(42));
+        assert_eq!(devices[0].endpoint.as_deref(), Some("203.0.113.5:51820"));
+        assert_eq!(devices[0].connection_type.as_deref(), Some("direct"));
+
+        assert!(!devices[1].is_tunnel_peer && devices[1].online);
+        assert_eq!(devices[1].connection_type, None);
+
+        let json = serde_json::to_value(&devices[1]).unwrap();
+        assert_eq!(json["id"], "b");
+        assert_eq!(json["is_tunnel_peer"], false);
+    }
+
+    #[test]
+    fn test_key_matches() {
+        let (private_key, public_key) = generate_keypair();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(private_key.as_bytes());
+        assert!(key_matches(&encoded, &public_key));
+
+        let (_, other_public_key) = generate_keypair();
+        assert!(!key_matches(&encoded, &other_public_key));
+        assert!(!key_matches("not base64", &public_key));
+    }
+
+    #[test]
+    fn test_config_with_key() {
+        let encode = |key: [u8; 32]| base64::engine::general_purpose::STANDARD.encode(key);
+        let fixture = format!(
+            "[Interface]\nPrivateKey = {}\nAddress = 10.100.0.2/16\n\n[Peer]\nPublicKey = {}\nAllowedIPs = 10.100.0.0/16\n",
+            encode([7; 32]), encode([8; 32]),
+        );
+        let private_key = |config: &str| *parse_wg_config(config).unwrap().private_key.as_bytes();
+        let response = |has_private_key| DeviceConfig { config: fixture.clone(), has_private_key };
+
+        let config = config_with_key(&response(true), None).unwrap().unwrap();
+        assert_eq!(private_key(&config), [7; 32]);
+
+        // A locally rotated key wins over the server's
+        let config = config_with_key(&response(true), Some(&encode([5; 32]))).unwrap().unwrap();
+        assert_eq!(private_key(&config), [5; 32]);
+
+        assert!(config_with_key(&response(false), None).unwrap().is_none());
+        assert!(config_with_key(&response(false), Some("not base64")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_switch_network_in_place() {
+        let b64 = |byte: u8| base64::engine::general_purpose::STANDARD.encode([byte; 32]);
+        let config = |private_key: u8, peer_key: u8| {
+            let mut config = parse_wg_config(&format!(
+                "[Interface]\nPrivateKey = {}\nAddress = 10.100.0.2/16\n\n\
+                 [Peer]\nPublicKey = {}\nAllowedIPs = 10.100.0.0/24\n",
+                b64(private_key), b64(peer_key),
+            )).unwrap();
+            config.bind_address = Some(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
+            config.listen_port = Some(0);
+            config.relay_only = true;
+            config
+        };
+
+        let manager = TunnelManager::new();
+        *manager.wg_tunnel.lock().await = Some(WgTunnel::new(config(7, 9), None).await.unwrap());
+        manager.is_running.store(true, Ordering::SeqCst);
+        *manager.current_device_id.write() = Some("device-a".to_string());
+        *manager.current_network_id.write() = Some("network-a".to_string());
+
+        // Devices are per network: the new one has its own id but the same key and address
+        assert!(manager.switch_network("device-b", "network-b", config(7, 10)).await.unwrap());
+        assert_eq!(manager.connected_device().as_deref(), Some("device-b"));
+        assert_eq!(manager.connected_network().as_deref(), Some("network-b"));
+        let peers = manager.wg_tunnel.lock().await.as_ref().unwrap().peers();
+        assert_eq!(peers.iter().map(|peer| peer.public_key).collect::<Vec<_>>(), vec![[10u8; 32]]);
+
+        // Another key needs a reconnect, and nothing changes
+        assert!(!manager.switch_network("device-c", "network-c", config(8, 11)).await.unwrap());
+        assert_eq!(manager.connected_device().as_deref(), Some("device-b"));
+        assert_eq!(manager.connected_network().as_deref(), Some("network-b"));
+        // ...unless the device there takes over the running key
+        let running_key = manager.key_for_switch(&config(8, 11)).await;
+        assert_eq!(running_key.map(|key| *key.as_bytes()), Some([7; 32]));
+
+        // Exit-node routing is only set up on connect
+        let mut exit = config(7, 11);
+        exit.peers[0].allowed_ips = parse_allowed_ips("0.0.0.0/0");
+        assert!(manager.key_for_switch(&exit).await.is_none());
+        assert!(!manager.switch_network("device-c", "network-c", exit).await.unwrap());
+    }
+}
@@ -2,17 +2,25 @@
 //! Receives peer endpoint updates for NAT traversal and direct P2P connections
 //! Uses Socket.IO protocol format (42["event",{data}])
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures_util::{SinkExt, StreamExt};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::sync::mpsc;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use tokio_tungstenite::{client_async_tls_with_config, connect_async_tls_with_config, tungstenite::protocol::Message};
+
+use crate::tls::TlsSettings;
+
+/// Expected interval between server pings (Socket.IO default pingInterval)
+const WS_PING_INTERVAL: Duration = Duration::from_secs(25);
+
+/// Connection is considered dead after 2x the ping interval without any message
+const WS_IDLE_TIMEOUT: Duration = Duration::from_secs(50);
 
 /// Events received from the control plane
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,6 +66,10 @@ pub enum WsMessage {
         device_id: String,
         endpoint: String,
     },
+    /// Forget this device's endpoint (sent on disconnect)
+    UnregisterEndpoint {
+        device_id: String,
+    },
     /// Subscribe to updates for a network
     Subscribe {
         network_id: String,
@@ -121,6 +133,10 @@ fn parse_socketio_message(text: &str) -> Option<WsEvent> {
                     let device_id = data.get("deviceId")?.as_str()?.to_string();
                     Some(WsEvent::PeerOffline { device_id })
                 }
+                "network_config_update" => {
+                    let network_id = data.get("networkId")?.as_str()?.to_string();
+                    Some(WsEvent::NetworkConfigUpdate { network_id })
+                }
                 _ => {
                     log::debug!("[WS] Unknown Socket.IO event: {}", event_name);
                     None
@@ -161,6 +177,9 @@ pub struct WsClient {
     pub tx: Option<mpsc::Sender<WsMessage>>,
     callbacks: Arc<RwLock<Vec<EventCallback>>>,
     peer_endpoints: Arc<RwLock<HashMap<String, SocketAddr>>>,
+    last_received: Arc<RwLock<Instant>>,
+    read_task: Option<tokio::task::JoinHandle<()>>,
+    tls: TlsSettings,
 }
 
 impl WsClient {
@@ -178,9 +197,18 @@ impl WsClient {
             tx: None,
             callbacks: Arc::new(RwLock::new(Vec::new())),
             peer_endpoints: Arc::new(RwLock::new(HashMap::new())),
+            last_received: Arc::new(RwLock::new(Instant::now())),
+            read_task: None,
+            tls: TlsSettings::default(),
         }
     }
 
+    /// Use custom TLS trust settings (private CA / unverified certs)
+    pub fn with_tls(mut self, tls: TlsSettings) -> Self {
+        self.tls = tls;
+        self
+    }
+
     /// Add a callback for WebSocket events
     pub fn on_event(&mut self, callback: EventCallback) {
         self.callbacks.write().push(callback);
@@ -204,9 +232,27 @@ impl WsClient {
 
         log::info!("Connecting to WebSocket: {}", self.base_url);
 
-        let (ws_stream, _) = connect_async(&ws_url)
-            .await
-            .map_err(|e| format!("WebSocket connection failed: {}", e))?;
+        let connector = self.tls.ws_connector()?;
+        let url = reqwest::Url::parse(&ws_url)
+            .map_err(|e| format!("Invalid WebSocket URL {}: {}", self.base_url, e))?;
+        let host = url.host_str()
+            .ok_or_else(|| format!("WebSocket URL {} has no host", self.base_url))?
+            .trim_matches(['[', ']']);
+        let handshake = match crate::proxy::for_host(host)? {
+            Some(proxy) => {
+                let port = url.port_or_known_default().unwrap_or(443);
+                log::info!("[WS] Connecting through proxy {}", proxy);
+                let stream = proxy.connect(host, port).await?;
+                client_async_tls_with_config(ws_url.as_str(), stream, None, connector).await
+            }
+            None => connect_async_tls_with_config(&ws_url, None, false, connector).await,
+        };
+        let (ws_stream, _) = handshake
+            .map_err(|e| if crate::tls::is_pin_mismatch(&e) {
+                format!("WebSocket connection refused: {} for {}", crate::tls::PIN_MISMATCH, self.base_url)
+            } else {
+                format!("WebSocket connection failed: {}", e)
+            })?;
 
         let (mut write, mut read) = ws_stream.split();
 
@@ -215,6 +261,7 @@ impl WsClient {
         self.tx = Some(tx.clone());
 
         *self.state.write() = WsState::Connected;
+        *self.last_received.write() = Instant::now();
         log::info!("WebSocket connected");
 
         // Clone for tasks
@@ -222,11 +269,33 @@ impl WsClient {
         let callbacks = self.callbacks.clone();
         let peer_endpoints = self.peer_endpoints.clone();
         let device_id = self.device_id.clone();
+        let last_received = self.last_received.clone();
 
-        // Spawn write task - sends Socket.IO formatted messages
+        // Spawn write task - sends Socket.IO formatted messages and liveness pings
         let state_write = state.clone();
         tokio::spawn(async move {
-            while let Some(msg) = rx.recv().await {
+            let mut ping_interval = tokio::time::interval_at(
+                tokio::time::Instant::now() + WS_PING_INTERVAL,
+                WS_PING_INTERVAL,
+            );
+
+            loop {
+                let msg = tokio::select! {
+                    msg = rx.recv() => match msg {
+                        Some(msg) => msg,
+                        None => break, // All senders dropped - we're disconnecting
+                    },
+                    _ = ping_interval.tick() => {
+                        // Client-initiated ping to probe liveness
+                        if let Err(e) = write.send(Message::Ping(Vec::new())).await {
+                            log::error!("WebSocket ping error: {}", e);
+                            *state_write.write() = WsState::Disconnected;
+                            return;
+                        }
+                        continue;
+                    }
+                };
+
                 // Format message as Socket.IO EVENT: 42["event_name",{data}]
                 let socketio_msg = match &msg {
                     WsMessage::RegisterDevice { device_id } => {
@@ -240,6 +309,11 @@ impl WsClient {
                             "endpoint": endpoint
                         }))
                     }
+                    WsMessage::UnregisterEndpoint { device_id } => {
+                        format_socketio_message("unregister_endpoint", &serde_json::json!({
+                            "deviceId": device_id
+                        }))
+                    }
                     WsMessage::Subscribe { network_id } => {
                         format_socketio_message("subscribe", &serde_json::json!({
                             "networkId": network_id
@@ -257,16 +331,27 @@ impl WsClient {
                 if let Err(e) = write.send(Message::Text(socketio_msg)).await {
                     log::error!("WebSocket send error: {}", e);
                     *state_write.write() = WsState::Disconnected;
-                    break;
+                    return;
                 }
             }
+
+            // Send a close frame so the server releases the session promptly
+            let _ = tokio::time::timeout(Duration::from_secs(2), write.close()).await;
         });
 
         // Spawn read task - parses Socket.IO formatted messages
         let tx_pong = tx.clone();
-        tokio::spawn(async move {
+        self.read_task = Some(tokio::spawn(async move {
             while let Some(result) = read.next().await {
+                if result.is_ok() {
+                    *last_received.write() = Instant::now();
+                }
+
                 match result {
+                    Ok(Message::Text(text)) if text.trim() == "2" => {
+                        // Engine.IO PING - answer so the server keeps the session open
+                        let _ = tx_pong.try_send(WsMessage::Pong);
+                    }
                     Ok(Message::Text(text)) => {
                         log::debug!("[WS] Received: {}", &text[..text.len().min(200)]);
 
@@ -305,7 +390,7 @@ impl WsClient {
                     _ => {}
                 }
             }
-        });
+        }));
 
         Ok(())
     }
@@ -344,19 +429,152 @@ impl WsClient {
         self.state.read().clone()
     }
 
+    /// Time since any message (including pings/pongs) was last received
+    pub fn idle_time(&self) -> Duration {
+        self.last_received.read().elapsed()
+    }
+
     /// Disconnect from WebSocket
     pub fn disconnect(&mut self) {
+        // Stop reading - this drops the read half and its pong sender, so the
+        // write task sees the channel close and sends a close frame
+        if let Some(task) = self.read_task.take() {
+            task.abort();
+        }
         self.tx = None;
         *self.state.write() = WsState::Disconnected;
         log::info!("WebSocket disconnected");
     }
 }
 
+/// Outgoing messages for a managed client, kept across reconnects
+#[derive(Default)]
+struct Outbox {
+    /// Messages not yet handed to a live connection, in send order
+    queue: VecDeque<WsMessage>,
+    /// Latest public endpoint, re-registered on every connection
+    endpoint: Option<SocketAddr>,
+    /// Networks to subscribe to on every connection
+    networks: Vec<String>,
+    /// Networks already subscribed on the current connection
+    subscribed: HashSet<String>,
+}
+
+impl Outbox {
+    /// Queue a message, dropping anything it makes redundant
+    fn push(&mut self, msg: WsMessage) {
+        match &msg {
+            WsMessage::RegisterEndpoint { endpoint, .. } => {
+                self.endpoint = endpoint.parse().ok();
+                // Only the latest endpoint matters
+                self.queue.retain(|m| !matches!(m, WsMessage::RegisterEndpoint { .. }));
+            }
+            WsMessage::UnregisterEndpoint { .. } => {
+                self.endpoint = None;
+                self.queue.retain(|m| !matches!(m, WsMessage::RegisterEndpoint { .. }));
+            }
+            WsMessage::Subscribe { network_id } => {
+                if !self.networks.contains(network_id) {
+                    self.networks.push(network_id.clone());
+                }
+                let queued = self.queue.iter().any(|m| matches!(
+                    m, WsMessage::Subscribe { network_id: n } if n == network_id
+                ));
+                if queued || self.subscribed.contains(network_id) {
+                    return;
+                }
+            }
+            WsMessage::Unsubscribe { network_id } => {
+                self.networks.retain(|n| n != network_id);
+                self.subscribed.remove(network_id);
+            }
+            _ => {}
+        }
+        self.queue.push_back(msg);
+    }
+
+    /// A new connection starts with no server-side state: put the device
+    /// registration, endpoint and subscriptions ahead of anything still queued
+    fn reset_for_connection(&mut self, device_id: &str) {
+        self.subscribed.clear();
+
+        let mut queue = VecDeque::new();
+        queue.push_back(WsMessage::RegisterDevice {
+            device_id: device_id.to_string(),
+        });
+        if let Some(endpoint) = self.endpoint {
+            queue.push_back(WsMessage::RegisterEndpoint {
+                device_id: device_id.to_string(),
+                endpoint: endpoint.to_string(),
+            });
+        }
+        for network_id in &self.networks {
+            queue.push_back(WsMessage::Subscribe {
+                network_id: network_id.clone(),
+            });
+        }
+
+        // Endpoint and subscriptions are covered above, and a fresh session has nothing to undo
+        queue.extend(self.queue.drain(..).filter(|m| !matches!(
+            m,
+            WsMessage::RegisterDevice { .. }
+                | WsMessage::RegisterEndpoint { .. }
+                | WsMessage::UnregisterEndpoint { .. }
+                | WsMessage::Subscribe { .. }
+                | WsMessage::Unsubscribe { .. }
+        )));
+        self.queue = queue;
+    }
+
+    /// Next message to send, skipping subscriptions already made on this connection
+    fn pop(&mut self) -> Option<WsMessage> {
+        while let Some(msg) = self.queue.pop_front() {
+            if let WsMessage::Subscribe { network_id } = &msg {
+                if !self.subscribed.insert(network_id.clone()) {
+                    continue;
+                }
+            }
+            return Some(msg);
+        }
+        None
+    }
+}
+
+/// Send everything queued in order; stops (keeping the rest queued) if the connection is gone
+async fn flush_outbox(outbox: &Mutex<Outbox>, tx: &mpsc::Sender<WsMessage>) -> Result<(), String> {
+    loop {
+        let Some(msg) = outbox.lock().pop() else {
+            return Ok(());
+        };
+
+        if let Err(e) = tx.send(msg.clone()).await {
+            let mut outbox = outbox.lock();
+            if let WsMessage::Subscribe { network_id } = &msg {
+                outbox.subscribed.remove(network_id);
+            }
+            outbox.queue.push_front(msg);
+            return Err(format!("Failed to send message: {}", e));
+        }
+
+        match &msg {
+            WsMessage::RegisterDevice { device_id } => log::info!("Device registered: {}", device_id),
+            WsMessage::RegisterEndpoint { endpoint, .. } => log::info!("Registered P2P endpoint: {}", endpoint),
+            WsMessage::UnregisterEndpoint { .. } => log::info!("Unregistered P2P endpoint"),
+            WsMessage::Subscribe { network_id } => log::info!("Subscribed to network: {}", network_id),
+            WsMessage::Unsubscribe { network_id } => log::info!("Unsubscribed from network: {}", network_id),
+            WsMessage::Pong => {}
+        }
+    }
+}
+
 /// Managed WebSocket client with automatic reconnection
 pub struct ManagedWsClient {
     client: Arc<RwLock<Option<WsClient>>>,
     config: WsConfig,
+    /// Starts as config.device_id, changed by switch_device
+    device_id: Arc<RwLock<String>>,
     running: Arc<std::sync::atomic::AtomicBool>,
+    outbox: Arc<Mutex<Outbox>>,
 }
 
 #[derive(Clone)]
@@ -365,14 +583,17 @@ pub struct WsConfig {
     pub token: String,
     pub device_id: String,
     pub reconnect_interval: Duration,
+    pub tls: TlsSettings,
 }
 
 impl ManagedWsClient {
     pub fn new(config: WsConfig) -> Self {
         Self {
             client: Arc::new(RwLock::new(None)),
+            device_id: Arc::new(RwLock::new(config.device_id.clone())),
             config,
             running: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            outbox: Arc::new(Mutex::new(Outbox::default())),
         }
     }
 
@@ -392,71 +613,60 @@ impl ManagedWsClient {
 
         self.running.store(true, Ordering::SeqCst);
 
+        {
+            let mut outbox = self.outbox.lock();
+            if let Some(endpoint) = public_endpoint {
+                outbox.endpoint = Some(endpoint);
+            }
+            if let Some(net_id) = network_id {
+                if !outbox.networks.contains(&net_id) {
+                    outbox.networks.push(net_id);
+                }
+            }
+        }
+
         let config = self.config.clone();
+        let current_device_id = self.device_id.clone();
         let client = self.client.clone();
         let running = self.running.clone();
+        let outbox = self.outbox.clone();
         let callbacks = Arc::new(RwLock::new(vec![on_event]));
 
         tokio::spawn(async move {
             while running.load(Ordering::SeqCst) {
+                let device_id = current_device_id.read().clone();
                 let mut ws_client = WsClient::new(
                     &config.base_url,
                     &config.token,
-                    &config.device_id,
-                );
+                    &device_id,
+                ).with_tls(config.tls.clone());
 
-                // Add callbacks
-                for cb in callbacks.read().iter() {
-                    // Note: This is simplified - in production you'd clone Arc callbacks
-                }
+                // Share callbacks so events reach the caller across reconnects
+                ws_client.callbacks = callbacks.clone();
 
                 match ws_client.connect().await {
                     Ok(()) => {
                         log::info!("WebSocket connected, registering device...");
 
-                        // Always register device first (shows as online)
-                        if let Some(tx) = &ws_client.tx {
-                            if let Err(e) = tx.send(WsMessage::RegisterDevice {
-                                device_id: config.device_id.clone(),
-                            }).await {
-                                log::warn!("Failed to register device: {}", e);
-                            } else {
-                                log::info!("Device registered: {}", config.device_id);
-                            }
-                        }
-
-                        // Register endpoint if STUN succeeded (enables P2P)
-                        if let Some(endpoint) = public_endpoint {
-                            if let Some(tx) = &ws_client.tx {
-                                if let Err(e) = tx.send(WsMessage::RegisterEndpoint {
-                                    device_id: config.device_id.clone(),
-                                    endpoint: endpoint.to_string(),
-                                }).await {
-                                    log::warn!("Failed to register endpoint: {}", e);
-                                } else {
-                                    log::info!("Registered P2P endpoint: {}", endpoint);
-                                }
-                            }
-                        } else {
+                        let endpoint = {
+                            let mut outbox = outbox.lock();
+                            outbox.reset_for_connection(&device_id);
+                            outbox.endpoint
+                        };
+                        if endpoint.is_none() {
                             log::warn!("No public endpoint (STUN failed) - P2P unavailable, using relay only");
                         }
 
-                        // Subscribe to network
-                        if let Some(ref net_id) = network_id {
-                            if let Some(tx) = &ws_client.tx {
-                                if let Err(e) = tx.send(WsMessage::Subscribe {
-                                    network_id: net_id.clone(),
-                                }).await {
-                                    log::warn!("Failed to subscribe to network: {}", e);
-                                } else {
-                                    log::info!("Subscribed to network: {}", net_id);
-                                }
+                        // Registration, subscriptions and anything queued while disconnected
+                        if let Some(tx) = ws_client.tx.clone() {
+                            if let Err(e) = flush_outbox(&outbox, &tx).await {
+                                log::warn!("Failed to flush queued messages: {}", e);
                             }
                         }
 
                         *client.write() = Some(ws_client);
                         log::info!("WebSocket ready for P2P updates (endpoint: {})",
-                            public_endpoint.map(|e| e.to_string()).unwrap_or_else(|| "relay-only".to_string()));
+                            endpoint.map(|e| e.to_string()).unwrap_or_else(|| "relay-only".to_string()));
 
                         // Monitor connection
                         loop {
@@ -475,6 +685,20 @@ impl ManagedWsClient {
                                 log::info!("WebSocket disconnected, will reconnect...");
                                 break;
                             }
+
+                            // Detect a silently dead connection (no close frame, no error)
+                            let idle = client.read()
+                                .as_ref()
+                                .map(|c| c.idle_time())
+                                .unwrap_or_default();
+
+                            if idle > WS_IDLE_TIMEOUT {
+                                log::warn!("WebSocket silent for {:?}, forcing reconnect...", idle);
+                                if let Some(c) = client.write().as_mut() {
+                                    c.disconnect();
+                                }
+                                break;
+                            }
                         }
                     }
                     Err(e) => {
@@ -497,6 +721,48 @@ impl ManagedWsClient {
         self.start_with_registration(on_event, None, None).await
     }
 
+    /// Unsubscribe and unregister our endpoint so the control plane drops the session
+    /// right away instead of waiting for it to time out, then stop
+    pub async fn shutdown(&self) {
+        // Get the tx channel without holding the lock across await
+        let tx = {
+            let guard = self.client.read();
+            guard.as_ref()
+                .filter(|c| c.state() == WsState::Connected)
+                .and_then(|c| c.tx.clone())
+        };
+
+        let goodbye: Vec<WsMessage> = {
+            let mut outbox = self.outbox.lock();
+            outbox.queue.clear();
+            outbox.endpoint = None;
+            outbox.subscribed.clear();
+            outbox.networks.drain(..)
+                .map(|network_id| WsMessage::Unsubscribe { network_id })
+                .chain(std::iter::once(WsMessage::UnregisterEndpoint {
+                    device_id: self.device_id.read().clone(),
+                }))
+                .collect()
+        };
+
+        if let Some(tx) = tx {
+            let send_all = async {
+                for msg in goodbye {
+                    tx.send(msg).await.map_err(|e| e.to_string())?;
+                }
+                Ok::<(), String>(())
+            };
+            // The write task drains these before sending the close frame
+            match tokio::time::timeout(Duration::from_secs(2), send_all).await {
+                Ok(Ok(())) => log::info!("Unsubscribed and unregistered endpoint"),
+                Ok(Err(e)) => log::warn!("Failed to send disconnect messages: {}", e),
+                Err(_) => log::warn!("Timed out sending disconnect messages"),
+            }
+        }
+
+        self.stop();
+    }
+
     /// Stop the managed connection
     pub fn stop(&self) {
         use std::sync::atomic::Ordering;
@@ -506,25 +772,28 @@ impl ManagedWsClient {
         }
     }
 
-    /// Register endpoint
+    /// Register endpoint (queued until the next connection if currently disconnected)
     pub async fn register_endpoint(&self, endpoint: SocketAddr) -> Result<(), String> {
-        // Get the tx channel without holding the lock across await
-        let tx = {
-            let guard = self.client.read();
-            guard.as_ref().and_then(|c| c.tx.clone())
-        };
+        let device_id = self.device_id.read().clone();
+        self.send_or_queue(WsMessage::RegisterEndpoint {
+            device_id,
+            endpoint: endpoint.to_string(),
+        }).await
+    }
 
-        if let Some(tx) = tx {
-            tx.send(WsMessage::RegisterEndpoint {
-                device_id: self.config.device_id.clone(),
-                endpoint: endpoint.to_string(),
-            })
-            .await
-            .map_err(|e| format!("Failed to send endpoint: {}", e))?;
-            log::info!("Registered endpoint with control plane: {}", endpoint);
-            Ok(())
-        } else {
-            Err("Not connected".to_string())
+    /// Carry on as `device_id` (after an in-place network switch): the old device's
+    /// endpoint is unregistered and the new device registered with it
+    pub async fn switch_device(&self, device_id: &str) -> Result<(), String> {
+        let old_device_id = std::mem::replace(&mut *self.device_id.write(), device_id.to_string());
+        if old_device_id == device_id {
+            return Ok(());
+        }
+        let endpoint = self.outbox.lock().endpoint;
+        self.send_or_queue(WsMessage::UnregisterEndpoint { device_id: old_device_id }).await?;
+        self.send_or_queue(WsMessage::RegisterDevice { device_id: device_id.to_string() }).await?;
+        match endpoint {
+            Some(endpoint) => self.register_endpoint(endpoint).await,
+            None => Ok(()),
         }
     }
 
@@ -535,24 +804,74 @@ impl ManagedWsClient {
             .and_then(|c| c.get_peer_endpoint(public_key))
     }
 
-    /// Subscribe to network updates
+    /// Subscribe to network updates (queued until the next connection if currently disconnected)
     pub async fn subscribe(&self, network_id: &str) -> Result<(), String> {
+        self.send_or_queue(WsMessage::Subscribe {
+            network_id: network_id.to_string(),
+        }).await
+    }
+
+    /// Stop network updates (dropped from the resubscribe list even if currently disconnected)
+    pub async fn unsubscribe(&self, network_id: &str) -> Result<(), String> {
+        self.send_or_queue(WsMessage::Unsubscribe {
+            network_id: network_id.to_string(),
+        }).await
+    }
+
+    async fn send_or_queue(&self, msg: WsMessage) -> Result<(), String> {
+        self.outbox.lock().push(msg);
+
         // Get the tx channel without holding the lock across await
         let tx = {
             let guard = self.client.read();
-            guard.as_ref().and_then(|c| c.tx.clone())
+            guard.as_ref()
+                .filter(|c| c.state() == WsState::Connected)
+                .and_then(|c| c.tx.clone())
         };
 
-        if let Some(tx) = tx {
-            tx.send(WsMessage::Subscribe {
-                network_id: network_id.to_string(),
-            })
-            .await
-            .map_err(|e| format!("Failed to subscribe: {}", e))?;
-            log::info!("Subscribed to network: {}", network_id);
-            Ok(())
-        } else {
-            Err("Not connected".to_string())
+        match tx {
+            Some(tx) => {
+                if let Err(e) = flush_outbox(&self.outbox, &tx).await {
+                    log::warn!("{} - will retry after reconnect", e);
+                }
+            }
+            None => log::info!("WebSocket not connected, queued message for reconnect"),
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(outbox: &mut Outbox) -> Vec<String> {
+        std::iter::from_fn(|| outbox.pop())
+            .map(|m| serde_json::to_string(&m).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_outbox_replays_once_per_connection() {
+        let mut outbox = Outbox::default();
+        outbox.push(WsMessage::Subscribe { network_id: "net1".to_string() });
+        outbox.push(WsMessage::Subscribe { network_id: "net1".to_string() });
+        outbox.push(WsMessage::RegisterEndpoint { device_id: "dev".to_string(), endpoint: "1.2.3.4:51820".to_string() });
+        outbox.push(WsMessage::RegisterEndpoint { device_id: "dev".to_string(), endpoint: "1.2.3.4:51821".to_string() });
+
+        outbox.reset_for_connection("dev");
+        let sent = drain(&mut outbox);
+        assert_eq!(sent.len(), 3);
+        assert!(sent[0].contains("RegisterDevice"));
+        assert!(sent[1].contains("1.2.3.4:51821"));
+        assert!(sent[2].contains("net1"));
+
+        // Already subscribed on this connection
+        outbox.push(WsMessage::Subscribe { network_id: "net1".to_string() });
+        assert!(drain(&mut outbox).is_empty());
+
+        // A reconnect subscribes again, exactly once
+        outbox.reset_for_connection("dev");
+        assert_eq!(drain(&mut outbox).iter().filter(|m| m.contains("net1")).count(), 1);
     }
 }
@@ -1,150 +1,851 @@
 //! WireGuard tunnel implementation using boringtun
 //! Handles encryption/decryption of VPN traffic
 
-use std::net::{SocketAddr, Ipv4Addr, UdpSocket as StdUdpSocket};
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, LazyLock};
+use std::sync::atomic::{AtomicU16, AtomicU64, AtomicUsize, Ordering as AtomicOrdering};
 use std::time::{Duration, Instant};
 
 use boringtun::noise::{Tunn, TunnResult};
 use dashmap::DashMap;
 use parking_lot::RwLock;
 use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
 use base64::Engine as _;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-use crate::tun_device::{TunDevice, TUN_MTU};
-use crate::stun::AsyncStunClient;
+use crate::tun_device::{TunDevice, TUN_MTU, TUN_NAME};
+use crate::capture;
+use crate::flows;
+use crate::error::PleError;
+use crate::pmtu::{self, MtuSearch, MIN_TUNNEL_MTU};
+use crate::stun::{self, AsyncStunClient};
+
+/// How long looking a hostname endpoint up may take before its cached address is used
+const ENDPOINT_RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Last address each hostname endpoint resolved to, for when DNS is unreachable
+/// (e.g. while the tunnel carrying it is down)
+static RESOLVED_ENDPOINTS: LazyLock<parking_lot::Mutex<HashMap<String, SocketAddr>>> =
+    LazyLock::new(|| parking_lot::Mutex::new(HashMap::new()));
 
 /// WireGuard default port range
 const WG_PORT_START: u16 = 51820;
 const WG_PORT_END: u16 = 51920;
 
-/// Keepalive interval
-const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(25);
+/// Lowest port a user may pin; anything below is reserved for system services
+pub const MIN_LISTEN_PORT: u16 = 1024;
+
+/// Port of the last successful bind (0 = none), reused on reconnect so the NAT
+/// mapping peers learned and the endpoint registered over WebSocket stay valid
+static PREFERRED_LISTEN_PORT: AtomicU16 = AtomicU16::new(0);
+
+/// Seed the preferred port, e.g. with the one persisted from a previous run
+pub fn set_preferred_listen_port(port: u16) {
+    PREFERRED_LISTEN_PORT.store(port, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Port the next tunnel will try first
+pub fn preferred_listen_port() -> Option<u16> {
+    match PREFERRED_LISTEN_PORT.load(std::sync::atomic::Ordering::Relaxed) {
+        0 => None,
+        port => Some(port),
+    }
+}
+
+/// Check a user-pinned listen port is outside the system range and free to bind
+pub fn check_listen_port(local_ip: Option<IpAddr>, port: u16) -> Result<(), PleError> {
+    if port < MIN_LISTEN_PORT {
+        return Err(PleError::Parse(format!(
+            "Listen port must be between {} and {}, got {}", MIN_LISTEN_PORT, u16::MAX, port)));
+    }
+    stun::bind_udp_on(local_ip, port)
+        .map(drop)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::AddrInUse => PleError::PortConflict(format!(
+                "UDP port {} is already in use by another program", port)),
+            _ => PleError::Other(format!("Can't listen on UDP port {}: {}", port, e)),
+        })
+}
+
+/// Default SO_RCVBUF/SO_SNDBUF for the WireGuard socket - the OS defaults drop
+/// bursts on gigabit links
+pub const DEFAULT_SOCKET_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// Accepted range for set_socket_buffer_size()
+pub const MIN_SOCKET_BUFFER_SIZE: usize = 64 * 1024;
+pub const MAX_SOCKET_BUFFER_SIZE: usize = 64 * 1024 * 1024;
+
+/// Buffer size requested for the next tunnel's socket
+static SOCKET_BUFFER_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_SOCKET_BUFFER_SIZE);
+
+/// Set the socket buffer size the next tunnel requests (clamped to the accepted range)
+pub fn set_socket_buffer_size(bytes: usize) {
+    let bytes = bytes.clamp(MIN_SOCKET_BUFFER_SIZE, MAX_SOCKET_BUFFER_SIZE);
+    SOCKET_BUFFER_SIZE.store(bytes, AtomicOrdering::Relaxed);
+}
+
+/// Socket buffer size the next tunnel will request
+pub fn socket_buffer_size() -> usize {
+    SOCKET_BUFFER_SIZE.load(AtomicOrdering::Relaxed)
+}
+
+/// Size of the echoes sent by echo(), like a default `ping`
+const ECHO_PACKET_LEN: u16 = 84;
+
+/// How long stop() waits for the packet loops to exit. They return as soon as
+/// they see the cancellation; this only guards against a wedged TUN read.
+const LOOP_EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long echo() waits for each reply
+const ECHO_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Keepalive interval for peers on a direct path - NAT mappings along the way
+/// can expire far sooner than the relay's
+const DIRECT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Keepalive interval for relayed peers without a configured persistent_keepalive
+const RELAY_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(25);
 
-/// Handshake timeout
+/// How often peer timers run - well under the shortest keepalive interval
+const TIMER_TICK: Duration = Duration::from_secs(1);
+
+/// Handshake timeout - also how long a new direct endpoint gets before falling back to the relay
 const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// Trade-off between reconnect speed and battery life, picked by the user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PowerProfile {
+    /// Frequent keepalives, fast handshake retries and tight STUN timeouts
+    Aggressive,
+    #[default]
+    Balanced,
+    /// Relaxed timings - fewer wakeups, slower to notice a dead path
+    BatterySaver,
+}
+
+/// Timings set by a power profile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProfileTimings {
+    pub direct_keepalive: Duration,
+    pub relay_keepalive: Duration,
+    /// How often peer timers run
+    pub timer_tick: Duration,
+    /// Resend an unanswered handshake initiation after this long. None leaves
+    /// retries to WireGuard's own timers (every 5s) - they can't be slowed down.
+    pub handshake_retry: Option<Duration>,
+    pub stun_timeout: Duration,
+    /// How often connection stats and throughput are sampled
+    pub stats_interval: Duration,
+}
+
+impl PowerProfile {
+    pub fn timings(self) -> ProfileTimings {
+        match self {
+            PowerProfile::Aggressive => ProfileTimings {
+                direct_keepalive: Duration::from_secs(5),
+                relay_keepalive: Duration::from_secs(15),
+                timer_tick: Duration::from_millis(250),
+                handshake_retry: Some(Duration::from_secs(2)),
+                stun_timeout: Duration::from_millis(1500),
+                stats_interval: Duration::from_secs(1),
+            },
+            PowerProfile::Balanced => ProfileTimings {
+                direct_keepalive: DIRECT_KEEPALIVE_INTERVAL,
+                relay_keepalive: RELAY_KEEPALIVE_INTERVAL,
+                timer_tick: TIMER_TICK,
+                handshake_retry: None,
+                stun_timeout: Duration::from_secs(3),
+                stats_interval: Duration::from_secs(1),
+            },
+            PowerProfile::BatterySaver => ProfileTimings {
+                direct_keepalive: Duration::from_secs(20),
+                relay_keepalive: Duration::from_secs(45),
+                timer_tick: Duration::from_secs(2),
+                handshake_retry: None,
+                stun_timeout: Duration::from_secs(5),
+                stats_interval: Duration::from_secs(5),
+            },
+        }
+    }
+}
+
+/// WireGuard message types (first byte of every packet)
+const MSG_HANDSHAKE_INIT: u8 = 1;
+const MSG_HANDSHAKE_RESP: u8 = 2;
+
+/// Number of recent RTT samples kept per peer for the rolling average
+const RTT_SAMPLE_WINDOW: usize = 10;
+
+/// A direct path with no traffic for this long is considered dead
+const DIRECT_PATH_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// A peer that hasn't answered for this many keepalive intervals is considered dead
+const DEAD_PEER_KEEPALIVES: u32 = 3;
+
+/// 32-byte WireGuard secret (private key or preshared key), wiped from memory
+/// when dropped and never printed by Debug
+#[derive(Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+#[repr(transparent)]
+pub struct SecretKey([u8; 32]);
+
+impl SecretKey {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for SecretKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretKey([REDACTED])")
+    }
+}
+
+/// Decode a base64 secret; `what` names it in errors ("private key", "preshared key").
+/// The intermediate buffer is wiped too.
+pub fn decode_secret(value: &str, what: &str) -> Result<SecretKey, String> {
+    let bytes = Zeroizing::new(
+        base64::engine::general_purpose::STANDARD
+            .decode(value)
+            .map_err(|e| format!("Invalid {}: {}", what, e))?,
+    );
+    if bytes.len() != 32 {
+        let mut label = what.to_string();
+        label[..1].make_ascii_uppercase();
+        return Err(format!("{} must be 32 bytes, got {}", label, bytes.len()));
+    }
+
+    let mut key = SecretKey([0u8; 32]);
+    key.0.copy_from_slice(&bytes);
+    Ok(key)
+}
+
 /// Peer configuration
 #[derive(Debug, Clone)]
 pub struct WgPeer {
     pub public_key: [u8; 32],
     pub endpoint: Option<SocketAddr>,
+    /// The Endpoint as written when it names a host, so it can be looked up again
+    pub endpoint_host: Option<String>,
     pub allowed_ips: Vec<(Ipv4Addr, u8)>, // (address, prefix_len)
+    pub allowed_ips_v6: Vec<(Ipv6Addr, u8)>,
     pub persistent_keepalive: Option<u16>,
-    pub preshared_key: Option<[u8; 32]>,
+    pub preshared_key: Option<SecretKey>,
 }
 
 /// WireGuard tunnel configuration
 #[derive(Debug, Clone)]
 pub struct WgConfig {
-    pub private_key: [u8; 32],
+    pub private_key: SecretKey,
     pub address: Ipv4Addr,
     pub netmask: Ipv4Addr,
-    pub dns: Option<Ipv4Addr>,
+    /// IPv6 address and prefix length, if the network assigns one
+    pub address_v6: Option<(Ipv6Addr, u8)>,
+    /// DNS servers, primary first
+    pub dns: Vec<Ipv4Addr>,
     pub peers: Vec<WgPeer>,
     pub listen_port: Option<u16>,
+    /// MTU from the config, clamped to MIN_TUNNEL_MTU..=TUN_MTU (None = TUN_MTU)
+    pub mtu: Option<u16>,
+    /// Local address to send from on multi-homed machines (None = let the OS pick)
+    pub bind_address: Option<IpAddr>,
+    pub power_profile: PowerProfile,
+    /// Block internet IPv6 outside the tunnel while it's the default gateway,
+    /// unless the tunnel carries IPv6 itself
+    pub block_ipv6_leaks: bool,
+    /// Stay on the configured (relay) endpoints: no STUN, no direct paths
+    pub relay_only: bool,
 }
 
 /// Active peer state
 struct PeerState {
     tunnel: Tunn,
     endpoint: Option<SocketAddr>,
+    /// Endpoint from the config (the relay) - fallback when a direct path dies
+    configured_endpoint: Option<SocketAddr>,
+    /// When a direct endpoint learned via P2P signalling was applied
+    direct_endpoint_set_at: Option<Instant>,
+    /// A handshake completed over the direct endpoint
+    direct_verified: bool,
+    /// Last authenticated packet from anywhere other than the relay
+    last_direct_rx: Option<Instant>,
+    allowed_ips: Vec<(Ipv4Addr, u8)>,
+    allowed_ips_v6: Vec<(Ipv6Addr, u8)>,
+    /// PersistentKeepalive from the config, in seconds (0 disables keepalives)
+    persistent_keepalive: Option<u16>,
+    /// PSK `tunnel` was created with, to tell whether an update changes it
+    preshared_key: Option<SecretKey>,
+    /// Last packet of any kind sent to this peer
+    last_tx: Option<Instant>,
+    /// Last authenticated packet received from this peer
+    last_rx: Option<Instant>,
+    /// When this state was created - stands in for last_rx until the peer first answers
+    added_at: Instant,
+    /// Set by keepalive_loop while the peer is silent (see is_dead)
+    dead: bool,
     last_handshake: Option<Instant>,
     tx_bytes: u64,
     rx_bytes: u64,
+    /// Handshake initiations sent (including retransmissions)
+    handshake_attempts: u32,
+    /// Handshake responses received
+    handshakes_completed: u32,
+    /// When the latest handshake initiation was sent, cleared on the response
+    handshake_sent_at: Option<Instant>,
+    last_handshake_rtt: Option<Duration>,
+    /// Recent handshake initiation -> response times, oldest first. Keepalives
+    /// aren't echoed, so they can't be timed.
+    rtt_samples: VecDeque<Duration>,
+    timings: ProfileTimings,
+}
+
+impl PeerState {
+    fn new(tunnel: Tunn, peer: &WgPeer, timings: ProfileTimings) -> Self {
+        Self {
+            tunnel,
+            endpoint: peer.endpoint,
+            configured_endpoint: peer.endpoint,
+            direct_endpoint_set_at: None,
+            direct_verified: false,
+            last_direct_rx: None,
+            allowed_ips: peer.allowed_ips.clone(),
+            allowed_ips_v6: peer.allowed_ips_v6.clone(),
+            persistent_keepalive: peer.persistent_keepalive,
+            preshared_key: peer.preshared_key.clone(),
+            last_tx: None,
+            last_rx: None,
+            added_at: Instant::now(),
+            dead: false,
+            last_handshake: None,
+            tx_bytes: 0,
+            rx_bytes: 0,
+            handshake_attempts: 0,
+            handshakes_completed: 0,
+            handshake_sent_at: None,
+            last_handshake_rtt: None,
+            rtt_samples: VecDeque::with_capacity(RTT_SAMPLE_WINDOW),
+            timings,
+        }
+    }
+
+    /// Record an outgoing packet for quality tracking
+    fn on_packet_sent(&mut self, data: &[u8]) {
+        self.last_tx = Some(Instant::now());
+        if data.first() == Some(&MSG_HANDSHAKE_INIT) {
+            // Retransmits restart the clock - the response answers the latest initiation
+            self.handshake_attempts += 1;
+            self.handshake_sent_at = Some(Instant::now());
+        }
+    }
+
+    /// Record an incoming packet that this peer's session accepted
+    fn on_packet_received(&mut self, data: &[u8], src: SocketAddr) {
+        self.last_rx = Some(Instant::now());
+        if self.configured_endpoint.is_some_and(|relay| relay != src) {
+            self.last_direct_rx = Some(Instant::now());
+            if data.first() == Some(&MSG_HANDSHAKE_RESP) && !self.direct_verified {
+                log::info!("[P2P] Direct handshake via {} succeeded", src);
+                self.direct_verified = true;
+            }
+        }
+        if data.first() == Some(&MSG_HANDSHAKE_RESP) {
+            self.handshakes_completed += 1;
+            if let Some(sent_at) = self.handshake_sent_at.take() {
+                let rtt = sent_at.elapsed();
+                self.last_handshake_rtt = Some(rtt);
+                self.record_rtt(rtt);
+            }
+        }
+    }
+
+    /// Traffic currently flows over a verified direct path (not via the relay)
+    fn is_direct(&self) -> bool {
+        self.direct_verified
+            && self.last_direct_rx.is_some_and(|at| at.elapsed() < DIRECT_PATH_TIMEOUT)
+    }
+
+    /// Whether the direct endpoint never handshook or has gone quiet, and the relay should take over
+    fn direct_path_expired(&self) -> bool {
+        let Some(set_at) = self.direct_endpoint_set_at else {
+            return false;
+        };
+        if !self.direct_verified {
+            return set_at.elapsed() >= HANDSHAKE_TIMEOUT;
+        }
+        let last_activity = self.last_direct_rx.map_or(set_at, |rx| rx.max(set_at));
+        last_activity.elapsed() >= DIRECT_PATH_TIMEOUT
+    }
+
+    /// How long the path to this peer may sit idle before a keepalive goes out.
+    /// A configured persistent_keepalive applies to relayed peers and caps the
+    /// direct interval; None when keepalives are disabled.
+    fn keepalive_interval(&self) -> Option<Duration> {
+        let configured = match self.persistent_keepalive {
+            Some(0) => return None,
+            Some(secs) => Some(Duration::from_secs(secs.into())),
+            None => None,
+        };
+        let direct = self.timings.direct_keepalive;
+        Some(if self.is_direct() {
+            configured.map_or(direct, |c| c.min(direct))
+        } else {
+            configured.unwrap_or(self.timings.relay_keepalive)
+        })
+    }
+
+    /// Whether an initiation has gone unanswered for longer than the profile's retry interval
+    fn handshake_retry_due(&self) -> bool {
+        self.timings.handshake_retry.is_some_and(|retry| {
+            self.handshake_sent_at.is_some_and(|at| at.elapsed() >= retry)
+        })
+    }
+
+    /// Whether the session is up and nothing has been sent for a keepalive interval
+    fn keepalive_due(&self) -> bool {
+        let Some(interval) = self.keepalive_interval() else {
+            return false;
+        };
+        self.tunnel.time_since_last_handshake().is_some()
+            && self.last_tx.is_some_and(|at| at.elapsed() >= interval)
+    }
+
+    /// Whether the peer has been silent for DEAD_PEER_KEEPALIVES keepalive intervals.
+    /// Never true with keepalives disabled - silence is expected then.
+    fn is_dead(&self) -> bool {
+        let Some(interval) = self.keepalive_interval() else {
+            return false;
+        };
+        self.last_rx.unwrap_or(self.added_at).elapsed() >= interval * DEAD_PEER_KEEPALIVES
+    }
+
+    fn record_rtt(&mut self, rtt: Duration) {
+        if self.rtt_samples.len() == RTT_SAMPLE_WINDOW {
+            self.rtt_samples.pop_front();
+        }
+        self.rtt_samples.push_back(rtt);
+    }
+
+    /// Latency snapshot for this peer
+    fn latency(&self, public_key: [u8; 32]) -> PeerLatency {
+        let average_rtt = if self.rtt_samples.is_empty() {
+            None
+        } else {
+            Some(self.rtt_samples.iter().sum::<Duration>() / self.rtt_samples.len() as u32)
+        };
+
+        PeerLatency {
+            public_key,
+            latest_rtt: self.rtt_samples.back().copied(),
+            average_rtt,
+            handshake_rtt: self.last_handshake_rtt,
+            keepalive_interval: self.keepalive_interval(),
+        }
+    }
+}
+
+/// Path MTU probe in progress toward one peer
+struct MtuProbe {
+    public_key: [u8; 32],
+    endpoint: SocketAddr,
+    /// Tunnel address that answers the echoes
+    target: Ipv4Addr,
+    ident: u16,
+    seq: u16,
+}
+
+/// Outcome of echoing a tunnel address through the data path
+#[derive(Debug, Clone)]
+pub struct EchoResult {
+    /// Peer the echoes were encapsulated for
+    pub public_key: [u8; 32],
+    pub endpoint: SocketAddr,
+    pub sent: u32,
+    /// Round-trip time of each echo that was answered
+    pub rtts: Vec<Duration>,
+}
+
+/// Snapshot of an active peer
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub public_key: [u8; 32],
+    /// Where packets for the peer currently go
+    pub endpoint: Option<SocketAddr>,
+    pub last_handshake_age: Option<Duration>,
+    /// On a verified, live direct path rather than the relay
+    pub direct: bool,
+    /// Not marked dead by keepalive_loop
+    pub responding: bool,
+}
+
+/// Round-trip latency measured for one peer
+#[derive(Debug, Clone)]
+pub struct PeerLatency {
+    pub public_key: [u8; 32],
+    /// Most recent handshake sample
+    pub latest_rtt: Option<Duration>,
+    /// Average over the last RTT_SAMPLE_WINDOW samples
+    pub average_rtt: Option<Duration>,
+    pub handshake_rtt: Option<Duration>,
+    /// Effective keepalive interval, None if keepalives are disabled
+    pub keepalive_interval: Option<Duration>,
+}
+
+/// Connection quality measured across all peers
+#[derive(Debug, Clone, Default)]
+pub struct QualityMetrics {
+    /// Most recent handshake round-trip time (ms)
+    pub handshake_rtt_ms: Option<u32>,
+    /// Steady-state round-trip time (ms): the average of recent handshake round
+    /// trips, which take the path keepalives do - keepalives themselves aren't
+    /// echoed, so can't be timed
+    pub keepalive_rtt_ms: Option<u32>,
+    pub handshake_attempts: u32,
+    pub handshakes_completed: u32,
+}
+
+/// Packet and byte counts for one protocol, in plaintext (before encryption)
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize)]
+pub struct ProtocolTraffic {
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+}
+
+/// Tunnel traffic split by IP protocol (ICMP includes ICMPv6)
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct ProtocolStats {
+    pub tcp: ProtocolTraffic,
+    pub udp: ProtocolTraffic,
+    pub icmp: ProtocolTraffic,
+    pub other: ProtocolTraffic,
+}
+
+/// Lock-free per-protocol counters updated by the packet loops, indexed by protocol_slot()
+#[derive(Default)]
+struct ProtocolCounters {
+    tx_packets: [AtomicU64; 4],
+    tx_bytes: [AtomicU64; 4],
+    rx_packets: [AtomicU64; 4],
+    rx_bytes: [AtomicU64; 4],
+    /// UDP datagrams that couldn't be WireGuard messages, dropped before decapsulate
+    rejected: AtomicU64,
+}
+
+impl ProtocolCounters {
+    fn record_tx(&self, packet: &[u8]) {
+        let slot = protocol_slot(packet);
+        self.tx_packets[slot].fetch_add(1, AtomicOrdering::Relaxed);
+        self.tx_bytes[slot].fetch_add(packet.len() as u64, AtomicOrdering::Relaxed);
+    }
+
+    fn record_rx(&self, packet: &[u8]) {
+        let slot = protocol_slot(packet);
+        self.rx_packets[slot].fetch_add(1, AtomicOrdering::Relaxed);
+        self.rx_bytes[slot].fetch_add(packet.len() as u64, AtomicOrdering::Relaxed);
+    }
+
+    fn snapshot(&self) -> ProtocolStats {
+        let traffic = |slot: usize| ProtocolTraffic {
+            tx_packets: self.tx_packets[slot].load(AtomicOrdering::Relaxed),
+            tx_bytes: self.tx_bytes[slot].load(AtomicOrdering::Relaxed),
+            rx_packets: self.rx_packets[slot].load(AtomicOrdering::Relaxed),
+            rx_bytes: self.rx_bytes[slot].load(AtomicOrdering::Relaxed),
+        };
+        ProtocolStats { tcp: traffic(0), udp: traffic(1), icmp: traffic(2), other: traffic(3) }
+    }
+}
+
+/// AllowedIPs that get a route of their own. 0.0.0.0/0 is left out: it means
+/// "send everything to this peer", which the tunnel does in exit-node mode
+/// (set_default_gateway) with 0.0.0.0/1 + 128.0.0.0/1. Those beat the physical
+/// default route on prefix length without replacing it, and leave room for the
+/// host route that keeps the relay's own packets off the tunnel. A literal
+/// default route would fight the physical one and swallow that bypass.
+fn routed_ips(allowed_ips: &[(Ipv4Addr, u8)]) -> impl Iterator<Item = (Ipv4Addr, u8)> + '_ {
+    allowed_ips.iter().copied().filter(|(_, prefix)| *prefix != 0)
+}
+
+/// Whether a peer takes 0.0.0.0/0, i.e. is an exit node (see routed_ips)
+fn takes_default_route(allowed_ips: &[(Ipv4Addr, u8)]) -> bool {
+    allowed_ips.iter().any(|(_, prefix)| *prefix == 0)
+}
+
+/// Whether a datagram has the type and size of a WireGuard message: handshake
+/// initiation (148 bytes), response (92), cookie reply (64) or data (32+), with
+/// the three reserved bytes zero. Scanners and stray traffic fail this cheaply.
+fn is_wireguard_message(datagram: &[u8]) -> bool {
+    let size_ok = match datagram.first() {
+        Some(1) => datagram.len() == 148,
+        Some(2) => datagram.len() == 92,
+        Some(3) => datagram.len() == 64,
+        Some(4) => datagram.len() >= 32,
+        _ => false,
+    };
+    size_ok && datagram[1..4] == [0, 0, 0]
+}
+
+/// Counter slot for an IP packet: TCP, UDP, ICMP/ICMPv6, anything else.
+/// IPv6 extension headers aren't followed, so those packets count as other.
+fn protocol_slot(packet: &[u8]) -> usize {
+    let protocol = match packet.first().map(|b| b >> 4) {
+        Some(4) if packet.len() >= 20 => packet[9],
+        Some(6) if packet.len() >= 40 => packet[6],
+        _ => return 3,
+    };
+    match protocol {
+        6 => 0,
+        17 => 1,
+        1 | 58 => 2,
+        _ => 3,
+    }
+}
+
+/// WireGuard UDP socket. Dual-stack where the OS allows it, so relays reachable
+/// only over IPv6 work; peers always see and report plain IPv4/IPv6 addresses.
+struct WgSocket {
+    inner: UdpSocket,
+    dual_stack: bool,
+}
+
+impl WgSocket {
+    fn bind(local_ip: Option<IpAddr>, port: u16) -> Result<Self, PleError> {
+        let std_socket = stun::bind_udp_on(local_ip, port)
+            .map_err(|e| match (e.kind(), local_ip) {
+                (std::io::ErrorKind::AddrInUse, _) => PleError::PortConflict(format!(
+                    "UDP port {} is already in use - is another WireGuard VPN running?", port)),
+                (_, Some(ip)) => PleError::Other(format!("Failed to bind UDP socket on {}:{}: {}", ip, port, e)),
+                (_, None) => PleError::Other(format!("Failed to bind UDP socket on port {}: {}", port, e)),
+            })?;
+        std_socket.set_nonblocking(true)
+            .map_err(|e| format!("Failed to set UDP socket non-blocking: {}", e))?;
+        Self::resize_buffers(&std_socket, socket_buffer_size());
+
+        let dual_stack = std_socket.local_addr().map(stun::is_dual_stack).unwrap_or(false);
+        let inner = UdpSocket::from_std(std_socket)
+            .map_err(|e| format!("Failed to register UDP socket: {}", e))?;
+
+        Ok(Self { inner, dual_stack })
+    }
+
+    /// Request larger kernel buffers. The OS may grant less (Linux caps at
+    /// net.core.rmem_max/wmem_max), so the granted sizes are logged.
+    fn resize_buffers(socket: &std::net::UdpSocket, bytes: usize) {
+        let socket = socket2::SockRef::from(socket);
+        if let Err(e) = socket.set_recv_buffer_size(bytes) {
+            log::warn!("Failed to set UDP receive buffer to {} bytes: {}", bytes, e);
+        }
+        if let Err(e) = socket.set_send_buffer_size(bytes) {
+            log::warn!("Failed to set UDP send buffer to {} bytes: {}", bytes, e);
+        }
+
+        let granted = |size: std::io::Result<usize>| size.map_or("unknown".to_string(), |s| s.to_string());
+        log::info!("UDP socket buffers: requested {} bytes, granted recv {} / send {}",
+            bytes, granted(socket.recv_buffer_size()), granted(socket.send_buffer_size()));
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+        self.inner.send_to(buf, stun::send_addr(self.dual_stack, target)).await
+    }
+
+    fn try_send_to(&self, buf: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+        self.inner.try_send_to(buf, stun::send_addr(self.dual_stack, target))
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        let (len, src) = self.inner.recv_from(buf).await?;
+        Ok((len, stun::canonical_addr(src)))
+    }
+
+    fn local_port(&self) -> Result<u16, String> {
+        self.inner.local_addr()
+            .map(|addr| addr.port())
+            .map_err(|e| format!("Failed to get local address: {}", e))
+    }
 }
 
 /// WireGuard tunnel manager
 pub struct WgTunnel {
+    /// The config connected with - its peers go stale once peers are added,
+    /// removed or updated, use peer_configs for those
     config: WgConfig,
     private_key: x25519_dalek::StaticSecret,
     public_key: x25519_dalek::PublicKey,
-    socket: Arc<UdpSocket>,
+    socket: Arc<WgSocket>,
     tun_device: Arc<TunDevice>,
     peers: Arc<DashMap<[u8; 32], PeerState>>,
+    /// Config of the current peers, changed along with `peers`
+    peer_configs: RwLock<Vec<WgPeer>>,
     running: Arc<std::sync::atomic::AtomicBool>,
+    /// Cancelled by stop() (or drop) so the packet loops exit immediately
+    cancel: CancellationToken,
+    /// The packet loops spawned by start(), awaited by stop()
+    tasks: parking_lot::Mutex<Vec<tokio::task::JoinHandle<()>>>,
+    /// While set, packets are dropped instead of forwarded (sessions stay alive)
+    paused: Arc<std::sync::atomic::AtomicBool>,
+    /// Whether set_default_gateway() is in effect, so resume() can restore it
+    default_gateway_set: std::sync::atomic::AtomicBool,
+    /// Whether the DNS leak block is installed and needs removing
+    dns_block_set: std::sync::atomic::AtomicBool,
+    /// Whether the TUN device's resolvers were set and need restoring
+    dns_servers_set: std::sync::atomic::AtomicBool,
+    /// Whether the IPv6 leak block is installed and needs removing
+    ipv6_block_set: std::sync::atomic::AtomicBool,
     public_endpoint: Arc<RwLock<Option<SocketAddr>>>,
+    protocol_counters: Arc<ProtocolCounters>,
 }
 
 impl WgTunnel {
-    /// Create a new WireGuard tunnel
-    pub async fn new(config: WgConfig) -> Result<Self, String> {
+    /// Create a new WireGuard tunnel. `device` is a TUN device kept from the previous
+    /// session (see release_device) - reusing it skips the slow device setup. It's
+    /// recreated if its addresses don't fit `config`.
+    pub async fn new(config: WgConfig, device: Option<Arc<TunDevice>>) -> Result<Self, PleError> {
         // Parse private key
-        let private_key = x25519_dalek::StaticSecret::from(config.private_key);
+        // StaticSecret wipes itself on drop as well
+        let private_key = x25519_dalek::StaticSecret::from(*config.private_key.as_bytes());
         let public_key = x25519_dalek::PublicKey::from(&private_key);
 
         log::info!("Creating WireGuard tunnel with public key: {}",
-            base64::engine::general_purpose::STANDARD.encode(public_key.as_bytes()));
-
-        // Find available port
-        let listen_port = config.listen_port.unwrap_or_else(|| Self::find_available_port());
-        let bind_addr = format!("0.0.0.0:{}", listen_port);
+            derive_public_key(config.private_key.as_bytes()));
 
         // Use tokio's async UDP socket for better performance
-        let socket = UdpSocket::bind(&bind_addr).await
-            .map_err(|e| format!("Failed to bind UDP socket on {}: {}", bind_addr, e))?;
-
-        log::info!("WireGuard listening on port {}", listen_port);
+        let socket = match config.listen_port {
+            Some(port) => WgSocket::bind(config.bind_address, port)?,
+            None => Self::bind_preferred_port(config.bind_address)?,
+        };
+        let listen_port = socket.local_port()?;
+        set_preferred_listen_port(listen_port);
+
+        log::info!("WireGuard listening on {}:{} (dual-stack: {})",
+            config.bind_address.map_or("*".to_string(), |ip| ip.to_string()), listen_port, socket.dual_stack);
+
+        // Discover public endpoint via STUN, from the same interface WireGuard uses
+        let stun_client = AsyncStunClient::new()
+            .bound_to(config.bind_address)
+            .with_timeout(config.power_profile.timings().stun_timeout);
+        let public_endpoint = if config.relay_only {
+            log::info!("Relay-only mode, skipping STUN discovery");
+            None
+        } else {
+            match stun_client.discover_for_port(listen_port).await {
+                Ok(result) if result.over_tcp => {
+                    log::warn!("Only TCP reached STUN (public IP {}). Direct P2P may not work.", result.public_addr.ip());
+                    None
+                }
+                Ok(result) => {
+                    log::info!("Public endpoint discovered: {}", result.public_addr);
+                    Some(result.public_addr)
+                }
+                Err(e) => {
+                    log::warn!("STUN discovery failed: {}. Direct P2P may not work.", e);
+                    None
+                }
+            }
+        };
 
-        // Discover public endpoint via STUN
-        let stun_client = AsyncStunClient::new();
-        let public_endpoint = match stun_client.discover_for_port(listen_port).await {
-            Ok(result) => {
-                log::info!("Public endpoint discovered: {}", result.public_addr);
-                Some(result.public_addr)
+        // Create TUN device, or reuse the previous session's
+        let tun_device = match device {
+            Some(device) if device_fits(&device, &config) => {
+                log::info!("Reusing TUN device {}", device.name());
+                device
             }
-            Err(e) => {
-                log::warn!("STUN discovery failed: {}. Direct P2P may not work.", e);
-                None
+            device => {
+                if device.is_some() {
+                    log::info!("Kept TUN device doesn't match the new addresses, recreating it");
+                }
+                // Only one device can have the name - the old one has to go first
+                drop(device);
+                Arc::new(TunDevice::create(TUN_NAME, config.address, config.netmask).await?)
             }
         };
-
-        // Create TUN device
-        let tun_device = TunDevice::create("ple7", config.address, config.netmask).await?;
+        // A reused device may still have a path-MTU-lowered MTU
+        let mtu = config.mtu.map_or(TUN_MTU, |mtu| mtu as usize);
+        if mtu != tun_device.mtu() {
+            if let Err(e) = tun_device.set_mtu(mtu).await {
+                log::warn!("Failed to apply configured MTU {}: {}", mtu, e);
+            }
+        }
 
         // Initialize peers with DashMap for lock-free concurrent access
         let peers_map = DashMap::new();
         for peer in &config.peers {
-            let peer_public_key = x25519_dalek::PublicKey::from(peer.public_key);
-
-            let tunnel = Tunn::new(
-                private_key.clone(),
-                peer_public_key,
-                peer.preshared_key,
-                peer.persistent_keepalive,
-                0,
-                None,
-            ).map_err(|e| format!("Failed to create tunnel for peer: {}", e))?;
-
-            peers_map.insert(peer.public_key, PeerState {
-                tunnel,
-                endpoint: peer.endpoint,
-                last_handshake: None,
-                tx_bytes: 0,
-                rx_bytes: 0,
-            });
+            let tunnel = Self::create_peer_tunnel(&private_key, peer)?;
+            peers_map.insert(peer.public_key, PeerState::new(tunnel, peer, config.power_profile.timings()));
         }
 
         Ok(Self {
+            peer_configs: RwLock::new(config.peers.clone()),
             config,
             private_key,
             public_key,
             socket: Arc::new(socket),
-            tun_device: Arc::new(tun_device),
+            tun_device,
             peers: Arc::new(peers_map),
             running: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            cancel: CancellationToken::new(),
+            tasks: parking_lot::Mutex::new(Vec::new()),
+            paused: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            default_gateway_set: std::sync::atomic::AtomicBool::new(false),
+            dns_block_set: std::sync::atomic::AtomicBool::new(false),
+            dns_servers_set: std::sync::atomic::AtomicBool::new(false),
+            ipv6_block_set: std::sync::atomic::AtomicBool::new(false),
             public_endpoint: Arc::new(RwLock::new(public_endpoint)),
+            protocol_counters: Arc::new(ProtocolCounters::default()),
         })
     }
 
-    fn find_available_port() -> u16 {
-        for port in WG_PORT_START..=WG_PORT_END {
-            if StdUdpSocket::bind(format!("0.0.0.0:{}", port)).is_ok() {
-                return port;
+    fn create_peer_tunnel(private_key: &x25519_dalek::StaticSecret, peer: &WgPeer) -> Result<Tunn, String> {
+        Self::new_peer_tunn(private_key, peer.public_key, peer.preshared_key.as_ref())
+    }
+
+    fn new_peer_tunn(
+        private_key: &x25519_dalek::StaticSecret,
+        public_key: [u8; 32],
+        preshared_key: Option<&SecretKey>,
+    ) -> Result<Tunn, String> {
+        // Persistent keepalives are sent by keepalive_loop, which adapts them to the path
+        Tunn::new(
+            private_key.clone(),
+            x25519_dalek::PublicKey::from(public_key),
+            preshared_key.map(|psk| *psk.as_bytes()),
+            None,
+            0,
+            None,
+        ).map_err(|e| format!("Failed to create tunnel for peer: {}", e))
+    }
+
+    /// Bind the previous session's port if it's still free, otherwise the first free one
+    fn bind_preferred_port(local_ip: Option<IpAddr>) -> Result<WgSocket, PleError> {
+        if let Some(port) = preferred_listen_port() {
+            match WgSocket::bind(local_ip, port) {
+                Ok(socket) => {
+                    log::info!("Reusing listen port {} from the previous session", port);
+                    return Ok(socket);
+                }
+                Err(e) => log::info!("Previous listen port unavailable, picking a new one: {}", e),
             }
         }
-        // Fallback to random port
-        0
+
+        WgSocket::bind(local_ip, Self::find_available_port(local_ip)?)
+    }
+
+    /// First free port in the WireGuard range. No ephemeral fallback - a random
+    /// port changes on every connect and breaks the endpoint peers learned via STUN.
+    fn find_available_port(local_ip: Option<IpAddr>) -> Result<u16, PleError> {
+        (WG_PORT_START..=WG_PORT_END)
+            .find(|port| stun::bind_udp_on(local_ip, *port).is_ok())
+            .ok_or_else(|| PleError::PortConflict(format!(
+                "WireGuard port range {}-{} exhausted - another VPN or WireGuard instance may be using it",
+                WG_PORT_START, WG_PORT_END,
+            )))
     }
 
     /// Start the tunnel
@@ -154,48 +855,58 @@ impl WgTunnel {
         if self.running.load(Ordering::SeqCst) {
             return Err("Tunnel already running".to_string());
         }
+        if self.cancel.is_cancelled() {
+            return Err("Tunnel was stopped".to_string());
+        }
 
         self.running.store(true, Ordering::SeqCst);
 
         // Add routes for allowed IPs
-        for peer in &self.config.peers {
-            for (addr, prefix) in &peer.allowed_ips {
-                if let Err(e) = self.tun_device.add_route(*addr, *prefix).await {
-                    log::warn!("Failed to add route {}/{}: {}", addr, prefix, e);
-                }
+        let routes: Vec<(Ipv4Addr, u8)> = self.peer_configs.read().iter()
+            .flat_map(|peer| routed_ips(&peer.allowed_ips).collect::<Vec<_>>())
+            .collect();
+        for (addr, prefix) in routes {
+            if let Err(e) = self.tun_device.add_route(addr, prefix).await {
+                log::warn!("Failed to add route {}/{}: {}", addr, prefix, e);
             }
         }
+        self.configure_ipv6().await;
 
         // Spawn packet handling tasks
         let socket_read = self.socket.clone();
         let socket_write = self.socket.clone();
         let tun = self.tun_device.clone();
         let peers = self.peers.clone();
-        let running = self.running.clone();
+        let paused = self.paused.clone();
         let private_key = self.private_key.clone();
 
         // Task 1: Read from UDP socket (incoming WireGuard packets)
         let peers_udp = peers.clone();
         let tun_udp = tun.clone();
-        let running_udp = running.clone();
-        tokio::spawn(async move {
-            Self::udp_read_loop(socket_read, peers_udp, tun_udp, running_udp).await;
+        let cancel_udp = self.cancel.clone();
+        let paused_udp = paused.clone();
+        let counters_udp = self.protocol_counters.clone();
+        let udp_task = tokio::spawn(async move {
+            Self::udp_read_loop(socket_read, peers_udp, tun_udp, cancel_udp, paused_udp, counters_udp).await;
         });
 
         // Task 2: Read from TUN device (outgoing packets from apps)
         let peers_tun = peers.clone();
-        let running_tun = running.clone();
-        tokio::spawn(async move {
-            Self::tun_read_loop(tun, socket_write, peers_tun, running_tun).await;
+        let cancel_tun = self.cancel.clone();
+        let counters_tun = self.protocol_counters.clone();
+        let tun_task = tokio::spawn(async move {
+            Self::tun_read_loop(tun, socket_write, peers_tun, cancel_tun, paused, counters_tun).await;
         });
 
         // Task 3: Periodic keepalive and handshake
         let peers_keepalive = peers.clone();
         let socket_keepalive = self.socket.clone();
-        let running_keepalive = running.clone();
-        tokio::spawn(async move {
-            Self::keepalive_loop(socket_keepalive, peers_keepalive, running_keepalive).await;
+        let cancel_keepalive = self.cancel.clone();
+        let timer_tick = self.config.power_profile.timings().timer_tick;
+        let keepalive_task = tokio::spawn(async move {
+            Self::keepalive_loop(socket_keepalive, peers_keepalive, cancel_keepalive, timer_tick).await;
         });
+        self.tasks.lock().extend([udp_task, tun_task, keepalive_task]);
 
         // Initiate handshakes with all peers
         self.initiate_handshakes().await?;
@@ -204,6 +915,67 @@ impl WgTunnel {
         Ok(())
     }
 
+    /// Give the TUN device its IPv6 address and routes for the peers' IPv6
+    /// AllowedIPs - without them the OS drops decrypted v6 packets
+    async fn configure_ipv6(&self) {
+        let routes: Vec<(Ipv6Addr, u8)> = self.peer_configs.read().iter()
+            .flat_map(|peer| peer.allowed_ips_v6.iter().copied())
+            .collect();
+
+        let Some((address, prefix)) = self.config.address_v6 else {
+            if !routes.is_empty() {
+                log::warn!("Peers have IPv6 AllowedIPs but the config has no IPv6 Address - IPv6 traffic will be dropped");
+            }
+            return;
+        };
+        // Already there on a reused device
+        if self.tun_device.ipv6_address() != Some((address, prefix)) {
+            if let Err(e) = self.tun_device.set_ipv6_address(address, prefix).await {
+                log::warn!("Failed to set IPv6 address {}/{}: {}", address, prefix, e);
+                return;
+            }
+        }
+
+        for (addr, prefix) in routes {
+            if let Err(e) = self.tun_device.add_route_v6(addr, prefix).await {
+                log::warn!("Failed to add route {}/{}: {}", addr, prefix, e);
+            }
+        }
+    }
+
+    /// Hand the TUN device over to the next tunnel (see new), first removing
+    /// the default gateway and AllowedIPs routes this tunnel put through it.
+    /// Call after stop().
+    pub async fn release_device(&self) -> Arc<TunDevice> {
+        use std::sync::atomic::Ordering;
+
+        if self.default_gateway_set.swap(false, Ordering::SeqCst) {
+            if let Err(e) = self.tun_device.clear_default_gateway().await {
+                log::warn!("Failed to clear default gateway: {}", e);
+            }
+        }
+
+        let routes: Vec<_> = self.peers.iter()
+            .map(|peer| (peer.allowed_ips.clone(), peer.allowed_ips_v6.clone()))
+            .collect();
+        for (allowed_ips, allowed_ips_v6) in routes {
+            for (addr, prefix) in routed_ips(&allowed_ips) {
+                if let Err(e) = self.tun_device.remove_route(addr, prefix).await {
+                    log::warn!("Failed to remove route {}/{}: {}", addr, prefix, e);
+                }
+            }
+            if self.tun_device.has_ipv6() {
+                for (addr, prefix) in allowed_ips_v6 {
+                    if let Err(e) = self.tun_device.remove_route_v6(addr, prefix).await {
+                        log::warn!("Failed to remove route {}/{}: {}", addr, prefix, e);
+                    }
+                }
+            }
+        }
+
+        self.tun_device.clone()
+    }
+
     /// Initiate handshakes with all peers
     async fn initiate_handshakes(&self) -> Result<(), String> {
         // Collect handshake packets - DashMap locks per-entry, not globally
@@ -215,6 +987,7 @@ impl WgTunnel {
                 let mut dst = [0u8; 2048];
                 match peer_state.tunnel.format_handshake_initiation(&mut dst, false) {
                     TunnResult::WriteToNetwork(data) => {
+                        peer_state.on_packet_sent(data);
                         packets.push((data.to_vec(), endpoint));
                     }
                     _ => {}
@@ -235,45 +1008,61 @@ impl WgTunnel {
     }
 
     /// Stop the tunnel
+    /// WireGuard has no teardown message, so peers only notice via their session
+    /// timers - the caller unregisters over WebSocket to make the relay let go sooner.
+    /// The packet loops have exited on return, so dropping the tunnel closes the
+    /// socket and the listen port can be bound again straight away.
     pub async fn stop(&self) -> Result<(), String> {
         use std::sync::atomic::Ordering;
 
         self.running.store(false, Ordering::SeqCst);
+        self.cancel.cancel();
+        let tasks = std::mem::take(&mut *self.tasks.lock());
+        join_loops(tasks).await;
+        self.remove_leak_blocks().await;
         log::info!("WireGuard tunnel stopped");
         Ok(())
     }
 
     /// UDP read loop - handles incoming WireGuard packets
     async fn udp_read_loop(
-        socket: Arc<UdpSocket>,
+        socket: Arc<WgSocket>,
         peers: Arc<DashMap<[u8; 32], PeerState>>,
         tun: Arc<TunDevice>,
-        running: Arc<std::sync::atomic::AtomicBool>,
+        cancel: CancellationToken,
+        paused: Arc<std::sync::atomic::AtomicBool>,
+        counters: Arc<ProtocolCounters>,
     ) {
         use std::sync::atomic::Ordering;
 
         // Reusable buffer to avoid allocations in hot path
         let mut buf = [0u8; 2048]; // WireGuard packets are max ~1500 bytes
+        let mut warned_no_ipv6 = false;
 
         loop {
-            if !running.load(Ordering::SeqCst) {
-                break;
-            }
-
             // Async UDP recv - no spawn_blocking overhead
-            let (len, src_addr) = match socket.recv_from(&mut buf).await {
-                Ok(data) => data,
-                Err(e) => {
-                    if e.kind() != std::io::ErrorKind::WouldBlock {
-                        log::error!("UDP recv error: {}", e);
+            let (len, src_addr) = tokio::select! {
+                _ = cancel.cancelled() => break,
+                result = socket.recv_from(&mut buf) => match result {
+                    Ok(data) => data,
+                    Err(e) => {
+                        if e.kind() != std::io::ErrorKind::WouldBlock {
+                            log::error!("UDP recv error: {}", e);
+                        }
+                        continue;
                     }
-                    continue;
-                }
+                },
             };
 
+            if !is_wireguard_message(&buf[..len]) {
+                counters.rejected.fetch_add(1, Ordering::Relaxed);
+                log::trace!("Ignoring {}-byte non-WireGuard datagram from {}", len, src_addr);
+                continue;
+            }
+
             // Process packet - DashMap locks per-entry, not globally
             let mut write_data: Option<Vec<u8>> = None;
-            let mut response_data: Option<Vec<u8>> = None;
+            let mut response_data: Vec<Vec<u8>> = Vec::new();
 
             for mut entry in peers.iter_mut() {
                 let peer_state = entry.value_mut();
@@ -281,21 +1070,38 @@ impl WgTunnel {
 
                 match peer_state.tunnel.decapsulate(None, &buf[..len], &mut dst) {
                     TunnResult::WriteToTunnelV4(data, _) => {
+                        peer_state.on_packet_received(&buf[..len], src_addr);
                         peer_state.rx_bytes += data.len() as u64;
                         peer_state.endpoint = Some(src_addr);
                         write_data = Some(data.to_vec());
                         break;
                     }
                     TunnResult::WriteToTunnelV6(data, _) => {
+                        peer_state.on_packet_received(&buf[..len], src_addr);
                         peer_state.rx_bytes += data.len() as u64;
                         peer_state.endpoint = Some(src_addr);
                         write_data = Some(data.to_vec());
                         break;
                     }
                     TunnResult::WriteToNetwork(data) => {
-                        response_data = Some(data.to_vec());
+                        peer_state.on_packet_received(&buf[..len], src_addr);
+                        response_data.push(data.to_vec());
+                        // Packets encapsulate() queued while the handshake was pending
+                        // only go out when we ask for them
+                        loop {
+                            let mut dst = [0u8; 2048];
+                            match peer_state.tunnel.decapsulate(None, &[], &mut dst) {
+                                TunnResult::WriteToNetwork(data) => {
+                                    peer_state.tx_bytes += data.len() as u64;
+                                    peer_state.on_packet_sent(data);
+                                    response_data.push(data.to_vec());
+                                }
+                                _ => break,
+                            }
+                        }
                     }
                     TunnResult::Done => {
+                        peer_state.on_packet_received(&buf[..len], src_addr);
                         peer_state.last_handshake = Some(Instant::now());
                     }
                     TunnResult::Err(_) => {
@@ -304,13 +1110,27 @@ impl WgTunnel {
                 }
             }
 
-            // Send handshake response (async)
-            if let Some(data) = response_data {
+            // Send handshake response and any flushed packets (async)
+            for data in response_data {
                 let _ = socket.send_to(&data, src_addr).await;
             }
 
-            // Write decrypted data to TUN
-            if let Some(data) = write_data {
+            // Path MTU probe replies are answered here, not by anything behind the TUN
+            let write_data = write_data.filter(|data| !pmtu::intercept(data));
+
+            // The OS would drop these silently - say why once instead
+            if write_data.as_ref().and_then(|data| data.first()).is_some_and(|b| b >> 4 == 6) && !tun.has_ipv6() {
+                if !warned_no_ipv6 {
+                    log::warn!("[WG] Dropping IPv6 packets: {} has no IPv6 address", tun.name());
+                    warned_no_ipv6 = true;
+                }
+                continue;
+            }
+
+            // Write decrypted data to TUN (dropped while paused - handshakes above still run)
+            if let Some(data) = write_data.filter(|_| !paused.load(Ordering::Relaxed)) {
+                capture::record(&data);
+                counters.record_rx(&data);
                 if let Err(e) = tun.write(&data).await {
                     log::error!("[WG] TUN write failed: {}", e);
                 }
@@ -321,36 +1141,45 @@ impl WgTunnel {
     /// TUN read loop - handles outgoing packets from applications
     async fn tun_read_loop(
         tun: Arc<TunDevice>,
-        socket: Arc<UdpSocket>,
+        socket: Arc<WgSocket>,
         peers: Arc<DashMap<[u8; 32], PeerState>>,
-        running: Arc<std::sync::atomic::AtomicBool>,
+        cancel: CancellationToken,
+        paused: Arc<std::sync::atomic::AtomicBool>,
+        counters: Arc<ProtocolCounters>,
     ) {
         use std::sync::atomic::Ordering;
 
         loop {
-            if !running.load(Ordering::SeqCst) {
-                break;
-            }
-
             // Read packet from TUN device
-            let packet = match tun.read().await {
-                Ok(p) => p,
-                Err(e) => {
-                    // Only log non-timeout errors
-                    let err_str = e.to_string();
-                    if running.load(Ordering::SeqCst) && !err_str.contains("timeout") && !err_str.contains("timed out") {
-                        log::error!("[TUN] TUN read error: {}", e);
+            let packet = tokio::select! {
+                _ = cancel.cancelled() => break,
+                result = tun.read() => match result {
+                    Ok(p) => p,
+                    Err(e) => {
+                        // Only log non-timeout errors
+                        let err_str = e.to_string();
+                        if !cancel.is_cancelled() && !err_str.contains("timeout") && !err_str.contains("timed out") {
+                            log::error!("[TUN] TUN read error: {}", e);
+                        }
+                        tokio::time::sleep(Duration::from_millis(1)).await;
+                        continue;
                     }
-                    tokio::time::sleep(Duration::from_millis(1)).await;
-                    continue;
-                }
+                },
             };
 
+            // Drop outgoing traffic while paused
+            if paused.load(Ordering::Relaxed) {
+                continue;
+            }
+
             // Skip invalid packets
             if packet.data.len() < 20 {
                 continue;
             }
 
+            capture::record(&packet.data);
+            flows::record(&packet.data);
+
             // Skip if no peers
             if peers.is_empty() {
                 continue;
@@ -364,11 +1193,22 @@ impl WgTunnel {
                 if let Some(endpoint) = peer_state.endpoint {
                     let mut dst = [0u8; 2048];
 
+                    // Without a session boringtun queues the packet and hands back a handshake
+                    // initiation (or Done if one is in flight) - udp_read_loop flushes the queue
                     match peer_state.tunnel.encapsulate(&packet.data, &mut dst) {
                         TunnResult::WriteToNetwork(data) => {
                             peer_state.tx_bytes += data.len() as u64;
+                            peer_state.on_packet_sent(data);
+                            counters.record_tx(&packet.data);
                             send_data = Some((data.to_vec(), endpoint));
                         }
+                        TunnResult::Done => {
+                            counters.record_tx(&packet.data);
+                            log::debug!("[WG] Packet queued until the handshake with {} completes", endpoint);
+                        }
+                        TunnResult::Err(e) => {
+                            log::warn!("[WG] Failed to encapsulate packet for {}: {:?}", endpoint, e);
+                        }
                         _ => {}
                     }
                     break;
@@ -382,36 +1222,58 @@ impl WgTunnel {
         }
     }
 
-    /// Keepalive loop - sends periodic keepalives and maintains handshakes
+    /// Keepalive loop - drives the WireGuard timers and sends each peer keepalives
+    /// at the interval its path needs
     async fn keepalive_loop(
-        socket: Arc<UdpSocket>,
+        socket: Arc<WgSocket>,
         peers: Arc<DashMap<[u8; 32], PeerState>>,
-        running: Arc<std::sync::atomic::AtomicBool>,
+        cancel: CancellationToken,
+        timer_tick: Duration,
     ) {
-        use std::sync::atomic::Ordering;
-
-        let mut interval = tokio::time::interval(KEEPALIVE_INTERVAL);
+        let mut interval = tokio::time::interval(timer_tick);
 
         loop {
-            interval.tick().await;
-
-            if !running.load(Ordering::SeqCst) {
-                break;
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = interval.tick() => {}
             }
 
             // Collect keepalive packets - DashMap locks per-entry
             let mut packets_to_send: Vec<(Vec<u8>, SocketAddr)> = Vec::new();
 
             for mut entry in peers.iter_mut() {
+                let public_key = *entry.key();
                 let peer_state = entry.value_mut();
+
+                // Keepalives keep going to dead peers, so they're noticed when they come back
+                let dead = peer_state.is_dead();
+                if dead != peer_state.dead {
+                    peer_state.dead = dead;
+                    let public_key = base64::engine::general_purpose::STANDARD.encode(public_key);
+                    if dead {
+                        log::warn!("[WG] Peer {} has gone silent, marking it dead", public_key);
+                    } else {
+                        log::info!("[WG] Peer {} is responding again", public_key);
+                    }
+                }
+
                 if let Some(endpoint) = peer_state.endpoint {
                     let mut dst = [0u8; 2048];
 
-                    match peer_state.tunnel.update_timers(&mut dst) {
-                        TunnResult::WriteToNetwork(data) => {
+                    if let TunnResult::WriteToNetwork(data) = peer_state.tunnel.update_timers(&mut dst) {
+                        peer_state.on_packet_sent(data);
+                        packets_to_send.push((data.to_vec(), endpoint));
+                    } else if peer_state.handshake_retry_due() {
+                        if let TunnResult::WriteToNetwork(data) = peer_state.tunnel.format_handshake_initiation(&mut dst, true) {
+                            peer_state.on_packet_sent(data);
+                            packets_to_send.push((data.to_vec(), endpoint));
+                        }
+                    } else if peer_state.keepalive_due() {
+                        // An empty data packet is a WireGuard keepalive
+                        if let TunnResult::WriteToNetwork(data) = peer_state.tunnel.encapsulate(&[], &mut dst) {
+                            peer_state.on_packet_sent(data);
                             packets_to_send.push((data.to_vec(), endpoint));
                         }
-                        _ => {}
                     }
                 }
             }
@@ -423,42 +1285,938 @@ impl WgTunnel {
         }
     }
 
+    /// Get the name of the TUN interface carrying this tunnel
+    pub fn tun_name(&self) -> &str {
+        self.tun_device.name()
+    }
+
     /// Get public endpoint (for reporting to control plane)
     pub fn public_endpoint(&self) -> Option<SocketAddr> {
         *self.public_endpoint.read()
     }
 
-    /// Get tunnel statistics
-    pub fn get_stats(&self) -> Vec<(String, u64, u64)> {
-        self.peers.iter().map(|entry| {
-            let key_b64 = base64::engine::general_purpose::STANDARD.encode(entry.key());
-            (key_b64, entry.value().tx_bytes, entry.value().rx_bytes)
-        }).collect()
+    /// Local address WireGuard traffic is sent from, None if the OS picks
+    pub fn bind_address(&self) -> Option<IpAddr> {
+        self.config.bind_address
     }
 
-    /// Update peer endpoint (for NAT traversal)
-    pub fn update_peer_endpoint(&self, public_key: &[u8; 32], endpoint: SocketAddr) {
-        if let Some(mut peer) = self.peers.get_mut(public_key) {
-            log::info!("Updating peer endpoint: {:?} -> {}", public_key, endpoint);
-            peer.endpoint = Some(endpoint);
-        }
+    /// UDP port WireGuard is bound to
+    pub fn listen_port(&self) -> Option<u16> {
+        self.socket.local_port().ok()
     }
 
-    /// Set default gateway to route all traffic through VPN
-    pub async fn set_default_gateway(&self) -> Result<(), String> {
-        log::info!("Setting default gateway through VPN tunnel");
+    /// Whether direct paths are disabled and traffic stays on the relay
+    pub fn relay_only(&self) -> bool {
+        self.config.relay_only
+    }
+
+    /// Power profile this tunnel's timings come from
+    pub fn power_profile(&self) -> PowerProfile {
+        self.config.power_profile
+    }
+
+    /// This tunnel's config as a wg-quick .conf, with the port actually bound and
+    /// the peers as they are now - added and removed peers, updated AllowedIPs
+    /// and PSKs, and the endpoint each peer is reached at. A hostname endpoint
+    /// is kept while the peer is still on it. Keys other than public ones are
+    /// only included when asked for.
+    pub fn to_wg_quick_string(&self, include_secrets: bool) -> String {
+        let mut config = self.config.clone();
+        if let Ok(port) = self.socket.local_port() {
+            config.listen_port = Some(port);
+        }
+        config.peers = self.peer_configs.read().clone();
+        for peer in &mut config.peers {
+            let Some(state) = self.peers.get(&peer.public_key) else {
+                continue;
+            };
+            if state.endpoint != state.configured_endpoint {
+                peer.endpoint_host = None;
+            }
+            peer.endpoint = state.endpoint;
+            peer.preshared_key = state.preshared_key.clone();
+        }
+        format_wg_config(&config, include_secrets)
+    }
+
+    /// Current MTU of the TUN device
+    pub fn mtu(&self) -> u16 {
+        self.tun_device.mtu() as u16
+    }
+
+    /// Find the largest tunnel MTU the path to the first reachable peer carries
+    /// and lower the TUN MTU to it. Results are cached per endpoint, so reconnects
+    /// skip the probe. None if the probe was inconclusive - the MTU is left alone.
+    pub async fn discover_mtu(&self) -> Option<u16> {
+        let ceiling = self.config.mtu.unwrap_or(TUN_MTU as u16);
+        let Some(mut probe) = self.peers.iter().find_map(|entry| {
+            let endpoint = entry.value().endpoint?;
+            let target = pmtu::probe_target(&entry.value().allowed_ips, self.config.address)?;
+            Some(MtuProbe {
+                public_key: *entry.key(),
+                endpoint,
+                target,
+                ident: rand::random(),
+                seq: 0,
+            })
+        }) else {
+            log::warn!("[PMTU] No peer to probe");
+            return None;
+        };
+
+        let mtu = match pmtu::cached(probe.endpoint) {
+            Some(mtu) => {
+                log::info!("[PMTU] Using cached MTU {} for {}", mtu, probe.endpoint);
+                mtu.min(ceiling)
+            }
+            None => {
+                let mtu = self.probe_path_mtu(&mut probe, ceiling).await?;
+                pmtu::remember(probe.endpoint, mtu);
+                mtu
+            }
+        };
+
+        if mtu as usize != self.tun_device.mtu() {
+            if let Err(e) = self.tun_device.set_mtu(mtu as usize).await {
+                log::warn!("[PMTU] Failed to apply MTU {}: {}", mtu, e);
+                return None;
+            }
+        }
+        Some(mtu)
+    }
+
+    /// Binary-search the largest echo that round-trips with Don't Fragment set
+    async fn probe_path_mtu(&self, probe: &mut MtuProbe, ceiling: u16) -> Option<u16> {
+        if !self.wait_for_handshake(&probe.public_key).await {
+            log::warn!("[PMTU] No handshake with {}, skipping probe", probe.endpoint);
+            return None;
+        }
+        if let Err(e) = pmtu::set_dont_fragment(&self.socket.inner, true) {
+            log::warn!("[PMTU] Can't set Don't Fragment, skipping probe: {}", e);
+            return None;
+        }
+
+        log::info!("[PMTU] Probing {} via {} for an MTU between {} and {}",
+            probe.endpoint, probe.target, MIN_TUNNEL_MTU, ceiling);
+
+        // Most paths carry the full size, so try that before searching
+        let result = if self.probe_size(probe, ceiling).await {
+            Some(ceiling)
+        } else if !self.probe_size(probe, MIN_TUNNEL_MTU).await {
+            log::warn!("[PMTU] No reply from {} even at {} bytes, keeping the current MTU",
+                probe.target, MIN_TUNNEL_MTU);
+            None
+        } else {
+            let mut search = MtuSearch::new(MIN_TUNNEL_MTU, ceiling);
+            while let Some(size) = search.next() {
+                let got_through = self.probe_size(probe, size).await;
+                search.record(size, got_through);
+            }
+            Some(search.result())
+        };
+
+        pmtu::cancel_reply();
+        if let Err(e) = pmtu::set_dont_fragment(&self.socket.inner, false) {
+            log::warn!("[PMTU] Failed to clear Don't Fragment: {}", e);
+        }
+        if let Some(mtu) = result {
+            log::info!("[PMTU] Path MTU to {}: {}", probe.endpoint, mtu);
+        }
+        result
+    }
+
+    /// Send a `size`-byte echo through the tunnel and wait for the reply
+    async fn probe_size(&self, probe: &mut MtuProbe, size: u16) -> bool {
+        for _ in 0..pmtu::PROBE_ATTEMPTS {
+            probe.seq = probe.seq.wrapping_add(1);
+            let packet = pmtu::echo_request(self.config.address, probe.target, probe.ident, probe.seq, size);
+            let reply = pmtu::expect_reply(probe.ident, probe.seq);
+
+            let data = {
+                let Some(mut peer_state) = self.peers.get_mut(&probe.public_key) else {
+                    return false;
+                };
+                let mut dst = [0u8; 2048];
+                match peer_state.tunnel.encapsulate(&packet, &mut dst) {
+                    TunnResult::WriteToNetwork(data) => {
+                        peer_state.on_packet_sent(data);
+                        data.to_vec()
+                    }
+                    _ => return false,
+                }
+            };
+
+            // Fails outright (EMSGSIZE) when bigger than the local interface allows
+            if let Err(e) = self.socket.send_to(&data, probe.endpoint).await {
+                log::debug!("[PMTU] {}-byte probe not sent: {}", size, e);
+                return false;
+            }
+            if let Ok(Ok(())) = tokio::time::timeout(pmtu::PROBE_TIMEOUT, reply).await {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Send `count` ICMP echoes to `target` through the tunnel and time the replies.
+    /// They go through encapsulate, the peer (or relay) and decapsulate like any
+    /// other packet, so a reply proves the whole data path, not just the OS stack.
+    pub async fn echo(&self, target: Ipv4Addr, count: u16) -> Result<EchoResult, String> {
+        if self.paused.load(AtomicOrdering::SeqCst) {
+            return Err("Tunnel is paused".to_string());
+        }
+        if target == self.config.address {
+            return Err(format!("{} is this device's own tunnel address", target));
+        }
+        let (public_key, endpoint) = self.peer_for(target)
+            .ok_or_else(|| format!("{} is not routed through the tunnel", target))?;
+        if !self.wait_for_handshake(&public_key).await {
+            return Err(format!("No handshake with {}", endpoint));
+        }
+
+        let ident: u16 = rand::random();
+        let mut rtts = Vec::new();
+        for seq in 1..=count {
+            let packet = pmtu::echo_request(self.config.address, target, ident, seq, ECHO_PACKET_LEN);
+            let reply = pmtu::expect_reply(ident, seq);
+
+            let data = {
+                let Some(mut peer_state) = self.peers.get_mut(&public_key) else {
+                    return Err("Peer was removed during the test".to_string());
+                };
+                let mut dst = [0u8; 2048];
+                match peer_state.tunnel.encapsulate(&packet, &mut dst) {
+                    TunnResult::WriteToNetwork(data) => {
+                        peer_state.on_packet_sent(data);
+                        data.to_vec()
+                    }
+                    _ => continue,
+                }
+            };
+
+            let sent_at = Instant::now();
+            if let Err(e) = self.socket.send_to(&data, endpoint).await {
+                log::warn!("[WG] Echo to {} not sent: {}", target, e);
+                continue;
+            }
+            if let Ok(Ok(())) = tokio::time::timeout(ECHO_TIMEOUT, reply).await {
+                rtts.push(sent_at.elapsed());
+            }
+        }
+        pmtu::cancel_reply();
+
+        log::info!("[WG] Echo test to {} via {}: {}/{} replies", target, endpoint, rtts.len(), count);
+        Ok(EchoResult { public_key, endpoint, sent: count as u32, rtts })
+    }
+
+    /// Peer whose AllowedIPs most specifically cover `target`. With the default
+    /// route through the tunnel anything else goes where the data path sends it.
+    fn peer_for(&self, target: Ipv4Addr) -> Option<([u8; 32], SocketAddr)> {
+        let covers = |(addr, prefix): &(Ipv4Addr, u8)| {
+            let Some(shift) = 32u32.checked_sub(*prefix as u32) else { return false };
+            let mask = u32::MAX.checked_shl(shift).unwrap_or(0);
+            u32::from(target) & mask == u32::from(*addr) & mask
+        };
+        let best = self.peers.iter()
+            .filter_map(|entry| {
+                let endpoint = entry.value().endpoint?;
+                let prefix = entry.value().allowed_ips.iter().filter(|net| covers(net)).map(|(_, prefix)| *prefix).max()?;
+                Some((prefix, *entry.key(), endpoint))
+            })
+            .max_by_key(|(prefix, _, _)| *prefix)
+            .map(|(_, public_key, endpoint)| (public_key, endpoint));
+
+        best.or_else(|| {
+            if !self.default_gateway_set.load(AtomicOrdering::SeqCst) {
+                return None;
+            }
+            self.peers.iter().find_map(|entry| Some((*entry.key(), entry.value().endpoint?)))
+        })
+    }
+
+    /// Wait up to HANDSHAKE_TIMEOUT for the first handshake with a peer
+    async fn wait_for_handshake(&self, public_key: &[u8; 32]) -> bool {
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            let completed = self.peers.get(public_key)
+                .is_some_and(|peer_state| peer_state.handshakes_completed > 0);
+            if completed {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Peers that haven't been marked dead by keepalive_loop
+    pub fn live_peer_count(&self) -> usize {
+        self.peers.iter().filter(|entry| !entry.value().dead).count()
+    }
+
+    /// Get tunnel statistics
+    pub fn get_stats(&self) -> Vec<(String, u64, u64)> {
+        self.peers.iter().map(|entry| {
+            let key_b64 = base64::engine::general_purpose::STANDARD.encode(entry.key());
+            (key_b64, entry.value().tx_bytes, entry.value().rx_bytes)
+        }).collect()
+    }
+
+    /// Get connection quality metrics aggregated over all peers
+    pub fn quality_metrics(&self) -> QualityMetrics {
+        let mut metrics = QualityMetrics::default();
+
+        for entry in self.peers.iter() {
+            let peer = entry.value();
+            let (_, _, _, _, rtt) = peer.tunnel.stats();
+
+            // Report the slowest peer - that's the one relay selection cares about
+            if let Some(rtt) = rtt {
+                metrics.handshake_rtt_ms = Some(metrics.handshake_rtt_ms.map_or(rtt, |m| m.max(rtt)));
+            }
+            if let Some(rtt) = peer.latency(*entry.key()).average_rtt {
+                let rtt = rtt.as_millis() as u32;
+                metrics.keepalive_rtt_ms = Some(metrics.keepalive_rtt_ms.map_or(rtt, |m| m.max(rtt)));
+            }
+            metrics.handshake_attempts += peer.handshake_attempts;
+            metrics.handshakes_completed += peer.handshakes_completed;
+        }
+
+        metrics
+    }
+
+    /// Traffic since the tunnel started, split by protocol
+    pub fn protocol_stats(&self) -> ProtocolStats {
+        self.protocol_counters.snapshot()
+    }
+
+    /// Datagrams on the listen port dropped for not being WireGuard messages
+    pub fn rejected_datagrams(&self) -> u64 {
+        self.protocol_counters.rejected.load(AtomicOrdering::Relaxed)
+    }
+
+    /// Time since the most recent completed handshake with any peer
+    pub fn last_handshake_age(&self) -> Option<Duration> {
+        self.peers.iter()
+            .filter_map(|entry| entry.value().last_handshake.map(|at| at.elapsed()))
+            .min()
+    }
+
+    /// Get handshake latency for every peer
+    pub fn peer_latencies(&self) -> Vec<PeerLatency> {
+        self.peers.iter()
+            .map(|entry| entry.value().latency(*entry.key()))
+            .collect()
+    }
+
+    /// Whether `config` can be applied by swapping peers alone: same private key
+    /// and everything can_adopt_peers() checks
+    pub fn can_adopt(&self, config: &WgConfig) -> bool {
+        self.config.private_key == config.private_key && self.can_adopt_peers(config)
+    }
+
+    /// Whether `config`'s peers can be swapped in once it uses this tunnel's
+    /// private key: same DNS servers, a TUN device that fits its addresses and
+    /// no exit node on either side - exit-node routing is only set up on connect
+    pub fn can_adopt_peers(&self, config: &WgConfig) -> bool {
+        self.config.dns == config.dns
+            && device_fits(&self.tun_device, config)
+            && !self.default_gateway_set.load(AtomicOrdering::SeqCst)
+            && !config.peers.iter().any(|peer| takes_default_route(&peer.allowed_ips))
+    }
+
+    /// Private key the tunnel runs with
+    pub fn private_key(&self) -> &SecretKey {
+        &self.config.private_key
+    }
+
+    /// Get a snapshot of all active peers
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        self.peers.iter()
+            .map(|entry| {
+                let peer = entry.value();
+                PeerInfo {
+                    public_key: *entry.key(),
+                    endpoint: peer.endpoint,
+                    last_handshake_age: peer.last_handshake.map(|at| at.elapsed()),
+                    direct: peer.is_direct(),
+                    responding: !peer.dead,
+                }
+            })
+            .collect()
+    }
+
+    /// Add a peer to the running tunnel without tearing it down
+    pub async fn add_peer(&self, peer: WgPeer) -> Result<(), String> {
+        if self.peers.contains_key(&peer.public_key) {
+            return Err("Peer already exists".to_string());
+        }
+        // Exit-node routing (default gateway, relay exclusion) is only set up on connect,
+        // and routed_ips skips /0, so such a peer would silently carry nothing
+        if takes_default_route(&peer.allowed_ips) {
+            return Err("A peer taking 0.0.0.0/0 can't be added to a running tunnel - reconnect with it in the config instead".to_string());
+        }
+
+        let mut tunnel = Self::create_peer_tunnel(&self.private_key, &peer)?;
+
+        // Kick off the handshake straight away if we know where the peer is
+        let mut handshake: Option<Vec<u8>> = None;
+        if peer.endpoint.is_some() {
+            let mut dst = [0u8; 2048];
+            if let TunnResult::WriteToNetwork(data) = tunnel.format_handshake_initiation(&mut dst, false) {
+                handshake = Some(data.to_vec());
+            }
+        }
+
+        let mut state = PeerState::new(tunnel, &peer, self.config.power_profile.timings());
+        if let Some(data) = &handshake {
+            state.on_packet_sent(data);
+        }
+        self.peers.insert(peer.public_key, state);
+        self.peer_configs.write().push(peer.clone());
+
+        log::info!("Added peer {} (endpoint={:?}, allowed_ips={:?})",
+            base64::engine::general_purpose::STANDARD.encode(peer.public_key), peer.endpoint, peer.allowed_ips);
+
+        for (addr, prefix) in routed_ips(&peer.allowed_ips) {
+            if let Err(e) = self.tun_device.add_route(addr, prefix).await {
+                log::warn!("Failed to add route {}/{}: {}", addr, prefix, e);
+            }
+        }
+        if self.tun_device.has_ipv6() {
+            for (addr, prefix) in &peer.allowed_ips_v6 {
+                if let Err(e) = self.tun_device.add_route_v6(*addr, *prefix).await {
+                    log::warn!("Failed to add route {}/{}: {}", addr, prefix, e);
+                }
+            }
+        }
+
+        if let (Some(data), Some(endpoint)) = (handshake, peer.endpoint) {
+            if let Err(e) = self.socket.send_to(&data, endpoint).await {
+                log::warn!("Failed to send handshake to {:?}: {}", endpoint, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove a peer from the running tunnel, dropping its session and the
+    /// routes no other peer takes
+    pub async fn remove_peer(&self, public_key: &[u8; 32]) -> Result<(), String> {
+        let (_, state) = self.peers.remove(public_key)
+            .ok_or_else(|| "Peer not found".to_string())?;
+        self.peer_configs.write().retain(|peer| peer.public_key != *public_key);
+
+        log::info!("Removed peer {}", base64::engine::general_purpose::STANDARD.encode(public_key));
+
+        self.remove_unshared_routes(&state.allowed_ips, &state.allowed_ips_v6).await;
+        Ok(())
+    }
+
+    /// Change the addresses a peer takes without touching its session: routes for
+    /// new ranges are added, dropped ones removed unless another peer has them.
+    /// Returns false if the peer already has exactly these.
+    pub async fn update_peer_allowed_ips(
+        &self,
+        public_key: &[u8; 32],
+        allowed_ips: Vec<(Ipv4Addr, u8)>,
+        allowed_ips_v6: Vec<(Ipv6Addr, u8)>,
+    ) -> Result<bool, String> {
+        let (old, old_v6) = {
+            let mut peer = self.peers.get_mut(public_key)
+                .ok_or_else(|| "Peer not found".to_string())?;
+            if peer.allowed_ips == allowed_ips && peer.allowed_ips_v6 == allowed_ips_v6 {
+                return Ok(false);
+            }
+            // Same as add_peer: exit-node routing is only set up on connect
+            if takes_default_route(&allowed_ips) && !takes_default_route(&peer.allowed_ips) {
+                return Err("A running peer can't start taking 0.0.0.0/0 - reconnect instead".to_string());
+            }
+            (
+                std::mem::replace(&mut peer.allowed_ips, allowed_ips.clone()),
+                std::mem::replace(&mut peer.allowed_ips_v6, allowed_ips_v6.clone()),
+            )
+        };
+        if let Some(peer) = self.peer_configs.write().iter_mut().find(|peer| peer.public_key == *public_key) {
+            peer.allowed_ips = allowed_ips.clone();
+            peer.allowed_ips_v6 = allowed_ips_v6.clone();
+        }
+
+        log::info!("Updated allowed IPs of peer {} to {:?}",
+            base64::engine::general_purpose::STANDARD.encode(public_key), allowed_ips);
+
+        for (addr, prefix) in routed_ips(&allowed_ips).filter(|route| !old.contains(route)) {
+            if let Err(e) = self.tun_device.add_route(addr, prefix).await {
+                log::warn!("Failed to add route {}/{}: {}", addr, prefix, e);
+            }
+        }
+        if self.tun_device.has_ipv6() {
+            for &(addr, prefix) in allowed_ips_v6.iter().filter(|route| !old_v6.contains(route)) {
+                if let Err(e) = self.tun_device.add_route_v6(addr, prefix).await {
+                    log::warn!("Failed to add route {}/{}: {}", addr, prefix, e);
+                }
+            }
+        }
+
+        let dropped: Vec<_> = old.into_iter().filter(|route| !allowed_ips.contains(route)).collect();
+        let dropped_v6: Vec<_> = old_v6.into_iter().filter(|route| !allowed_ips_v6.contains(route)).collect();
+        self.remove_unshared_routes(&dropped, &dropped_v6).await;
+        Ok(true)
+    }
+
+    /// Remove these routes from the TUN device, except those a peer still has in
+    /// its AllowedIPs - a subnet shared by several peers stays routed
+    async fn remove_unshared_routes(&self, allowed_ips: &[(Ipv4Addr, u8)], allowed_ips_v6: &[(Ipv6Addr, u8)]) {
+        let in_use: HashSet<_> = self.peers.iter().flat_map(|peer| peer.allowed_ips.clone()).collect();
+        let in_use_v6: HashSet<_> = self.peers.iter().flat_map(|peer| peer.allowed_ips_v6.clone()).collect();
+
+        for (addr, prefix) in routed_ips(allowed_ips).filter(|route| !in_use.contains(route)) {
+            if let Err(e) = self.tun_device.remove_route(addr, prefix).await {
+                log::warn!("Failed to remove route {}/{}: {}", addr, prefix, e);
+            }
+        }
+        if self.tun_device.has_ipv6() {
+            for &(addr, prefix) in allowed_ips_v6.iter().filter(|route| !in_use_v6.contains(route)) {
+                if let Err(e) = self.tun_device.remove_route_v6(addr, prefix).await {
+                    log::warn!("Failed to remove route {}/{}: {}", addr, prefix, e);
+                }
+            }
+        }
+    }
+
+    /// Switch a peer to a new preshared key (None removes it) without touching its
+    /// endpoint, routes or counters. Sessions are derived from the PSK, so the
+    /// peer's Tunn is replaced and re-handshakes right away; outgoing packets
+    /// queue in the new Tunn until then, and anything still arriving under the
+    /// old session fails to decrypt and is dropped. Returns false if the peer
+    /// already uses this key.
+    pub async fn update_peer_psk(&self, public_key: &[u8; 32], preshared_key: Option<SecretKey>) -> Result<bool, String> {
+        let handshake = {
+            let mut peer = self.peers.get_mut(public_key)
+                .ok_or_else(|| "Peer not found".to_string())?;
+            if peer.preshared_key == preshared_key {
+                return Ok(false);
+            }
+
+            peer.tunnel = Self::new_peer_tunn(&self.private_key, *public_key, preshared_key.as_ref())?;
+            if let Some(config) = self.peer_configs.write().iter_mut().find(|config| config.public_key == *public_key) {
+                config.preshared_key = preshared_key.clone();
+            }
+            peer.preshared_key = preshared_key;
+            peer.last_handshake = None;
+            peer.handshake_sent_at = None;
+
+            let mut dst = [0u8; 2048];
+            match (peer.endpoint, peer.tunnel.format_handshake_initiation(&mut dst, false)) {
+                (Some(endpoint), TunnResult::WriteToNetwork(data)) => {
+                    let data = data.to_vec();
+                    peer.on_packet_sent(&data);
+                    Some((data, endpoint))
+                }
+                _ => None,
+            }
+        };
+
+        log::info!("Updated preshared key of peer {}", base64::engine::general_purpose::STANDARD.encode(public_key));
+        if let Some((data, endpoint)) = handshake {
+            self.socket.send_to(&data, endpoint).await
+                .map_err(|e| format!("Failed to send handshake to {}: {}", endpoint, e))?;
+        }
+        Ok(true)
+    }
+
+    /// Update peer endpoint (for NAT traversal). Returns false if there's no such peer.
+    pub fn update_peer_endpoint(&self, public_key: &[u8; 32], endpoint: SocketAddr) -> bool {
+        let Some(mut peer) = self.peers.get_mut(public_key) else {
+            return false;
+        };
+        if self.config.relay_only && peer.configured_endpoint != Some(endpoint) {
+            log::info!("Relay-only mode, ignoring direct endpoint {}", endpoint);
+            return true;
+        }
+        log::info!("Updating peer endpoint: {:?} -> {}", public_key, endpoint);
+        peer.endpoint = Some(endpoint);
+
+        if peer.configured_endpoint == Some(endpoint) {
+            peer.direct_endpoint_set_at = None;
+            return true;
+        }
+
+        // The direct path only counts once a handshake completes over it
+        peer.direct_endpoint_set_at = Some(Instant::now());
+        peer.direct_verified = false;
+
+        let mut dst = [0u8; 2048];
+        if let TunnResult::WriteToNetwork(data) = peer.tunnel.format_handshake_initiation(&mut dst, true) {
+            peer.on_packet_sent(data);
+            if let Err(e) = self.socket.try_send_to(data, endpoint) {
+                log::warn!("Failed to send handshake to direct endpoint {}: {}", endpoint, e);
+            }
+        }
+        true
+    }
+
+    /// After a network change: drop direct paths (their NAT mappings are gone) and
+    /// re-handshake every peer over its configured endpoint right away
+    pub fn refresh_paths(&self) {
+        for mut entry in self.peers.iter_mut() {
+            let peer = entry.value_mut();
+            peer.endpoint = peer.configured_endpoint;
+            peer.direct_endpoint_set_at = None;
+            peer.direct_verified = false;
+
+            let Some(endpoint) = peer.endpoint else {
+                continue;
+            };
+            let mut dst = [0u8; 2048];
+            if let TunnResult::WriteToNetwork(data) = peer.tunnel.format_handshake_initiation(&mut dst, true) {
+                peer.on_packet_sent(data);
+                if let Err(e) = self.socket.try_send_to(data, endpoint) {
+                    log::warn!("Failed to send handshake to {}: {}", endpoint, e);
+                }
+            }
+        }
+    }
+
+    /// "direct" if any peer has a live, verified direct path, otherwise "relay"
+    pub fn connection_type(&self) -> &'static str {
+        if self.peers.iter().any(|entry| entry.value().is_direct()) {
+            "direct"
+        } else {
+            "relay"
+        }
+    }
+
+    /// Point peers whose direct path went quiet back at their configured relay endpoint
+    pub fn expire_direct_paths(&self) {
+        for mut entry in self.peers.iter_mut() {
+            let peer = entry.value_mut();
+            if !peer.direct_path_expired() {
+                continue;
+            }
+
+            if peer.direct_verified {
+                log::warn!("Direct path to {:?} is dead, falling back to relay {:?}",
+                    peer.endpoint, peer.configured_endpoint);
+            } else {
+                log::warn!("No handshake over direct endpoint {:?} within {:?}, falling back to relay {:?}",
+                    peer.endpoint, HANDSHAKE_TIMEOUT, peer.configured_endpoint);
+            }
+            peer.endpoint = peer.configured_endpoint;
+            peer.direct_endpoint_set_at = None;
+            peer.direct_verified = false;
+
+            // Re-handshake over the relay right away instead of waiting for the timers
+            let Some(relay) = peer.configured_endpoint else {
+                continue;
+            };
+            let mut dst = [0u8; 2048];
+            if let TunnResult::WriteToNetwork(data) = peer.tunnel.format_handshake_initiation(&mut dst, true) {
+                peer.on_packet_sent(data);
+                if let Err(e) = self.socket.try_send_to(data, relay) {
+                    log::warn!("Failed to send handshake to relay {}: {}", relay, e);
+                }
+            }
+        }
+    }
+
+    /// Set default gateway to route all traffic through VPN
+    pub async fn set_default_gateway(&self) -> Result<(), String> {
+        use std::sync::atomic::Ordering;
+
+        log::info!("Setting default gateway through VPN tunnel");
 
         // Get the relay endpoint IP to exclude from VPN routing (prevents routing loop)
-        let exclude_ip = self.config.peers.first()
-            .and_then(|peer| peer.endpoint)
-            .map(|endpoint| endpoint.ip().to_string());
+        let exclude_ip = self.relay_endpoint_ip().map(|ip| ip.to_string());
+
+        if let Some(ref ip) = exclude_ip {
+            log::info!("Excluding relay endpoint {} from VPN routing", ip);
+        }
+
+        self.tun_device.set_default_gateway(exclude_ip.as_deref()).await?;
+        self.default_gateway_set.store(true, Ordering::SeqCst);
+
+        // Apps prefer native IPv6 when the host has it, which skips an IPv4-only tunnel
+        if self.config.block_ipv6_leaks {
+            if self.carries_ipv6() {
+                log::info!("Tunnel carries IPv6, not blocking IPv6 egress");
+            } else {
+                // WireGuard's own IPv6 traffic (v6 relay or peer endpoints) must still get out
+                let endpoints = ipv6_endpoints(self.peers.iter()
+                    .flat_map(|peer| [peer.endpoint, peer.configured_endpoint])
+                    .flatten());
+                match self.tun_device.block_ipv6_leaks(&endpoints, self.listen_port()).await {
+                    Ok(()) => self.ipv6_block_set.store(true, Ordering::SeqCst),
+                    Err(e) => log::warn!("Failed to block IPv6 leaks: {}", e),
+                }
+            }
+        }
+
+        if self.config.dns.is_empty() {
+            log::info!("No tunnel DNS configured, skipping DNS setup and leak block");
+            return Ok(());
+        }
+
+        // Resolvers stay on the physical network unless pointed at the tunnel, and
+        // the block below would then drop every query they send
+        if let Err(e) = self.tun_device.set_dns(&self.config.dns).await {
+            log::warn!("Failed to set tunnel DNS, not blocking DNS leaks: {}", e);
+            return Ok(());
+        }
+        self.dns_servers_set.store(true, Ordering::SeqCst);
+
+        // Routes alone don't stop apps that talk to hardcoded resolvers
+        match self.tun_device.block_dns_leaks(&self.config.dns).await {
+            Ok(()) => self.dns_block_set.store(true, Ordering::SeqCst),
+            Err(e) => log::warn!("Failed to block DNS leaks: {}", e),
+        }
+        Ok(())
+    }
+
+    /// Endpoint IP of the peer taking 0.0.0.0/0 if there is one, otherwise the first
+    fn relay_endpoint_ip(&self) -> Option<IpAddr> {
+        let (public_key, endpoint) = {
+            let peers = self.peer_configs.read();
+            let peer = peers.iter().find(|peer| takes_default_route(&peer.allowed_ips)).or(peers.first())?;
+            (peer.public_key, peer.endpoint)
+        };
+        self.peers.get(&public_key)
+            .and_then(|state| state.configured_endpoint)
+            .or(endpoint)
+            .map(|endpoint| endpoint.ip())
+    }
+
+    /// Peers with a hostname endpoint, to look up again after a network change.
+    /// The lookups happen without the tunnel, see apply_resolved_endpoints.
+    pub fn endpoint_hosts(&self) -> Vec<([u8; 32], String)> {
+        self.peer_configs.read().iter()
+            .filter_map(|peer| peer.endpoint_host.clone().map(|host| (peer.public_key, host)))
+            .collect()
+    }
+
+    /// Move peers to the addresses their hostnames resolve to now, so a relay whose
+    /// IP changed is reached at the new one, moving its exclusion from the default
+    /// route along with it
+    pub async fn apply_resolved_endpoints(&self, resolved: &[([u8; 32], SocketAddr)]) {
+        let old_relay_ip = self.relay_endpoint_ip();
+        for (public_key, addr) in resolved {
+            if let Some(mut state) = self.peers.get_mut(public_key) {
+                if state.configured_endpoint != Some(*addr) {
+                    log::info!("Endpoint of peer {} now resolves to {} (was {:?})",
+                        base64::engine::general_purpose::STANDARD.encode(public_key), addr, state.configured_endpoint);
+                    state.configured_endpoint = Some(*addr);
+                }
+            }
+        }
+
+        let relay_ip = self.relay_endpoint_ip();
+        if relay_ip != old_relay_ip && self.default_gateway_set.load(AtomicOrdering::SeqCst) {
+            log::info!("Relay endpoint moved to {:?}, excluding it from VPN routing instead", relay_ip);
+            let exclude_ip = relay_ip.map(|ip| ip.to_string());
+            let moved = async {
+                self.tun_device.clear_default_gateway().await?;
+                self.tun_device.set_default_gateway(exclude_ip.as_deref()).await
+            };
+            if let Err(e) = moved.await {
+                log::warn!("Failed to move the relay route exclusion: {}", e);
+            }
+        }
+    }
+
+    /// Route all traffic through the tunnel without the DNS and IPv6 handling of
+    /// set_default_gateway(), for diagnosing routing. `exclude_ip` defaults to
+    /// the relay endpoint.
+    pub async fn set_default_gateway_manual(&self, exclude_ip: Option<IpAddr>) -> Result<(), String> {
+        let exclude_ip = exclude_ip.or_else(|| self.relay_endpoint_ip());
+        log::info!("Manually setting default gateway through VPN tunnel (excluding {:?})", exclude_ip);
+
+        self.tun_device.set_default_gateway(exclude_ip.map(|ip| ip.to_string()).as_deref()).await?;
+        self.default_gateway_set.store(true, std::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Hand the default route back to the physical interface, whether it was set
+    /// by connect or set_default_gateway_manual()
+    pub async fn restore_default_gateway_manual(&self) -> Result<(), String> {
+        log::info!("Manually restoring the default gateway");
+
+        self.remove_leak_blocks().await;
+        self.tun_device.clear_default_gateway().await?;
+        self.default_gateway_set.store(false, std::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// DNS servers from the config
+    pub fn dns_servers(&self) -> &[Ipv4Addr] {
+        &self.config.dns
+    }
+
+    /// Whether the DNS leak block from set_default_gateway() is in place
+    pub fn dns_leak_block_active(&self) -> bool {
+        self.dns_block_set.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Whether a peer takes 0.0.0.0/0, which asks for exit-node mode (see routed_ips)
+    pub fn is_full_tunnel(&self) -> bool {
+        self.peer_configs.read().iter().any(|peer| takes_default_route(&peer.allowed_ips))
+    }
+
+    /// Whether IPv6 internet traffic can go through the tunnel: the device has an
+    /// IPv6 address and a peer takes ::/0
+    fn carries_ipv6(&self) -> bool {
+        self.tun_device.has_ipv6() && self.peer_configs.read().iter()
+            .any(|peer| peer.allowed_ips_v6.iter().any(|(_, prefix)| *prefix == 0))
+    }
+
+    /// Undo the DNS and IPv6 blocks made by set_default_gateway()
+    async fn remove_leak_blocks(&self) {
+        use std::sync::atomic::Ordering;
+
+        if self.ipv6_block_set.swap(false, Ordering::SeqCst) {
+            if let Err(e) = self.tun_device.unblock_ipv6_leaks().await {
+                log::warn!("Failed to remove IPv6 leak block: {}", e);
+            }
+        }
+
+        if self.dns_block_set.swap(false, Ordering::SeqCst) {
+            if let Err(e) = self.tun_device.unblock_dns_leaks().await {
+                log::warn!("Failed to remove DNS leak block: {}", e);
+            }
+        }
+        if self.dns_servers_set.swap(false, Ordering::SeqCst) {
+            if let Err(e) = self.tun_device.restore_dns().await {
+                log::warn!("Failed to restore DNS: {}", e);
+            }
+        }
+    }
+
+    /// Stop forwarding traffic and hand the default route back to the physical interface
+    /// Peer sessions stay established so resume() doesn't need STUN or new handshakes
+    pub async fn pause(&self) -> Result<(), String> {
+        use std::sync::atomic::Ordering;
+
+        self.paused.store(true, Ordering::SeqCst);
 
-        if let Some(ref ip) = exclude_ip {
-            log::info!("Excluding relay endpoint {} from VPN routing", ip);
+        if self.default_gateway_set.load(Ordering::SeqCst) {
+            self.remove_leak_blocks().await;
+            self.tun_device.clear_default_gateway().await?;
+        }
+
+        log::info!("WireGuard tunnel paused");
+        Ok(())
+    }
+
+    /// Reinstall routes removed by pause() and continue forwarding
+    pub async fn resume(&self) -> Result<(), String> {
+        use std::sync::atomic::Ordering;
+
+        if self.default_gateway_set.load(Ordering::SeqCst) {
+            self.set_default_gateway().await?;
         }
 
-        self.tun_device.set_default_gateway(exclude_ip.as_deref()).await
+        self.paused.store(false, Ordering::SeqCst);
+        log::info!("WireGuard tunnel resumed");
+        Ok(())
+    }
+}
+
+impl Drop for WgTunnel {
+    fn drop(&mut self) {
+        // Make sure packet loops exit even if stop() was never reached
+        // (e.g. the connect future was cancelled mid-way)
+        self.running.store(false, std::sync::atomic::Ordering::SeqCst);
+        self.cancel.cancel();
+    }
+}
+
+/// Native IPv6 addresses among `endpoints`, without duplicates
+fn ipv6_endpoints(endpoints: impl IntoIterator<Item = SocketAddr>) -> Vec<Ipv6Addr> {
+    let mut ips: Vec<Ipv6Addr> = endpoints.into_iter()
+        .filter_map(|endpoint| match stun::canonical_addr(endpoint) {
+            SocketAddr::V6(v6) => Some(*v6.ip()),
+            SocketAddr::V4(_) => None,
+        })
+        .collect();
+    ips.sort_unstable();
+    ips.dedup();
+    ips
+}
+
+/// Wait (up to LOOP_EXIT_TIMEOUT) for cancelled packet loops to return and drop
+/// their handles on the socket
+async fn join_loops(tasks: Vec<tokio::task::JoinHandle<()>>) {
+    let count = tasks.len();
+    if tokio::time::timeout(LOOP_EXIT_TIMEOUT, futures::future::join_all(tasks)).await.is_err() {
+        log::warn!("[WG] Packet loops still running {:?} after stop", LOOP_EXIT_TIMEOUT);
+    } else if count > 0 {
+        log::debug!("[WG] {} packet loops exited", count);
+    }
+}
+
+/// Whether a kept TUN device can carry a tunnel for `config`: same IPv4 address
+/// and netmask, and no IPv6 address other than the one the config wants
+fn device_fits(device: &TunDevice, config: &WgConfig) -> bool {
+    device.address() == config.address
+        && device.netmask() == config.netmask
+        && device.ipv6_address().is_none_or(|v6| Some(v6) == config.address_v6)
+}
+
+/// Generate a random 32-byte preshared key (base64) for a new peer
+pub fn generate_preshared_key() -> String {
+    use rand::RngCore;
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    rand::rngs::OsRng.fill_bytes(key.as_mut());
+    base64::engine::general_purpose::STANDARD.encode(key.as_ref())
+}
+
+/// Generate a fresh WireGuard keypair: the private key and its base64 public key
+pub fn generate_keypair() -> (SecretKey, String) {
+    use rand::RngCore;
+
+    let mut private_key = SecretKey([0u8; 32]);
+    rand::rngs::OsRng.fill_bytes(&mut private_key.0);
+    let public_key = derive_public_key(private_key.as_bytes());
+    (private_key, public_key)
+}
+
+/// Swap the [Interface] PrivateKey of a wg-quick config for `private_key`
+pub fn replace_private_key(config_str: &str, private_key: &SecretKey) -> Zeroizing<String> {
+    let encoded = Zeroizing::new(base64::engine::general_purpose::STANDARD.encode(private_key.as_bytes()));
+    let mut out = Zeroizing::new(String::with_capacity(config_str.len()));
+    let mut in_interface = false;
+
+    for line in config_str.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_interface = trimmed == "[Interface]";
+            out.push_str(line);
+            out.push('\n');
+            if in_interface {
+                out.push_str("PrivateKey = ");
+                out.push_str(&encoded);
+                out.push('\n');
+            }
+            continue;
+        }
+        let is_private_key = trimmed
+            .split_once('=')
+            .is_some_and(|(key, _)| key.trim() == "PrivateKey");
+        if in_interface && is_private_key {
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
     }
+    out
+}
+
+/// Derive the base64-encoded WireGuard public key for a private key
+pub fn derive_public_key(private_key: &[u8; 32]) -> String {
+    let secret = x25519_dalek::StaticSecret::from(*private_key);
+    let public_key = x25519_dalek::PublicKey::from(&secret);
+    base64::engine::general_purpose::STANDARD.encode(public_key.as_bytes())
 }
 
 /// Parse WireGuard config string into WgConfig
@@ -466,8 +2224,10 @@ pub fn parse_wg_config(config_str: &str) -> Result<WgConfig, String> {
     let mut private_key = None;
     let mut address = None;
     let mut netmask = Ipv4Addr::new(255, 255, 255, 0);
-    let mut dns = None;
+    let mut address_v6 = None;
+    let mut dns = Vec::new();
     let mut listen_port = None;
+    let mut mtu = None;
     let mut peers = Vec::new();
     let mut current_peer: Option<WgPeer> = None;
 
@@ -488,7 +2248,9 @@ pub fn parse_wg_config(config_str: &str) -> Result<WgConfig, String> {
             current_peer = Some(WgPeer {
                 public_key: [0u8; 32],
                 endpoint: None,
+                endpoint_host: None,
                 allowed_ips: Vec::new(),
+                allowed_ips_v6: Vec::new(),
                 persistent_keepalive: None,
                 preshared_key: None,
             });
@@ -501,35 +2263,43 @@ pub fn parse_wg_config(config_str: &str) -> Result<WgConfig, String> {
 
             match key {
                 "PrivateKey" => {
-                    let bytes = base64::engine::general_purpose::STANDARD
-                        .decode(value)
-                        .map_err(|e| format!("Invalid private key: {}", e))?;
-                    let arr: [u8; 32] = bytes.try_into()
-                        .map_err(|_| "Private key must be 32 bytes")?;
-                    private_key = Some(arr);
+                    private_key = Some(decode_secret(value, "private key")?);
                 }
                 "Address" => {
-                    // Parse address with optional CIDR
-                    let (addr_str, prefix) = if value.contains('/') {
-                        let parts: Vec<&str> = value.split('/').collect();
-                        (parts[0], parts.get(1).and_then(|p| p.parse::<u8>().ok()))
-                    } else {
-                        (value, None)
-                    };
-                    address = Some(addr_str.parse::<Ipv4Addr>()
-                        .map_err(|e| format!("Invalid address: {}", e))?);
-                    if let Some(prefix) = prefix {
-                        netmask = prefix_to_netmask(prefix);
+                    // One IPv4 and optionally one IPv6 address, each with optional CIDR
+                    for entry in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                        let (addr_str, prefix) = match entry.split_once('/') {
+                            Some((addr, prefix)) => (addr, prefix.parse::<u8>().ok()),
+                            None => (entry, None),
+                        };
+                        if addr_str.contains(':') {
+                            let addr = addr_str.parse::<Ipv6Addr>()
+                                .map_err(|e| format!("Invalid IPv6 address: {}", e))?;
+                            address_v6 = Some((addr, prefix.unwrap_or(128).min(128)));
+                            continue;
+                        }
+                        address = Some(addr_str.parse::<Ipv4Addr>()
+                            .map_err(|e| format!("Invalid address: {}", e))?);
+                        if let Some(prefix) = prefix {
+                            netmask = prefix_to_netmask(prefix);
+                        }
                     }
                 }
                 "DNS" => {
-                    dns = Some(value.parse::<Ipv4Addr>()
-                        .map_err(|e| format!("Invalid DNS: {}", e))?);
+                    for server in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                        dns.push(server.parse::<Ipv4Addr>()
+                            .map_err(|e| format!("Invalid DNS: {}", e))?);
+                    }
                 }
                 "ListenPort" => {
                     listen_port = Some(value.parse::<u16>()
                         .map_err(|e| format!("Invalid listen port: {}", e))?);
                 }
+                "MTU" => {
+                    let value = value.parse::<u16>()
+                        .map_err(|e| format!("Invalid MTU: {}", e))?;
+                    mtu = Some(value.clamp(MIN_TUNNEL_MTU, TUN_MTU as u16));
+                }
                 "PublicKey" => {
                     if let Some(ref mut peer) = current_peer {
                         let bytes = base64::engine::general_purpose::STANDARD
@@ -541,34 +2311,15 @@ pub fn parse_wg_config(config_str: &str) -> Result<WgConfig, String> {
                 }
                 "Endpoint" => {
                     if let Some(ref mut peer) = current_peer {
-                        peer.endpoint = Some(value.parse::<SocketAddr>()
-                            .map_err(|e| format!("Invalid endpoint: {}", e))?);
+                        let (endpoint, host) = parse_endpoint(value)?;
+                        peer.endpoint = endpoint;
+                        peer.endpoint_host = host;
                     }
                 }
                 "AllowedIPs" => {
                     if let Some(ref mut peer) = current_peer {
-                        for ip_range in value.split(',') {
-                            let ip_range = ip_range.trim();
-                            // Skip IPv6 addresses (contain colons)
-                            if ip_range.contains(':') {
-                                continue;
-                            }
-                            let (addr, prefix) = if ip_range.contains('/') {
-                                let parts: Vec<&str> = ip_range.split('/').collect();
-                                let addr = match parts[0].parse::<Ipv4Addr>() {
-                                    Ok(a) => a,
-                                    Err(_) => continue, // Skip invalid addresses
-                                };
-                                let prefix = parts[1].parse::<u8>().unwrap_or(32);
-                                (addr, prefix)
-                            } else {
-                                match ip_range.parse::<Ipv4Addr>() {
-                                    Ok(addr) => (addr, 32),
-                                    Err(_) => continue, // Skip invalid addresses
-                                }
-                            };
-                            peer.allowed_ips.push((addr, prefix));
-                        }
+                        peer.allowed_ips.extend(parse_allowed_ips(value));
+                        peer.allowed_ips_v6.extend(parse_allowed_ips_v6(value));
                     }
                 }
                 "PersistentKeepalive" => {
@@ -578,12 +2329,12 @@ pub fn parse_wg_config(config_str: &str) -> Result<WgConfig, String> {
                     }
                 }
                 "PresharedKey" => {
+                    if current_peer.is_none() {
+                        // PSKs are per-peer; one under [Interface] has nothing to apply to
+                        log::warn!("Ignoring PresharedKey outside a [Peer] section");
+                    }
                     if let Some(ref mut peer) = current_peer {
-                        let bytes = base64::engine::general_purpose::STANDARD
-                            .decode(value)
-                            .map_err(|e| format!("Invalid preshared key: {}", e))?;
-                        peer.preshared_key = Some(bytes.try_into()
-                            .map_err(|_| "Preshared key must be 32 bytes")?);
+                        peer.preshared_key = Some(decode_secret(value, "preshared key")?);
                     }
                 }
                 _ => {}
@@ -599,12 +2350,265 @@ pub fn parse_wg_config(config_str: &str) -> Result<WgConfig, String> {
         private_key: private_key.ok_or("Missing PrivateKey")?,
         address: address.ok_or("Missing Address")?,
         netmask,
+        address_v6,
         dns,
         peers,
         listen_port,
+        mtu,
+        bind_address: None,
+        power_profile: PowerProfile::default(),
+        block_ipv6_leaks: false,
+        relay_only: false,
     })
 }
 
+/// Write a config back out in wg-quick format - the inverse of parse_wg_config.
+/// Without `include_secrets` the private and preshared keys are left out, so the
+/// output is safe to share but has to have them filled in before it can connect.
+pub fn format_wg_config(config: &WgConfig, include_secrets: bool) -> String {
+    use std::fmt::Write as _;
+
+    let encode = |key: &[u8; 32]| base64::engine::general_purpose::STANDARD.encode(key);
+    let join = |items: Vec<String>| items.join(", ");
+    let mut out = String::from("[Interface]\n");
+
+    if include_secrets {
+        let _ = writeln!(out, "PrivateKey = {}", *Zeroizing::new(encode(config.private_key.as_bytes())));
+    } else {
+        out.push_str("# PrivateKey omitted\n");
+    }
+    let prefix = u32::from(config.netmask).count_ones();
+    let mut addresses = vec![format!("{}/{}", config.address, prefix)];
+    if let Some((addr, prefix)) = config.address_v6 {
+        addresses.push(format!("{}/{}", addr, prefix));
+    }
+    let _ = writeln!(out, "Address = {}", join(addresses));
+    if !config.dns.is_empty() {
+        let _ = writeln!(out, "DNS = {}", join(config.dns.iter().map(Ipv4Addr::to_string).collect()));
+    }
+    if let Some(port) = config.listen_port {
+        let _ = writeln!(out, "ListenPort = {}", port);
+    }
+    if let Some(mtu) = config.mtu {
+        let _ = writeln!(out, "MTU = {}", mtu);
+    }
+
+    for peer in &config.peers {
+        let _ = writeln!(out, "\n[Peer]\nPublicKey = {}", encode(&peer.public_key));
+        if let Some(psk) = &peer.preshared_key {
+            if include_secrets {
+                let _ = writeln!(out, "PresharedKey = {}", *Zeroizing::new(encode(psk.as_bytes())));
+            } else {
+                out.push_str("# PresharedKey omitted\n");
+            }
+        }
+        match (&peer.endpoint_host, peer.endpoint) {
+            (Some(host), _) => { let _ = writeln!(out, "Endpoint = {}", host); }
+            (None, Some(endpoint)) => { let _ = writeln!(out, "Endpoint = {}", endpoint); }
+            (None, None) => {}
+        }
+        let allowed_ips: Vec<String> = peer.allowed_ips.iter().map(|(addr, prefix)| format!("{}/{}", addr, prefix))
+            .chain(peer.allowed_ips_v6.iter().map(|(addr, prefix)| format!("{}/{}", addr, prefix)))
+            .collect();
+        if !allowed_ips.is_empty() {
+            let _ = writeln!(out, "AllowedIPs = {}", join(allowed_ips));
+        }
+        if let Some(keepalive) = peer.persistent_keepalive {
+            let _ = writeln!(out, "PersistentKeepalive = {}", keepalive);
+        }
+    }
+
+    out
+}
+
+/// Outcome of checking a WireGuard config without connecting
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct ConfigReport {
+    /// No errors - the config can be used to connect
+    pub valid: bool,
+    /// Problems that will stop the tunnel from working
+    pub errors: Vec<String>,
+    /// Problems that may break some traffic but don't block connecting
+    pub warnings: Vec<String>,
+}
+
+/// Check a WireGuard config for problems that would otherwise only show up at connect time
+pub fn verify_wg_config(config_str: &str) -> ConfigReport {
+    let mut report = ConfigReport::default();
+
+    // Key lengths and encodings are enforced by the parser
+    let config = match parse_wg_config(config_str) {
+        Ok(config) => config,
+        Err(e) => {
+            report.errors.push(e);
+            return report;
+        }
+    };
+
+    let address = config.address;
+    if address.is_unspecified() || address.is_loopback() || address.is_multicast() || address.is_broadcast() {
+        report.errors.push(format!("Address {} can't be used for a tunnel interface", address));
+    } else {
+        let mask = u32::from(config.netmask);
+        let host_bits = u32::from(address) & !mask;
+        // /31 and /32 have no network or broadcast address to collide with
+        if mask.count_ones() < 31 && (host_bits == 0 || host_bits == !mask) {
+            report.errors.push(format!(
+                "Address {} is the network or broadcast address of its /{} subnet",
+                address, mask.count_ones()
+            ));
+        }
+    }
+
+    if config.peers.is_empty() {
+        report.errors.push("Config has no [Peer] section".to_string());
+    }
+    if !config.peers.is_empty() && config.peers.iter().all(|peer| peer.endpoint.is_none() && peer.endpoint_host.is_none()) {
+        report.errors.push("No peer has an Endpoint, so there is nothing to connect to".to_string());
+    }
+
+    let own_key = x25519_dalek::PublicKey::from(&x25519_dalek::StaticSecret::from(*config.private_key.as_bytes())).to_bytes();
+    let mut seen_keys = HashSet::new();
+    for (i, peer) in config.peers.iter().enumerate() {
+        let n = i + 1;
+        if peer.public_key == [0u8; 32] {
+            report.errors.push(format!("Peer {} is missing a PublicKey", n));
+        } else if peer.public_key == own_key {
+            report.errors.push(format!("Peer {} has this device's own public key", n));
+        } else if !seen_keys.insert(peer.public_key) {
+            report.errors.push(format!("Peer {} has the same PublicKey as an earlier peer", n));
+        }
+
+        match peer.endpoint {
+            Some(endpoint) if endpoint.port() == 0 => {
+                report.errors.push(format!("Peer {} Endpoint {} has no port", n, endpoint));
+            }
+            Some(_) => {}
+            // Looked up at connect time
+            None if peer.endpoint_host.is_some() => {}
+            None => report.warnings.push(format!(
+                "Peer {} is missing an Endpoint and is only reachable if it connects first", n
+            )),
+        }
+        if peer.allowed_ips.is_empty() {
+            report.warnings.push(format!("Peer {} has no IPv4 AllowedIPs, so no traffic will be sent to it", n));
+        }
+    }
+
+    report.valid = report.errors.is_empty();
+    report
+}
+
+/// Parse an Endpoint value: an address, or a host:port that comes back without
+/// an address. Hostnames aren't looked up here - parsing must not block - but by
+/// resolve_peer_endpoints before the peers are used.
+pub fn parse_endpoint(value: &str) -> Result<(Option<SocketAddr>, Option<String>), String> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Ok((Some(addr), None));
+    }
+    match value.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && !host.contains(':') && port.parse::<u16>().is_ok() => {
+            Ok((None, Some(value.to_string())))
+        }
+        _ => Err(format!("Invalid endpoint {} - expected host:port", value)),
+    }
+}
+
+/// Look a host:port endpoint up without blocking, falling back to its last known address
+pub async fn resolve_endpoint(host: &str) -> Result<SocketAddr, String> {
+    let looked_up = match tokio::time::timeout(ENDPOINT_RESOLVE_TIMEOUT, tokio::net::lookup_host(host)).await {
+        Ok(Ok(mut addrs)) => addrs.next().ok_or_else(|| "no addresses found".to_string()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("timed out after {:?}", ENDPOINT_RESOLVE_TIMEOUT)),
+    };
+    remember_resolution(host, looked_up)
+}
+
+/// Fill in the endpoint of each peer that has a hostname (possibly with an IPv6 address)
+pub async fn resolve_peer_endpoints(peers: &mut [WgPeer]) -> Result<(), String> {
+    let lookups = peers.iter()
+        .map(|peer| async move {
+            match &peer.endpoint_host {
+                Some(host) => resolve_endpoint(host).await.map(Some),
+                None => Ok(None),
+            }
+        });
+    let resolved = futures::future::join_all(lookups).await;
+    for (peer, addr) in peers.iter_mut().zip(resolved) {
+        if let Some(addr) = addr? {
+            peer.endpoint = Some(addr);
+        }
+    }
+    Ok(())
+}
+
+/// Cache a successful lookup of `host`, or fall back to its last known address
+fn remember_resolution(host: &str, looked_up: Result<SocketAddr, String>) -> Result<SocketAddr, String> {
+    let mut resolved = RESOLVED_ENDPOINTS.lock();
+    match looked_up {
+        Ok(addr) => {
+            resolved.insert(host.to_string(), addr);
+            Ok(addr)
+        }
+        Err(e) => match resolved.get(host) {
+            Some(&addr) => {
+                log::warn!("Could not resolve endpoint host {} ({}), using last known address {}", host, e, addr);
+                Ok(addr)
+            }
+            None => Err(format!("Could not resolve endpoint host {}: {}", host, e)),
+        },
+    }
+}
+
+/// Parse a comma-separated AllowedIPs value into IPv4 (address, prefix_len) pairs
+/// IPv6 and invalid entries are skipped
+pub fn parse_allowed_ips(value: &str) -> Vec<(Ipv4Addr, u8)> {
+    let mut allowed_ips = Vec::new();
+
+    for ip_range in value.split(',') {
+        let ip_range = ip_range.trim();
+        // IPv6 ranges are handled by parse_allowed_ips_v6
+        if ip_range.contains(':') {
+            continue;
+        }
+        let (addr, prefix) = if ip_range.contains('/') {
+            let parts: Vec<&str> = ip_range.split('/').collect();
+            let addr = match parts[0].parse::<Ipv4Addr>() {
+                Ok(a) => a,
+                Err(_) => continue, // Skip invalid addresses
+            };
+            let prefix = parts[1].parse::<u8>().unwrap_or(32);
+            if prefix > 32 {
+                continue; // Skip out-of-range prefixes
+            }
+            (addr, prefix)
+        } else {
+            match ip_range.parse::<Ipv4Addr>() {
+                Ok(addr) => (addr, 32),
+                Err(_) => continue, // Skip invalid addresses
+            }
+        };
+        allowed_ips.push((addr, prefix));
+    }
+
+    allowed_ips
+}
+
+/// IPv6 counterpart of parse_allowed_ips - IPv4 entries are skipped
+pub fn parse_allowed_ips_v6(value: &str) -> Vec<(Ipv6Addr, u8)> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|ip_range| ip_range.contains(':'))
+        .filter_map(|ip_range| {
+            let (addr, prefix) = match ip_range.split_once('/') {
+                Some((addr, prefix)) => (addr, prefix.parse::<u8>().ok()?),
+                None => (ip_range, 128),
+            };
+            Some((addr.parse::<Ipv6Addr>().ok()?, prefix.min(128)))
+        })
+        .collect()
+}
+
 fn prefix_to_netmask(prefix: u8) -> Ipv4Addr {
     let mask: u32 = if prefix == 0 {
         0
@@ -615,3 +2619,661 @@ fn prefix_to_netmask(prefix: u8) -> Ipv4Addr {
     };
     Ipv4Addr::from(mask.to_be_bytes())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_allowed_ips() {
+        let ips = parse_allowed_ips("10.100.0.0/24, 10.100.1.5, fd00::/64, bogus/8, 10.0.0.0/40");
+        assert_eq!(ips, vec![
+            (Ipv4Addr::new(10, 100, 0, 0), 24),
+            (Ipv4Addr::new(10, 100, 1, 5), 32),
+        ]);
+
+        let ips = parse_allowed_ips_v6("10.100.0.0/24, fd00::/64, 2001:db8::1, ::/0, fd00::/x");
+        assert_eq!(ips, vec![
+            ("fd00::".parse().unwrap(), 64),
+            ("2001:db8::1".parse().unwrap(), 128),
+            (Ipv6Addr::UNSPECIFIED, 0),
+        ]);
+    }
+
+    #[test]
+    fn test_default_route_not_routed() {
+        let ips = parse_allowed_ips("0.0.0.0/0, 10.100.0.0/24");
+        assert_eq!(routed_ips(&ips).collect::<Vec<_>>(), vec![(Ipv4Addr::new(10, 100, 0, 0), 24)]);
+
+        // add_peer refuses these rather than adding a peer with no route
+        assert!(takes_default_route(&ips));
+        assert!(!takes_default_route(&parse_allowed_ips("10.100.0.0/24, ::/0")));
+    }
+
+    #[test]
+    fn test_parse_dual_stack_address() {
+        let key = base64::engine::general_purpose::STANDARD.encode([7u8; 32]);
+        let config = parse_wg_config(&format!(
+            "[Interface]\nPrivateKey = {}\nAddress = 10.100.0.2/16, fd00:100::2/64\n", key,
+        )).unwrap();
+        assert_eq!(config.address, Ipv4Addr::new(10, 100, 0, 2));
+        assert_eq!(config.netmask, Ipv4Addr::new(255, 255, 0, 0));
+        assert_eq!(config.address_v6, Some(("fd00:100::2".parse().unwrap(), 64)));
+    }
+
+    /// Run one handshake between two peers and report whether it completed
+    fn handshake_completes(initiator_psk: Option<[u8; 32]>, responder_psk: Option<[u8; 32]>) -> bool {
+        let initiator_key = x25519_dalek::StaticSecret::from([1u8; 32]);
+        let responder_key = x25519_dalek::StaticSecret::from([2u8; 32]);
+        let peer = |key: &x25519_dalek::StaticSecret, psk: Option<[u8; 32]>| WgPeer {
+            public_key: x25519_dalek::PublicKey::from(key).to_bytes(),
+            endpoint: None,
+            endpoint_host: None,
+            allowed_ips: Vec::new(),
+            allowed_ips_v6: Vec::new(),
+            persistent_keepalive: None,
+            preshared_key: psk.map(SecretKey::from),
+        };
+
+        let mut initiator = WgTunnel::create_peer_tunnel(&initiator_key, &peer(&responder_key, initiator_psk)).unwrap();
+        let mut responder = WgTunnel::create_peer_tunnel(&responder_key, &peer(&initiator_key, responder_psk)).unwrap();
+
+        let mut buf = [0u8; 2048];
+        let init = match initiator.format_handshake_initiation(&mut buf, false) {
+            TunnResult::WriteToNetwork(data) => data.to_vec(),
+            _ => panic!("no handshake initiation"),
+        };
+
+        let mut buf = [0u8; 2048];
+        let response = match responder.decapsulate(None, &init, &mut buf) {
+            TunnResult::WriteToNetwork(data) => data.to_vec(),
+            _ => return false,
+        };
+
+        let mut buf = [0u8; 2048];
+        matches!(initiator.decapsulate(None, &response, &mut buf),
+            TunnResult::WriteToNetwork(_) | TunnResult::Done)
+    }
+
+    #[test]
+    fn test_format_wg_config_round_trip() {
+        let encode = |key: [u8; 32]| base64::engine::general_purpose::STANDARD.encode(key);
+        let original = format!(
+            "[Interface]\nPrivateKey = {}\nAddress = 10.100.0.2/16, fd00:100::2/64\nDNS = 1.1.1.1, 9.9.9.9\nListenPort = 51820\nMTU = 1380\n\n\
+             [Peer]\nPublicKey = {}\nPresharedKey = {}\nEndpoint = 203.0.113.5:51820\nAllowedIPs = 10.100.0.0/16, fd00:100::/64\nPersistentKeepalive = 25\n\n\
+             [Peer]\nPublicKey = {}\nEndpoint = [2001:db8::1]:51820\nAllowedIPs = 0.0.0.0/0\n",
+            encode([7; 32]), encode([8; 32]), encode([9; 32]), encode([10; 32]),
+        );
+        let config = parse_wg_config(&original).unwrap();
+
+        let exported = format_wg_config(&config, true);
+        assert_eq!(exported, original);
+        let reparsed = parse_wg_config(&exported).unwrap();
+        assert_eq!(format!("{:?}", reparsed), format!("{:?}", config));
+
+        // Without secrets the keys are gone but everything else survives
+        let shared = format_wg_config(&config, false);
+        assert!(!shared.contains(&encode([7; 32])) && !shared.contains(&encode([9; 32])));
+        assert!(shared.contains("# PrivateKey omitted") && shared.contains(&encode([8; 32])));
+        assert!(parse_wg_config(&shared).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hostname_endpoints() {
+        let literal: SocketAddr = "203.0.113.5:51820".parse().unwrap();
+        assert_eq!(parse_endpoint("203.0.113.5:51820").unwrap(), (Some(literal), None));
+        assert_eq!(parse_endpoint("localhost:51820").unwrap(), (None, Some("localhost:51820".to_string())));
+        assert!(parse_endpoint("localhost").is_err());
+        assert!(parse_endpoint("fd00::1:51820x").is_err());
+
+        let addr = resolve_endpoint("localhost:51820").await.unwrap();
+        assert!(addr.ip().is_loopback() && addr.port() == 51820);
+
+        // A failed lookup falls back to the last address the host resolved to
+        let host = "relay.test-cache.invalid:51820";
+        let err = remember_resolution(host, Err("no such host".to_string())).unwrap_err();
+        assert_eq!(err, "Could not resolve endpoint host relay.test-cache.invalid:51820: no such host");
+        assert_eq!(remember_resolution(host, Ok(literal)), Ok(literal));
+        assert_eq!(remember_resolution(host, Err("no such host".to_string())), Ok(literal));
+
+        // Exports keep the hostname so the config still follows the relay
+        let encode = |key: [u8; 32]| base64::engine::general_purpose::STANDARD.encode(key);
+        let config = format!(
+            "[Interface]\nPrivateKey = {}\nAddress = 10.100.0.2/16\n\n[Peer]\nPublicKey = {}\nEndpoint = localhost:51820\nAllowedIPs = 10.100.0.0/16\n",
+            encode([7; 32]), encode([8; 32]),
+        );
+        let exported = format_wg_config(&parse_wg_config(&config).unwrap(), true);
+        assert!(exported.contains("Endpoint = localhost:51820"));
+    }
+
+    #[test]
+    fn test_check_listen_port() {
+        assert!(matches!(check_listen_port(None, 80), Err(PleError::Parse(_))));
+
+        let taken = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let local = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert!(matches!(check_listen_port(local, port), Err(PleError::PortConflict(_))));
+        drop(taken);
+        assert!(check_listen_port(local, port).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_device_fits() {
+        let config = |address: &str| {
+            let key = base64::engine::general_purpose::STANDARD.encode([7u8; 32]);
+            parse_wg_config(&format!("[Interface]\nPrivateKey = {}\nAddress = {}\n", key, address)).unwrap()
+        };
+        let device = TunDevice::create(TUN_NAME, Ipv4Addr::new(10, 100, 0, 2), Ipv4Addr::new(255, 255, 0, 0)).await.unwrap();
+
+        assert!(device_fits(&device, &config("10.100.0.2/16")));
+        assert!(device_fits(&device, &config("10.100.0.2/16, fd00:100::2/64")));
+        assert!(!device_fits(&device, &config("10.100.0.3/16")));
+        assert!(!device_fits(&device, &config("10.100.0.2/24")));
+
+        device.set_ipv6_address("fd00:100::2".parse().unwrap(), 64).await.unwrap();
+        assert!(device_fits(&device, &config("10.100.0.2/16, fd00:100::2/64")));
+        assert!(!device_fits(&device, &config("10.100.0.2/16")));
+        assert!(!device_fits(&device, &config("10.100.0.2/16, fd00:100::3/64")));
+    }
+
+    /// Relay-only tunnel on a loopback device, with no peers yet
+    async fn idle_tunnel() -> WgTunnel {
+        let key = base64::engine::general_purpose::STANDARD.encode([7u8; 32]);
+        let mut config = parse_wg_config(&format!("[Interface]\nPrivateKey = {}\nAddress = 10.100.0.2/16\n", key)).unwrap();
+        config.bind_address = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        config.listen_port = Some(0);
+        config.relay_only = true;
+        WgTunnel::new(config, None).await.unwrap()
+    }
+
+    fn routed_peer(key: u8, allowed_ips: &str) -> WgPeer {
+        WgPeer {
+            public_key: x25519_dalek::PublicKey::from(&x25519_dalek::StaticSecret::from([key; 32])).to_bytes(),
+            endpoint: None,
+            endpoint_host: None,
+            allowed_ips: parse_allowed_ips(allowed_ips),
+            allowed_ips_v6: Vec::new(),
+            persistent_keepalive: None,
+            preshared_key: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remove_peer_keeps_shared_routes() {
+        let tunnel = idle_tunnel().await;
+        let (a, b) = (routed_peer(8, "10.100.0.0/24, 10.1.0.0/16"), routed_peer(9, "10.100.0.0/24"));
+        let (key_a, key_b) = (a.public_key, b.public_key);
+        tunnel.add_peer(a).await.unwrap();
+        tunnel.add_peer(b).await.unwrap();
+
+        tunnel.remove_peer(&key_a).await.unwrap();
+        assert_eq!(tunnel.tun_device.loopback_routes(), vec![(Ipv4Addr::new(10, 100, 0, 0), 24)]);
+        tunnel.remove_peer(&key_b).await.unwrap();
+        assert!(tunnel.tun_device.loopback_routes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_wg_quick_export_follows_peer_changes() {
+        let tunnel = idle_tunnel().await;
+        let peer = routed_peer(8, "10.1.0.0/16");
+        let key = peer.public_key;
+        tunnel.add_peer(peer).await.unwrap();
+        tunnel.update_peer_allowed_ips(&key, parse_allowed_ips("10.2.0.0/16"), Vec::new()).await.unwrap();
+        tunnel.update_peer_psk(&key, Some(SecretKey::from([3; 32]))).await.unwrap();
+
+        let exported = parse_wg_config(&tunnel.to_wg_quick_string(true)).unwrap();
+        assert_eq!(exported.peers.len(), 1);
+        assert_eq!(exported.peers[0].allowed_ips, parse_allowed_ips("10.2.0.0/16"));
+        assert_eq!(exported.peers[0].preshared_key, Some(SecretKey::from([3; 32])));
+
+        tunnel.remove_peer(&key).await.unwrap();
+        assert!(!tunnel.to_wg_quick_string(false).contains("[Peer]"));
+    }
+
+    #[tokio::test]
+    async fn test_peer_changes_reach_peer_configs() {
+        let tunnel = idle_tunnel().await;
+        let mut peer = routed_peer(8, "10.1.0.0/16");
+        peer.endpoint_host = Some("relay.example.com:51820".to_string());
+        let key = peer.public_key;
+        tunnel.add_peer(peer).await.unwrap();
+        assert_eq!(tunnel.endpoint_hosts(), vec![(key, "relay.example.com:51820".to_string())]);
+
+        tunnel.update_peer_allowed_ips(&key, parse_allowed_ips("10.2.0.0/16"), Vec::new()).await.unwrap();
+        assert_eq!(tunnel.peer_configs.read()[0].allowed_ips, parse_allowed_ips("10.2.0.0/16"));
+
+        tunnel.remove_peer(&key).await.unwrap();
+        assert!(tunnel.endpoint_hosts().is_empty());
+        assert!(tunnel.peer_configs.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_update_peer_allowed_ips() {
+        let tunnel = idle_tunnel().await;
+        let (a, b) = (routed_peer(8, "10.1.0.0/16, 10.100.0.0/24"), routed_peer(9, "10.100.0.0/24"));
+        let key_a = a.public_key;
+        tunnel.add_peer(a).await.unwrap();
+        tunnel.add_peer(b).await.unwrap();
+        tunnel.peers.get_mut(&key_a).unwrap().handshakes_completed = 3;
+
+        // The shared subnet stays routed even though peer a gives it up
+        let updated = tunnel.update_peer_allowed_ips(&key_a, parse_allowed_ips("10.2.0.0/16"), Vec::new()).await;
+        assert_eq!(updated, Ok(true));
+        let mut routes = tunnel.tun_device.loopback_routes();
+        routes.sort();
+        assert_eq!(routes, vec![(Ipv4Addr::new(10, 2, 0, 0), 16), (Ipv4Addr::new(10, 100, 0, 0), 24)]);
+        // Same peer state, so the session is kept
+        assert_eq!(tunnel.peers.get(&key_a).unwrap().handshakes_completed, 3);
+
+        assert_eq!(tunnel.update_peer_allowed_ips(&key_a, parse_allowed_ips("10.2.0.0/16"), Vec::new()).await, Ok(false));
+        assert!(tunnel.update_peer_allowed_ips(&key_a, parse_allowed_ips("0.0.0.0/0"), Vec::new()).await.is_err());
+    }
+
+    #[test]
+    fn test_replace_private_key() {
+        let encode = |key: [u8; 32]| base64::engine::general_purpose::STANDARD.encode(key);
+        let original = format!(
+            "[Interface]\nPrivateKey = {}\nAddress = 10.100.0.2/16\n\n[Peer]\nPublicKey = {}\nAllowedIPs = 10.100.0.0/16\n",
+            encode([7; 32]), encode([8; 32]),
+        );
+        let (private_key, public_key) = generate_keypair();
+        assert_eq!(public_key, derive_public_key(private_key.as_bytes()));
+
+        let replaced = replace_private_key(&original, &private_key);
+        assert!(!replaced.contains(&encode([7; 32])));
+        let config = parse_wg_config(&replaced).unwrap();
+        assert_eq!(config.private_key, private_key);
+        assert_eq!(config.peers.len(), 1);
+        assert_eq!(config.peers[0].public_key, [8; 32]);
+    }
+
+    #[test]
+    fn test_is_wireguard_message() {
+        let message = |kind: u8, len: usize| {
+            let mut datagram = vec![0u8; len];
+            datagram[0] = kind;
+            datagram
+        };
+        assert!(is_wireguard_message(&message(1, 148)));
+        assert!(is_wireguard_message(&message(2, 92)));
+        assert!(is_wireguard_message(&message(3, 64)));
+        assert!(is_wireguard_message(&message(4, 32)));
+        assert!(is_wireguard_message(&message(4, 1452)));
+
+        assert!(!is_wireguard_message(&message(1, 92)));
+        assert!(!is_wireguard_message(&message(4, 31)));
+        assert!(!is_wireguard_message(&message(5, 148)));
+        assert!(!is_wireguard_message(&[]));
+        let mut reserved = message(4, 64);
+        reserved[2] = 1;
+        assert!(!is_wireguard_message(&reserved));
+    }
+
+    #[test]
+    fn test_protocol_slot() {
+        let v4 = |protocol: u8| {
+            let mut packet = vec![0u8; 20];
+            packet[0] = 0x45;
+            packet[9] = protocol;
+            protocol_slot(&packet)
+        };
+        assert_eq!((v4(6), v4(17), v4(1), v4(47)), (0, 1, 2, 3));
+
+        let mut v6 = vec![0u8; 40];
+        v6[0] = 0x60;
+        v6[6] = 58;
+        assert_eq!(protocol_slot(&v6), 2);
+        assert_eq!(protocol_slot(&v6[..30]), 3);
+        assert_eq!(protocol_slot(&[]), 3);
+    }
+
+    /// Two peers that haven't handshaked yet: ours, the remote, and our config entry for the remote
+    fn peer_pair() -> (Tunn, Tunn, WgPeer) {
+        let our_key = x25519_dalek::StaticSecret::from([1u8; 32]);
+        let remote_key = x25519_dalek::StaticSecret::from([2u8; 32]);
+        let peer = |key: &x25519_dalek::StaticSecret| WgPeer {
+            public_key: x25519_dalek::PublicKey::from(key).to_bytes(),
+            endpoint: None,
+            endpoint_host: None,
+            allowed_ips: vec![(Ipv4Addr::new(10, 100, 0, 0), 16)],
+            allowed_ips_v6: Vec::new(),
+            persistent_keepalive: None,
+            preshared_key: None,
+        };
+        let remote_peer = peer(&remote_key);
+        let ours = WgTunnel::create_peer_tunnel(&our_key, &remote_peer).unwrap();
+        let remote = WgTunnel::create_peer_tunnel(&remote_key, &peer(&our_key)).unwrap();
+        (ours, remote, remote_peer)
+    }
+
+    /// Like peer_pair(), with an established session
+    fn session_pair() -> (Tunn, Tunn, WgPeer) {
+        let (mut ours, mut remote, remote_peer) = peer_pair();
+
+        // initiation -> response -> keepalive confirming the session on the remote side
+        let mut message = match ours.format_handshake_initiation(&mut [0u8; 2048], false) {
+            TunnResult::WriteToNetwork(data) => data.to_vec(),
+            _ => panic!("no handshake initiation"),
+        };
+        for step in 0..3 {
+            let tunn = if step % 2 == 0 { &mut remote } else { &mut ours };
+            let mut buf = [0u8; 2048];
+            message = match tunn.decapsulate(None, &message, &mut buf) {
+                TunnResult::WriteToNetwork(data) => data.to_vec(),
+                TunnResult::Done => break,
+                _ => panic!("handshake failed at step {}", step),
+            };
+        }
+        (ours, remote, remote_peer)
+    }
+
+    /// Minimal IPv4/UDP packet - boringtun only checks the version and total length
+    fn ipv4_packet(src: Ipv4Addr, dst: Ipv4Addr, payload: &[u8]) -> Vec<u8> {
+        let total_len = (28 + payload.len()) as u16;
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&total_len.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0]);
+        packet.extend_from_slice(&src.octets());
+        packet.extend_from_slice(&dst.octets());
+        packet.extend_from_slice(&[0x30, 0x39, 0x30, 0x39]);
+        packet.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    const TEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Our packet loops on a loopback TUN, with the remote peer behind a plain UDP socket
+    struct DataPath {
+        remote_socket: UdpSocket,
+        local_addr: SocketAddr,
+        apps: crate::tun_device::LoopbackHandle,
+        peers: Arc<DashMap<[u8; 32], PeerState>>,
+        counters: Arc<ProtocolCounters>,
+        cancel: CancellationToken,
+    }
+
+    impl DataPath {
+        async fn start(ours: Tunn, mut peer: WgPeer) -> Self {
+            let remote_socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let socket = Arc::new(WgSocket::bind(Some(IpAddr::V4(Ipv4Addr::LOCALHOST)), 0).unwrap());
+            let local_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, socket.local_port().unwrap()));
+
+            let tun = Arc::new(TunDevice::create(TUN_NAME, Ipv4Addr::new(10, 100, 0, 2), Ipv4Addr::new(255, 255, 0, 0)).await.unwrap());
+            let apps = tun.loopback_handle().unwrap();
+
+            peer.endpoint = Some(remote_socket.local_addr().unwrap());
+            let peers = Arc::new(DashMap::new());
+            peers.insert(peer.public_key, PeerState::new(ours, &peer, PowerProfile::Balanced.timings()));
+
+            let cancel = CancellationToken::new();
+            let paused = Arc::new(std::sync::atomic::AtomicBool::new(false));
+            let counters = Arc::new(ProtocolCounters::default());
+            tokio::spawn(WgTunnel::tun_read_loop(tun.clone(), socket.clone(), peers.clone(), cancel.clone(), paused.clone(), counters.clone()));
+            tokio::spawn(WgTunnel::udp_read_loop(socket, peers.clone(), tun, cancel.clone(), paused, counters.clone()));
+
+            Self { remote_socket, local_addr, apps, peers, counters, cancel }
+        }
+
+        /// Next datagram the remote peer receives from us
+        async fn remote_recv(&self) -> Vec<u8> {
+            let mut buf = [0u8; 2048];
+            let (len, src) = tokio::time::timeout(TEST_TIMEOUT, self.remote_socket.recv_from(&mut buf)).await.unwrap().unwrap();
+            assert_eq!(src, self.local_addr);
+            buf[..len].to_vec()
+        }
+    }
+
+    impl Drop for DataPath {
+        fn drop(&mut self) {
+            self.cancel.cancel();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_loopback_data_path() {
+        let (ours, mut remote, peer) = session_pair();
+        let mut path = DataPath::start(ours, peer.clone()).await;
+
+        // App -> peer: the remote decrypts exactly what the app sent
+        let outbound = ipv4_packet(Ipv4Addr::new(10, 100, 0, 2), Ipv4Addr::new(10, 100, 0, 1), b"ping");
+        path.apps.send(&outbound);
+        let datagram = path.remote_recv().await;
+        let mut dst = [0u8; 2048];
+        match remote.decapsulate(None, &datagram, &mut dst) {
+            TunnResult::WriteToTunnelV4(data, _) => assert_eq!(data, &outbound[..]),
+            _ => panic!("remote couldn't decrypt the outbound packet"),
+        }
+
+        // Peer -> app: the encrypted reply is written to the TUN in plaintext
+        let inbound = ipv4_packet(Ipv4Addr::new(10, 100, 0, 1), Ipv4Addr::new(10, 100, 0, 2), b"pong");
+        let mut dst = [0u8; 2048];
+        match remote.encapsulate(&inbound, &mut dst) {
+            TunnResult::WriteToNetwork(data) => path.remote_socket.send_to(data, path.local_addr).await.unwrap(),
+            _ => panic!("remote couldn't encrypt the inbound packet"),
+        };
+        assert_eq!(tokio::time::timeout(TEST_TIMEOUT, path.apps.recv()).await.unwrap(), Some(inbound));
+
+        let state = path.peers.get(&peer.public_key).unwrap();
+        assert!(state.tx_bytes > 0 && state.rx_bytes > 0);
+        drop(state);
+
+        let udp = path.counters.snapshot().udp;
+        assert_eq!(udp, ProtocolTraffic { tx_packets: 1, tx_bytes: 32, rx_packets: 1, rx_bytes: 32 });
+        assert_eq!(path.counters.snapshot().tcp, ProtocolTraffic::default());
+    }
+
+    #[tokio::test]
+    async fn test_listen_port_free_after_stop() {
+        let (ours, _, mut peer) = session_pair();
+        let localhost = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let socket = Arc::new(WgSocket::bind(localhost, 0).unwrap());
+        let port = socket.local_port().unwrap();
+        let tun = Arc::new(TunDevice::create(TUN_NAME, Ipv4Addr::new(10, 100, 0, 2), Ipv4Addr::new(255, 255, 0, 0)).await.unwrap());
+
+        peer.endpoint = Some(SocketAddr::from((Ipv4Addr::LOCALHOST, 9)));
+        let peers = Arc::new(DashMap::new());
+        peers.insert(peer.public_key, PeerState::new(ours, &peer, PowerProfile::Balanced.timings()));
+        let cancel = CancellationToken::new();
+        let paused = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let counters = Arc::new(ProtocolCounters::default());
+        let tasks = vec![
+            tokio::spawn(WgTunnel::tun_read_loop(tun.clone(), socket.clone(), peers.clone(), cancel.clone(), paused.clone(), counters.clone())),
+            tokio::spawn(WgTunnel::udp_read_loop(socket.clone(), peers.clone(), tun, cancel.clone(), paused, counters)),
+            tokio::spawn(WgTunnel::keepalive_loop(socket.clone(), peers, cancel.clone(), Duration::from_secs(25))),
+        ];
+        drop(socket);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // What stop() does: the loops are gone well before the keepalive tick
+        let stopped_at = Instant::now();
+        cancel.cancel();
+        join_loops(tasks).await;
+        assert!(stopped_at.elapsed() < LOOP_EXIT_TIMEOUT);
+        assert!(WgSocket::bind(localhost, port).is_ok());
+    }
+
+    #[test]
+    fn test_peer_goes_dead_without_replies() {
+        let (ours, _, peer) = session_pair();
+        let mut state = PeerState::new(ours, &peer, PowerProfile::Balanced.timings());
+        let interval = state.keepalive_interval().unwrap();
+        assert!(!state.is_dead());
+
+        state.last_rx = Instant::now().checked_sub(interval * DEAD_PEER_KEEPALIVES);
+        assert!(state.is_dead());
+
+        // Any message type counts as a sign of life, here a data message
+        state.on_packet_received(&[4], "203.0.113.5:51820".parse().unwrap());
+        assert!(!state.is_dead());
+
+        // Silence is expected with keepalives off
+        state.persistent_keepalive = Some(0);
+        state.last_rx = Instant::now().checked_sub(interval * DEAD_PEER_KEEPALIVES);
+        assert!(!state.is_dead());
+    }
+
+    #[tokio::test]
+    async fn test_packets_queued_during_handshake_are_sent() {
+        let (ours, mut remote, peer) = peer_pair();
+        let path = DataPath::start(ours, peer).await;
+
+        // The first packet of a flow triggers the handshake and waits in boringtun's queue
+        let first = ipv4_packet(Ipv4Addr::new(10, 100, 0, 2), Ipv4Addr::new(10, 100, 0, 1), b"first");
+        path.apps.send(&first);
+        let initiation = path.remote_recv().await;
+        let mut dst = [0u8; 2048];
+        match remote.decapsulate(None, &initiation, &mut dst) {
+            TunnResult::WriteToNetwork(response) => path.remote_socket.send_to(response, path.local_addr).await.unwrap(),
+            _ => panic!("expected a handshake initiation"),
+        };
+
+        // The handshake response releases it, after the session-confirming keepalive
+        let mut delivered = None;
+        while delivered.is_none() {
+            let datagram = path.remote_recv().await;
+            let mut dst = [0u8; 2048];
+            match remote.decapsulate(None, &datagram, &mut dst) {
+                TunnResult::WriteToTunnelV4(data, _) => delivered = Some(data.to_vec()),
+                TunnResult::Done => {}
+                _ => panic!("unexpected datagram from the tunnel"),
+            }
+        }
+        assert_eq!(delivered, Some(first));
+    }
+
+    #[test]
+    fn test_preshared_key_handshake() {
+        let psk = base64::engine::general_purpose::STANDARD
+            .decode(generate_preshared_key())
+            .unwrap()
+            .try_into()
+            .unwrap();
+
+        assert!(handshake_completes(Some(psk), Some(psk)));
+        assert!(!handshake_completes(Some(psk), None));
+    }
+
+    #[test]
+    fn test_secret_key_zeroed_on_drop() {
+        let mut slot = std::mem::MaybeUninit::new(SecretKey::from([0x5a; 32]));
+        // SAFETY: initialized above and not used as a SecretKey after the drop;
+        // repr(transparent) makes the slot's bytes the key bytes
+        let bytes = unsafe {
+            slot.assume_init_drop();
+            std::ptr::read(slot.as_ptr() as *const [u8; 32])
+        };
+        assert_eq!(bytes, [0u8; 32]);
+
+        assert_eq!(format!("{:?}", SecretKey::from([1; 32])), "SecretKey([REDACTED])");
+    }
+
+    #[test]
+    fn test_keepalive_interval_per_path() {
+        let key = x25519_dalek::StaticSecret::from([1u8; 32]);
+        let relay: SocketAddr = "198.51.100.1:51820".parse().unwrap();
+        let state = |persistent_keepalive| {
+            let peer = WgPeer {
+                public_key: x25519_dalek::PublicKey::from(&x25519_dalek::StaticSecret::from([2u8; 32])).to_bytes(),
+                endpoint: Some(relay),
+                endpoint_host: None,
+                allowed_ips: Vec::new(),
+                allowed_ips_v6: Vec::new(),
+                persistent_keepalive,
+                preshared_key: None,
+            };
+            PeerState::new(WgTunnel::create_peer_tunnel(&key, &peer).unwrap(), &peer, PowerProfile::Balanced.timings())
+        };
+        let go_direct = |peer: &mut PeerState, persistent_keepalive| {
+            *peer = state(persistent_keepalive);
+            peer.direct_verified = true;
+            peer.last_direct_rx = Some(Instant::now());
+        };
+
+        let mut peer = state(None);
+        assert_eq!(peer.keepalive_interval(), Some(RELAY_KEEPALIVE_INTERVAL));
+        go_direct(&mut peer, None);
+        assert_eq!(peer.keepalive_interval(), Some(DIRECT_KEEPALIVE_INTERVAL));
+
+        // A configured interval applies to the relay and only ever shortens the direct one
+        assert_eq!(state(Some(60)).keepalive_interval(), Some(Duration::from_secs(60)));
+        go_direct(&mut peer, Some(60));
+        assert_eq!(peer.keepalive_interval(), Some(DIRECT_KEEPALIVE_INTERVAL));
+        go_direct(&mut peer, Some(5));
+        assert_eq!(peer.keepalive_interval(), Some(Duration::from_secs(5)));
+
+        assert_eq!(state(Some(0)).keepalive_interval(), None);
+    }
+
+    #[test]
+    fn test_verify_wg_config() {
+        let b64 = |byte: u8| base64::engine::general_purpose::STANDARD.encode([byte; 32]);
+        let config = |address: &str, peer: &str| format!(
+            "[Interface]\nPrivateKey = {}\nAddress = {}\n{}", b64(7), address, peer
+        );
+        let peer = |endpoint: &str| format!(
+            "[Peer]\nPublicKey = {}\nAllowedIPs = 10.100.0.0/24\n{}", b64(9), endpoint
+        );
+
+        let report = verify_wg_config(&config("10.100.0.2/24", &peer("Endpoint = 198.51.100.1:51820")));
+        assert!(report.valid, "{:?}", report);
+        assert!(report.warnings.is_empty(), "{:?}", report);
+
+        let report = verify_wg_config(&config("10.100.0.2/24", &peer("")));
+        assert!(!report.valid);
+        assert!(report.errors.iter().any(|e| e.contains("Endpoint")), "{:?}", report);
+
+        let report = verify_wg_config(&config("10.100.0.0/24", &peer("Endpoint = 198.51.100.1:51820")));
+        assert!(report.errors.iter().any(|e| e.contains("network or broadcast")), "{:?}", report);
+
+        let report = verify_wg_config(&config("10.100.0.2/24", ""));
+        assert!(report.errors.iter().any(|e| e.contains("no [Peer]")), "{:?}", report);
+
+        let report = verify_wg_config("[Interface]\nAddress = 10.100.0.2/24\n");
+        assert_eq!(report.errors, vec!["Missing PrivateKey".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_private_key_errors() {
+        let config = |key: &str| format!("[Interface]\nPrivateKey = {}\nAddress = 10.100.0.2/24\n", key);
+        let b64 = |len: usize| base64::engine::general_purpose::STANDARD.encode(vec![7u8; len]);
+
+        assert!(parse_wg_config(&config(&b64(32))).is_ok());
+
+        let err = parse_wg_config(&config(&b64(31))).err().unwrap();
+        assert!(err.contains("got 31"), "{}", err);
+
+        let err = parse_wg_config(&config(&b64(33))).err().unwrap();
+        assert!(err.contains("got 33"), "{}", err);
+
+        let err = parse_wg_config(&config("not*valid*base64")).err().unwrap();
+        assert!(err.starts_with("Invalid private key"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_dns_servers() {
+        let key = base64::engine::general_purpose::STANDARD.encode([7u8; 32]);
+        let config = |dns: &str| format!("[Interface]\nPrivateKey = {}\nAddress = 10.100.0.2/24\n{}", key, dns);
+
+        assert!(parse_wg_config(&config("")).unwrap().dns.is_empty());
+        assert_eq!(
+            parse_wg_config(&config("DNS = 10.100.0.1, 1.1.1.1\n")).unwrap().dns,
+            vec![Ipv4Addr::new(10, 100, 0, 1), Ipv4Addr::new(1, 1, 1, 1)],
+        );
+        assert!(parse_wg_config(&config("DNS = 10.100.0.1, nope\n")).is_err());
+    }
+
+    #[test]
+    fn test_ipv6_endpoints() {
+        let endpoints = [
+            "[2001:db8::1]:51820".parse().unwrap(),
+            "203.0.113.7:51820".parse().unwrap(),
+            // A v4-mapped source is an IPv4 peer
+            "[::ffff:203.0.113.8]:51820".parse().unwrap(),
+            "[2001:db8::1]:443".parse().unwrap(),
+        ];
+        assert_eq!(ipv6_endpoints(endpoints), vec!["2001:db8::1".parse::<Ipv6Addr>().unwrap()]);
+    }
+}
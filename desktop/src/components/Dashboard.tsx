@@ -20,6 +20,7 @@ import {
   Server,
 } from "lucide-react";
 import PleiadesLogo from "./PleiadesLogo";
+import { errorMessage } from "../errors";
 
 interface NetworkData {
   id: string;
@@ -81,6 +82,7 @@ export default function Dashboard({ onLogout }: DashboardProps) {
   const [error, setError] = useState("");
   const [appVersion, setAppVersion] = useState("");
   const [connectedDevice, setConnectedDevice] = useState<Device | null>(null);
+  const [sessionId, setSessionId] = useState<string | null>(null);
   const pendingConnectChecked = useRef(false);
 
   // Check for pending connection on startup (after UAC elevation)
@@ -151,7 +153,7 @@ export default function Dashboard({ onLogout }: DashboardProps) {
         setSelectedNetwork(data[0]);
       }
     } catch (err: any) {
-      setError(err.toString());
+      setError(errorMessage(err));
     } finally {
       setLoading(false);
     }
@@ -233,14 +235,15 @@ export default function Dashboard({ onLogout }: DashboardProps) {
         exitId: exitNodeId,
       });
 
-      // Connect VPN
-      await invoke("connect_vpn", {
+      // Connect VPN - the returned id tags this session's app and helper logs
+      const id = await invoke<string>("connect_vpn", {
         deviceId: device.id,
         networkId: selectedNetwork.id,
         exitNodeType,
         exitNodeId,
       });
 
+      setSessionId(id);
       setConnectionStatus("connected");
 
       // Clear pending connection state on success
@@ -252,7 +255,7 @@ export default function Dashboard({ onLogout }: DashboardProps) {
         // Ignore
       }
     } catch (err: any) {
-      setError(err.toString());
+      setError(errorMessage(err));
       setConnectionStatus("disconnected");
 
       // Clear pending connection state on error
@@ -287,8 +290,9 @@ export default function Dashboard({ onLogout }: DashboardProps) {
 
       setConnectionStatus("disconnected");
       setConnectedDevice(null);
+      setSessionId(null);
     } catch (err: any) {
-      setError(err.toString());
+      setError(errorMessage(err));
       setConnectionStatus("connected");
     }
   };
@@ -353,6 +357,11 @@ export default function Dashboard({ onLogout }: DashboardProps) {
                   {connectedDevice.ip_address}
                 </p>
               )}
+              {isConnected && sessionId && (
+                <p className="text-xs text-muted-foreground select-all">
+                  Session {sessionId}
+                </p>
+              )}
             </div>
           </div>
 
@@ -5,6 +5,7 @@ import { getVersion } from "@tauri-apps/api/app";
 import { motion } from "framer-motion";
 import { Mail, Lock, LogIn, Loader2, AlertCircle } from "lucide-react";
 import PleiadesLogo from "./PleiadesLogo";
+import { errorMessage } from "../errors";
 
 // Google icon component with brand colors
 const GoogleIcon = () => (
@@ -66,7 +67,7 @@ export default function Login({ onLogin }: LoginProps) {
       await invoke("store_token", { token: result.token });
       onLogin(result.user);
     } catch (err: any) {
-      setError(err.toString() || "Login failed. Please check your credentials.");
+      setError(errorMessage(err) || "Login failed. Please check your credentials.");
     } finally {
       setLoading(false);
     }
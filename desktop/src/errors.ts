@@ -0,0 +1,64 @@
+// Errors returned by Tauri commands: either a plain string (older commands)
+// or a typed PleError from the backend.
+export interface PleError {
+  kind:
+    | "network"
+    | "auth"
+    | "rateLimited"
+    | "api"
+    | "notFound"
+    | "forbidden"
+    | "helperUnavailable"
+    | "helperInstallFailed"
+    | "helperStartSlow"
+    | "helperVersionMismatch"
+    | "adminRequired"
+    | "handshakeTimeout"
+    | "setupTimeout"
+    | "routeFailed"
+    | "portConflict"
+    | "adapterInUse"
+    | "missingPrivateKey"
+    | "certificatePinMismatch"
+    | "parse"
+    | "invalidImage"
+    | "noQrCode"
+    | "multipleQrCodes"
+    | "other";
+  message: string;
+  retryable: boolean;
+  retryAfterSecs?: number;
+}
+
+// Payload of the "connection-failed" event - `reason` picks the remediation to show
+export type ConnectionFailure =
+  | "needsAdmin"
+  | "helperNotInstalled"
+  | "helperInstallFailed"
+  | "helperStarting"
+  | "helperOutdated"
+  | "handshakeTimeout"
+  | "setupTimeout"
+  | "authExpired"
+  | "network"
+  | "serverRejected"
+  | "resourceInUse"
+  | "routeFailed"
+  | "untrustedServer"
+  | "missingPrivateKey"
+  | "invalidConfig"
+  | "other";
+
+export interface ConnectionFailedEvent {
+  reason: ConnectionFailure;
+  error: PleError;
+}
+
+export function isPleError(err: unknown): err is PleError {
+  return typeof err === "object" && err !== null && "kind" in err && "message" in err;
+}
+
+export function errorMessage(err: unknown): string {
+  if (isPleError(err)) return err.message;
+  return String(err);
+}